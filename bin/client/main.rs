@@ -20,7 +20,6 @@ use bindle::{
 };
 
 use clap::Parser;
-use sha2::Digest;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 use tokio_stream::StreamExt;
@@ -467,31 +466,18 @@ async fn generate_label(
     name: Option<String>,
     media_type: Option<String>,
 ) -> Result<bindle::Label> {
-    let path = file_path.as_ref().to_owned();
-    let mut file = tokio::fs::File::open(&path).await?;
-    let media_type = media_type.unwrap_or_else(|| {
-        mime_guess::from_path(&path)
-            .first_or_octet_stream()
-            .to_string()
-    });
-    info!("Using media type {}", media_type);
-    // Note: Should be able to unwrap here because the file opening step would have
-    // failed in conditions where this returns `None`
-    let name = name.unwrap_or_else(|| path.file_name().unwrap().to_string_lossy().to_string());
-    info!("Using name {}", name);
-    let size = file.metadata().await?.len();
-    let mut sha = bindle::async_util::AsyncSha256::new();
-    tokio::io::copy(&mut file, &mut sha).await?;
-    let result = sha.into_inner().expect("data lock error").finalize();
-
-    Ok(bindle::Label {
-        sha256: format!("{:x}", result),
-        media_type,
-        size,
-        name,
-        annotations: None, // TODO: allow annotations from command line
-        ..bindle::Label::default()
-    })
+    let mut builder = bindle::LabelBuilder::from_path(file_path).await?;
+    if let Some(name) = name {
+        builder = builder.name(name);
+    }
+    if let Some(media_type) = media_type {
+        builder = builder.media_type(media_type);
+    }
+    // TODO: allow annotations from command line
+    let label = builder.build();
+    info!("Using media type {}", label.media_type);
+    info!("Using name {}", label.name);
+    Ok(label)
 }
 
 async fn get_parcel<C: Cache + Send + Sync + Clone>(cache: C, opts: GetParcel) -> Result<()> {
@@ -578,7 +564,7 @@ async fn get_all<C: Cache + Send + Sync + Clone>(cache: C, opts: Get) -> Result<
                 }
                 Err(e) => {
                     match e {
-                        ProviderError::NotFound => warn!("Parcel {} does not exist", sha),
+                        ProviderError::NotFound { .. } => warn!("Parcel {} does not exist", sha),
                         ProviderError::ProxyError(err)
                             if matches!(err, ClientError::ParcelNotFound) =>
                         {
@@ -627,7 +613,7 @@ async fn get_all<C: Cache + Send + Sync + Clone>(cache: C, opts: Get) -> Result<
 
 fn map_storage_error(e: ProviderError) -> ClientError {
     match e {
-        ProviderError::Io(e) => ClientError::Io(e),
+        ProviderError::Io { source, .. } => ClientError::Io(source),
         ProviderError::ProxyError(inner) => inner,
         ProviderError::InvalidId(parse_err) => ClientError::InvalidId(parse_err),
         _ => ClientError::Other(format!("{}", e)),
@@ -103,6 +103,7 @@ async fn main() {
         annotations: None,
         group: None,
         signature: None,
+        extra: Default::default(),
     };
 
     if let Some(auth) = package.author {
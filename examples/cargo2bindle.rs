@@ -133,6 +133,7 @@ async fn main() {
         annotations: None,
         group: None,
         signature: None,
+        extra: Default::default(),
     };
 
     if !parcels.is_empty() {
@@ -171,4 +171,9 @@ where
     {
         self.local.parcel_exists(bindle_id, parcel_id).await
     }
+
+    // The cache is only usable if the local provider is
+    async fn health_check(&self) -> Result<()> {
+        self.local.health_check().await
+    }
 }
@@ -91,6 +91,12 @@ where
         }
     }
 
+    #[instrument(level = "trace", skip(self))]
+    async fn warm_cache(&self, ids: &[String]) -> Result<usize> {
+        // Loading an invoice through the cache fetches it from the remote and caches it
+        crate::provider::warm_cache_via_get(self, ids).await
+    }
+
     #[instrument(level = "trace", skip(self, id), fields(invoice_id))]
     async fn yank_invoice<I>(&self, id: I) -> Result<()>
     where
@@ -187,6 +193,10 @@ where
         ))
     }
 
+    async fn health_check(&self) -> Result<()> {
+        self.remote.health_check().await
+    }
+
     #[instrument(level = "trace", skip(self, bindle_id), fields(invoice_id))]
     async fn parcel_exists<I>(&self, bindle_id: I, parcel_id: &str) -> Result<bool>
     where
@@ -403,6 +413,44 @@ mod test {
         )
     }
 
+    #[tokio::test]
+    async fn test_warm_cache() {
+        let provider = TestProvider::default();
+        let cache = LruCache::new(10, provider.clone());
+        let ids = vec![
+            "enterprise.com/warpcore/1.0.0".to_owned(),
+            "enterprise.com/warpcore/2.0.0".to_owned(),
+        ];
+
+        assert_eq!(
+            2,
+            cache.warm_cache(&ids).await.expect("Should warm cache"),
+            "Both invoices should be warmed"
+        );
+        for id in ids.iter() {
+            cache
+                .get_invoice(id.as_str())
+                .await
+                .expect("Should be able to get invoice");
+        }
+
+        assert_eq!(
+            2,
+            *provider.get_yanked_count.lock().await,
+            "Warmed invoices should be returned from the cache"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        // The test provider doesn't check any storage, so it shouldn't be reported as healthy
+        let cache = LruCache::new(10, TestProvider::default());
+        assert!(matches!(
+            cache.health_check().await,
+            Err(ProviderError::Unsupported(_))
+        ));
+    }
+
     #[tokio::test]
     async fn test_passthrough() {
         // Make sure all the create operations pass through
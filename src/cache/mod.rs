@@ -10,6 +10,9 @@ use crate::{
 pub mod dumb;
 pub use dumb::DumbCache;
 
+pub mod read_through;
+pub use read_through::ReadThroughCache;
+
 // Once implemented, we can export this
 mod lru;
 pub use self::lru::LruCache;
@@ -27,7 +30,7 @@ pub(crate) type CacheResult<T> = Result<Option<T>, crate::provider::ProviderErro
 pub(crate) fn into_cache_result<T>(res: crate::provider::Result<T>) -> CacheResult<T> {
     match res {
         Ok(val) => Ok(Some(val)),
-        Err(e) if matches!(e, ProviderError::NotFound) => Ok(None),
+        Err(e) if matches!(e, ProviderError::NotFound { .. }) => Ok(None),
         Err(e) => Err(e),
     }
 }
@@ -0,0 +1,344 @@
+//! A read-through cache that fills a local provider from an upstream provider on demand
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, instrument, trace, warn};
+use tracing_futures::Instrument;
+
+use super::{into_cache_result, Cache};
+use crate::provider::{Provider, ProviderError, Result};
+use crate::verification::Verified;
+use crate::{Id, Signed};
+
+/// A read-through cache built purely on top of two providers. Reads are served from the local
+/// provider when possible. On a miss, the data is fetched from the upstream provider, stored in
+/// the local provider, and then returned. All writes go only to the local provider, so the upstream
+/// is never modified.
+///
+/// Yanked invoices are never added to the cache. Fetching one with `get_invoice` returns a
+/// [`ProviderError::Yanked`] error just as it would from the upstream
+#[derive(Clone)]
+pub struct ReadThroughCache<Local, Upstream> {
+    local: Local,
+    upstream: Upstream,
+}
+
+impl<Local: Provider, Upstream: Provider> ReadThroughCache<Local, Upstream> {
+    /// Returns a new cache that stores data in `local` and fills it from `upstream`
+    pub fn new(local: Local, upstream: Upstream) -> ReadThroughCache<Local, Upstream> {
+        ReadThroughCache { local, upstream }
+    }
+}
+
+impl<Local, Upstream> Cache for ReadThroughCache<Local, Upstream>
+where
+    Local: Provider + Send + Sync,
+    Upstream: Provider + Send + Sync,
+{
+}
+
+#[async_trait::async_trait]
+impl<Local, Upstream> Provider for ReadThroughCache<Local, Upstream>
+where
+    Local: Provider + Send + Sync,
+    Upstream: Provider + Send + Sync,
+{
+    async fn create_invoice<I>(&self, inv: I) -> Result<(crate::Invoice, Vec<crate::Label>)>
+    where
+        I: Signed + Verified + Send + Sync,
+    {
+        self.local.create_invoice(inv).await
+    }
+
+    #[instrument(level = "trace", skip(self, id))]
+    async fn get_yanked_invoice<I>(&self, id: I) -> Result<crate::Invoice>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        if let Some(inv) = into_cache_result(self.local.get_yanked_invoice(&parsed_id).await)? {
+            return Ok(inv);
+        }
+        async {
+            debug!("Cache miss for invoice, attempting to fetch from upstream");
+            let inv = self.upstream.get_yanked_invoice(&parsed_id).await?;
+            if inv.yanked.unwrap_or(false) {
+                // Don't cache yanked invoices, so they can't be served from the cache later
+                trace!("Upstream invoice is yanked, not caching");
+                return Ok(inv);
+            }
+
+            // Attempt to insert the invoice into the local store. If it fails, warn the user and
+            // return the invoice anyway
+            if let Err(e) = self
+                .local
+                .create_invoice(super::noop_verify_and_sign(inv.clone()))
+                .await
+            {
+                warn!(error = %e, "Fetched invoice from upstream, but unable to save it locally");
+            }
+            Ok(inv)
+        }
+        .instrument(tracing::trace_span!("get_invoice_cache_miss", invoice_id = %parsed_id))
+        .await
+    }
+
+    async fn invoice_exists<I>(&self, id: I) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        if self.local.invoice_exists(&parsed_id).await? {
+            return Ok(true);
+        }
+        self.upstream.invoice_exists(&parsed_id).await
+    }
+
+    async fn yank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.local.yank_invoice(id).await
+    }
+
+    async fn unyank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.local.unyank_invoice(id).await
+    }
+
+    async fn update_invoice_annotations<I>(
+        &self,
+        id: I,
+        annotations: BTreeMap<String, String>,
+    ) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.local.update_invoice_annotations(id, annotations).await
+    }
+
+    async fn add_parcels_to_invoice<I>(
+        &self,
+        id: I,
+        new_parcels: Vec<crate::Parcel>,
+    ) -> Result<Vec<crate::Label>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.local.add_parcels_to_invoice(id, new_parcels).await
+    }
+
+    async fn create_parcel<I, R, B>(&self, bindle_id: I, parcel_id: &str, data: R) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+        R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
+        B: bytes::Buf + Send,
+    {
+        self.local.create_parcel(bindle_id, parcel_id, data).await
+    }
+
+    async fn delete_parcel(&self, parcel_id: &str) -> Result<()> {
+        self.local.delete_parcel(parcel_id).await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        // All writes go to the local provider and every cached read is served from it
+        self.local.health_check().await
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id))]
+    async fn get_parcel<I>(
+        &self,
+        bindle_id: I,
+        parcel_id: &str,
+    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = bindle_id.try_into().map_err(|e| e.into())?;
+        if let Some(parcel) = into_cache_result(self.local.get_parcel(&parsed_id, parcel_id).await)?
+        {
+            return Ok(parcel);
+        }
+        async {
+            debug!("Cache miss for parcel, attempting to fetch from upstream");
+            // The local provider can only store parcels that belong to an invoice it has, so make
+            // sure the invoice is cached first. If it can't be, storing the parcel below will fail
+            // and we fall back to streaming from upstream
+            if let Err(e) = self.get_yanked_invoice(&parsed_id).await {
+                warn!(error = %e, "Unable to cache invoice for parcel");
+            }
+
+            let stream = self
+                .upstream
+                .get_parcel(&parsed_id, parcel_id)
+                .await?
+                .map(|res| {
+                    res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+                });
+            // The stream is consumed when storing it, so it has to be fetched again afterwards
+            trace!("Attempting to store parcel in cache");
+            match self
+                .local
+                .create_parcel(&parsed_id, parcel_id, stream)
+                .await
+            {
+                Ok(_) => self.local.get_parcel(&parsed_id, parcel_id).await,
+                Err(e) => {
+                    warn!(error = %e, "Fetched parcel from upstream, but unable to save it locally");
+                    self.upstream.get_parcel(&parsed_id, parcel_id).await
+                }
+            }
+        }
+        .instrument(
+            tracing::trace_span!("get_parcel_cache_miss", invoice_id = %parsed_id, parcel_id),
+        )
+        .await
+    }
+
+    async fn parcel_exists<I>(&self, bindle_id: I, parcel_id: &str) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = bindle_id.try_into().map_err(|e| e.into())?;
+        if self.local.parcel_exists(&parsed_id, parcel_id).await? {
+            return Ok(true);
+        }
+        self.upstream.parcel_exists(&parsed_id, parcel_id).await
+    }
+}
+
+#[cfg(all(test, feature = "providers"))]
+mod test {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+    use crate::search::StrictEngine;
+    use crate::testing;
+    use crate::verification::NoopVerified;
+    use crate::NoopSigned;
+
+    use tokio_util::codec::{BytesCodec, FramedRead};
+
+    type Memory = MemoryProvider<StrictEngine>;
+
+    /// Returns a local and an upstream provider, with the upstream holding the given scaffold
+    async fn setup(scaffold: &testing::Scaffold) -> (Memory, Memory) {
+        let local = MemoryProvider::new(StrictEngine::default());
+        let upstream = MemoryProvider::new(StrictEngine::default());
+        upstream
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created upstream");
+        for parcel in scaffold.parcel_files.values() {
+            upstream
+                .create_parcel(
+                    &scaffold.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created upstream");
+        }
+        (local, upstream)
+    }
+
+    #[tokio::test]
+    async fn test_should_fill_cache_on_first_read() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = &scaffold.invoice.bindle.id;
+        let (local, upstream) = setup(&scaffold).await;
+        let cache = ReadThroughCache::new(local.clone(), upstream);
+
+        assert!(!local.invoice_exists(id).await.unwrap());
+        let inv = cache
+            .get_invoice(id)
+            .await
+            .expect("Invoice should be fetched from upstream");
+        assert_eq!(id, &inv.bindle.id);
+        assert!(
+            local.invoice_exists(id).await.unwrap(),
+            "Invoice should be cached locally"
+        );
+
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let mut stream = cache
+            .get_parcel(id, &parcel.sha)
+            .await
+            .expect("Parcel should be fetched from upstream");
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(parcel.data, data);
+        assert!(
+            local.parcel_exists(id, &parcel.sha).await.unwrap(),
+            "Parcel should be cached locally"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_not_cache_yanked_invoices() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = &scaffold.invoice.bindle.id;
+        let (local, upstream) = setup(&scaffold).await;
+        upstream.yank_invoice(id).await.unwrap();
+        let cache = ReadThroughCache::new(local.clone(), upstream);
+
+        let err = cache
+            .get_invoice(id)
+            .await
+            .expect_err("Yanked invoice should not be returned");
+        assert!(
+            matches!(err, ProviderError::Yanked),
+            "Expected Yanked, got {:?}",
+            err
+        );
+        assert!(
+            !local.invoice_exists(id).await.unwrap(),
+            "Yanked invoice should not be cached"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_check_local_health() {
+        let local = MemoryProvider::new(StrictEngine::default());
+        let upstream = MemoryProvider::new(StrictEngine::default());
+        ReadThroughCache::new(local, upstream)
+            .health_check()
+            .await
+            .expect("Cache should be healthy");
+    }
+
+    #[tokio::test]
+    async fn test_should_only_write_locally() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let local = MemoryProvider::new(StrictEngine::default());
+        let upstream = MemoryProvider::new(StrictEngine::default());
+        let cache = ReadThroughCache::new(local.clone(), upstream.clone());
+
+        cache
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        assert!(local
+            .invoice_exists(&scaffold.invoice.bindle.id)
+            .await
+            .unwrap());
+        assert!(!upstream
+            .invoice_exists(&scaffold.invoice.bindle.id)
+            .await
+            .unwrap());
+    }
+}
@@ -13,6 +13,8 @@ pub enum ParseError {
     InvalidId(String),
     #[error("Version {0} is not a valid semantic version (e.g. 1.2.3)")]
     InvalidSemver(String),
+    #[error("Invalid parcel ID '{0}'. A parcel ID should only contain ASCII letters and digits")]
+    InvalidParcelId(String),
 }
 
 type Result<T> = std::result::Result<T, ParseError>;
@@ -168,6 +170,15 @@ mod test {
         Id::from_str("example.com/a/long/path/foo/1.0.0").expect("Should parse long ID");
         Id::from_str("example.com/foo/1.0.0-rc.1").expect("Should parse RC version ID");
 
+        Id::from_str("example.com/foo/1.2.3-beta.1+build.5")
+            .expect("Should parse version with build metadata");
+
+        // Only the last segment should be treated as the version
+        let id = Id::from_str("example.com/foo/bar/1.2.3-beta.1+build.5").unwrap();
+        assert_eq!(id.name(), "example.com/foo/bar");
+        assert_eq!(id.version_string(), "1.2.3-beta.1+build.5");
+        assert_eq!(id.to_string(), "example.com/foo/bar/1.2.3-beta.1+build.5");
+
         // Invalid paths
         assert!(
             Id::from_str("foo/").is_err(),
@@ -0,0 +1,253 @@
+//! Definition of the `InvoiceBuilder` and `LabelBuilder` types, for constructing invoices and
+//! labels without filling in every field by hand
+
+use std::path::Path;
+
+use tokio::io::AsyncReadExt;
+
+use crate::invoice::{
+    AnnotationMap, BindleSpec, DigestAlgorithm, Group, Invoice, Label, Parcel, ValidationError,
+};
+use crate::BINDLE_VERSION_1;
+
+/// The number of bytes read from a file at a time when computing its digest
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A builder for constructing an [`Invoice`](crate::Invoice). Created using `Invoice::builder`.
+///
+/// The name and version of the bindle are required, everything else is optional. The bindle
+/// version of the invoice defaults to [`BINDLE_VERSION_1`](crate::BINDLE_VERSION_1)
+#[derive(Debug, Clone, Default)]
+pub struct InvoiceBuilder {
+    name: Option<String>,
+    version: Option<String>,
+    description: Option<String>,
+    authors: Vec<String>,
+    parcels: Vec<Parcel>,
+    annotations: AnnotationMap,
+    groups: Vec<Group>,
+}
+
+impl InvoiceBuilder {
+    /// Sets the name of the bindle, such as `example.com/foo`
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the version of the bindle. This must be a valid semantic version (e.g. 1.2.3)
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Sets the description of the bindle
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Adds an author to the bindle. Can be called multiple times to add several authors
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.authors.push(author.into());
+        self
+    }
+
+    /// Adds a parcel to the invoice
+    pub fn add_parcel(mut self, parcel: Parcel) -> Self {
+        self.parcels.push(parcel);
+        self
+    }
+
+    /// Sets an annotation on the invoice, replacing any previous value for the same key
+    pub fn annotation(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    /// Adds a group to the invoice
+    pub fn group(mut self, group: Group) -> Self {
+        self.groups.push(group);
+        self
+    }
+
+    /// Returns the built invoice, or every [`ValidationError`] found if the name or version are
+    /// missing or the invoice is not valid (see [`Invoice::validate`](crate::Invoice::validate))
+    pub fn build(self) -> Result<Invoice, Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let name = self.name.filter(|n| !n.is_empty());
+        if name.is_none() {
+            errors.push(ValidationError::EmptyName);
+        }
+        let version = match self.version.filter(|v| !v.is_empty()) {
+            Some(v) => match v.parse::<semver::Version>() {
+                Ok(_) => Some(v),
+                Err(_) => {
+                    errors.push(ValidationError::InvalidVersion(v));
+                    None
+                }
+            },
+            None => {
+                errors.push(ValidationError::MissingVersion);
+                None
+            }
+        };
+        let (name, version) = match (name, version) {
+            (Some(name), Some(version)) => (name, version),
+            _ => return Err(errors),
+        };
+
+        let id = format!("{}/{}", name, version)
+            .parse()
+            .map_err(|_| vec![ValidationError::InvalidVersion(version)])?;
+        let invoice = Invoice {
+            bindle_version: BINDLE_VERSION_1.to_owned(),
+            yanked: None,
+            yanked_signature: None,
+            bindle: BindleSpec {
+                id,
+                description: self.description,
+                authors: (!self.authors.is_empty()).then(|| self.authors),
+            },
+            annotations: (!self.annotations.is_empty()).then(|| self.annotations),
+            parcel: (!self.parcels.is_empty()).then(|| self.parcels),
+            group: (!self.groups.is_empty()).then(|| self.groups),
+            signature: None,
+            extra: Default::default(),
+        };
+
+        let errors = invoice.validate();
+        if errors.is_empty() {
+            Ok(invoice)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A builder for constructing a [`Label`](crate::Label) for some parcel data. The `sha256` and
+/// `size` of the label are computed from the data, so they always match what is uploaded.
+///
+/// Unless it is set explicitly, the media type is guessed from the file extension (when built
+/// from a path with the `client` feature), then detected from the data itself (with the
+/// `providers` feature), falling back to `application/octet-stream`
+#[derive(Debug, Clone)]
+pub struct LabelBuilder {
+    sha256: String,
+    size: u64,
+    name: String,
+    media_type: Option<String>,
+    guessed_media_type: Option<String>,
+    annotations: AnnotationMap,
+}
+
+impl LabelBuilder {
+    /// Returns a builder for a label describing the given data. The label has no name until one is
+    /// set with [`name`](LabelBuilder::name)
+    pub fn from_bytes(data: impl AsRef<[u8]>) -> Self {
+        let data = data.as_ref();
+        LabelBuilder {
+            sha256: DigestAlgorithm::Sha256.digest(data),
+            size: data.len() as u64,
+            name: String::new(),
+            media_type: None,
+            guessed_media_type: sniff_media_type(data),
+            annotations: AnnotationMap::new(),
+        }
+    }
+
+    /// Returns a builder for a label describing the file at the given path, reading the file to
+    /// compute its digest. The name of the label defaults to the file name
+    pub async fn from_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut hasher = DigestAlgorithm::Sha256.hasher();
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        // Only the start of the file is needed to detect its media type
+        let mut head = Vec::new();
+        let mut size = 0u64;
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            if head.len() < SNIFF_SIZE {
+                let wanted = (SNIFF_SIZE - head.len()).min(n);
+                head.extend_from_slice(&buf[..wanted]);
+            }
+            size += n as u64;
+        }
+
+        Ok(LabelBuilder {
+            sha256: hasher.finalize_hex(),
+            size,
+            name: path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            media_type: None,
+            guessed_media_type: guess_media_type(path).or_else(|| sniff_media_type(&head)),
+            annotations: AnnotationMap::new(),
+        })
+    }
+
+    /// Sets the name of the label
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the media type of the label, instead of guessing it
+    pub fn media_type(mut self, media_type: impl Into<String>) -> Self {
+        self.media_type = Some(media_type.into());
+        self
+    }
+
+    /// Sets an annotation on the label, replacing any previous value for the same key
+    pub fn annotation(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns the built label
+    pub fn build(self) -> Label {
+        let default_media_type = Label::default().media_type;
+        Label {
+            sha256: self.sha256,
+            size: self.size,
+            name: self.name,
+            media_type: self
+                .media_type
+                .or(self.guessed_media_type)
+                .unwrap_or(default_media_type),
+            annotations: (!self.annotations.is_empty()).then(|| self.annotations),
+            ..Label::default()
+        }
+    }
+}
+
+#[cfg(feature = "providers")]
+const SNIFF_SIZE: usize = crate::provider::file::SNIFF_SIZE as usize;
+#[cfg(not(feature = "providers"))]
+const SNIFF_SIZE: usize = 0;
+
+#[cfg(feature = "providers")]
+fn sniff_media_type(data: &[u8]) -> Option<String> {
+    crate::provider::file::sniff_media_type(&data[..data.len().min(SNIFF_SIZE)])
+}
+
+#[cfg(not(feature = "providers"))]
+fn sniff_media_type(_data: &[u8]) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "client")]
+fn guess_media_type(path: &Path) -> Option<String> {
+    mime_guess::from_path(path).first().map(|m| m.to_string())
+}
+
+#[cfg(not(feature = "client"))]
+fn guess_media_type(_path: &Path) -> Option<String> {
+    None
+}
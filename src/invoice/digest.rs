@@ -0,0 +1,122 @@
+//! Definition of the `DigestAlgorithm` type and the hashing helpers used to compute parcel digests
+
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+/// The hashing algorithm used to produce a parcel's digest
+///
+/// Labels that do not specify an algorithm are assumed to use SHA-256, so existing invoices remain
+/// valid
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Default for DigestAlgorithm {
+    fn default() -> Self {
+        DigestAlgorithm::Sha256
+    }
+}
+
+impl DigestAlgorithm {
+    /// Returns the lowercase hex encoded digest of the given data
+    pub fn digest(&self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize_hex()
+    }
+
+    /// Returns the length of a hex encoded digest produced by this algorithm
+    pub fn hex_len(&self) -> usize {
+        match self {
+            DigestAlgorithm::Sha256 => 64,
+            DigestAlgorithm::Sha512 => 128,
+        }
+    }
+
+    /// Returns a new incremental hasher for this algorithm
+    pub(crate) fn hasher(&self) -> Hasher {
+        match self {
+            DigestAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            DigestAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+}
+
+/// An incremental hasher for any of the supported `DigestAlgorithm`s
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+        }
+    }
+
+    /// Consumes the hasher, returning the lowercase hex encoded digest
+    pub(crate) fn finalize_hex(self) -> String {
+        match self {
+            Hasher::Sha256(h) => format!("{:x}", h.finalize()),
+            Hasher::Sha512(h) => format!("{:x}", h.finalize()),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digest_algorithms() {
+        assert_eq!(
+            DigestAlgorithm::Sha256.digest(b"hello"),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(
+            DigestAlgorithm::Sha512.digest(b"hello"),
+            "9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca72323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043"
+        );
+    }
+
+    #[test]
+    fn test_label_without_algorithm_defaults_to_sha256() {
+        let raw = r#"
+        sha256 = "abcdef"
+        mediaType = "text/plain"
+        name = "foo.txt"
+        size = 6
+        "#;
+        let label: crate::Label = toml::from_str(raw).expect("label should parse");
+        assert_eq!(label.digest_algorithm, None);
+        assert_eq!(label.algorithm(), DigestAlgorithm::Sha256);
+
+        let raw = r#"
+        sha256 = "abcdef"
+        digestAlgorithm = "sha512"
+        mediaType = "text/plain"
+        name = "foo.txt"
+        size = 6
+        "#;
+        let label: crate::Label = toml::from_str(raw).expect("label should parse");
+        assert_eq!(label.algorithm(), DigestAlgorithm::Sha512);
+    }
+}
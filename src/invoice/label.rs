@@ -3,21 +3,68 @@
 //! See the [Label Spec](https://github.com/deislabs/bindle/blob/master/docs/label-spec.md) for more
 //! detailed information
 
+use std::hash::{Hash, Hasher};
+
+use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
 
-use crate::invoice::{AnnotationMap, FeatureMap};
+use crate::invoice::{
+    serialize_extra_fields, AnnotationMap, DigestAlgorithm, ExtraFields, FeatureMap,
+};
 
 /// Metadata of a stored parcel
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct Label {
     pub sha256: String,
+    /// The algorithm used to compute the digest stored in `sha256`. If not set, SHA-256 is assumed
+    pub digest_algorithm: Option<DigestAlgorithm>,
     pub media_type: String,
     pub name: String,
     pub size: u64,
     pub annotations: Option<AnnotationMap>,
     pub feature: Option<FeatureMap>,
     pub origin: Option<String>,
+    /// Any fields not known to this version of Bindle. These are not signed
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// Serialized by hand for the same reason as `Invoice`, so extra fields can be written in an order
+// TOML allows
+impl Serialize for Label {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("sha256", &self.sha256)?;
+        map.serialize_entry("digestAlgorithm", &self.digest_algorithm)?;
+        map.serialize_entry("mediaType", &self.media_type)?;
+        map.serialize_entry("name", &self.name)?;
+        map.serialize_entry("size", &self.size)?;
+        serialize_extra_fields(&mut map, &self.extra, false)?;
+        map.serialize_entry("annotations", &self.annotations)?;
+        map.serialize_entry("feature", &self.feature)?;
+        map.serialize_entry("origin", &self.origin)?;
+        serialize_extra_fields(&mut map, &self.extra, true)?;
+        map.end()
+    }
+}
+
+// Extra fields could in theory hold a NaN float, which isn't equal to itself, but labels are still
+// treated as fully comparable as nothing writes them. Extra fields are left out of the hash, which
+// stays consistent with equality
+impl Eq for Label {}
+
+impl Hash for Label {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sha256.hash(state);
+        self.digest_algorithm.hash(state);
+        self.media_type.hash(state);
+        self.name.hash(state);
+        self.size.hash(state);
+        self.annotations.hash(state);
+        self.feature.hash(state);
+        self.origin.hash(state);
+    }
 }
 
 impl Label {
@@ -28,18 +75,175 @@ impl Label {
             ..Label::default()
         }
     }
+
+    /// Returns the media type of this label in its normalized form, or `None` if it is not a valid
+    /// RFC 6838 media type. See [`normalize_media_type`]
+    pub fn normalized_media_type(&self) -> Option<String> {
+        normalize_media_type(&self.media_type)
+    }
+
+    /// Returns true if the media type of this label matches the given pattern. Only the type and
+    /// subtype are compared, ignoring case and any parameters. The pattern may use `*` as the
+    /// subtype to match any subtype of a type, such as `image/*`, and `*/*` matches any valid
+    /// media type. Labels with an invalid media type never match
+    pub fn media_type_matches(&self, pattern: &str) -> bool {
+        let media_type = match self.normalized_media_type() {
+            Some(m) => m,
+            None => return false,
+        };
+        let essence = media_type.split(';').next().unwrap_or_default();
+        let (ty, subtype) = match essence.split_once('/') {
+            Some(parts) => parts,
+            None => return false,
+        };
+        let pattern = pattern.split(';').next().unwrap_or_default().trim();
+        match pattern.split_once('/') {
+            Some(("*", "*")) => true,
+            Some((pattern_ty, "*")) => pattern_ty.eq_ignore_ascii_case(ty),
+            Some((pattern_ty, pattern_subtype)) => {
+                pattern_ty.eq_ignore_ascii_case(ty) && pattern_subtype.eq_ignore_ascii_case(subtype)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the algorithm used to compute this label's digest, defaulting to SHA-256
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.digest_algorithm.unwrap_or_default()
+    }
+
+    /// Returns true if the digest in `sha256` is a lowercase hex string of the length produced by
+    /// this label's digest algorithm
+    pub fn has_valid_digest(&self) -> bool {
+        self.sha256.len() == self.algorithm().hex_len()
+            && self
+                .sha256
+                .chars()
+                .all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+    }
 }
 
 impl Default for Label {
     fn default() -> Self {
         Self {
             sha256: "".to_owned(),
+            digest_algorithm: None,
             media_type: "application/octet-stream".to_owned(),
             name: "".to_owned(),
             size: 0,
             annotations: None,
             feature: None,
             origin: None,
+            extra: ExtraFields::new(),
+        }
+    }
+}
+
+/// Parses the given value as an RFC 6838 `type/subtype` media type (with optional parameters),
+/// returning it in a normalized form or `None` if it is not valid.
+///
+/// The type, subtype and parameter names are lowercased and surrounding whitespace is removed, so
+/// `Text/Plain ;Charset=UTF-8` becomes `text/plain; charset=UTF-8`. Parameter values are case
+/// sensitive and are left as is
+pub fn normalize_media_type(media_type: &str) -> Option<String> {
+    let mut parts = media_type.split(';');
+    let (ty, subtype) = parts.next()?.trim().split_once('/')?;
+    if !is_restricted_name(ty) || !is_restricted_name(subtype) {
+        return None;
+    }
+    let mut normalized = format!(
+        "{}/{}",
+        ty.to_ascii_lowercase(),
+        subtype.to_ascii_lowercase()
+    );
+    for param in parts {
+        let (name, value) = param.trim().split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if !is_restricted_name(name) || value.is_empty() {
+            return None;
         }
+        normalized.push_str("; ");
+        normalized.push_str(&name.to_ascii_lowercase());
+        normalized.push('=');
+        normalized.push_str(value);
+    }
+    Some(normalized)
+}
+
+/// Checks if the given value is a `restricted-name` as defined in RFC 6838 section 4.2
+fn is_restricted_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphanumeric() => (),
+        _ => return false,
+    }
+    name.len() <= 127 && chars.all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_media_type() {
+        assert_eq!(
+            Some("text/plain".to_owned()),
+            normalize_media_type("text/plain")
+        );
+        assert_eq!(
+            Some("application/vnd.bindle+toml".to_owned()),
+            normalize_media_type(" Application/VND.Bindle+TOML ")
+        );
+        assert_eq!(
+            Some("text/plain; charset=UTF-8".to_owned()),
+            normalize_media_type("Text/Plain ;Charset=UTF-8")
+        );
+
+        for invalid in [
+            "",
+            "not a type",
+            "text",
+            "text/",
+            "/plain",
+            "text/plain/extra",
+            "text/pl ain",
+            "text/plain; charset",
+            "text/plain; =utf-8",
+        ] {
+            assert_eq!(
+                None,
+                normalize_media_type(invalid),
+                "{} should be invalid",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_media_type_matches() {
+        let label = Label {
+            media_type: "Image/PNG; foo=bar".to_owned(),
+            ..Label::default()
+        };
+        for pattern in ["image/png", "IMAGE/png; charset=utf-8", "image/*", "*/*"] {
+            assert!(
+                label.media_type_matches(pattern),
+                "{} should match",
+                pattern
+            );
+        }
+        for pattern in ["image/gif", "text/*", "image", "*", ""] {
+            assert!(
+                !label.media_type_matches(pattern),
+                "{} should not match",
+                pattern
+            );
+        }
+
+        let invalid = Label {
+            media_type: "not a type".to_owned(),
+            ..Label::default()
+        };
+        assert!(!invalid.media_type_matches("*/*"));
     }
 }
@@ -3,12 +3,16 @@
 
 mod api;
 mod bindle_spec;
+mod builder;
 mod condition;
+mod digest;
 mod group;
 mod label;
 mod parcel;
+mod policy;
 mod sealed;
 pub mod signature;
+mod validation;
 pub mod verification;
 
 #[cfg(feature = "client")]
@@ -22,25 +26,35 @@ pub use api::{ErrorResponse, InvoiceCreateResponse, MissingParcelsResponse, Quer
 #[doc(inline)]
 pub use bindle_spec::BindleSpec;
 #[doc(inline)]
+pub use builder::{InvoiceBuilder, LabelBuilder};
+#[doc(inline)]
 pub use condition::Condition;
 #[doc(inline)]
+pub use digest::DigestAlgorithm;
+pub(crate) use digest::Hasher;
+#[doc(inline)]
 pub use group::Group;
 #[doc(inline)]
-pub use label::Label;
+pub use label::{normalize_media_type, Label};
 #[doc(inline)]
 pub use parcel::Parcel;
 #[doc(inline)]
+pub use policy::{check_policy, InvoicePolicy, PolicyViolation, RequiredField};
+#[doc(inline)]
 pub use signature::{SecretKeyEntry, Signature, SignatureError, SignatureRole};
 #[doc(inline)]
+pub use validation::ValidationError;
+#[doc(inline)]
 pub use verification::VerificationStrategy;
 
 use ed25519_dalek::{Signature as EdSignature, Signer};
 use semver::{Version, VersionReq};
+use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, trace};
 
 use std::borrow::{Borrow, BorrowMut};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Debug;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -53,6 +67,38 @@ pub type FeatureMap = BTreeMap<String, BTreeMap<String, String>>;
 /// Alias for annotations map
 pub type AnnotationMap = BTreeMap<String, String>;
 
+/// Alias for the map of fields in an invoice or label that are not part of the spec this version
+/// of Bindle understands. They are kept so they aren't lost when an invoice is loaded, modified
+/// and stored again.
+///
+/// Extra fields are not covered by invoice signatures, so they can be changed without
+/// invalidating them and must not be trusted for anything that needs to be verified
+pub type ExtraFields = BTreeMap<String, toml::Value>;
+
+/// The top level fields of an invoice, as they are named in TOML
+const INVOICE_FIELDS: &[&str] = &[
+    "bindleVersion",
+    "yanked",
+    "yankedSignature",
+    "bindle",
+    "annotations",
+    "parcel",
+    "group",
+    "signature",
+];
+
+/// The fields of a label, as they are named in TOML
+const LABEL_FIELDS: &[&str] = &[
+    "sha256",
+    "digestAlgorithm",
+    "mediaType",
+    "name",
+    "size",
+    "annotations",
+    "feature",
+    "origin",
+];
+
 /// A sealed trait used to mark that an invoice has been signed. This trait cannot be implemented by
 /// consumers of the bindle crate
 pub trait Signed: sealed::Sealed {
@@ -68,8 +114,8 @@ pub trait Signed: sealed::Sealed {
 ///
 /// Most fields on this struct are singular to best represent the specification. There,
 /// fields like `group` and `parcel` are singular due to the conventions of TOML.
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Invoice {
     pub bindle_version: String,
     pub yanked: Option<bool>,
@@ -79,6 +125,79 @@ pub struct Invoice {
     pub parcel: Option<Vec<Parcel>>,
     pub group: Option<Vec<Group>>,
     pub signature: Option<Vec<Signature>>,
+    /// Any top level fields not known to this version of Bindle. These are not signed
+    #[serde(flatten)]
+    pub extra: ExtraFields,
+}
+
+// Serialized by hand so that extra fields can be split around the known ones, as TOML doesn't
+// allow a plain value to come after a table. Known fields keep the order they are declared in
+impl Serialize for Invoice {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("bindleVersion", &self.bindle_version)?;
+        map.serialize_entry("yanked", &self.yanked)?;
+        serialize_extra_fields(&mut map, &self.extra, false)?;
+        map.serialize_entry("yankedSignature", &self.yanked_signature)?;
+        map.serialize_entry("bindle", &self.bindle)?;
+        map.serialize_entry("annotations", &self.annotations)?;
+        map.serialize_entry("parcel", &self.parcel)?;
+        map.serialize_entry("group", &self.group)?;
+        map.serialize_entry("signature", &self.signature)?;
+        serialize_extra_fields(&mut map, &self.extra, true)?;
+        map.end()
+    }
+}
+
+/// Returns true if the key is not one of the known fields, but matches one when case and `_` or
+/// `-` separators are ignored, or is a single edit away from one
+fn is_misspelling(key: &str, known: &[&str]) -> bool {
+    let normalize = |s: &str| -> Vec<char> {
+        s.chars()
+            .filter(|c| !matches!(c, '_' | '-'))
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let key_chars = normalize(key);
+    !known.contains(&key)
+        && known
+            .iter()
+            .any(|k| within_one_edit(&key_chars, &normalize(k)))
+}
+
+/// Returns true if `a` can be turned into `b` with at most one insertion, deletion or substitution
+fn within_one_edit(a: &[char], b: &[char]) -> bool {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if long.len() - short.len() > 1 {
+        return false;
+    }
+    let prefix = short.iter().zip(long).take_while(|(x, y)| x == y).count();
+    if short.len() == long.len() {
+        short[prefix..]
+            .iter()
+            .skip(1)
+            .eq(long[prefix..].iter().skip(1))
+    } else {
+        short[prefix..] == long[prefix + 1..]
+    }
+}
+
+/// Serializes the extra fields that TOML writes as tables (or arrays of tables) if `tables` is
+/// true, or the ones it writes inline if it is false
+pub(crate) fn serialize_extra_fields<M: SerializeMap>(
+    map: &mut M,
+    extra: &ExtraFields,
+    tables: bool,
+) -> Result<(), M::Error> {
+    let is_table = |value: &toml::Value| match value {
+        toml::Value::Table(_) => true,
+        toml::Value::Array(items) => items.first().map(toml::Value::is_table).unwrap_or(false),
+        _ => false,
+    };
+    for (key, value) in extra.iter().filter(|(_, v)| is_table(v) == tables) {
+        map.serialize_entry(key, value)?;
+    }
+    Ok(())
 }
 
 impl Invoice {
@@ -95,9 +214,15 @@ impl Invoice {
             annotations: None,
             signature: None,
             group: None,
+            extra: ExtraFields::new(),
         }
     }
 
+    /// Returns an [`InvoiceBuilder`] for constructing an invoice from its parts
+    pub fn builder() -> InvoiceBuilder {
+        InvoiceBuilder::default()
+    }
+
     /// produce a slash-delimited "invoice name"
     ///
     /// For example, an invoice with the bindle name "hello" and the bindle version
@@ -134,6 +259,72 @@ impl Invoice {
         version_compare(self.bindle.id.version(), requirement)
     }
 
+    /// Checks the invariants of the invoice that are not enforced when it is deserialized,
+    /// returning every violation found. An empty list means the invoice is valid
+    ///
+    /// Currently this checks that the bindle version and name are not empty, and that every parcel
+    /// has a non-empty `sha256` and a valid `mediaType`
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        if self.bindle_version.is_empty() {
+            errors.push(ValidationError::EmptyBindleVersion);
+        }
+        if self.bindle.id.name().is_empty() {
+            errors.push(ValidationError::EmptyName);
+        }
+        for (i, p) in self.parcel.iter().flatten().enumerate() {
+            if p.label.sha256.is_empty() {
+                errors.push(ValidationError::EmptySha(i));
+            }
+            if p.label.media_type.is_empty() {
+                errors.push(ValidationError::EmptyMediaType(i));
+            } else if p.label.normalized_media_type().is_none() {
+                errors.push(ValidationError::InvalidMediaType(i));
+            }
+        }
+        errors
+    }
+
+    /// Returns the path of every extra field (see [`ExtraFields`]) whose name is so close to a
+    /// field in the spec that it is most likely a typo, such as `parcels` or `media_type`. Such
+    /// fields would otherwise be silently kept as extra fields, leaving out the data they were
+    /// meant to hold. Label fields are returned as `parcel[<index>].label.<name>`
+    pub fn check_extra_fields(&self) -> Vec<String> {
+        let mut misspelled: Vec<String> = self
+            .extra
+            .keys()
+            .filter(|key| is_misspelling(key, INVOICE_FIELDS))
+            .cloned()
+            .collect();
+        for (i, p) in self.parcel.iter().flatten().enumerate() {
+            misspelled.extend(
+                p.label
+                    .extra
+                    .keys()
+                    .filter(|key| is_misspelling(key, LABEL_FIELDS))
+                    .map(|key| format!("parcel[{}].label.{}", i, key)),
+            );
+        }
+        misspelled
+    }
+
+    /// Returns the name of every parcel whose name is shared with a parcel that has a different
+    /// SHA, in sorted order. Invoices may legitimately contain such parcels, but it usually means a
+    /// mistake was made when building the bindle, so this is not checked by
+    /// [`validate`](Invoice::validate)
+    pub fn check_parcel_names(&self) -> Vec<String> {
+        let mut shas: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+        for p in self.parcel.iter().flatten() {
+            shas.entry(&p.label.name)
+                .or_default()
+                .insert(&p.label.sha256);
+        }
+        shas.into_iter()
+            .filter(|(_, shas)| shas.len() > 1)
+            .map(|(name, _)| name.to_owned())
+            .collect()
+    }
+
     /// Check whether a group by this name is present.
     pub fn has_group(&self, name: &str) -> bool {
         let empty = Vec::with_capacity(0);
@@ -162,6 +353,41 @@ impl Invoice {
             .collect()
     }
 
+    /// Get all of the parcels that should be used when the given groups are active.
+    ///
+    /// A parcel is included if it is in the implicit global group (it has no conditions or an
+    /// empty `memberOf` list) or if it is a member of any active group. Groups listed in the
+    /// `requires` condition of an included parcel are activated as well, so the result also
+    /// contains the parcels of any group required (directly or transitively) by an included parcel
+    pub fn active_parcels(&self, active_groups: &[String]) -> Vec<Parcel> {
+        let parcels = match self.parcel.as_ref() {
+            Some(p) => p,
+            None => return vec![],
+        };
+        let mut active: HashSet<&str> = active_groups.iter().map(|g| g.as_str()).collect();
+        loop {
+            let required: Vec<&str> = parcels
+                .iter()
+                .filter(|p| p.is_global_group() || active.iter().any(|g| p.member_of(g)))
+                .filter_map(|p| p.conditions.as_ref()?.requires.as_ref())
+                .flatten()
+                .map(|g| g.as_str())
+                .filter(|g| !active.contains(g))
+                .collect();
+            if required.is_empty() {
+                break;
+            }
+            trace!(groups = ?required, "Activating required groups");
+            active.extend(required);
+        }
+
+        parcels
+            .iter()
+            .filter(|p| p.is_global_group() || active.iter().any(|g| p.member_of(g)))
+            .cloned()
+            .collect()
+    }
+
     fn cleartext(&self, by: &str, role: &SignatureRole) -> String {
         let mut buf = vec![
             by.to_owned(),
@@ -234,6 +460,21 @@ impl Invoice {
     }
 }
 
+/// Returns a stable, hex encoded SHA-256 digest of the whole invoice, which can be used to detect
+/// whether two copies of a bindle (for example, on different mirrors) are the same.
+///
+/// The invoice is serialized with its fields in a fixed order and all maps sorted by key, so
+/// logically equal invoices always produce the same digest. The yanked status (and any yank
+/// signatures) are excluded, as they don't change the content of the bindle. Returns an error if
+/// the invoice can't be serialized
+pub fn invoice_digest(inv: &Invoice) -> Result<String, serde_json::Error> {
+    let mut canonical = inv.clone();
+    canonical.yanked = None;
+    canonical.yanked_signature = None;
+    let data = serde_json::to_vec(&canonical)?;
+    Ok(DigestAlgorithm::Sha256.digest(&data))
+}
+
 /// Sign the parcels in the invoice using the given list of roles and keys. This is a list of tuples
 /// containing a [`SignatureRole`] and [`SecretKeyEntry`] in that order. Returns a [`SignedInvoice`]
 ///
@@ -598,12 +839,14 @@ mod test {
     fn test_invoice_should_serialize() {
         let label = Label {
             sha256: "abcdef1234567890987654321".to_owned(),
+            digest_algorithm: None,
             media_type: "text/toml".to_owned(),
             name: "foo.toml".to_owned(),
             size: 101,
             annotations: None,
             feature: None,
             origin: None,
+            extra: Default::default(),
         };
         let parcel = Parcel {
             label,
@@ -623,6 +866,7 @@ mod test {
             annotations: None,
             group: None,
             signature: None,
+            extra: Default::default(),
         };
 
         let res = toml::to_string(&inv).unwrap();
@@ -710,6 +954,376 @@ mod test {
         assert!(!txt.member_of("telescopes"));
     }
 
+    #[test]
+    fn test_invoice_should_keep_unknown_fields() {
+        let raw = r#"
+        bindleVersion = "1.0.0"
+        futureFlag = true
+
+        [bindle]
+        name = "foo"
+        version = "1.2.3"
+
+        [futureTable]
+        answer = 42
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "abc123"
+        mediaType = "text/plain"
+        name = "foo.txt"
+        size = 9
+        futureLabelField = "kept"
+        "#;
+        let inv: Invoice = toml::from_str(raw).expect("Unknown fields should be accepted");
+        assert_eq!(inv.extra["futureFlag"], toml::Value::Boolean(true));
+        assert_eq!(inv.extra["futureTable"]["answer"], toml::Value::Integer(42));
+        assert_eq!(
+            inv.parcel.as_ref().unwrap()[0].label.extra["futureLabelField"],
+            toml::Value::String("kept".to_owned())
+        );
+
+        // Known fields are not duplicated into the extra fields
+        assert_eq!(inv.extra.len(), 2);
+        assert_eq!(inv.parcel.as_ref().unwrap()[0].label.extra.len(), 1);
+
+        // Extra values and tables can both be written back out as valid TOML
+        let serialized = toml::to_string(&inv).expect("Invoice should serialize");
+        let inv2: Invoice = toml::from_str(&serialized).expect("Invoice should round trip");
+        assert_eq!(inv2.extra, inv.extra);
+        assert_eq!(inv2.parcel.unwrap()[0].label, inv.parcel.unwrap()[0].label);
+    }
+
+    #[test]
+    fn test_should_find_misspelled_fields() {
+        let raw = r#"
+        bindleVersion = "1.0.0"
+        futureFlag = true
+        bindle_version = "1.0.0"
+
+        [bindle]
+        name = "foo"
+        version = "1.2.3"
+
+        [[parcels]]
+        [parcels.label]
+        sha256 = "abc123"
+        mediaType = "text/plain"
+        name = "foo.txt"
+        size = 9
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "abc123"
+        media_type = "text/plain"
+        mediaType = "text/plain"
+        name = "foo.txt"
+        size = 9
+        origin = "somewhere"
+        orgin = "somewhere"
+        futureLabelField = "kept"
+        "#;
+        let inv: Invoice = toml::from_str(raw).expect("Unknown fields should be accepted");
+        assert_eq!(
+            inv.check_extra_fields(),
+            vec![
+                "bindle_version",
+                "parcels",
+                "parcel[0].label.media_type",
+                "parcel[0].label.orgin",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invoice_validation() {
+        let mut invoice: Invoice = toml::from_slice(
+            &read(Path::new("test/data/simple-invoice.toml")).expect("read file contents"),
+        )
+        .expect("parse invoice");
+        assert!(invoice.validate().is_empty(), "Invoice should be valid");
+
+        invoice.bindle_version = String::new();
+        assert_eq!(
+            invoice.validate(),
+            vec![ValidationError::EmptyBindleVersion]
+        );
+        invoice.bindle_version = crate::BINDLE_VERSION_1.to_owned();
+
+        // Ids can't be parsed with an empty name, so go through deserialization
+        invoice.bindle.id = serde_json::from_str(r#"{"name": "", "version": "1.0.0"}"#).unwrap();
+        assert_eq!(invoice.validate(), vec![ValidationError::EmptyName]);
+        invoice.bindle.id = "foo/1.0.0".try_into().unwrap();
+
+        let mut label = Label::new("foo.txt".to_owned(), String::new());
+        invoice.parcel = Some(vec![Parcel {
+            label: label.clone(),
+            conditions: None,
+        }]);
+        assert_eq!(invoice.validate(), vec![ValidationError::EmptySha(0)]);
+
+        label.sha256 = "abc123".to_owned();
+        label.media_type = String::new();
+        invoice.parcel = Some(vec![
+            Parcel {
+                label: Label::new("bar.txt".to_owned(), "def456".to_owned()),
+                conditions: None,
+            },
+            Parcel {
+                label,
+                conditions: None,
+            },
+        ]);
+        assert_eq!(invoice.validate(), vec![ValidationError::EmptyMediaType(1)]);
+
+        invoice.parcel.as_mut().unwrap()[1].label.media_type = "not a type".to_owned();
+        assert_eq!(
+            invoice.validate(),
+            vec![ValidationError::InvalidMediaType(1)]
+        );
+    }
+
+    #[test]
+    fn test_invoice_builder() {
+        let invoice = Invoice::builder()
+            .name("example.com/foo")
+            .version("1.2.3")
+            .build()
+            .expect("Minimal invoice should build");
+        assert_eq!(invoice.bindle.id.to_string(), "example.com/foo/1.2.3");
+        assert_eq!(invoice.bindle_version, crate::BINDLE_VERSION_1);
+        assert!(invoice.yanked.is_none());
+        assert!(invoice.parcel.is_none());
+        assert!(invoice.annotations.is_none());
+        assert!(invoice.bindle.authors.is_none());
+
+        let invoice = Invoice::builder()
+            .name("example.com/foo")
+            .version("1.2.3")
+            .description("A foo")
+            .author("Matt Butcher")
+            .author("Radu Matei")
+            .annotation("key", "value")
+            .add_parcel(Parcel {
+                label: Label::new("foo.txt".to_owned(), "abc123".to_owned()),
+                conditions: None,
+            })
+            .group(Group {
+                name: "extras".to_owned(),
+                required: None,
+                satisfied_by: None,
+            })
+            .build()
+            .expect("Full invoice should build");
+        assert_eq!(invoice.bindle.description.as_deref(), Some("A foo"));
+        assert_eq!(invoice.bindle.authors.unwrap().len(), 2);
+        assert_eq!(invoice.annotations.unwrap()["key"], "value");
+        assert_eq!(invoice.parcel.unwrap()[0].label.name, "foo.txt");
+        assert_eq!(invoice.group.unwrap()[0].name, "extras");
+    }
+
+    #[test]
+    fn test_invoice_builder_requires_name_and_version() {
+        assert_eq!(
+            Invoice::builder().version("1.2.3").build().unwrap_err(),
+            vec![ValidationError::EmptyName]
+        );
+        assert_eq!(
+            Invoice::builder().name("foo").build().unwrap_err(),
+            vec![ValidationError::MissingVersion]
+        );
+        assert_eq!(
+            Invoice::builder().build().unwrap_err(),
+            vec![ValidationError::EmptyName, ValidationError::MissingVersion]
+        );
+        assert_eq!(
+            Invoice::builder()
+                .name("foo")
+                .version("not.a.version")
+                .build()
+                .unwrap_err(),
+            vec![ValidationError::InvalidVersion("not.a.version".to_owned())]
+        );
+
+        // Parcels are validated like any other invoice
+        let err = Invoice::builder()
+            .name("foo")
+            .version("1.2.3")
+            .add_parcel(Parcel {
+                label: Label::new("foo.txt".to_owned(), String::new()),
+                conditions: None,
+            })
+            .build()
+            .unwrap_err();
+        assert_eq!(err, vec![ValidationError::EmptySha(0)]);
+    }
+
+    #[test]
+    fn test_check_parcel_names() {
+        let parcel = |name: &str, sha: &str| Parcel {
+            label: Label::new(name.to_owned(), sha.to_owned()),
+            conditions: None,
+        };
+        let mut invoice = Invoice::builder()
+            .name("foo")
+            .version("1.0.0")
+            .add_parcel(parcel("foo.toml", "abc123"))
+            .add_parcel(parcel("bar.toml", "def456"))
+            .build()
+            .unwrap();
+        assert!(
+            invoice.check_parcel_names().is_empty(),
+            "Unique names should not be flagged"
+        );
+
+        invoice.parcel.as_mut().unwrap().extend([
+            parcel("foo.toml", "fed789"),
+            // The same parcel listed twice is not a naming mistake
+            parcel("bar.toml", "def456"),
+        ]);
+        assert_eq!(invoice.check_parcel_names(), vec!["foo.toml".to_owned()]);
+    }
+
+    #[test]
+    fn test_label_builder_from_bytes() {
+        use sha2::Digest;
+
+        let data = b"hello from a parcel";
+        let label = LabelBuilder::from_bytes(data)
+            .name("hello.txt")
+            .annotation("key", "value")
+            .build();
+        assert_eq!(
+            label.sha256,
+            format!("{:x}", sha2::Sha256::digest(data)),
+            "SHA should match one computed independently"
+        );
+        assert_eq!(label.size, data.len() as u64);
+        assert_eq!(label.name, "hello.txt");
+        assert!(label.has_valid_digest());
+        assert_eq!(label.annotations.unwrap()["key"], "value");
+
+        let label = LabelBuilder::from_bytes([0u8, 159, 146, 150])
+            .media_type("application/x-custom")
+            .build();
+        assert_eq!(label.size, 4);
+        assert_eq!(
+            label.media_type, "application/x-custom",
+            "An explicit media type should override detection"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_label_builder_from_path() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("data.json");
+        let data = br#"{"hello": "world"}"#;
+        std::fs::write(&path, data).expect("write file");
+
+        let label = LabelBuilder::from_path(&path)
+            .await
+            .expect("read file")
+            .build();
+        let expected = LabelBuilder::from_bytes(data).build();
+        assert_eq!(label.sha256, expected.sha256);
+        assert_eq!(label.size, expected.size);
+        assert_eq!(
+            label.name, "data.json",
+            "Name should default to the file name"
+        );
+        #[cfg(feature = "client")]
+        assert_eq!(label.media_type, "application/json");
+
+        assert!(LabelBuilder::from_path(dir.path().join("missing"))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_active_parcels() {
+        let invoice = r#"
+        bindleVersion = "1.0.0"
+
+        [bindle]
+        name = "aricebo"
+        version = "1.2.3"
+
+        [[group]]
+        name = "telescopes"
+
+        [[group]]
+        name = "radio"
+
+        [[group]]
+        name = "antennas"
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "aaabbbcccdddeeefff"
+        name = "telescope.gif"
+        mediaType = "image/gif"
+        size = 123_456
+        [parcel.conditions]
+        memberOf = ["telescopes"]
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "aaabbbcccdddeeeggg"
+        name = "radio.wasm"
+        mediaType = "application/wasm"
+        size = 123_456
+        [parcel.conditions]
+        memberOf = ["radio"]
+        requires = ["antennas"]
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "aaabbbcccdddeeehhh"
+        name = "antenna.wasm"
+        mediaType = "application/wasm"
+        size = 123_456
+        [parcel.conditions]
+        memberOf = ["antennas"]
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "111aaabbbcccdddeee"
+        name = "telescope.txt"
+        mediaType = "text/plain"
+        size = 123_456
+        "#;
+
+        let invoice: crate::Invoice = toml::from_str(invoice).expect("a nice clean parse");
+        let names = |groups: &[&str]| {
+            let groups: Vec<String> = groups.iter().map(|g| g.to_string()).collect();
+            invoice
+                .active_parcels(&groups)
+                .into_iter()
+                .map(|p| p.label.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec!["telescope.txt"],
+            names(&[]),
+            "Only global parcels should be included with no active groups"
+        );
+        assert_eq!(
+            vec!["telescope.gif", "telescope.txt"],
+            names(&["telescopes"])
+        );
+        assert_eq!(
+            vec!["radio.wasm", "antenna.wasm", "telescope.txt"],
+            names(&["radio"]),
+            "Required groups should be activated"
+        );
+        assert_eq!(
+            vec!["telescope.txt"],
+            names(&["nonexistent"]),
+            "Unknown groups should not match any parcels"
+        );
+    }
+
     #[test]
     fn test_group_members() {
         let invoice = r#"
@@ -752,4 +1366,63 @@ mod test {
         let members = invoice.group_members("telescopes");
         assert_eq!(2, members.len());
     }
+
+    #[test]
+    fn test_invoice_digest() {
+        let invoice = r#"
+        bindleVersion = "1.0.0"
+
+        [bindle]
+        name = "aricebo"
+        version = "1.2.3"
+
+        [annotations]
+        first = "1"
+        second = "2"
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "aaabbbcccdddeeefff"
+        name = "telescope.gif"
+        mediaType = "image/gif"
+        size = 123_456
+        "#;
+        let reordered = r#"
+        bindleVersion = "1.0.0"
+        yanked = true
+
+        [bindle]
+        name = "aricebo"
+        version = "1.2.3"
+
+        [annotations]
+        second = "2"
+        first = "1"
+
+        [[parcel]]
+        [parcel.label]
+        sha256 = "aaabbbcccdddeeefff"
+        name = "telescope.gif"
+        mediaType = "image/gif"
+        size = 123_456
+        "#;
+
+        let invoice: crate::Invoice = toml::from_str(invoice).expect("a nice clean parse");
+        let reordered: crate::Invoice = toml::from_str(reordered).expect("a nice clean parse");
+        let digest = invoice_digest(&invoice).expect("invoice should be digested");
+        assert_eq!(64, digest.len());
+        assert_eq!(
+            digest,
+            invoice_digest(&reordered).unwrap(),
+            "Annotation order and yanked status should not change the digest"
+        );
+
+        let mut changed = invoice.clone();
+        changed.parcel.as_mut().unwrap()[0].label.sha256 = "111aaabbbcccdddeee".to_owned();
+        assert_ne!(
+            digest,
+            invoice_digest(&changed).unwrap(),
+            "Changing a parcel SHA should change the digest"
+        );
+    }
 }
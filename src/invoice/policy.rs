@@ -0,0 +1,212 @@
+//! Definition of the `InvoicePolicy` type, for enforcing rules about invoices beyond what the
+//! spec requires, such as which fields must be filled in
+
+use thiserror::Error;
+
+use crate::invoice::{normalize_media_type, Invoice};
+
+/// A field that an [`InvoicePolicy`] can require to be set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequiredField {
+    /// The bindle must have a non-empty description
+    Description,
+    /// The bindle must list at least one author
+    Authors,
+    /// The invoice must have an annotation with the given key
+    Annotation(String),
+}
+
+/// A set of rules that invoices must follow, on top of the invariants checked by
+/// [`Invoice::validate`](crate::Invoice::validate). The default policy allows everything. See
+/// [`check_policy`]
+#[derive(Debug, Clone, Default)]
+pub struct InvoicePolicy {
+    /// Fields that must be set on every invoice
+    pub required_fields: Vec<RequiredField>,
+    /// The media types parcels are allowed to have. Media types are compared after being
+    /// normalized, so `Text/Plain; charset=utf-8` matches `text/plain`. If `None`, any media type
+    /// is allowed
+    pub allowed_media_types: Option<Vec<String>>,
+    /// The maximum number of parcels an invoice can have. If `None`, there is no limit
+    pub max_parcels: Option<usize>,
+}
+
+/// A single rule of an [`InvoicePolicy`] broken by an invoice
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// The bindle has no description
+    #[error("bindle must have a description")]
+    MissingDescription,
+    /// The bindle has no authors
+    #[error("bindle must have at least one author")]
+    MissingAuthors,
+    /// The invoice does not have a required annotation. Contains the key of the annotation
+    #[error("invoice must have the annotation {0}")]
+    MissingAnnotation(String),
+    /// A parcel has a media type that is not allowed. Contains the index of the parcel in the
+    /// invoice
+    #[error("parcel {0} has a mediaType that is not allowed")]
+    DisallowedMediaType(usize),
+    /// The invoice has more parcels than allowed
+    #[error("invoice has {count} parcels, but at most {max} are allowed")]
+    TooManyParcels { count: usize, max: usize },
+}
+
+/// Checks the invoice against the given policy, returning every rule it breaks
+pub fn check_policy(inv: &Invoice, policy: &InvoicePolicy) -> Result<(), Vec<PolicyViolation>> {
+    let mut violations = Vec::new();
+    for field in policy.required_fields.iter() {
+        match field {
+            RequiredField::Description => {
+                if inv
+                    .bindle
+                    .description
+                    .as_deref()
+                    .map(|d| d.trim().is_empty())
+                    .unwrap_or(true)
+                {
+                    violations.push(PolicyViolation::MissingDescription);
+                }
+            }
+            RequiredField::Authors => {
+                if inv
+                    .bindle
+                    .authors
+                    .as_ref()
+                    .map(|a| a.is_empty())
+                    .unwrap_or(true)
+                {
+                    violations.push(PolicyViolation::MissingAuthors);
+                }
+            }
+            RequiredField::Annotation(key) => {
+                if !inv
+                    .annotations
+                    .as_ref()
+                    .map(|a| a.contains_key(key))
+                    .unwrap_or(false)
+                {
+                    violations.push(PolicyViolation::MissingAnnotation(key.clone()));
+                }
+            }
+        }
+    }
+
+    let parcels = inv.parcel.as_deref().unwrap_or_default();
+    if let Some(allowed) = policy.allowed_media_types.as_ref() {
+        let allowed: Vec<String> = allowed
+            .iter()
+            .filter_map(|m| normalize_media_type(m))
+            .collect();
+        for (i, parcel) in parcels.iter().enumerate() {
+            let allowed = parcel
+                .label
+                .normalized_media_type()
+                .map(|m| allowed.contains(&m))
+                .unwrap_or(false);
+            if !allowed {
+                violations.push(PolicyViolation::DisallowedMediaType(i));
+            }
+        }
+    }
+
+    if let Some(max) = policy.max_parcels {
+        if parcels.len() > max {
+            violations.push(PolicyViolation::TooManyParcels {
+                count: parcels.len(),
+                max,
+            });
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::invoice::{Label, Parcel};
+
+    fn invoice_fixture() -> Invoice {
+        let parcel = |name: &str, media_type: &str| Parcel {
+            label: Label {
+                sha256: "e1706ab0a39ac88094b6d54a3f5cdba41fe5a901".to_owned(),
+                name: name.to_owned(),
+                media_type: media_type.to_owned(),
+                ..Label::default()
+            },
+            conditions: None,
+        };
+        Invoice::builder()
+            .name("example.com/policy")
+            .version("1.0.0")
+            .description("A bindle that follows the rules")
+            .author("Matt Butcher <matt@example.com>")
+            .annotation("team", "bindle")
+            .add_parcel(parcel("readme.txt", "text/plain"))
+            .add_parcel(parcel("logo.png", "image/png"))
+            .build()
+            .expect("fixture should be valid")
+    }
+
+    fn strict_policy() -> InvoicePolicy {
+        InvoicePolicy {
+            required_fields: vec![
+                RequiredField::Description,
+                RequiredField::Authors,
+                RequiredField::Annotation("team".to_owned()),
+            ],
+            allowed_media_types: Some(vec!["Text/Plain".to_owned(), "image/png".to_owned()]),
+            max_parcels: Some(2),
+        }
+    }
+
+    #[test]
+    fn test_compliant_invoice_should_pass() {
+        check_policy(&invoice_fixture(), &strict_policy()).expect("invoice should pass");
+        check_policy(&invoice_fixture(), &InvoicePolicy::default())
+            .expect("default policy should allow everything");
+    }
+
+    #[test]
+    fn test_should_require_fields() {
+        let mut inv = invoice_fixture();
+        inv.bindle.description = Some("  ".to_owned());
+        inv.bindle.authors = Some(Vec::new());
+        inv.annotations = None;
+        assert_eq!(
+            check_policy(&inv, &strict_policy()).unwrap_err(),
+            vec![
+                PolicyViolation::MissingDescription,
+                PolicyViolation::MissingAuthors,
+                PolicyViolation::MissingAnnotation("team".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_should_reject_disallowed_media_types() {
+        let mut inv = invoice_fixture();
+        inv.parcel.as_mut().unwrap()[1].label.media_type = "image/gif".to_owned();
+        assert_eq!(
+            check_policy(&inv, &strict_policy()).unwrap_err(),
+            vec![PolicyViolation::DisallowedMediaType(1)]
+        );
+    }
+
+    #[test]
+    fn test_should_limit_parcel_count() {
+        let policy = InvoicePolicy {
+            max_parcels: Some(1),
+            ..strict_policy()
+        };
+        assert_eq!(
+            check_policy(&invoice_fixture(), &policy).unwrap_err(),
+            vec![PolicyViolation::TooManyParcels { count: 2, max: 1 }]
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Definition of the `ValidationError` type returned when an invoice violates an invariant of the
+//! spec that cannot be enforced by deserialization alone
+
+use thiserror::Error;
+
+/// A single invariant violated by an invoice. See [`Invoice::validate`](crate::Invoice::validate)
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The invoice does not specify a `bindleVersion`
+    #[error("bindleVersion must not be empty")]
+    EmptyBindleVersion,
+    /// The bindle name is empty
+    #[error("bindle name must not be empty")]
+    EmptyName,
+    /// The bindle version is empty. Only returned when building an invoice, as an invoice without
+    /// a version cannot be deserialized
+    #[error("bindle version must not be empty")]
+    MissingVersion,
+    /// The bindle version is not a valid semantic version. Only returned when building an invoice,
+    /// as an invoice with an invalid version cannot be deserialized
+    #[error("bindle version {0} is not a valid semantic version")]
+    InvalidVersion(String),
+    /// A parcel does not have a digest. Contains the index of the parcel in the invoice
+    #[error("parcel {0} must have a non-empty sha256")]
+    EmptySha(usize),
+    /// A parcel does not have a media type. Contains the index of the parcel in the invoice
+    #[error("parcel {0} must have a non-empty mediaType")]
+    EmptyMediaType(usize),
+    /// A parcel's media type is not a valid RFC 6838 media type. Contains the index of the parcel
+    /// in the invoice
+    #[error("parcel {0} has an invalid mediaType")]
+    InvalidMediaType(usize),
+    /// A parcel's digest is not a lowercase hex string of the length produced by its digest
+    /// algorithm. Contains the index of the parcel in the invoice
+    #[error("parcel {0} has a sha256 that is not a valid digest")]
+    InvalidSha(usize),
+    /// More than one parcel with a different digest has the same name, which usually means a
+    /// mistake was made building the bindle. Contains the duplicated name. See
+    /// [`Invoice::check_parcel_names`](crate::Invoice::check_parcel_names)
+    #[error("more than one parcel is named {0}")]
+    DuplicateParcelName(String),
+    /// A field that is not part of the spec has a name very close to one that is, so it is most
+    /// likely a typo (such as `[[parcels]]` instead of `[[parcel]]`). Contains the path of the
+    /// field. See [`Invoice::check_extra_fields`](crate::Invoice::check_extra_fields)
+    #[error("{0} is not a known field, but looks like a misspelling of one")]
+    MisspelledField(String),
+}
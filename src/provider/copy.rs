@@ -0,0 +1,153 @@
+//! Helpers for copying bindles between providers
+
+use std::convert::TryInto;
+
+use tokio_stream::StreamExt;
+use tracing::{debug, trace};
+
+use super::{Provider, ProviderError, Result};
+use crate::verification::NoopVerified;
+use crate::{Id, NoopSigned};
+
+/// Copies a bindle, including all of its parcels, from one provider to another using only the
+/// [`Provider`] trait. This can be used to migrate data between backends, such as from a
+/// `FileProvider` to an `S3Provider`.
+///
+/// Providers only accept parcels that belong to an invoice they already have, so the invoice is
+/// created in the destination first and each parcel is then streamed across. The copy is
+/// idempotent: if the invoice or any of its parcels already exist in the destination they are
+/// skipped, so an interrupted copy can be resumed by calling this again. A yanked invoice is
+/// yanked in the destination once it has been copied.
+///
+/// The invoice is not verified or signed again, as it was already checked when it was stored in
+/// the source
+pub async fn copy_bindle<S, D, I>(src: &S, dst: &D, id: I) -> Result<()>
+where
+    S: Provider + Sync,
+    D: Provider + Sync,
+    I: TryInto<Id> + Send,
+    I::Error: Into<ProviderError>,
+{
+    let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+    let mut inv = src.get_yanked_invoice(&parsed_id).await?;
+    // Yanked invoices can't be created, so the invoice is yanked after it has been copied
+    let yanked = inv.yanked.take().unwrap_or(false);
+
+    match dst
+        .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+        .await
+    {
+        Ok(_) => debug!(invoice_id = %parsed_id, "Created invoice in destination"),
+        Err(ProviderError::Exists) => {
+            trace!(invoice_id = %parsed_id, "Invoice already exists in destination")
+        }
+        Err(e) => return Err(e),
+    }
+
+    for parcel in inv.parcel.iter().flatten() {
+        let sha = parcel.label.sha256.as_str();
+        if dst.parcel_exists(&parsed_id, sha).await? {
+            trace!(
+                parcel_id = sha,
+                "Parcel already exists in destination, skipping"
+            );
+            continue;
+        }
+        debug!(parcel_id = sha, "Copying parcel to destination");
+        let stream = src.get_parcel(&parsed_id, sha).await?.map(|res| {
+            res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+        });
+        dst.create_parcel(&parsed_id, sha, stream).await?;
+    }
+
+    if yanked {
+        debug!(invoice_id = %parsed_id, "Yanking copied invoice");
+        dst.yank_invoice(&parsed_id).await?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "providers"))]
+mod test {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+    use crate::search::StrictEngine;
+    use crate::testing;
+
+    use tokio_util::codec::{BytesCodec, FramedRead};
+
+    #[tokio::test]
+    async fn test_should_copy_bindle() {
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = &scaffold.invoice.bindle.id;
+        let src = MemoryProvider::new(StrictEngine::default());
+        let dst = MemoryProvider::new(StrictEngine::default());
+        src.create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created in source");
+        for parcel in scaffold.parcel_files.values() {
+            src.create_parcel(
+                id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created in source");
+        }
+
+        copy_bindle(&src, &dst, id)
+            .await
+            .expect("Bindle should be copied");
+        // Copying again should be a no-op
+        copy_bindle(&src, &dst, id)
+            .await
+            .expect("Copying again should succeed");
+
+        let inv = dst
+            .get_invoice(id)
+            .await
+            .expect("Invoice should exist in destination");
+        assert_eq!(id, &inv.bindle.id);
+        for parcel in scaffold.parcel_files.values() {
+            let mut stream = dst
+                .get_parcel(id, &parcel.sha)
+                .await
+                .expect("Parcel should exist in destination");
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                data.extend_from_slice(&chunk.unwrap());
+            }
+            assert_eq!(parcel.data, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_copy_yanked_bindle() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = &scaffold.invoice.bindle.id;
+        let src = MemoryProvider::new(StrictEngine::default());
+        let dst = MemoryProvider::new(StrictEngine::default());
+        src.create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created in source");
+        for parcel in scaffold.parcel_files.values() {
+            src.create_parcel(
+                id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created in source");
+        }
+        src.yank_invoice(id).await.unwrap();
+
+        copy_bindle(&src, &dst, id)
+            .await
+            .expect("Yanked bindle should be copied");
+        let inv = dst.get_yanked_invoice(id).await.unwrap();
+        assert!(
+            inv.yanked.unwrap_or(false),
+            "Copied invoice should be yanked"
+        );
+    }
+}
@@ -11,11 +11,11 @@
 //!
 //! This will only be available if the `provider` feature is enabled
 
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::path::Path;
 use std::sync::Arc;
 
-use sha2::{Digest, Sha256};
 use sled::Error as SledError;
 use tokio::io::AsyncReadExt;
 use tokio::sync::Semaphore;
@@ -25,7 +25,10 @@ use tokio_util::io::StreamReader;
 use tracing::{debug, error, info, instrument, trace, warn};
 use tracing_futures::Instrument;
 
-use crate::provider::{Provider, ProviderError, Result};
+use crate::provider::{
+    check_existing_parcel, check_invoice_policy, from_cbor, merge_annotations, referenced_parcels,
+    to_cbor, validate_new_invoice, Provider, ProviderError, Result, StorageStats,
+};
 use crate::search::Search;
 use crate::verification::Verified;
 use crate::{Id, Signed};
@@ -49,6 +52,8 @@ pub struct EmbeddedProvider<T> {
     parcels: sled::Tree,
     index: T,
     semaphore: Arc<Semaphore>,
+    policy: Option<Arc<crate::InvoicePolicy>>,
+    reject_duplicate_parcel_names: bool,
 }
 
 impl<T: Clone> Clone for EmbeddedProvider<T> {
@@ -58,6 +63,8 @@ impl<T: Clone> Clone for EmbeddedProvider<T> {
             parcels: self.parcels.clone(),
             index: self.index.clone(),
             semaphore: self.semaphore.clone(),
+            policy: self.policy.clone(),
+            reject_duplicate_parcel_names: self.reject_duplicate_parcel_names,
         }
     }
 }
@@ -76,15 +83,121 @@ impl<T: Search + Send + Sync> EmbeddedProvider<T> {
             parcels,
             index,
             semaphore: Arc::new(Semaphore::new(BLOCKING_THREAD_COUNT)),
+            policy: None,
+            reject_duplicate_parcel_names: false,
         };
         debug!("warming index");
-        if let Err(e) = emb.warm_index().await {
+        if let Err(e) = emb.rebuild_index().await {
             warn!(error = %e, "Error warming index");
         }
         Ok(emb)
     }
 
-    /// This warms the index by loading all of the invoices currently in the DB
+    /// Sets a policy that invoices must meet to be created or to have their annotations updated.
+    /// Invoices that break any of its rules are rejected with a
+    /// [`PolicyViolation`](crate::provider::ProviderError::PolicyViolation) error listing every
+    /// rule broken. Invoices already in storage are only checked when their contents are changed,
+    /// so they can still be yanked and unyanked. No policy is set by default
+    pub fn policy(mut self, policy: crate::InvoicePolicy) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Sets whether invoices containing different parcels with the same name are rejected when
+    /// they are created, with an [`Invalid`](crate::provider::ProviderError::Invalid) error listing
+    /// each duplicated name. Otherwise such invoices are accepted with a warning. See
+    /// [`Invoice::check_parcel_names`](crate::Invoice::check_parcel_names). Disabled by default
+    pub fn reject_duplicate_parcel_names(mut self, reject: bool) -> Self {
+        self.reject_duplicate_parcel_names = reject;
+        self
+    }
+
+    /// Reads and parses the invoice with the given ID from the database
+    async fn read_invoice(&self, id: &Id) -> Result<crate::Invoice> {
+        let invoice_id = id.sha();
+        let invoices = self.invoices.clone();
+        let data = match spawn_lock(self.semaphore.clone(), move || invoices.get(&invoice_id))
+            .await?
+            .map_err(map_sled_error)?
+        {
+            Some(d) => d,
+            None => return Err(ProviderError::not_found(id)),
+        };
+
+        // Parse
+        trace!("Parsing invoice from raw data");
+        let invoice: crate::Invoice = from_cbor(data.as_ref())?;
+
+        // Return object
+        Ok(invoice)
+    }
+
+    /// Sets the yanked status of an existing invoice, writing it back to the database and
+    /// re-indexing it. Returns `NotFound` if the invoice does not exist
+    async fn set_yanked(&self, parsed_id: Id, yanked: bool) -> Result<()> {
+        self.update_invoice(&parsed_id, |inv| {
+            inv.yanked = Some(yanked);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Applies the given update to an existing invoice, writing it back to the database and
+    /// re-indexing it. Returns `NotFound` if the invoice does not exist. If the update returns an
+    /// error, nothing is written
+    async fn update_invoice<F>(&self, parsed_id: &Id, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut crate::Invoice) -> Result<()> + Send,
+    {
+        trace!("Fetching invoice from storage");
+        let mut inv = self.read_invoice(parsed_id).await?;
+        update(&mut inv)?;
+
+        // NOTE: Using the update_and_fetch method would result in a double deserialization step so
+        // we can re-index. There _is_ a small possibility that someone could fetch the current
+        // value from the DB right before we mutate, but the consequences of this are likely small
+        // or non-existent, so we aren't worrying about wrapping in a transaction
+
+        // Attempt to update the index. Right now, we log an error if the index update
+        // fails.
+        trace!("Indexing updated invoice");
+        if let Err(e) = self.index.index(&inv).await {
+            error!(error = %e, "Error indexing updated invoice");
+        }
+
+        // Encode the invoice into a TOML object
+        trace!("Encoding invoice");
+        let serialized = to_cbor(&inv)?;
+        let invoices = self.invoices.clone();
+        let invoice_id = parsed_id.sha();
+        debug!("Writing updated invoice to database");
+        spawn_lock(self.semaphore.clone(), move || {
+            invoices.insert(&invoice_id, serialized)
+        })
+        .await?
+        .map_err(map_sled_error)?;
+
+        Ok(())
+    }
+
+    /// Returns the SHA and size of every parcel in the database
+    async fn list_parcel_sizes(&self) -> Result<Vec<(String, u64)>> {
+        debug!("Reading parcel sizes from database");
+        let parcels = self.parcels.clone();
+        spawn_lock(self.semaphore.clone(), move || {
+            parcels
+                .iter()
+                .map(|res| {
+                    res.map(|(k, v)| (String::from_utf8_lossy(&k).into_owned(), v.len() as u64))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .await?
+        .map_err(map_sled_error)
+    }
+
+    /// This warms the index by loading all of the invoices currently in the DB, returning the
+    /// number of invoices that were indexed.
     ///
     /// Warming the index is something that the storage backend should do, though I am
     /// not sure whether EVERY storage backend should do it. It is the responsibility of
@@ -92,11 +205,14 @@ impl<T: Search + Send + Sync> EmbeddedProvider<T> {
     /// in the repository. So it needs to communicate (on startup) what documents it knows
     /// about. The storage engine merely needs to store any non-duplicates. So we can
     /// safely insert, but ignore errors that come back because of duplicate entries.
+    ///
+    /// This is called automatically by `new`, but is safe to call again at any time (for example,
+    /// if the index was swapped out or lost its data)
     #[instrument(level = "trace", skip(self))]
-    async fn warm_index(&self) -> anyhow::Result<()> {
+    pub async fn rebuild_index(&self) -> Result<usize> {
         // Read all invoices
         info!("Beginning index warm");
-        let mut total_indexed: u64 = 0;
+        let mut total_indexed: usize = 0;
         // NOTE(thomastaylor312): Trying to do this async and spawn blocking is impossible unless we
         // add a clone constraint to T. So technically this could cause a blocking issue depending
         // on the cache size and if there are other IO operations (though it does have the advantage
@@ -104,15 +220,14 @@ impl<T: Search + Send + Sync> EmbeddedProvider<T> {
         for res in self.invoices.iter() {
             let (key, raw) = res.map_err(map_sled_error)?;
             let sha = String::from_utf8_lossy(key.as_ref());
-            let invoice: crate::Invoice = serde_cbor::from_slice(raw.as_ref())?;
+            let invoice: crate::Invoice = from_cbor(raw.as_ref())?;
 
             let digest = invoice.canonical_name();
             if sha != digest {
-                anyhow::bail!(
+                return Err(ProviderError::Other(format!(
                     "SHA {} did not match computed digest {}. Delete this record.",
-                    sha,
-                    digest
-                );
+                    sha, digest
+                )));
             }
 
             if let Err(e) = self.index.index(&invoice).await {
@@ -121,7 +236,7 @@ impl<T: Search + Send + Sync> EmbeddedProvider<T> {
             total_indexed += 1;
         }
         debug!(total_indexed, "Warmed index");
-        Ok(())
+        Ok(total_indexed)
     }
 }
 
@@ -132,20 +247,20 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
     where
         I: Signed + Verified + Send + Sync,
     {
-        let inv = invoice.signed();
+        let mut inv = invoice.signed();
         tracing::span::Span::current()
             .record("invoice_id", &tracing::field::display(&inv.bindle.id));
-        // It is illegal to create a yanked invoice.
-        if inv.yanked.unwrap_or(false) {
-            debug!(id = %inv.bindle.id, "Invoice being created is set to yanked");
-            return Err(ProviderError::CreateYanked);
-        }
+        validate_new_invoice(
+            &mut inv,
+            self.policy.as_deref(),
+            self.reject_duplicate_parcel_names,
+        )?;
 
         let invoice_id = inv.canonical_name();
 
         let invoices = self.invoices.clone();
 
-        let serialized = serde_cbor::to_vec(&inv)?;
+        let serialized = to_cbor(&inv)?;
 
         debug!("Inserting invoice into database");
         let res = spawn_lock(self.semaphore.clone(), move || {
@@ -195,12 +310,13 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
                 }
             });
 
-        let labels = futures::future::join_all(missing)
+        let mut labels: Vec<crate::Label> = futures::future::join_all(missing)
             .instrument(tracing::trace_span!("lookup_missing"))
             .await
             .into_iter()
             .flatten()
             .collect();
+        labels.sort_by(|a, b| a.sha256.cmp(&b.sha256));
         Ok((inv, labels))
     }
 
@@ -217,22 +333,62 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
         // here
         debug!("Getting invoice from database");
 
+        self.read_invoice(&parsed_id).await
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn invoice_exists<I>(&self, id: I) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        debug!("Checking if invoice exists in database");
         let invoice_id = parsed_id.sha();
         let invoices = self.invoices.clone();
-        let data = match spawn_lock(self.semaphore.clone(), move || invoices.get(&invoice_id))
-            .await?
-            .map_err(map_sled_error)?
-        {
-            Some(d) => d,
-            None => return Err(ProviderError::NotFound),
-        };
+        spawn_lock(self.semaphore.clone(), move || {
+            invoices.contains_key(&invoice_id)
+        })
+        .await?
+        .map_err(map_sled_error)
+    }
 
-        // Parse
-        trace!("Parsing invoice from raw data");
-        let invoice: crate::Invoice = serde_cbor::from_slice(data.as_ref())?;
+    #[instrument(level = "trace", skip(self))]
+    async fn list_invoices(&self) -> Result<Vec<crate::Invoice>> {
+        let invoices = self.invoices.clone();
+        debug!("Listing invoices from database");
+        let raw = spawn_lock(self.semaphore.clone(), move || {
+            invoices
+                .iter()
+                .values()
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .await?
+        .map_err(map_sled_error)?;
 
-        // Return object
-        Ok(invoice)
+        trace!(total = raw.len(), "Parsing listed invoices");
+        raw.iter().map(|data| from_cbor(data.as_ref())).collect()
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn query_invoices(&self, term: &str) -> Result<Vec<crate::Invoice>> {
+        let ids = self.index.query_ids(term).await.map_err(|e| {
+            error!(error = %e, "Error querying index");
+            ProviderError::Other(format!("Unable to query search index: {}", e))
+        })?;
+        trace!(total = ids.len(), "Loading matching invoices");
+        let mut invoices = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.read_invoice(&id).await {
+                Ok(inv) => invoices.push(inv),
+                // The index can be slightly behind storage, so skip anything that is gone
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(invoices)
     }
 
     #[instrument(level = "trace", skip(self, id), fields(id))]
@@ -243,37 +399,40 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
     {
         let parsed_id = id.try_into().map_err(|e| e.into())?;
         tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
-        trace!("Fetching invoice from storage");
-        let mut inv = self.get_yanked_invoice(&parsed_id).await?;
-        inv.yanked = Some(true);
-
         debug!("Yanking invoice");
+        self.set_yanked(parsed_id, true).await
+    }
 
-        // NOTE: Using the update_and_fetch method would result in a double deserialization step so
-        // we can re-index. There _is_ a small possibility that someone could fetch the current
-        // value from the DB right before we mutate, but the consequences of this are likely small
-        // or non-existent, so we aren't worrying about wrapping in a transaction
-
-        // Attempt to update the index. Right now, we log an error if the index update
-        // fails.
-        trace!("Indexing yanked invoice");
-        if let Err(e) = self.index.index(&inv).await {
-            error!(error = %e, "Error indexing yanked invoice");
-        }
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn unyank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Unyanking invoice");
+        self.set_yanked(parsed_id, false).await
+    }
 
-        // Encode the invoice into a TOML object
-        trace!("Encoding invoice");
-        let serialized = serde_cbor::to_vec(&inv)?;
-        let invoice_id = inv.canonical_name();
-        let invoices = self.invoices.clone();
-        debug!("Writing yanked invoice to database");
-        spawn_lock(self.semaphore.clone(), move || {
-            invoices.insert(&invoice_id, serialized)
+    #[instrument(level = "trace", skip(self, id, annotations), fields(id))]
+    async fn update_invoice_annotations<I>(
+        &self,
+        id: I,
+        annotations: BTreeMap<String, String>,
+    ) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Updating invoice annotations");
+        self.update_invoice(&parsed_id, |inv| {
+            merge_annotations(inv, annotations)?;
+            check_invoice_policy(inv, self.policy.as_deref())
         })
-        .await?
-        .map_err(map_sled_error)?;
-
-        Ok(())
+        .await
     }
 
     #[instrument(level = "trace", skip(self, bindle_id, data), fields(id))]
@@ -289,6 +448,16 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
         tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
         let label = self.validate_parcel(parsed_id, parcel_id).await?;
 
+        let parcels = self.parcels.clone();
+        let pid = parcel_id.to_owned();
+        if let Some(existing) = spawn_lock(self.semaphore.clone(), move || parcels.get(&pid))
+            .await?
+            .map_err(map_sled_error)?
+        {
+            debug!("Parcel already exists");
+            return check_existing_parcel(&label, existing.len() as u64);
+        }
+
         debug!("Reading data from stream");
 
         // Read the data into memory (it is going to start there anyway in the database before
@@ -307,14 +476,20 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
                 read_bytes = parcel_data.len(),
                 "Attempted to insert parcel with incorrect size"
             );
-            return Err(ProviderError::SizeMismatch);
+            return Err(ProviderError::SizeMismatch {
+                expected: label.size,
+                actual: parcel_data.len() as u64,
+            });
         }
 
-        debug!("Validating sha");
-        let calculated = format!("{:x}", Sha256::digest(&parcel_data));
+        debug!("Validating digest");
+        let calculated = label.algorithm().digest(&parcel_data);
         if label.sha256 != calculated {
             info!(expected_sha = %label.sha256, %calculated, "Mismatched SHA when creating parcel");
-            return Err(ProviderError::DigestMismatch);
+            return Err(ProviderError::DigestMismatch {
+                expected: label.sha256,
+                actual: calculated,
+            });
         }
 
         debug!("Inserting parcel into database");
@@ -328,9 +503,92 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
         match res {
             Ok(Ok(())) => Ok(()),
             Err(e) => Err(map_sled_error(e)),
-            // This error is only possible if the parcel already exists
-            Ok(Err(_)) => Err(ProviderError::Exists),
+            // This error is only possible if another upload of the parcel finished while we were
+            // reading the data. As the digest has been validated, the content is the same
+            Ok(Err(_)) => Ok(()),
+        }
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn delete_parcel(&self, parcel_id: &str) -> Result<()> {
+        let parcels = self.parcels.clone();
+        let pid = parcel_id.to_owned();
+        if !spawn_lock(self.semaphore.clone(), move || parcels.contains_key(&pid))
+            .await?
+            .map_err(map_sled_error)?
+        {
+            return Err(ProviderError::not_found(parcel_id));
+        }
+
+        trace!("Checking for invoices referencing parcel");
+        if self.parcel_reference_count(parcel_id).await? > 0 {
+            debug!("Parcel is still referenced by an invoice, refusing to delete");
+            return Err(ProviderError::InUse);
         }
+
+        debug!("Deleting parcel from database");
+        let parcels = self.parcels.clone();
+        let pid = parcel_id.to_owned();
+        spawn_lock(self.semaphore.clone(), move || parcels.remove(&pid))
+            .await?
+            .map_err(map_sled_error)?;
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn health_check(&self) -> Result<()> {
+        // Flushing writes to disk, so it fails if the database can no longer be written
+        for tree in [self.invoices.clone(), self.parcels.clone()] {
+            spawn_lock(self.semaphore.clone(), move || tree.flush())
+                .await?
+                .map_err(map_sled_error)?;
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let invoices = self.list_invoices().await?;
+        let referenced = referenced_parcels(&invoices, false);
+        let sizes = self.list_parcel_sizes().await?;
+
+        Ok(StorageStats {
+            invoice_count: invoices.len(),
+            parcel_count: sizes.len(),
+            total_parcel_bytes: sizes.iter().map(|(_, size)| size).sum(),
+            dangling_parcel_count: sizes
+                .iter()
+                .filter(|(sha, _)| !referenced.contains(sha))
+                .count(),
+        })
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn gc_parcels(&self, keep_yanked: bool) -> Result<Vec<String>> {
+        let invoices = self.list_invoices().await?;
+        let referenced = referenced_parcels(&invoices, keep_yanked);
+        let dangling: Vec<String> = self
+            .list_parcel_sizes()
+            .await?
+            .into_iter()
+            .map(|(sha, _)| sha)
+            .filter(|sha| !referenced.contains(sha))
+            .collect();
+
+        debug!(
+            total = dangling.len(),
+            "Removing unreferenced parcels from database"
+        );
+        let parcels = self.parcels.clone();
+        spawn_lock(self.semaphore.clone(), move || {
+            let mut batch = sled::Batch::default();
+            for sha in dangling.iter() {
+                batch.remove(sha.as_bytes());
+            }
+            parcels.apply_batch(batch).map(|_| dangling)
+        })
+        .await?
+        .map_err(map_sled_error)
     }
 
     #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
@@ -357,15 +615,28 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
         {
             // Wrap the data in a cursor so it implements AsyncRead and can be streamed
             Some(d) => std::io::Cursor::new(d),
-            None => return Err(ProviderError::NotFound),
+            None => return Err(ProviderError::not_found(parcel_id)),
         };
 
+        let pid = parcel_id.to_owned();
         Ok::<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>, _>(Box::new(
             FramedRead::new(data, BytesCodec::new())
-                .map(|res| res.map_err(map_io_error).map(|b| b.freeze())),
+                .map(move |res| res.map_err(|e| map_io_error(e, &pid)).map(|b| b.freeze())),
         ))
     }
 
+    #[instrument(level = "trace", skip(self))]
+    async fn get_parcel_size(&self, parcel_id: &str) -> Result<u64> {
+        let parcels = self.parcels.clone();
+        let pid = parcel_id.to_owned();
+        trace!("Reading parcel size from database");
+        spawn_lock(self.semaphore.clone(), move || parcels.get(&pid))
+            .await?
+            .map_err(map_sled_error)?
+            .map(|d| d.len() as u64)
+            .ok_or_else(|| ProviderError::not_found(parcel_id))
+    }
+
     #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
     async fn parcel_exists<I>(&self, bindle_id: I, parcel_id: &str) -> Result<bool>
     where
@@ -386,9 +657,9 @@ impl<T: crate::search::Search + Send + Sync> Provider for EmbeddedProvider<T> {
     }
 }
 
-fn map_io_error(e: std::io::Error) -> ProviderError {
+fn map_io_error(e: std::io::Error, id: &str) -> ProviderError {
     if matches!(e.kind(), std::io::ErrorKind::NotFound) {
-        return ProviderError::NotFound;
+        return ProviderError::not_found(id);
     }
     ProviderError::from(e)
 }
@@ -404,7 +675,7 @@ fn map_sled_error(e: SledError) -> ProviderError {
         SledError::Io(i) => {
             error!(error = ?e, "IO error occurred while accessingata store");
             // Add some more decoration as to _where_ the IO error came from
-            ProviderError::Io(std::io::Error::new(
+            ProviderError::from(std::io::Error::new(
                 i.kind(),
                 format!("Error accessing local data store: {}", i),
             ))
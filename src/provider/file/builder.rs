@@ -0,0 +1,384 @@
+//! A builder for configuring a `FileProvider`
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::lru::LruCache;
+use tokio::sync::Mutex as TokioMutex;
+use tracing::{debug, warn};
+
+use super::quota::ParcelQuota;
+use super::retry::RetryPolicy;
+use super::{
+    FileProvider, Layout, NamingStrategy, Sha256Naming, StorageObserver, CACHE_SIZE,
+    PARCEL_CHECK_CONCURRENCY,
+};
+use crate::search::Search;
+
+/// A builder for a [`FileProvider`](super::FileProvider) that allows the on-disk layout to be
+/// customized.
+///
+/// By default, the builder uses the same layout as `FileProvider::new`. Changing the directory
+/// or file names allows multiple installations to share the same root directory or migrate
+/// between layouts:
+///
+/// ```no_run
+/// # async fn example() {
+/// use bindle::provider::file::FileProviderBuilder;
+/// use bindle::search::StrictEngine;
+///
+/// let provider = FileProviderBuilder::new("/var/lib/bindle", StrictEngine::default())
+///     .invoice_directory("my-invoices")
+///     .parcel_directory("my-parcels")
+///     .build()
+///     .await;
+/// # }
+/// ```
+pub struct FileProviderBuilder<T> {
+    root: PathBuf,
+    layout: Layout,
+    naming: Arc<dyn NamingStrategy>,
+    index: T,
+    cache_size: usize,
+    label_cache_size: usize,
+    parcel_check_concurrency: usize,
+    sniff_media_types: bool,
+    fail_on_index_error: bool,
+    #[cfg(target_family = "unix")]
+    mode: Option<u32>,
+    retry: RetryPolicy,
+    binary_cache: bool,
+    read_only: bool,
+    drop_yanked_from_index: bool,
+    sync_writes: bool,
+    compress_parcels: bool,
+    compress_invoices: bool,
+    reject_duplicate_parcel_names: bool,
+    staging_dir: Option<PathBuf>,
+    policy: Option<crate::InvoicePolicy>,
+    verify_canonical_names: bool,
+    max_parcel_size: Option<u64>,
+    max_total_bytes: Option<u64>,
+    observer: Option<Arc<dyn StorageObserver>>,
+}
+
+impl<T: Search + Send + Sync> FileProviderBuilder<T> {
+    /// Returns a new builder that will store data in the given root directory and use the given
+    /// search index
+    pub fn new<P: AsRef<Path>>(root: P, index: T) -> Self {
+        FileProviderBuilder {
+            root: root.as_ref().to_owned(),
+            layout: Layout::default(),
+            naming: Arc::new(Sha256Naming),
+            index,
+            cache_size: CACHE_SIZE,
+            label_cache_size: 0,
+            parcel_check_concurrency: PARCEL_CHECK_CONCURRENCY,
+            sniff_media_types: false,
+            fail_on_index_error: false,
+            #[cfg(target_family = "unix")]
+            mode: None,
+            retry: RetryPolicy::default(),
+            binary_cache: false,
+            read_only: false,
+            drop_yanked_from_index: false,
+            sync_writes: false,
+            compress_parcels: false,
+            compress_invoices: false,
+            reject_duplicate_parcel_names: false,
+            staging_dir: None,
+            policy: None,
+            verify_canonical_names: false,
+            max_parcel_size: None,
+            max_total_bytes: None,
+            observer: None,
+        }
+    }
+
+    /// Sets the root directory where all data will be stored
+    pub fn root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.root = root.as_ref().to_owned();
+        self
+    }
+
+    /// Sets the directory where files are written before being moved into place, including the
+    /// data of resumable uploads. By default files are staged next to their final location under
+    /// the root. Staging on a fast local disk can speed up writes when the root is on a slow
+    /// network mount, but if the staging directory is on a different file system than the root,
+    /// files can't be renamed into place and are copied instead
+    pub fn staging_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.staging_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the name of the directory (relative to the root) where invoices are stored. Defaults
+    /// to `invoices`
+    pub fn invoice_directory(mut self, name: impl Into<String>) -> Self {
+        self.layout.invoice_directory = name.into();
+        self
+    }
+
+    /// Sets the name of the directory (relative to the root) where parcels are stored. Defaults to
+    /// `parcels`
+    pub fn parcel_directory(mut self, name: impl Into<String>) -> Self {
+        self.layout.parcel_directory = name.into();
+        self
+    }
+
+    /// Sets the file name used for each invoice. Defaults to `invoice.toml`
+    pub fn invoice_file(mut self, name: impl Into<String>) -> Self {
+        self.layout.invoice_file = name.into();
+        self
+    }
+
+    /// Sets the file name used for each parcel's data. Defaults to `parcel.dat`
+    pub fn parcel_file(mut self, name: impl Into<String>) -> Self {
+        self.layout.parcel_file = name.into();
+        self
+    }
+
+    /// Sets the strategy used to name the directory each invoice is stored in. Defaults to
+    /// [`Sha256Naming`](super::Sha256Naming). Note that changing the strategy for an existing root
+    /// directory will make any previously stored invoices inaccessible
+    pub fn naming_strategy<N: NamingStrategy + 'static>(mut self, naming: N) -> Self {
+        self.naming = Arc::new(naming);
+        self
+    }
+
+    /// Sets the maximum number of parsed invoices kept in memory, so frequently read invoices don't
+    /// have to be read and parsed from disk each time. Defaults to 50. A size of 0 disables caching
+    pub fn cache_size(mut self, size: usize) -> Self {
+        self.cache_size = size;
+        self
+    }
+
+    /// Sets the maximum number of generated parcel labels kept in memory. These labels are stored
+    /// for compressed parcels and parcels uploaded without an invoice, and are read whenever such
+    /// a parcel is read or its size is needed. Labels never change once written, so cached labels
+    /// are only dropped when their parcel is removed. Defaults to 0, which disables caching
+    pub fn label_cache_size(mut self, size: usize) -> Self {
+        self.label_cache_size = size;
+        self
+    }
+
+    /// Sets the maximum number of parcels checked for existence at once when creating an invoice.
+    /// Defaults to 32. A limit of 0 is treated as 1
+    pub fn parcel_check_concurrency(mut self, limit: usize) -> Self {
+        self.parcel_check_concurrency = limit.max(1);
+        self
+    }
+
+    /// Sets whether to detect the media type of uploaded parcels whose label uses the generic
+    /// `application/octet-stream` type. When enabled, the start of each such parcel is inspected
+    /// after it is stored and, if a more specific type is detected, it is recorded in the parcel's
+    /// generated label (as returned by [`parcels`](super::FileProvider::parcels)). Invoices are
+    /// never modified, so their labels and signatures stay as they were created. Disabled by
+    /// default
+    pub fn sniff_media_types(mut self, sniff: bool) -> Self {
+        self.sniff_media_types = sniff;
+        self
+    }
+
+    /// Sets whether a failure to update the search index should be returned as an error when
+    /// creating or yanking an invoice. By default, index failures are only logged
+    pub fn fail_on_index_error(mut self, fail: bool) -> Self {
+        self.fail_on_index_error = fail;
+        self
+    }
+
+    /// Sets the permissions that invoice and parcel files are created with, such as `0o600`.
+    /// Directories created by the provider get the same permissions, plus the execute bit for
+    /// anyone who can read them. By default, the permissions are left to the process umask. Only
+    /// available on Unix
+    #[cfg(target_family = "unix")]
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets the maximum number of times a read from disk, or a write of data that is already in
+    /// memory (such as an invoice or label), is attempted when it fails with a transient error
+    /// (such as `EINTR` or `EAGAIN`, which can happen on network file systems). Parcel uploads are
+    /// streamed and so are never retried, and neither are other errors, like a missing file.
+    /// Defaults to 3. A value of 0 is treated as 1, which disables retries
+    pub fn io_max_attempts(mut self, attempts: u32) -> Self {
+        self.retry.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets how long to wait before retrying an operation that failed with a transient error. The
+    /// delay doubles with each following attempt. Defaults to 10ms
+    pub fn io_retry_delay(mut self, delay: Duration) -> Self {
+        self.retry.base_delay = delay;
+        self
+    }
+
+    /// Sets whether to keep a binary copy of each invoice next to its TOML file. When enabled, an
+    /// invoice is written to the binary format after it is parsed, and later reads use the binary
+    /// copy (which is much faster to parse) as long as it was made from the current TOML, which is
+    /// checked using the TOML's digest. The TOML file is always the source of truth, so the copy is
+    /// removed whenever the invoice is written and rebuilt on the next read. Disabled by default
+    pub fn binary_cache(mut self, enabled: bool) -> Self {
+        self.binary_cache = enabled;
+        self
+    }
+
+    /// Sets whether the provider is read-only, such as for a mirror that should never accept
+    /// writes. When enabled, every method that would change storage immediately returns
+    /// [`ProviderError::ReadOnly`](crate::provider::ProviderError::ReadOnly) without touching the
+    /// disk, while reads work as usual. Disabled by default
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets whether yanked invoices are removed from the search index, so they no longer show up
+    /// in query results. Yanked invoices can still be fetched directly, and are added back to the
+    /// index if they are unyanked. Disabled by default
+    pub fn drop_yanked_from_index(mut self, drop: bool) -> Self {
+        self.drop_yanked_from_index = drop;
+        self
+    }
+
+    /// Sets whether written files are flushed to disk (using `fsync`) before a write is reported as
+    /// successful. On Unix, the directory containing each file is also synced after the file is
+    /// moved into place, so the new entry survives a crash or power loss. This makes writes
+    /// noticeably slower, so it is disabled by default
+    pub fn sync_writes(mut self, sync: bool) -> Self {
+        self.sync_writes = sync;
+        self
+    }
+
+    /// Sets whether newly uploaded parcels are stored gzip compressed, which saves space for text
+    /// heavy parcels such as TOML or JSON. Compressed parcels are decompressed transparently when
+    /// read, and their SHA still refers to the uncompressed data. Each compressed parcel has a
+    /// generated label stored alongside it, annotated with
+    /// [`COMPRESSION_ANNOTATION`](super::COMPRESSION_ANNOTATION). Parcels that were stored before
+    /// enabling this are still read as normal. Disabled by default
+    pub fn compress_parcels(mut self, compress: bool) -> Self {
+        self.compress_parcels = compress;
+        self
+    }
+
+    /// Sets whether invoices are stored gzip compressed, as `invoice.toml.gz` instead of
+    /// `invoice.toml`. This saves a lot of space for invoices with many parcels. Invoices are read
+    /// from whichever file exists, so stores containing invoices written with either setting can
+    /// still be read, and existing invoices are converted when they are next rewritten. Disabled
+    /// by default
+    pub fn compress_invoices(mut self, compress: bool) -> Self {
+        self.compress_invoices = compress;
+        self
+    }
+
+    /// Sets whether invoices containing different parcels with the same name are rejected when
+    /// they are created, with an [`Invalid`](crate::provider::ProviderError::Invalid) error listing
+    /// each duplicated name. Otherwise such invoices are accepted with a warning. See
+    /// [`Invoice::check_parcel_names`](crate::Invoice::check_parcel_names). Disabled by default
+    pub fn reject_duplicate_parcel_names(mut self, reject: bool) -> Self {
+        self.reject_duplicate_parcel_names = reject;
+        self
+    }
+
+    /// Sets a policy that invoices must meet to be created, planned or modified. Invoices that
+    /// break any of its rules are rejected with a
+    /// [`PolicyViolation`](crate::provider::ProviderError::PolicyViolation) error listing every
+    /// rule broken. Invoices already in storage are only checked when their contents are changed,
+    /// so they can still be yanked and unyanked. No policy is set by default
+    pub fn policy(mut self, policy: crate::InvoicePolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Sets whether [`verify_store`](crate::provider::Provider::verify_store) also checks that every
+    /// invoice is stored under the canonical name derived from its name and version, listing any
+    /// that aren't in [`VerifyReport::misplaced_invoices`](crate::provider::VerifyReport). See
+    /// [`verify_canonical_name`](crate::provider::Provider::verify_canonical_name). Disabled by
+    /// default
+    pub fn verify_canonical_names(mut self, verify: bool) -> Self {
+        self.verify_canonical_names = verify;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a single parcel. Uploads declaring a larger size are
+    /// rejected up front, and any upload is aborted as soon as more data than the limit has been
+    /// received, so an oversized upload never gets fully written to disk. Uploads over the limit
+    /// fail with [`ProviderError::TooLarge`](crate::provider::ProviderError::TooLarge). There is no
+    /// limit by default
+    pub fn max_parcel_size(mut self, limit: u64) -> Self {
+        self.max_parcel_size = Some(limit);
+        self
+    }
+
+    /// Sets the maximum total size in bytes of all stored parcels. An upload that would take the
+    /// total over the quota fails with
+    /// [`ProviderError::QuotaExceeded`](crate::provider::ProviderError::QuotaExceeded). The
+    /// current total is found by scanning the parcels on disk when the provider is built, and is
+    /// then kept up to date as parcels are added and removed. Parcels are counted by their
+    /// uncompressed size, and chunked uploads are counted as each chunk is written. There is no
+    /// quota by default
+    pub fn max_total_bytes(mut self, limit: u64) -> Self {
+        self.max_total_bytes = Some(limit);
+        self
+    }
+
+    /// Sets an observer that is notified after invoices and parcels are successfully created or
+    /// yanked. See [`StorageObserver`](super::StorageObserver) for details. No observer is set by
+    /// default
+    pub fn observer(mut self, observer: Arc<dyn StorageObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Builds the `FileProvider`, warming the search index with any invoices already on disk
+    pub async fn build(self) -> FileProvider<T> {
+        debug!(path = %self.root.display(), cache_size = self.cache_size, layout = ?self.layout, "Creating new file provider");
+        let mut fs = FileProvider {
+            root: self.root,
+            layout: self.layout,
+            naming: self.naming,
+            index: self.index,
+            invoice_cache: (self.cache_size > 0)
+                .then(|| Arc::new(TokioMutex::new(LruCache::new(self.cache_size)))),
+            label_cache: (self.label_cache_size > 0)
+                .then(|| Arc::new(TokioMutex::new(LruCache::new(self.label_cache_size)))),
+            invoice_locks: Default::default(),
+            versions: Default::default(),
+            parcel_check_concurrency: self.parcel_check_concurrency,
+            sniff_media_types: self.sniff_media_types,
+            fail_on_index_error: self.fail_on_index_error,
+            #[cfg(target_family = "unix")]
+            mode: self.mode,
+            retry: self.retry,
+            binary_cache: self.binary_cache,
+            read_only: self.read_only,
+            drop_yanked_from_index: self.drop_yanked_from_index,
+            sync_writes: self.sync_writes,
+            compress_parcels: self.compress_parcels,
+            compress_invoices: self.compress_invoices,
+            reject_duplicate_parcel_names: self.reject_duplicate_parcel_names,
+            staging_dir: self.staging_dir,
+            policy: self.policy.map(Arc::new),
+            verify_canonical_names: self.verify_canonical_names,
+            max_parcel_size: self.max_parcel_size,
+            quota: None,
+            observer: self.observer,
+        };
+        if let Some(limit) = self.max_total_bytes {
+            debug!(limit, "Counting stored parcels for storage quota");
+            let used = match fs.used_parcel_bytes().await {
+                Ok(used) => used,
+                Err(e) => {
+                    warn!(error = %e, "Error counting stored parcels, assuming none are stored");
+                    0
+                }
+            };
+            fs.quota = Some(Arc::new(ParcelQuota::new(limit, used)));
+        }
+        debug!("warming index");
+        if let Err(e) = fs.rebuild_index().await {
+            warn!(error = %e, "Error warming index");
+        }
+        fs
+    }
+}
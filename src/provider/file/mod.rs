@@ -6,29 +6,46 @@
 //!
 //! This will only be available if the `provider` feature is enabled
 
-use std::io::Write;
+mod builder;
+mod naming;
+mod observer;
+mod quota;
+mod retry;
+
+pub use builder::FileProviderBuilder;
+pub use naming::{HierarchicalNaming, NamingStrategy, Sha256Naming};
+pub use observer::StorageObserver;
+
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use std::pin::Pin;
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::task::{Context, Poll};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{convert::TryInto, ffi::OsString};
 
 use ::lru::LruCache;
-use sha2::{Digest, Sha256};
+use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder};
 use tokio::fs::{create_dir_all, File, OpenOptions};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
-use tokio::sync::Mutex as TokioMutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::sync::{Mutex as TokioMutex, OwnedMutexGuard};
 use tokio_stream::{Stream, StreamExt};
 use tokio_util::codec::{BytesCodec, FramedRead};
 use tokio_util::io::StreamReader;
 use tracing::{debug, error, info, instrument, trace, warn};
 use tracing_futures::Instrument;
 
-use crate::provider::{Provider, ProviderError, Result};
+use crate::provider::hashing::HashingReader;
+use crate::provider::{
+    append_parcels, check_existing_parcel, check_invoice_policy, check_parcel_id, from_cbor,
+    invoice_etag, latest_version, merge_annotations, range_end, referenced_parcels, to_cbor,
+    validate_new_invoice, warm_cache_via_get, InvoiceStatus, ParcelUpload, Provider, ProviderError,
+    Result, StorageStats, UploadPlan, VerifyReport,
+};
 use crate::search::Search;
 use crate::verification::Verified;
 use crate::{Id, Signed};
+use quota::{ParcelQuota, QuotaReservation};
+use retry::RetryPolicy;
 
 /// The folder name for the invoices directory
 const INVOICE_DIRECTORY: &str = "invoices";
@@ -36,48 +53,152 @@ const INVOICE_DIRECTORY: &str = "invoices";
 pub const PARCEL_DIRECTORY: &str = "parcels";
 const INVOICE_TOML: &str = "invoice.toml";
 pub const PARCEL_DAT: &str = "parcel.dat";
+/// The file name of the label stored alongside parcels uploaded without one
+const LABEL_TOML: &str = "label.toml";
+/// The extension added to the parcel file name when the parcel is stored compressed
+const COMPRESSED_EXTENSION: &str = "gz";
+/// The label annotation recording how a stored parcel is compressed
+pub const COMPRESSION_ANNOTATION: &str = "bindle.compression";
+/// The value of the compression annotation for gzip compressed parcels
+const GZIP: &str = "gzip";
+/// The default number of parsed invoices to keep in memory
 const CACHE_SIZE: usize = 50;
+/// The default number of parcels checked for existence at once when creating an invoice
+const PARCEL_CHECK_CONCURRENCY: usize = 32;
 const PART_EXTENSION: &str = "part";
+/// The extension of the binary copy of an invoice kept when the binary cache is enabled
+const INVOICE_BIN_EXTENSION: &str = "bin";
+/// The extension of the staging file that chunks of a resumable upload are written to
+const PARTIAL_EXTENSION: &str = "partial";
+/// The number of bytes at the start of a parcel inspected when detecting its media type
+pub(crate) const SNIFF_SIZE: u64 = 8192;
+/// The folder name for the directory containing the aliases of each bindle
+const ALIAS_DIRECTORY: &str = "aliases";
+/// The name of the file stored alongside an invoice or parcel recording when it was created, as
+/// nanoseconds since the Unix epoch
+const CREATED_FILE: &str = "created";
+/// The name of the file at the root of the store listing every bindle, written by
+/// [`FileProvider::write_catalog_file`]
+const CATALOG_FILE: &str = "catalog.toml";
+/// The media type used for parcels without a more specific type
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// The names of the directories and files used to lay out a `FileProvider` on disk
+#[derive(Clone, Debug)]
+struct Layout {
+    invoice_directory: String,
+    parcel_directory: String,
+    invoice_file: String,
+    parcel_file: String,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            invoice_directory: INVOICE_DIRECTORY.to_owned(),
+            parcel_directory: PARCEL_DIRECTORY.to_owned(),
+            invoice_file: INVOICE_TOML.to_owned(),
+            parcel_file: PARCEL_DAT.to_owned(),
+        }
+    }
+}
 
 /// A file system backend for storing and retrieving bindles and parcles.
 ///
 /// Given a root directory, FileProvider brings its own storage layout for keeping track
-/// of Bindles.
+/// of Bindles. The names used in the layout can be customized using a
+/// [`FileProviderBuilder`](FileProviderBuilder).
 ///
 /// A FileProvider needs a search engine implementation. When invoices are created or yanked,
 /// the index will be updated.
 pub struct FileProvider<T> {
     root: PathBuf,
+    layout: Layout,
+    naming: Arc<dyn NamingStrategy>,
     index: T,
-    invoice_cache: Arc<TokioMutex<LruCache<Id, crate::Invoice>>>,
+    invoice_cache: InvoiceCache,
+    label_cache: LabelCache,
+    invoice_locks: InvoiceLocks,
+    versions: VersionIndex,
+    parcel_check_concurrency: usize,
+    sniff_media_types: bool,
+    fail_on_index_error: bool,
+    #[cfg(target_family = "unix")]
+    mode: Option<u32>,
+    retry: RetryPolicy,
+    binary_cache: bool,
+    read_only: bool,
+    drop_yanked_from_index: bool,
+    sync_writes: bool,
+    compress_parcels: bool,
+    compress_invoices: bool,
+    reject_duplicate_parcel_names: bool,
+    staging_dir: Option<PathBuf>,
+    policy: Option<Arc<crate::InvoicePolicy>>,
+    verify_canonical_names: bool,
+    max_parcel_size: Option<u64>,
+    quota: Option<Arc<ParcelQuota>>,
+    observer: Option<Arc<dyn StorageObserver>>,
 }
 
+/// A cache of parsed invoices shared between clones. Caching is disabled if this is `None`
+type InvoiceCache = Option<Arc<TokioMutex<LruCache<Id, crate::Invoice>>>>;
+
+/// A cache of generated parcel labels keyed by SHA, shared between clones. As parcels are content
+/// addressed, entries only need to be dropped when a parcel is removed. Caching is disabled if
+/// this is `None`
+type LabelCache = Option<Arc<TokioMutex<LruCache<String, crate::Label>>>>;
+
+/// A map of per-invoice locks, keyed by canonical name, used to serialize mutations to an invoice
+type InvoiceLocks = Arc<Mutex<HashMap<String, Arc<TokioMutex<()>>>>>;
+
+/// An index of every stored version of each bindle name, mapped to whether that version is yanked.
+/// This allows versions to be looked up without reading every invoice from disk
+type VersionIndex = Arc<RwLock<HashMap<String, BTreeMap<semver::Version, bool>>>>;
+
 impl<T: Clone> Clone for FileProvider<T> {
     fn clone(&self) -> Self {
         FileProvider {
             root: self.root.clone(),
+            layout: self.layout.clone(),
+            naming: Arc::clone(&self.naming),
             index: self.index.clone(),
-            invoice_cache: Arc::clone(&self.invoice_cache),
+            invoice_cache: self.invoice_cache.clone(),
+            label_cache: self.label_cache.clone(),
+            invoice_locks: Arc::clone(&self.invoice_locks),
+            versions: Arc::clone(&self.versions),
+            parcel_check_concurrency: self.parcel_check_concurrency,
+            sniff_media_types: self.sniff_media_types,
+            fail_on_index_error: self.fail_on_index_error,
+            #[cfg(target_family = "unix")]
+            mode: self.mode,
+            retry: self.retry,
+            binary_cache: self.binary_cache,
+            read_only: self.read_only,
+            drop_yanked_from_index: self.drop_yanked_from_index,
+            sync_writes: self.sync_writes,
+            compress_parcels: self.compress_parcels,
+            compress_invoices: self.compress_invoices,
+            reject_duplicate_parcel_names: self.reject_duplicate_parcel_names,
+            staging_dir: self.staging_dir.clone(),
+            policy: self.policy.clone(),
+            verify_canonical_names: self.verify_canonical_names,
+            max_parcel_size: self.max_parcel_size,
+            quota: self.quota.clone(),
+            observer: self.observer.clone(),
         }
     }
 }
 
 impl<T: Search + Send + Sync> FileProvider<T> {
+    /// Creates a new FileProvider at the given path using the default layout. This is a
+    /// convenience wrapper around [`FileProviderBuilder`](FileProviderBuilder)
     pub async fn new<P: AsRef<Path>>(path: P, index: T) -> Self {
-        debug!(path = %path.as_ref().display(), cache_size = CACHE_SIZE, "Creating new file provider");
-        let fs = FileProvider {
-            root: path.as_ref().to_owned(),
-            index,
-            invoice_cache: Arc::new(TokioMutex::new(LruCache::new(CACHE_SIZE))),
-        };
-        debug!("warming index");
-        if let Err(e) = fs.warm_index().await {
-            warn!(error = %e, "Error warming index");
-        }
-        fs
+        FileProviderBuilder::new(path, index).build().await
     }
 
-    /// This warms the index by loading all of the invoices currently on disk.
+    /// This warms the index by loading all of the invoices currently on disk, returning the number
+    /// of invoices that were indexed.
     ///
     /// Warming the index is something that the storage backend should do, though I am
     /// not sure whether EVERY storage backend should do it. It is the responsibility of
@@ -85,669 +206,8522 @@ impl<T: Search + Send + Sync> FileProvider<T> {
     /// in the repository. So it needs to communicate (on startup) what documents it knows
     /// about. The storage engine merely needs to store any non-duplicates. So we can
     /// safely insert, but ignore errors that come back because of duplicate entries.
+    ///
+    /// This is called automatically by `new`, but is safe to call again at any time (for example,
+    /// if the index was swapped out or lost its data). Invoices that can't be read, can't be parsed
+    /// or are stored under the wrong name are logged and skipped
     #[instrument(level = "trace", skip(self))]
-    async fn warm_index(&self) -> anyhow::Result<()> {
+    pub async fn rebuild_index(&self) -> Result<usize> {
         // Read all invoices
         info!(path = %self.root.display(), "Beginning index warm");
-        let mut total_indexed: u64 = 0;
+        let mut total_indexed: usize = 0;
+        let mut versions: HashMap<String, BTreeMap<semver::Version, bool>> = HashMap::new();
         // Check if the invoice directory exists. If it doesn't, this is likely the first time and
         // we should just return
-        let invoice_path = self.invoice_path("");
-        match tokio::fs::metadata(&invoice_path).await {
-            Ok(_) => (),
-            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => return Ok(()),
-            Err(e) => return Err(e.into()),
-        };
-        let mut readdir = tokio::fs::read_dir(invoice_path).await?;
-        while let Some(e) = readdir.next_entry().await? {
-            let p = e.path();
-            let sha = match p.file_name().map(|f| f.to_string_lossy()) {
-                Some(sha_opt) => sha_opt,
-                None => continue,
+        for name in self.invoice_names().await? {
+            // Load invoice. A record that can't be loaded is skipped rather than returned as an
+            // error, as that would leave every other bindle out of the version index
+            info!(invoice_name = %name, "Loading invoice into search index");
+            let inv_toml = match self.read_invoice_toml(&name).await {
+                Ok(data) => data,
+                Err(e) => {
+                    error!(invoice_name = %name, error = %e, "Unable to read invoice, skipping");
+                    continue;
+                }
             };
-            // Load invoice
-            let inv_path = self.invoice_toml_path(&sha);
-            info!(path = %inv_path.display(), "Loading invoice into search index");
-            // Open file
-            let inv_toml = tokio::fs::read(inv_path).await?;
 
             // Parse
-            let invoice: crate::Invoice = toml::from_slice(&inv_toml)?;
-            let digest = invoice.canonical_name();
-            if sha != digest {
-                anyhow::bail!(
-                    "SHA {} did not match computed digest {}. Delete this record.",
-                    sha,
-                    digest
+            let invoice: crate::Invoice = match toml::from_slice(&inv_toml) {
+                Ok(inv) => inv,
+                Err(e) => {
+                    error!(invoice_name = %name, error = %e, "Unable to parse invoice, skipping");
+                    continue;
+                }
+            };
+            let expected = self.invoice_name(&invoice.bindle.id);
+            if name != expected {
+                error!(
+                    invoice_name = %name,
+                    computed_name = %expected,
+                    "Name did not match computed name, skipping. Delete this record."
                 );
+                continue;
             }
 
-            if let Err(e) = self.index.index(&invoice).await {
+            if self.drop_yanked_from_index && invoice.yanked.unwrap_or(false) {
+                trace!(invoice_id = %invoice.bindle.id, "Skipping yanked invoice");
+            } else if let Err(e) = self.index.index(&invoice).await {
                 error!(invoice_id = %invoice.bindle.id, error = %e, "Error indexing invoice");
             }
+            versions
+                .entry(invoice.bindle.id.name().to_owned())
+                .or_default()
+                .insert(
+                    invoice.bindle.id.version().clone(),
+                    invoice.yanked.unwrap_or(false),
+                );
             total_indexed += 1;
         }
+        *self.versions.write().unwrap() = versions;
         debug!(total_indexed, "Warmed index");
-        Ok(())
+        Ok(total_indexed)
     }
 
-    /// Return the path to the invoice directory for a particular bindle.
-    fn invoice_path(&self, invoice_id: &str) -> PathBuf {
-        let mut path = self.root.join(INVOICE_DIRECTORY);
-        path.push(invoice_id);
-        path
+    /// Writes a `catalog.toml` file to the root of the store listing the name, version and
+    /// description of every bindle that is not yanked, sorted by name and then version, and
+    /// returns its path. This gives a static view of the store that can be browsed and cached
+    /// using a plain file server.
+    ///
+    /// The file is only written when this is called, so it will be out of date once bindles are
+    /// created or yanked until it is written again. Invoices that can't be read are left out
+    #[instrument(level = "trace", skip(self))]
+    pub async fn write_catalog_file(&self) -> Result<PathBuf> {
+        self.check_writable()?;
+        let mut bindles = Vec::new();
+        for name in self.invoice_names().await? {
+            let inv = match self.read_invoice(&name).await {
+                Ok(inv) => inv,
+                // The invoice was removed after it was listed
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => {
+                    warn!(invoice_name = %name, error = %e, "Unable to read invoice, leaving it out of catalog");
+                    continue;
+                }
+            };
+            if !inv.yanked.unwrap_or(false) {
+                bindles.push((inv.bindle.id, inv.bindle.description));
+            }
+        }
+        bindles.sort_by(|(a, _), (b, _)| {
+            a.name()
+                .cmp(b.name())
+                .then_with(|| a.version().cmp(b.version()))
+        });
+        let catalog = Catalog {
+            bindle: bindles
+                .into_iter()
+                .map(|(id, description)| CatalogEntry {
+                    name: id.name().to_owned(),
+                    version: id.version_string(),
+                    description,
+                })
+                .collect(),
+        };
+
+        self.create_dir(&self.root)
+            .await
+            .map_err(|e| ProviderError::io_at(&self.root, e))?;
+        let path = self.root.join(CATALOG_FILE);
+        let encoded = toml::to_vec(&catalog)?;
+        self.write_part_file(path.clone(), &encoded).await?;
+        debug!(path = %path.display(), total = catalog.bindle.len(), "Wrote catalog file");
+        Ok(path)
     }
-    /// Return the path for an invoice.toml for a particular bindle.
-    fn invoice_toml_path(&self, invoice_id: &str) -> PathBuf {
-        self.invoice_path(invoice_id).join(INVOICE_TOML)
+
+    /// Returns a stream of every parcel in the given bindle, paired with a reader for its data.
+    ///
+    /// The invoice is loaded once when the stream is first polled, and each parcel's file is only
+    /// opened when the consumer pulls its item, so memory use stays flat regardless of how large
+    /// the bindle is. If the invoice is yanked, the stream yields a single `Yanked` error
+    pub fn stream_bindle<I>(
+        &self,
+        id: I,
+    ) -> impl Stream<Item = Result<(crate::Label, Box<dyn AsyncRead + Unpin + Send>)>> + Send + '_
+    where
+        I: TryInto<Id> + Send + 'static,
+        I::Error: Into<ProviderError>,
+    {
+        let labels = futures::StreamExt::flat_map(
+            futures::stream::once(async move { self.get_invoice(id).await }),
+            |res| match res {
+                Ok(inv) => futures::StreamExt::left_stream(futures::stream::iter(
+                    inv.parcel
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|p| Ok(p.label)),
+                )),
+                Err(e) => futures::StreamExt::right_stream(futures::stream::once(
+                    futures::future::ready(Err(e)),
+                )),
+            },
+        );
+        futures::StreamExt::then(labels, move |res| async move {
+            let label = res?;
+            let path = self.parcel_data_path(&label.sha256);
+            trace!(path = %path.display(), "Opening parcel for bindle stream");
+            let reader = self
+                .open_parcel(&label.sha256)
+                .await
+                .map_err(|e| map_io_error(e, &label.sha256, &path))?;
+            Ok((label, reader as Box<dyn AsyncRead + Unpin + Send>))
+        })
     }
-    /// Return the parcel-specific path for storing a parcel.
-    fn parcel_path(&self, parcel_id: &str) -> PathBuf {
-        let mut path = self.root.join(PARCEL_DIRECTORY);
-        path.push(parcel_id);
-        path
+
+    /// Returns a stream of the labels of every parcel in storage, reading each parcel's label from
+    /// disk as the stream is polled so that all labels are never held in memory at once. Parcels
+    /// that were uploaded along with an invoice have no stored label, so a label with only the SHA
+    /// and size filled in is returned for them. Parcels that are still being written are skipped.
+    ///
+    /// An error reading a single parcel's label is returned as an `Err` item, and the stream
+    /// continues with the next parcel. The stream only ends early if the parcel directory itself
+    /// can't be read
+    pub fn parcels(&self) -> impl Stream<Item = Result<crate::Label>> + Send + '_ {
+        futures::StreamExt::filter_map(self.parcel_dirs(), move |res| async move {
+            let sha = match res {
+                Ok(sha) => sha,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.stored_parcel_label(&sha).await {
+                Ok(Some(label)) => Some(Ok(label)),
+                // This can happen if a parcel is currently being written, so skip it
+                Ok(None) => None,
+                Err(e) => {
+                    warn!(parcel_id = %sha, error = %e, "Unable to read parcel label");
+                    Some(Err(e))
+                }
+            }
+        })
     }
-    /// Return the path to the parcel.dat file for the given box ID
-    fn parcel_data_path(&self, parcel_id: &str) -> PathBuf {
-        self.parcel_path(parcel_id).join(PARCEL_DAT)
+
+    /// Returns a stream of the SHAs of every directory in the parcel directory, including parcels
+    /// that are still being written. Anything that isn't a directory doesn't belong there, so it is
+    /// skipped. If the parcel directory can't be read, an error is returned and the stream ends
+    fn parcel_dirs(&self) -> impl Stream<Item = Result<String>> + Send + '_ {
+        let parcel_path = self.parcel_path("");
+        futures::stream::unfold(ParcelWalk::Start, move |state| {
+            let parcel_path = parcel_path.clone();
+            async move {
+                let mut readdir = match state {
+                    ParcelWalk::Done => return None,
+                    ParcelWalk::Reading(r) => r,
+                    ParcelWalk::Start => match tokio::fs::read_dir(&parcel_path).await {
+                        Ok(r) => r,
+                        // If the directory doesn't exist, no parcels have been stored yet
+                        Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => return None,
+                        Err(e) => {
+                            return Some((
+                                Err(ProviderError::io_at(parcel_path, e)),
+                                ParcelWalk::Done,
+                            ))
+                        }
+                    },
+                };
+                loop {
+                    let entry = match readdir.next_entry().await {
+                        Ok(Some(e)) => e,
+                        Ok(None) => return None,
+                        Err(e) => {
+                            return Some((
+                                Err(ProviderError::io_at(parcel_path, e)),
+                                ParcelWalk::Done,
+                            ))
+                        }
+                    };
+                    if !entry.file_type().await.map(|t| t.is_dir()).unwrap_or(false) {
+                        debug!(path = %entry.path().display(), "Skipping unexpected entry in parcel directory");
+                        continue;
+                    }
+                    let sha = entry.file_name().to_string_lossy().into_owned();
+                    return Some((Ok(sha), ParcelWalk::Reading(readdir)));
+                }
+            }
+        })
     }
-}
 
-#[async_trait::async_trait]
-impl<T: crate::search::Search + Send + Sync> Provider for FileProvider<T> {
-    #[instrument(level = "trace", skip(self, invoice), fields(invoice_id = tracing::field::Empty))]
-    async fn create_invoice<I>(&self, invoice: I) -> Result<(crate::Invoice, Vec<crate::Label>)>
-    where
-        I: Signed + Verified + Send + Sync,
-    {
-        let inv = invoice.signed();
-        tracing::span::Span::current()
-            .record("invoice_id", &tracing::field::display(&inv.bindle.id));
-        // It is illegal to create a yanked invoice.
-        if inv.yanked.unwrap_or(false) {
-            debug!(id = %inv.bindle.id, "Invoice being created is set to yanked");
-            return Err(ProviderError::CreateYanked);
+    /// Opens a reader for every given parcel, returning them keyed by SHA.
+    ///
+    /// Files are opened concurrently, bounded by the same limit used to check for missing parcels
+    /// when creating an invoice. If any parcels are missing, a [`ProviderError::MissingParcels`]
+    /// error listing every missing SHA is returned, rather than failing on the first one
+    pub async fn get_parcels(
+        &self,
+        labels: &[crate::Label],
+    ) -> Result<HashMap<String, Box<dyn AsyncRead + Unpin + Send>>> {
+        for label in labels {
+            check_parcel_id(&label.sha256)?;
         }
+        let opens = labels.iter().map(|label| async move {
+            let path = self.parcel_data_path(&label.sha256);
+            trace!(path = %path.display(), "Opening parcel");
+            let res = self.open_parcel(&label.sha256).await;
+            (label.sha256.as_str(), path, res)
+        });
+        let results = futures::StreamExt::collect::<Vec<_>>(futures::StreamExt::buffered(
+            futures::stream::iter(opens),
+            self.parcel_check_concurrency,
+        ))
+        .instrument(tracing::trace_span!("get_parcels"))
+        .await;
 
-        let invoice_id = inv.canonical_name();
+        let mut readers = HashMap::with_capacity(results.len());
+        let mut missing: Vec<String> = Vec::new();
+        for (sha, path, res) in results {
+            match res {
+                Ok(reader) => {
+                    readers.insert(sha.to_owned(), reader as Box<dyn AsyncRead + Unpin + Send>);
+                }
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => {
+                    if !missing.iter().any(|m| m == sha) {
+                        missing.push(sha.to_owned());
+                    }
+                }
+                Err(e) => return Err(ProviderError::io_at(path, e)),
+            }
+        }
+        if !missing.is_empty() {
+            debug!(
+                total_missing = missing.len(),
+                "Requested parcels are missing"
+            );
+            return Err(ProviderError::MissingParcels(missing));
+        }
+        Ok(readers)
+    }
 
-        // Create the base path if necessary
-        let inv_path = self.invoice_path(&invoice_id);
-        trace!(path = %inv_path.display(), "Checking if invoice base path already exists on disk");
-        // With errors we default to false in this logic block, so just convert to an Option
-        let metadata = tokio::fs::metadata(&inv_path).await.ok();
-        if !metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false) {
-            // If it exists and is a regular file, we have a problem
-            if metadata.map(|m| m.is_file()).unwrap_or(false) {
-                debug!("Invoice being created already exists in storage");
-                return Err(ProviderError::Exists);
+    /// Notifies the observer, if any, that an invoice was created
+    async fn notify_invoice_created(&self, inv: &crate::Invoice) {
+        if let Some(observer) = self.observer.as_ref() {
+            if let Err(e) = observer.on_invoice_created(inv).await {
+                warn!(invoice_id = %inv.bindle.id, error = %e, "Storage observer failed to handle created invoice");
             }
-            trace!(path = %inv_path.display(), "Base path doesn't exist, creating");
-            if let Err(e) = create_dir_all(inv_path).await {
-                error!(error = %e, "Unable to create invoice storage directory");
-                return Err(e.into());
+        }
+    }
+
+    /// Notifies the observer, if any, that an invoice was yanked
+    async fn notify_invoice_yanked(&self, id: &Id) {
+        if let Some(observer) = self.observer.as_ref() {
+            if let Err(e) = observer.on_invoice_yanked(id).await {
+                warn!(invoice_id = %id, error = %e, "Storage observer failed to handle yanked invoice");
             }
         }
+    }
 
-        // Open the destination or error out if it already exists.
-        let dest = self.invoice_toml_path(&invoice_id);
-        trace!(path = %dest.display(), "Checking if invoice already exists on disk");
-        // We can't just call `exists` because it can do IO calls, so look up using the metadata
-        if tokio::fs::metadata(&dest)
-            .await
-            .map(|m| m.is_file())
-            .unwrap_or(false)
-        {
-            debug!("Invoice being created already exists in storage");
-            return Err(ProviderError::Exists);
+    /// Notifies the observer, if any, that a parcel was created
+    async fn notify_parcel_created(&self, label: &crate::Label) {
+        if let Some(observer) = self.observer.as_ref() {
+            if let Err(e) = observer.on_parcel_created(label).await {
+                warn!(parcel_id = %label.sha256, error = %e, "Storage observer failed to handle created parcel");
+            }
         }
+    }
 
-        // Create the part file to indicate that we are currently writing
-        let mut part = PartFile::new(dest).await?;
-        part.write_invoice(&inv).await?;
-        part.finalize().await?;
+    /// Returns true if the data for the given parcel has been stored, in either plain or
+    /// compressed form
+    async fn parcel_data_exists(&self, parcel_id: &str) -> bool {
+        for data_path in [
+            self.parcel_data_path(parcel_id),
+            self.compressed_parcel_path(parcel_id),
+        ] {
+            if let Ok(m) = tokio::fs::metadata(&data_path).await {
+                if m.is_file() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
 
-        // Attempt to update the index. Right now, we log an error if the index update
-        // fails.
-        if let Err(e) = self.index.index(&inv).await {
-            error!(error = %e, "Error indexing new invoice");
+    /// Removes the files written when creating the invoice with the given canonical name, after it
+    /// failed to be indexed. Only the invoice file and its creation time are removed, as other
+    /// invoices can be stored beneath its directory (such as with [`HierarchicalNaming`]), and the
+    /// directory is only removed if nothing else is left in it
+    async fn remove_unindexed_invoice(&self, invoice_id: &str, inv_path: &Path) {
+        let [(written, _), _] = self.invoice_file_paths(invoice_id);
+        for path in [written, inv_path.join(CREATED_FILE)] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(_) => (),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => (),
+                Err(e) => {
+                    error!(path = %path.display(), error = %e, "Unable to clean up unindexed invoice");
+                    return;
+                }
+            }
         }
+        // This fails if the directory is not empty, in which case it is left in place
+        if let Err(e) = tokio::fs::remove_dir(inv_path).await {
+            trace!(path = %inv_path.display(), error = %e, "Leaving invoice directory in place");
+        }
+    }
 
-        // if there are no parcels, bail early
-        if inv.parcel.is_none() {
-            return Ok((inv, Vec::with_capacity(0)));
+    /// Records the current time as the creation time of the invoice or parcel stored in the given
+    /// directory. The data has already been stored by the time this is called, so failures are
+    /// only logged, and reads fall back to the modification time of the directory
+    async fn record_created(&self, dir: &Path) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let res = self
+            .write_part_file(dir.join(CREATED_FILE), now.to_string().as_bytes())
+            .await;
+        if let Err(e) = res {
+            warn!(path = %dir.display(), error = %e, "Unable to record creation time");
         }
+    }
 
-        trace!("Checking for missing parcels listed in newly created invoice");
-        // Note: this will not allocate
-        let zero_vec = Vec::with_capacity(0);
-        // Loop through the boxes and see what exists
-        let missing = inv
-            .parcel
-            .as_ref()
-            .unwrap_or(&zero_vec)
-            .iter()
-            .map(|k| async move {
-                let parcel_path = self.parcel_path(k.label.sha256.as_str());
-                // Stat k to see if it exists. If it does not exist or is not a directory, add it.
-                let res = tokio::fs::metadata(parcel_path).await;
-                match res {
-                    Ok(stat) if !stat.is_dir() => Some(k.label.clone()),
-                    Err(_e) => Some(k.label.clone()),
-                    _ => None,
-                }
-            });
+    /// Returns the creation time of the invoice or parcel stored in the given directory.
+    ///
+    /// Data stored before creation times were recorded is treated as created at the Unix epoch.
+    /// The directory's modification time can't be used instead, as it changes whenever the
+    /// invoice is rewritten (such as when it is yanked), and not every platform records when a
+    /// file was first written. Using the epoch keeps the time stable and sorts old data first
+    async fn read_created(&self, dir: &Path) -> Result<SystemTime> {
+        let path = dir.join(CREATED_FILE);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => {
+                let nanos: u128 = raw.trim().parse().map_err(|_| {
+                    ProviderError::Other(format!("Invalid creation time in {}", path.display()))
+                })?;
+                Ok(UNIX_EPOCH
+                    + Duration::new(
+                        (nanos / 1_000_000_000) as u64,
+                        (nanos % 1_000_000_000) as u32,
+                    ))
+            }
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => {
+                trace!(path = %dir.display(), "No creation time recorded, using the Unix epoch");
+                // Make sure the data exists at all before falling back
+                tokio::fs::metadata(dir)
+                    .await
+                    .map(|_| UNIX_EPOCH)
+                    .map_err(|e| map_io_error(e, &dir.display().to_string(), dir))
+            }
+            Err(e) => Err(ProviderError::io_at(path, e)),
+        }
+    }
+
+    /// Returns the labels of all parcels in the invoice that are not yet in storage, sorted by SHA
+    async fn missing_parcels(&self, inv: &crate::Invoice) -> Vec<crate::Label> {
+        // if there are no parcels, bail early
+        let parcels = match inv.parcel.as_ref() {
+            Some(p) => p,
+            None => return Vec::with_capacity(0),
+        };
 
-        let labels = futures::future::join_all(missing)
+        // Loop through the boxes and see what exists, only statting a bounded number of parcels at
+        // once so large invoices don't exhaust file descriptors. The labels are cloned up front so
+        // the futures don't borrow from the invoice
+        let labels: Vec<crate::Label> = parcels.iter().map(|p| p.label.clone()).collect();
+        let missing = labels.into_iter().map(|label| async move {
+            // Check for the data rather than the parcel directory, as the directory also exists
+            // while a resumable upload is in progress
+            if self.parcel_data_exists(label.sha256.as_str()).await {
+                None
+            } else {
+                Some(label)
+            }
+        });
+
+        let checks = futures::StreamExt::buffer_unordered(
+            futures::stream::iter(missing),
+            self.parcel_check_concurrency,
+        );
+        let mut labels: Vec<crate::Label> = futures::StreamExt::collect::<Vec<_>>(checks)
             .instrument(tracing::trace_span!("lookup_missing"))
             .await
             .into_iter()
             .flatten()
             .collect();
-        Ok((inv, labels))
+        labels.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+        labels
     }
 
-    #[instrument(level = "trace", skip(self, id), fields(id))]
-    async fn get_yanked_invoice<I>(&self, id: I) -> Result<crate::Invoice>
-    where
-        I: TryInto<Id> + Send,
-        I::Error: Into<ProviderError>,
-    {
-        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
-        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+    /// Returns a `TooLarge` error if a parcel of the given size is over the configured limit
+    fn check_parcel_size(&self, size: u64) -> Result<()> {
+        match self.max_parcel_size {
+            Some(limit) if size > limit => {
+                debug!(size, limit, "Rejecting parcel over the size limit");
+                Err(ProviderError::TooLarge { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reserves space for a parcel of the given size if a storage quota is configured, returning
+    /// a `QuotaExceeded` error if it would not fit
+    fn reserve_quota(&self, size: u64) -> Result<Option<QuotaReservation>> {
+        self.quota.as_ref().map(|q| q.reserve(size)).transpose()
+    }
 
-        if let Some(inv) = self.invoice_cache.lock().await.get(&parsed_id) {
-            debug!("Found invoice in cache, returning");
-            return Ok(inv.clone());
+    /// Returns how much of the quota is used by the parcel directory with the given SHA. This is
+    /// the size of the stored parcel if it has been finalized, or otherwise the data uploaded so
+    /// far if its staging file is kept in the parcel directory
+    async fn parcel_dir_usage(&self, parcel_id: &str) -> u64 {
+        if let Ok(size) = self.stored_parcel_size(parcel_id).await {
+            return size;
         }
-        debug!("Getting invoice from file system");
+        let partial_path = self.partial_parcel_path(parcel_id);
+        if !partial_path.starts_with(self.parcel_path(parcel_id)) {
+            return 0;
+        }
+        tokio::fs::metadata(&partial_path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0)
+    }
 
-        let invoice_id = parsed_id.sha();
+    /// Returns the total size counted against the quota: every stored parcel plus the data of any
+    /// unfinished chunked uploads
+    async fn used_parcel_bytes(&self) -> Result<u64> {
+        let mut used: u64 = self
+            .list_parcel_sizes()
+            .await?
+            .iter()
+            .map(|(_, size)| size)
+            .sum();
+        // Unfinished uploads have no data yet, so they aren't returned by `parcels`
+        let mut dirs = Box::pin(self.parcel_dirs());
+        while let Some(sha) = dirs.next().await {
+            if let Ok(m) = tokio::fs::metadata(self.partial_parcel_path(&sha?)).await {
+                used += m.len();
+            }
+        }
+        Ok(used)
+    }
 
-        // Now construct a path and read it
-        let invoice_path = self.invoice_toml_path(&invoice_id);
+    /// Gives back the quota used by a removed parcel of the given size
+    fn release_quota(&self, size: u64) {
+        if let Some(quota) = self.quota.as_ref() {
+            quota.release(size);
+        }
+    }
 
-        debug!(
-            path = %invoice_path.display(),
-            "Reading invoice"
-        );
-        // Open file
-        let inv_toml = tokio::fs::read(invoice_path).await.map_err(map_io_error)?;
+    /// Returns a `ReadOnly` error if the provider is in read-only mode
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            debug!("Rejecting write to read-only provider");
+            return Err(ProviderError::ReadOnly);
+        }
+        Ok(())
+    }
 
-        // Parse
-        trace!("Parsing invoice from raw TOML data");
-        let invoice: crate::Invoice = toml::from_slice(&inv_toml)?;
+    /// Creates a new part file for the given destination, using the configured permissions and
+    /// sync setting
+    async fn part_file(&self, final_location: PathBuf) -> Result<PartFile> {
+        self.create_staging_dir().await?;
+        let mut part = PartFile::new_in(
+            final_location,
+            self.staging_dir.as_deref(),
+            self.file_mode(),
+        )
+        .await?;
+        part.sync = self.sync_writes;
+        Ok(part)
+    }
 
-        // Put it into the cache
-        trace!("Putting invoice into cache");
-        self.invoice_cache
-            .lock()
+    /// Writes the given data to a part file and moves it to `final_location`. As the data is
+    /// already in memory, the whole write is retried if it fails with a transient error
+    async fn write_part_file(&self, final_location: PathBuf, data: &[u8]) -> Result<()> {
+        self.retry
+            .retry(|| {
+                let final_location = final_location.clone();
+                async move {
+                    let mut part = self.part_file(final_location).await?;
+                    part.file
+                        .write_all(data)
+                        .await
+                        .map_err(|e| ProviderError::io_at(&part.path, e))?;
+                    part.finalize().await
+                }
+            })
             .await
-            .put(parsed_id, invoice.clone());
-
-        // Return object
-        Ok(invoice)
     }
 
-    #[instrument(level = "trace", skip(self, id), fields(id))]
-    async fn yank_invoice<I>(&self, id: I) -> Result<()>
-    where
-        I: TryInto<Id> + Send,
-        I::Error: Into<ProviderError>,
-    {
-        let parsed_id = id.try_into().map_err(|e| e.into())?;
-        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
-        trace!("Fetching invoice from storage");
-        let mut inv = self.get_yanked_invoice(&parsed_id).await?;
-        inv.yanked = Some(true);
+    /// Creates the configured staging directory if it doesn't exist yet
+    async fn create_staging_dir(&self) -> Result<()> {
+        if let Some(dir) = self.staging_dir.as_ref() {
+            self.create_dir(dir)
+                .await
+                .map_err(|e| ProviderError::io_at(dir, e))?;
+        }
+        Ok(())
+    }
 
-        debug!("Yanking invoice");
+    /// Returns the permissions new files are created with, if configured
+    fn file_mode(&self) -> Option<u32> {
+        #[cfg(target_family = "unix")]
+        {
+            self.mode
+        }
+        #[cfg(not(target_family = "unix"))]
+        {
+            None
+        }
+    }
 
-        // Attempt to update the index. Right now, we log an error if the index update
-        // fails.
-        trace!("Indexing yanked invoice");
-        if let Err(e) = self.index.index(&inv).await {
-            error!(error = %e, "Error indexing yanked invoice");
+    /// Creates the given directory and any missing parents, applying the configured permissions
+    /// (if any) to every directory that is created
+    async fn create_dir(&self, path: &Path) -> std::io::Result<()> {
+        #[cfg(target_family = "unix")]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            let mut missing = Vec::new();
+            let mut current = Some(path);
+            while let Some(p) = current {
+                if tokio::fs::metadata(p).await.is_ok() {
+                    break;
+                }
+                missing.push(p.to_owned());
+                current = p.parent();
+            }
+            create_dir_all(path).await?;
+            // Set the permissions explicitly, as the mode given at creation is masked by the umask
+            for dir in missing {
+                tokio::fs::set_permissions(&dir, std::fs::Permissions::from_mode(dir_mode(mode)))
+                    .await?;
+            }
+            return Ok(());
         }
+        create_dir_all(path).await
+    }
 
-        // Open the destination or error out if it already exists.
-        let dest = self.invoice_toml_path(&inv.canonical_name());
+    /// Records the given version of a bindle, and whether it is yanked, in the version index
+    fn index_version(&self, id: &Id, yanked: bool) {
+        trace!(%id, yanked, "Updating version index");
+        self.versions
+            .write()
+            .unwrap()
+            .entry(id.name().to_owned())
+            .or_default()
+            .insert(id.version().clone(), yanked);
+    }
 
-        // Encode the invoice into a TOML object
-        trace!("Encoding invoice to TOML");
-        let data = toml::to_vec(&inv)?;
-        // NOTE: Right now, this just force-overwites the existing invoice. We are assuming
-        // that the bindle has already been confirmed to be present. However, we have not
-        // ensured that here. So it is theoretically possible (if get_invoice was not used
-        // to build the invoice) that this could _create_ a new file. We could probably change
-        // this behavior with OpenOptions.
-        debug!(path = %dest.display(), "Writing yanked invoice to disk");
-        tokio::fs::write(dest, data).await?;
-
-        // Drop the invoice from the cache (as it is unlikely that someone will want to fetch it
-        // right after yanking it)
-        trace!("Dropping yanked invoice from cache");
-        self.invoice_cache.lock().await.pop(&parsed_id);
-        Ok(())
+    /// Returns the name used to store the invoice with the given ID, as determined by the naming
+    /// strategy
+    fn invoice_name(&self, id: &Id) -> String {
+        self.naming.canonical_name(id.name(), &id.version_string())
     }
 
-    #[instrument(level = "trace", skip(self, bindle_id, data), fields(id))]
-    async fn create_parcel<I, R, B>(&self, bindle_id: I, parcel_id: &str, data: R) -> Result<()>
-    where
-        I: TryInto<Id> + Send,
-        I::Error: Into<ProviderError>,
-        R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
-        B: bytes::Buf + Send,
-    {
-        debug!("Validating bindle -> parcel relationship");
-        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
-        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
-        let label = self.validate_parcel(parsed_id, parcel_id).await?;
+    /// Returns the names of all invoices on disk. As names can contain `/`, this walks the whole
+    /// invoice directory, treating any directory that contains an invoice file as an invoice
+    async fn invoice_names(&self) -> Result<Vec<String>> {
+        let root = self.invoice_path("");
+        let mut names = Vec::new();
+        let mut to_visit = vec![String::new()];
+        while let Some(name) = to_visit.pop() {
+            let dir = root.join(&name);
+            let mut readdir = match tokio::fs::read_dir(&dir).await {
+                Ok(r) => r,
+                // If the directory doesn't exist, nothing has been stored yet
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => continue,
+                Err(e) => return Err(ProviderError::io_at(dir, e)),
+            };
+            while let Some(e) = readdir
+                .next_entry()
+                .await
+                .map_err(|e| ProviderError::io_at(&dir, e))?
+            {
+                let file_type = e
+                    .file_type()
+                    .await
+                    .map_err(|err| ProviderError::io_at(e.path(), err))?;
+                if !file_type.is_dir() {
+                    continue;
+                }
+                let child = match name.as_str() {
+                    "" => e.file_name().to_string_lossy().into_owned(),
+                    _ => format!("{}/{}", name, e.file_name().to_string_lossy()),
+                };
+                if self.find_invoice_file(&child).await.is_ok() {
+                    names.push(child.clone());
+                }
+                // Other invoices can be nested inside an invoice directory, so always keep going
+                to_visit.push(child);
+            }
+        }
+        Ok(names)
+    }
 
-        // Test if a dir with that SHA exists. If so, this is an error.
-        let par_path = self.parcel_path(parcel_id);
-        if tokio::fs::metadata(&par_path)
+    /// Reads and parses the invoice with the given ID from disk, bypassing the cache. Unlike
+    /// `read_invoice`, a `NotFound` error will contain the bindle ID rather than the name on disk.
+    ///
+    /// A naming strategy can map different IDs to the same name (for example, when
+    /// [`HierarchicalNaming`] sanitizes two names to the same path), so a `NotFound` error is also
+    /// returned if the invoice stored under the name belongs to a different bindle
+    async fn read_invoice_by_id(&self, id: &Id) -> Result<crate::Invoice> {
+        let inv = self
+            .read_invoice(&self.invoice_name(id))
             .await
-            .map(|m| m.is_dir())
-            .unwrap_or(false)
-        {
-            debug!(path = %par_path.display(), "Parcel directory already exists");
-            return Err(ProviderError::Exists);
+            .map_err(|e| match e {
+                ProviderError::NotFound { .. } => ProviderError::not_found(id),
+                e => e,
+            })?;
+        if inv.bindle.id != *id {
+            warn!(%id, stored_id = %inv.bindle.id, "Invoice stored under the name of the requested ID belongs to a different bindle");
+            return Err(ProviderError::not_found(id));
         }
-        // Create box dir
-        trace!(path = %par_path.display(), "Creating parcel directory");
-        if let Err(e) = create_dir_all(par_path).await {
-            error!(error = %e, "Unable to create parcel storage directory");
-            return Err(e.into());
+        Ok(inv)
+    }
+
+    /// Finds the file the invoice with the given canonical name is stored in, returning its path
+    /// and whether it is compressed. Returns a `NotFound` error if it is not stored in either
+    /// format
+    async fn find_invoice_file(&self, invoice_id: &str) -> std::io::Result<(PathBuf, bool)> {
+        for (path, compressed) in self.invoice_file_paths(invoice_id) {
+            match tokio::fs::metadata(&path).await {
+                Ok(m) if m.is_file() => return Ok((path, compressed)),
+                Ok(_) => (),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => (),
+                Err(e) => return Err(e),
+            }
         }
+        Err(std::io::ErrorKind::NotFound.into())
+    }
 
-        // Write data
-        let mut part = PartFile::new(self.parcel_data_path(parcel_id)).await?;
-        part.write_parcel(data, parcel_id, label.size).await?;
-        part.finalize().await
+    /// Reads the raw TOML of the invoice with the given canonical name, decompressing it if it was
+    /// stored compressed
+    async fn read_invoice_toml(&self, invoice_id: &str) -> Result<Vec<u8>> {
+        let (invoice_path, compressed) = self
+            .find_invoice_file(invoice_id)
+            .await
+            .map_err(|e| map_io_error(e, invoice_id, &self.invoice_toml_path(invoice_id)))?;
+        debug!(
+            path = %invoice_path.display(),
+            compressed,
+            "Reading invoice"
+        );
+        let raw = self
+            .retry
+            .retry(|| tokio::fs::read(&invoice_path))
+            .await
+            .map_err(|e| map_io_error(e, invoice_id, &invoice_path))?;
+        if !compressed {
+            return Ok(raw);
+        }
+        let mut inv_toml = Vec::new();
+        GzipDecoder::new(raw.as_slice())
+            .read_to_end(&mut inv_toml)
+            .await
+            .map_err(|e| ProviderError::io_at(&invoice_path, e))?;
+        Ok(inv_toml)
     }
 
-    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
-    async fn get_parcel<I>(
-        &self,
-        bindle_id: I,
-        parcel_id: &str,
-    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
-    where
-        I: TryInto<Id> + Send,
-        I::Error: Into<ProviderError>,
-    {
-        debug!("Validating bindle -> parcel relationship");
-        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
-        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
-        self.validate_parcel(parsed_id, parcel_id).await?;
+    /// Writes the invoice with the given canonical name to disk, compressing it if configured. Any
+    /// copy stored in the other format and any binary copy are removed afterwards so they can't be
+    /// read instead. Callers must hold the invoice lock
+    async fn write_invoice_file(&self, invoice_id: &str, inv: &crate::Invoice) -> Result<()> {
+        let [(dest, compress), (other, _)] = self.invoice_file_paths(invoice_id);
+        // The invoice is already in memory, so like any other buffered write this can be retried
+        self.retry
+            .retry(|| {
+                let dest = dest.clone();
+                async move {
+                    let mut part = self.part_file(dest).await?;
+                    part.write_invoice(inv, compress).await?;
+                    part.finalize().await
+                }
+            })
+            .await?;
+        for stale in [other, self.invoice_bin_path(invoice_id)] {
+            match tokio::fs::remove_file(&stale).await {
+                Ok(_) => trace!(path = %stale.display(), "Removed stale copy of invoice"),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => (),
+                Err(e) => return Err(ProviderError::io_at(stale, e)),
+            }
+        }
+        Ok(())
+    }
 
-        let name = self.parcel_data_path(parcel_id);
-        debug!(path = %name.display(), "Getting parcel from storage");
-        let reader = File::open(name).await.map_err(map_io_error)?;
-        Ok::<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>, _>(Box::new(
-            FramedRead::new(reader, BytesCodec::new())
-                .map(|res| res.map_err(map_io_error).map(|b| b.freeze())),
-        ))
+    /// Reads the status of the invoice with the given ID from its TOML, skipping over the parcel
+    /// list rather than parsing every label
+    async fn read_invoice_status(&self, id: &Id) -> Result<InvoiceStatus> {
+        let inv_toml = match self.read_invoice_toml(&self.invoice_name(id)).await {
+            Ok(data) => data,
+            Err(ProviderError::NotFound { .. }) => return Ok(InvoiceStatus::missing(id)),
+            Err(e) => return Err(e),
+        };
+        let summary: InvoiceSummary = toml::from_slice(&inv_toml)?;
+        // Another bindle can be stored under the same name, see `read_invoice_by_id`
+        if summary.bindle.id != *id {
+            return Ok(InvoiceStatus::missing(id));
+        }
+        Ok(InvoiceStatus {
+            exists: true,
+            yanked: summary.yanked.unwrap_or(false),
+            version: id.version_string(),
+            parcel_count: summary.parcel.len(),
+        })
     }
 
-    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
-    async fn parcel_exists<I>(&self, bindle_id: I, parcel_id: &str) -> Result<bool>
-    where
-        I: TryInto<Id> + Send,
-        I::Error: Into<ProviderError>,
-    {
-        debug!("Validating bindle -> parcel relationship");
-        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
-        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
-        self.validate_parcel(parsed_id, parcel_id).await?;
+    /// Reads and parses the invoice with the given canonical name from disk, bypassing the cache
+    async fn read_invoice(&self, invoice_id: &str) -> Result<crate::Invoice> {
+        let inv_toml = self.read_invoice_toml(invoice_id).await?;
 
-        let label_path = self.parcel_data_path(parcel_id);
-        debug!(path = %label_path.display(), "Checking if parcel exists in storage");
-        match tokio::fs::metadata(label_path).await {
-            Ok(m) => Ok(m.is_file()),
-            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => Ok(false),
-            Err(e) => Err(e.into()),
+        // The binary copy records the digest of the TOML it was made from, so a copy written from
+        // an older version of the invoice is never used
+        let toml_sha = self.binary_cache.then(|| {
+            use sha2::Digest;
+            format!("{:x}", sha2::Sha256::digest(&inv_toml))
+        });
+        if let Some(toml_sha) = toml_sha.as_deref() {
+            if let Some(inv) = self.read_binary_invoice(invoice_id, toml_sha).await {
+                return Ok(inv);
+            }
         }
-    }
-}
 
-fn map_io_error(e: std::io::Error) -> ProviderError {
-    if matches!(e.kind(), std::io::ErrorKind::NotFound) {
-        return ProviderError::NotFound;
+        // Parse
+        trace!("Parsing invoice from raw TOML data");
+        let inv: crate::Invoice = toml::from_slice(&inv_toml)?;
+
+        if let (Some(toml_sha), false) = (toml_sha, self.read_only) {
+            // The TOML is still the source of truth, so failing to write the cache is not fatal
+            if let Err(e) = self.write_binary_invoice(invoice_id, toml_sha, &inv).await {
+                warn!(error = %e, "Unable to write binary invoice cache");
+            }
+        }
+        Ok(inv)
     }
-    ProviderError::from(e)
-}
 
-/// An internal wrapper to implement `AsyncWrite` on Sha256
-pub(crate) struct AsyncSha256 {
-    inner: Mutex<Sha256>,
-}
+    /// Reads the binary copy of the invoice with the given canonical name. Returns `None` if the
+    /// copy doesn't exist, can't be read or was not made from the TOML with the given digest
+    async fn read_binary_invoice(
+        &self,
+        invoice_id: &str,
+        toml_sha: &str,
+    ) -> Option<crate::Invoice> {
+        let bin_path = self.invoice_bin_path(invoice_id);
+        let raw = tokio::fs::read(&bin_path).await.ok()?;
+        debug!(path = %bin_path.display(), "Reading binary invoice cache");
+        let cached: BinaryInvoice<crate::Invoice> = match from_cbor(&raw) {
+            Ok(cached) => cached,
+            Err(e) => {
+                warn!(path = %bin_path.display(), error = %e, "Unable to parse binary invoice cache");
+                return None;
+            }
+        };
+        if cached.toml_sha256 != toml_sha {
+            trace!(path = %bin_path.display(), "Binary invoice cache is stale");
+            return None;
+        }
+        Some(cached.invoice)
+    }
 
-impl AsyncSha256 {
-    /// Equivalent to the `Sha256::new()` function
-    pub(crate) fn new() -> Self {
-        AsyncSha256 {
-            inner: Mutex::new(Sha256::new()),
+    /// Writes a binary copy of the invoice next to its TOML so later reads can skip parsing it.
+    /// `toml_sha` is the digest of the TOML the invoice was parsed from.
+    ///
+    /// The copy is written under the invoice lock, and only if the TOML still has the same digest,
+    /// so a copy of an invoice that was changed after it was read is never left behind. If the lock
+    /// is already held (including by the caller), nothing is written as the invoice may be about to
+    /// change
+    async fn write_binary_invoice(
+        &self,
+        invoice_id: &str,
+        toml_sha: String,
+        inv: &crate::Invoice,
+    ) -> Result<()> {
+        let _lock = match self.try_lock_invoice(invoice_id) {
+            Some(lock) => lock,
+            None => {
+                trace!(invoice_id, "Invoice is locked, not writing binary cache");
+                return Ok(());
+            }
+        };
+        let current = self.read_invoice_toml(invoice_id).await?;
+        let current_sha = {
+            use sha2::Digest;
+            format!("{:x}", sha2::Sha256::digest(&current))
+        };
+        if current_sha != toml_sha {
+            trace!(
+                invoice_id,
+                "Invoice changed since it was read, not writing binary cache"
+            );
+            return Ok(());
         }
+
+        let bin_path = self.invoice_bin_path(invoice_id);
+        trace!(path = %bin_path.display(), "Writing binary invoice cache");
+        let data = to_cbor(&BinaryInvoice {
+            toml_sha256: toml_sha,
+            invoice: inv,
+        })?;
+        self.write_part_file(bin_path, &data).await
     }
 
-    /// Consumes self and returns the bare Sha256. This should only be called once you are done
-    /// writing. This will only return an error if for some reason the underlying mutex was poisoned
-    pub(crate) fn into_inner(self) -> std::sync::LockResult<Sha256> {
-        self.inner.into_inner()
+    /// Returns the SHA and size of every parcel on disk. Parcels that are still being written are
+    /// skipped
+    async fn list_parcel_sizes(&self) -> Result<Vec<(String, u64)>> {
+        self.parcels()
+            .map(|res| res.map(|label| (label.sha256, label.size)))
+            .collect()
+            .await
     }
-}
 
-impl tokio::io::AsyncWrite for AsyncSha256 {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<std::result::Result<usize, std::io::Error>> {
-        // Because the hasher is all in memory, we only need to make sure only one caller at a time
-        // can write using the mutex
-        let mut inner = match self.inner.try_lock() {
+    /// Returns the label of a stored parcel. If the parcel has no generated label, a label with
+    /// just its SHA and size is returned. Returns `None` if the parcel's data has not been stored
+    async fn stored_parcel_label(&self, parcel_id: &str) -> Result<Option<crate::Label>> {
+        // Generated labels are written before the data, so check the data has been stored first
+        if !self.parcel_data_exists(parcel_id).await {
+            return Ok(None);
+        }
+        if let Some(label) = self.read_parcel_label(parcel_id).await? {
+            return Ok(Some(label));
+        }
+        match self.stored_parcel_size(parcel_id).await {
+            Ok(size) => Ok(Some(crate::Label {
+                sha256: parcel_id.to_owned(),
+                size,
+                ..crate::Label::default()
+            })),
+            Err(ProviderError::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Re-hashes the data of the given parcel, checking it against the parcel's SHA and, if the
+    /// parcel has a generated label, against the label. Returns `None` if the parcel has no data,
+    /// which can happen if it is currently being written
+    async fn parcel_is_intact(&self, parcel_id: &str) -> Result<Option<bool>> {
+        let label = match self.read_parcel_label(parcel_id).await {
             Ok(l) => l,
-            Err(_) => return Poll::Pending,
+            Err(ProviderError::Malformed(_)) => return Ok(Some(false)),
+            Err(e) => return Err(e),
+        };
+        let reader = match self.open_parcel(parcel_id).await {
+            Ok(r) => r,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => return Ok(None),
+            Err(e) => return Err(ProviderError::io_at(self.parcel_path(parcel_id), e)),
         };
+        // Parcels uploaded with an invoice have no stored label, so fall back to working out the
+        // algorithm from the length of the digest
+        let algorithm = match label.as_ref() {
+            Some(l) => l.algorithm(),
+            None if parcel_id.len() == 128 => crate::DigestAlgorithm::Sha512,
+            None => crate::DigestAlgorithm::Sha256,
+        };
+        let mut reader = HashingReader::with_algorithm(reader, algorithm);
+        match tokio::io::copy(&mut reader, &mut tokio::io::sink()).await {
+            Ok(_) => (),
+            // Compressed data that can't be decoded is corrupt
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                return Ok(Some(false))
+            }
+            Err(e) => return Err(ProviderError::io_at(self.parcel_path(parcel_id), e)),
+        }
+        let size = reader.bytes_read();
+        let digest = reader.finalize();
+        let label_matches = label
+            .map(|l| l.sha256 == parcel_id && l.size == size)
+            .unwrap_or(true);
+        Ok(Some(digest == parcel_id && label_matches))
+    }
+
+    /// Sets the yanked status of an existing invoice, rewriting it on disk and re-indexing it.
+    /// Returns `NotFound` if the invoice does not exist
+    async fn set_yanked(&self, parsed_id: Id, yanked: bool) -> Result<()> {
+        let inv = self
+            .update_invoice(&parsed_id, |inv| {
+                inv.yanked = Some(yanked);
+                Ok(())
+            })
+            .await?;
+        self.index_version(&parsed_id, yanked);
 
-        Poll::Ready(inner.write(buf))
+        if yanked && self.drop_yanked_from_index {
+            trace!("Removing yanked invoice from index");
+            if let Err(e) = self.index.remove(&inv).await {
+                warn!(invoice_id = %inv.bindle.id, error = %e, "Error removing yanked invoice from index");
+                if self.fail_on_index_error {
+                    return Err(ProviderError::Other(format!(
+                        "Unable to remove invoice from index: {}",
+                        e
+                    )));
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn poll_flush(
-        self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
-    ) -> Poll<std::result::Result<(), std::io::Error>> {
-        let mut inner = match self.inner.try_lock() {
-            Ok(l) => l,
-            Err(_) => return Poll::Pending,
-        };
+    /// Runs every check an invoice must pass before it can be created, normalizing its media types
+    /// in place. Shared by `create_invoice` and `plan_invoice` so a plan fails exactly when
+    /// creating the invoice would
+    fn validate_new_invoice(&self, inv: &mut crate::Invoice) -> Result<()> {
+        validate_new_invoice(
+            inv,
+            self.policy.as_deref(),
+            self.reject_duplicate_parcel_names,
+        )
+    }
 
-        Poll::Ready(inner.flush())
+    /// Checks the invoice against the configured policy, if any, returning a `PolicyViolation`
+    /// error listing every rule it breaks
+    fn check_policy(&self, inv: &crate::Invoice) -> Result<()> {
+        check_invoice_policy(inv, self.policy.as_deref())
     }
 
-    fn poll_shutdown(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-    ) -> Poll<std::result::Result<(), std::io::Error>> {
-        // There are no actual shutdown tasks to perform, so just flush things as defined in the
-        // trait documentation
-        self.poll_flush(cx)
+    /// Applies the given update to an existing invoice while holding its lock, then re-indexes it
+    /// and atomically rewrites it on disk. Returns `NotFound` if the invoice does not exist. If the
+    /// update returns an error, nothing is written
+    async fn update_invoice<F>(&self, parsed_id: &Id, update: F) -> Result<crate::Invoice>
+    where
+        F: FnOnce(&mut crate::Invoice) -> Result<()> + Send,
+    {
+        let invoice_id = self.invoice_name(parsed_id);
+        let _lock = self.lock_invoice(&invoice_id).await;
+
+        // Read directly from disk now that we hold the lock, as the cache could be stale. This
+        // also ensures we never create an invoice that doesn't already exist
+        trace!("Fetching invoice from storage");
+        let mut inv = self.read_invoice_by_id(parsed_id).await?;
+        update(&mut inv)?;
+        self.store_updated_invoice(&invoice_id, &inv).await?;
+        Ok(inv)
     }
-}
 
-/// Validate that the File path matches the given SHA256
-async fn validate_sha256(file: &mut File, sha: &str) -> Result<()> {
-    let mut hasher = AsyncSha256::new();
-    tokio::io::copy(file, &mut hasher).await?;
-    let hasher = match hasher.into_inner() {
-        Ok(h) => h,
-        Err(_) => {
-            return Err(ProviderError::Io(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "data write corruption, mutex poisoned",
-            )))
+    /// Re-indexes an updated invoice and writes it over the existing one on disk. The caller must
+    /// hold the invoice's lock
+    async fn store_updated_invoice(&self, invoice_id: &str, inv: &crate::Invoice) -> Result<()> {
+        // Attempt to update the index. By default, we only log a warning if the index update
+        // fails.
+        trace!("Indexing updated invoice");
+        if let Err(e) = self.index.index(inv).await {
+            warn!(invoice_id = %inv.bindle.id, error = %e, "Error indexing updated invoice");
+            if self.fail_on_index_error {
+                return Err(ProviderError::Other(format!(
+                    "Unable to index invoice: {}",
+                    e
+                )));
+            }
         }
-    };
-    let result = hasher.finalize();
 
-    if format!("{:x}", result) != sha {
-        return Err(ProviderError::DigestMismatch);
+        // Write to a part file and rename it over the existing invoice so readers never see a
+        // partially written file
+        debug!(invoice_id, "Writing updated invoice to disk");
+        self.write_invoice_file(invoice_id, inv).await?;
+
+        // Drop the invoice from the cache so the update is picked up on the next read
+        self.uncache_invoice(&inv.bindle.id).await;
+        Ok(())
     }
 
-    Ok(())
-}
+    /// Detects the media type of the stored parcel with the given SHA from its first few KB of
+    /// data. Returns `None` if no specific type could be detected
+    async fn sniff_parcel_media_type(&self, parcel_id: &str) -> Result<Option<String>> {
+        let path = self.parcel_data_path(parcel_id);
+        let reader = self
+            .open_parcel(parcel_id)
+            .await
+            .map_err(|e| map_io_error(e, parcel_id, &path))?;
+        let mut buf = Vec::with_capacity(SNIFF_SIZE as usize);
+        reader
+            .take(SNIFF_SIZE)
+            .read_to_end(&mut buf)
+            .await
+            .map_err(|e| ProviderError::io_at(&path, e))?;
+        Ok(sniff_media_type(&buf))
+    }
 
-/// A helper struct for a part file that will clean up the file on drop if it still exists. Also
-/// contains functionality for writing to the file and finalizing it (i.e moving it to the correct
-/// location)
-struct PartFile {
-    path: PathBuf,
-    final_location: PathBuf,
-    file: File,
-}
+    /// Records a detected media type in the generated label of a stored parcel, creating the label
+    /// from the given one if the parcel doesn't have one yet. Invoices are never changed, as their
+    /// labels may be covered by signatures
+    async fn set_parcel_media_type(&self, label: &crate::Label, media_type: &str) -> Result<()> {
+        let mut stored = self
+            .read_parcel_label(&label.sha256)
+            .await?
+            .unwrap_or_else(|| label.clone());
+        stored.media_type = media_type.to_owned();
+        debug!(parcel_id = %label.sha256, media_type, "Writing detected media type to parcel label");
+        self.write_parcel_label(&stored).await
+    }
 
-impl PartFile {
-    /// Creates a new PartFile that will eventually be located at the given `final_location`. This
-    /// will attempt to create a new part file and return an error if one already exists
-    async fn new(final_location: PathBuf) -> Result<Self> {
-        let extension = match final_location.extension() {
-            Some(s) => {
-                let mut ext = s.to_owned();
-                ext.push(".");
-                ext.push(PART_EXTENSION);
-                ext
-            }
-            None => OsString::from(PART_EXTENSION),
-        };
-        let part = final_location.with_extension(extension);
-        trace!(path = %part.display(), "Checking that a write is not currently in progress");
-        // Make sure we aren't already writing
-        if tokio::fs::metadata(&part)
+    /// Removes the given invoice from the cache, if caching is enabled
+    async fn uncache_invoice(&self, id: &Id) {
+        if let Some(cache) = self.invoice_cache.as_ref() {
+            trace!(%id, "Dropping invoice from cache");
+            cache.lock().await.pop(id);
+        }
+    }
+
+    /// Acquires the lock for the parcel with the given SHA, which must be held while uploaded
+    /// chunks are written or finalized and while the parcel is removed, so the quota is kept
+    /// accurate. This shares the invoice lock map, keyed by the parcel's path so it can never
+    /// clash with an invoice name
+    async fn lock_parcel(&self, parcel_id: &str) -> OwnedMutexGuard<()> {
+        self.lock_invoice(&self.parcel_path(parcel_id).to_string_lossy())
             .await
-            .map(|m| m.is_file())
-            .unwrap_or(false)
-        {
-            return Err(ProviderError::WriteInProgress);
+    }
+
+    /// Acquires the lock for the invoice with the given canonical name. All writes to an invoice
+    /// must hold this lock so that concurrent mutations cannot interleave. Reads do not need the
+    /// lock because invoices are always written atomically
+    async fn lock_invoice(&self, invoice_id: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.invoice_locks.lock().unwrap();
+            // Clean up any locks that are no longer held by anyone so the map doesn't grow forever
+            locks.retain(|_, l| Arc::strong_count(l) > 1);
+            Arc::clone(locks.entry(invoice_id.to_owned()).or_default())
+        };
+        trace!(invoice_id, "Waiting for invoice lock");
+        lock.lock_owned().await
+    }
+
+    /// Acquires the lock for the invoice with the given canonical name if no one else holds it
+    fn try_lock_invoice(&self, invoice_id: &str) -> Option<OwnedMutexGuard<()>> {
+        let lock = {
+            let mut locks = self.invoice_locks.lock().unwrap();
+            locks.retain(|_, l| Arc::strong_count(l) > 1);
+            Arc::clone(locks.entry(invoice_id.to_owned()).or_default())
+        };
+        lock.try_lock_owned().ok()
+    }
+
+    /// Return the path to the invoice directory for a particular bindle.
+    fn invoice_path(&self, invoice_id: &str) -> PathBuf {
+        let mut path = self.root.join(&self.layout.invoice_directory);
+        path.push(invoice_id);
+        path
+    }
+    /// Return the path for an invoice.toml for a particular bindle.
+    fn invoice_toml_path(&self, invoice_id: &str) -> PathBuf {
+        self.invoice_path(invoice_id)
+            .join(&self.layout.invoice_file)
+    }
+    /// Return the path for a gzip compressed invoice.toml for a particular bindle
+    fn compressed_invoice_path(&self, invoice_id: &str) -> PathBuf {
+        self.invoice_path(invoice_id).join(format!(
+            "{}.{}",
+            self.layout.invoice_file, COMPRESSED_EXTENSION
+        ))
+    }
+    /// Return the paths an invoice may be stored at along with whether each is compressed. The
+    /// format used for new writes comes first
+    fn invoice_file_paths(&self, invoice_id: &str) -> [(PathBuf, bool); 2] {
+        let plain = (self.invoice_toml_path(invoice_id), false);
+        let compressed = (self.compressed_invoice_path(invoice_id), true);
+        if self.compress_invoices {
+            [compressed, plain]
+        } else {
+            [plain, compressed]
         }
-        #[cfg(target_family = "windows")]
-        let file = match OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .read(true)
-            // 4 is the value for FILE_SHARE_DELETE so we don't have to import the winapi constants.
-            // We need this so we can delete the file on drop. See
-            // https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-createfilea
-            .share_mode(4)
-            .open(&part)
+    }
+    /// Return the path of the binary copy of a bindle's invoice, which uses the invoice file name
+    /// with a `bin` extension
+    fn invoice_bin_path(&self, invoice_id: &str) -> PathBuf {
+        self.invoice_toml_path(invoice_id)
+            .with_extension(INVOICE_BIN_EXTENSION)
+    }
+    /// Return the parcel-specific path for storing a parcel.
+    fn parcel_path(&self, parcel_id: &str) -> PathBuf {
+        let mut path = self.root.join(&self.layout.parcel_directory);
+        path.push(parcel_id);
+        path
+    }
+    /// Return the path to the parcel.dat file for the given box ID
+    fn parcel_data_path(&self, parcel_id: &str) -> PathBuf {
+        self.parcel_path(parcel_id).join(&self.layout.parcel_file)
+    }
+    /// Return the path to the compressed parcel data for the given box ID
+    fn compressed_parcel_path(&self, parcel_id: &str) -> PathBuf {
+        self.parcel_path(parcel_id).join(format!(
+            "{}.{}",
+            self.layout.parcel_file, COMPRESSED_EXTENSION
+        ))
+    }
+    /// Return the path to the staging file for a resumable upload of the given box ID
+    fn partial_parcel_path(&self, parcel_id: &str) -> PathBuf {
+        match self.staging_dir.as_ref() {
+            Some(dir) => dir.join(format!("{}.{}", parcel_id, PARTIAL_EXTENSION)),
+            None => self
+                .parcel_path(parcel_id)
+                .join(format!("{}.{}", self.layout.parcel_file, PARTIAL_EXTENSION)),
+        }
+    }
+    /// Return the path to the generated label.toml file for the given box ID
+    fn parcel_label_path(&self, parcel_id: &str) -> PathBuf {
+        self.parcel_path(parcel_id).join(LABEL_TOML)
+    }
+
+    /// Opens the data of the given parcel, transparently decompressing it if it was stored
+    /// compressed. Returns a `NotFound` error if the parcel is not stored in either form
+    async fn open_parcel(
+        &self,
+        parcel_id: &str,
+    ) -> std::io::Result<Box<dyn AsyncRead + Unpin + Send + Sync>> {
+        let path = self.parcel_data_path(parcel_id);
+        match self.retry.retry(|| File::open(&path)).await {
+            Ok(file) => return Ok(Box::new(file)),
+            Err(e) if !matches!(e.kind(), std::io::ErrorKind::NotFound) => return Err(e),
+            Err(_) => (),
+        }
+        let path = self.compressed_parcel_path(parcel_id);
+        let file = self.retry.retry(|| File::open(&path)).await?;
+        trace!(path = %path.display(), "Decompressing parcel");
+        Ok(Box::new(GzipDecoder::new(BufReader::new(file))))
+    }
+
+    /// Returns the size of the given parcel's data. For compressed parcels this is the size of the
+    /// uncompressed data, which is read from the parcel's generated label
+    async fn stored_parcel_size(&self, parcel_id: &str) -> Result<u64> {
+        let path = self.parcel_data_path(parcel_id);
+        match self.retry.retry(|| tokio::fs::metadata(&path)).await {
+            Ok(m) => return Ok(m.len()),
+            Err(e) if !matches!(e.kind(), std::io::ErrorKind::NotFound) => {
+                return Err(ProviderError::io_at(path, e))
+            }
+            Err(_) => (),
+        }
+        if tokio::fs::metadata(self.compressed_parcel_path(parcel_id))
             .await
+            .is_err()
         {
-            Ok(f) => f,
-            // This is the error code for a sharing violation, which will happen if there is a write
-            // in progress
-            Err(e) if e.raw_os_error().unwrap_or_default() == 32 => {
-                return Err(ProviderError::WriteInProgress)
-            }
-            Err(e) => {
-                return Err(e.into());
+            return Err(ProviderError::not_found(parcel_id));
+        }
+        match self.read_parcel_label(parcel_id).await? {
+            Some(label) => Ok(label.size),
+            None => Err(ProviderError::not_found(parcel_id)),
+        }
+    }
+
+    /// Writes the generated label for a parcel. Returns `WriteInProgress` if another upload is
+    /// currently writing it
+    async fn write_parcel_label(&self, label: &crate::Label) -> Result<()> {
+        // A failed upload can leave a different label cached for the same SHA
+        self.uncache_parcel_label(&label.sha256).await;
+        let encoded = toml::to_vec(label)?;
+        self.write_part_file(self.parcel_label_path(&label.sha256), &encoded)
+            .await
+    }
+
+    /// Returns the path of the file mapping the aliases of the bindle with the given name to
+    /// versions. The file is named after the hex encoded SHA-256 of the name
+    fn alias_path(&self, name: &str) -> PathBuf {
+        use sha2::Digest;
+        let mut path = self.root.join(ALIAS_DIRECTORY);
+        path.push(format!("{:x}.toml", sha2::Sha256::digest(name.as_bytes())));
+        path
+    }
+
+    /// Reads the aliases of the bindle with the given name, mapped to the versions they point at.
+    /// Returns an empty map if no aliases have been set
+    async fn read_aliases(&self, name: &str) -> Result<BTreeMap<String, String>> {
+        let path = self.alias_path(name);
+        match self.retry.retry(|| tokio::fs::read(&path)).await {
+            Ok(data) => Ok(toml::from_slice(&data)?),
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => Ok(BTreeMap::new()),
+            Err(e) => Err(ProviderError::io_at(path, e)),
+        }
+    }
+
+    /// Reads the generated label of a parcel uploaded without one. Returns `None` if the parcel
+    /// was uploaded with a label from an invoice, and so has no generated label
+    async fn read_parcel_label(&self, parcel_id: &str) -> Result<Option<crate::Label>> {
+        if let Some(cache) = self.label_cache.as_ref() {
+            if let Some(label) = cache.lock().await.get(parcel_id) {
+                trace!(parcel_id, "Found parcel label in cache");
+                return Ok(Some(label.clone()));
             }
+        }
+        let label_path = self.parcel_label_path(parcel_id);
+        let label: crate::Label = match tokio::fs::read(&label_path).await {
+            Ok(data) => toml::from_slice(&data)?,
+            // A missing label isn't cached, as one is written when a parcel is uploaded compressed
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => return Ok(None),
+            Err(e) => return Err(ProviderError::io_at(label_path, e)),
         };
-        #[cfg(target_family = "unix")]
-        let file = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .read(true)
-            .open(&part)
-            .await?;
-        Ok(PartFile {
-            path: part,
-            final_location,
-            file,
-        })
+        if let Some(cache) = self.label_cache.as_ref() {
+            cache.lock().await.put(parcel_id.to_owned(), label.clone());
+        }
+        Ok(Some(label))
     }
 
-    async fn write_invoice(&mut self, inv: &crate::Invoice) -> Result<()> {
-        debug!(
-            path = %self.path.display(),
-            "Storing invoice in part file"
-        );
+    /// Removes the generated label of the given parcel, logging any error as there is nothing
+    /// more the caller can do about it
+    async fn remove_parcel_label(&self, parcel_id: &str) {
+        let path = self.parcel_label_path(parcel_id);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            if !matches!(e.kind(), std::io::ErrorKind::NotFound) {
+                error!(path = %path.display(), error = %e, "Unable to remove parcel label");
+            }
+        }
+        self.uncache_parcel_label(parcel_id).await;
+    }
 
-        trace!("Encoding invoice to TOML");
-        // Encode the invoice into a TOML object
-        let data = toml::to_vec(inv)?;
-        self.file
-            .write_all(data.as_slice())
-            .await
-            .map_err(|e| e.into())
+    /// Drops the generated label of the given parcel from the cache, if it is cached
+    async fn uncache_parcel_label(&self, parcel_id: &str) {
+        if let Some(cache) = self.label_cache.as_ref() {
+            trace!(parcel_id, "Dropping parcel label from cache");
+            cache.lock().await.pop(parcel_id);
+        }
     }
+}
 
-    async fn write_parcel<R, B>(
-        &mut self,
-        data: R,
-        parcel_id: &str,
-        expected_length: u64,
-    ) -> Result<()>
+#[async_trait::async_trait]
+impl<T: crate::search::Search + Send + Sync> Provider for FileProvider<T> {
+    #[instrument(level = "trace", skip(self, invoice), fields(invoice_id = tracing::field::Empty))]
+    async fn create_invoice<I>(&self, invoice: I) -> Result<(crate::Invoice, Vec<crate::Label>)>
     where
-        R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
-        B: bytes::Buf + Send,
+        I: Signed + Verified + Send + Sync,
     {
-        // Create the part file to indicate that we are currently writing
-        debug!(
-            path = %self.path.display(),
-            parcel_id,
-            "Storing parcel data in part file"
-        );
-        trace!("Copying data to open file");
-        let written = tokio::io::copy(
-            &mut StreamReader::new(
-                data.map(|res| res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
-            ),
-            &mut self.file,
-        )
-        .instrument(tracing::trace_span!("parcel_data_write"))
-        .await?;
+        self.check_writable()?;
+        let mut inv = invoice.signed();
+        tracing::span::Span::current()
+            .record("invoice_id", &tracing::field::display(&inv.bindle.id));
+        self.validate_new_invoice(&mut inv)?;
 
-        // Make sure the right amount of data was sent
-        trace!(bytes_written = written, "Wrote data to part file");
-        if written != expected_length {
-            return Err(ProviderError::SizeMismatch);
-        }
-        // Verify parcel by rewinding the parcel and then hashing it.
-        // This MUST be after the last write to out, otherwise the results will
-        // not be correct.
-        self.file.flush().await?;
-        self.file.seek(std::io::SeekFrom::Start(0)).await?;
-        trace!("Validating data for parcel");
-        validate_sha256(&mut self.file, parcel_id)
-            .instrument(tracing::trace_span!("parcel_data_validation"))
-            .await?;
-        trace!("SHA data validated");
-        Ok(())
-    }
+        let invoice_id = self.invoice_name(&inv.bindle.id);
+        let _lock = self.lock_invoice(&invoice_id).await;
 
-    /// Moves the file to the configured final location, consuming the part file
-    async fn finalize(mut self) -> Result<()> {
-        debug!(
-            renamed_path = %self.final_location.display(),
-            "Renaming part file for parcel"
+        // Create the base path if necessary
+        let inv_path = self.invoice_path(&invoice_id);
+        trace!(path = %inv_path.display(), "Checking if invoice base path already exists on disk");
+        // With errors we default to false in this logic block, so just convert to an Option
+        let metadata = tokio::fs::metadata(&inv_path).await.ok();
+        if !metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false) {
+            // If it exists and is a regular file, we have a problem
+            if metadata.map(|m| m.is_file()).unwrap_or(false) {
+                debug!("Invoice being created already exists in storage");
+                return Err(ProviderError::Exists);
+            }
+            trace!(path = %inv_path.display(), "Base path doesn't exist, creating");
+            if let Err(e) = self.create_dir(&inv_path).await {
+                error!(error = %e, "Unable to create invoice storage directory");
+                // Something was created at the path after we checked it
+                if matches!(e.kind(), std::io::ErrorKind::AlreadyExists) {
+                    return Err(ProviderError::Exists);
+                }
+                return Err(ProviderError::io_at(inv_path, e));
+            }
+        }
+
+        // Error out if the invoice already exists, in either format
+        for (dest, _) in self.invoice_file_paths(&invoice_id) {
+            trace!(path = %dest.display(), "Checking if invoice already exists on disk");
+            // We can't just call `exists` because it can do IO calls, so look up using the
+            // metadata. Anything at that path means the invoice can't be written, so treat it as
+            // existing rather than failing with an IO error when the part file is renamed
+            if tokio::fs::metadata(&dest).await.is_ok() {
+                debug!("Invoice being created already exists in storage");
+                return Err(ProviderError::Exists);
+            }
+        }
+
+        self.write_invoice_file(&invoice_id, &inv).await?;
+        self.record_created(&inv_path).await;
+        // Make sure a stale copy is never served, for example if the invoice was removed from disk
+        // and created again
+        self.uncache_invoice(&inv.bindle.id).await;
+
+        // Attempt to update the index. By default, we only log a warning if the index update
+        // fails.
+        if let Err(e) = self.index.index(&inv).await {
+            warn!(invoice_id = %inv.bindle.id, error = %e, "Error indexing new invoice");
+            if self.fail_on_index_error {
+                // Remove the invoice we just wrote so storage and the index stay consistent
+                self.remove_unindexed_invoice(&invoice_id, &inv_path).await;
+                return Err(ProviderError::Other(format!(
+                    "Unable to index invoice: {}",
+                    e
+                )));
+            }
+        }
+        self.index_version(&inv.bindle.id, false);
+
+        self.notify_invoice_created(&inv).await;
+
+        trace!("Checking for missing parcels listed in newly created invoice");
+        let labels = self.missing_parcels(&inv).await;
+        Ok((inv, labels))
+    }
+
+    #[instrument(level = "trace", skip(self, inv), fields(invoice_id = %inv.bindle.id))]
+    async fn plan_invoice(&self, inv: &crate::Invoice) -> Result<Vec<crate::Label>> {
+        let mut inv = inv.clone();
+        self.validate_new_invoice(&mut inv)?;
+        if self.invoice_exists(&inv.bindle.id).await? {
+            debug!("Invoice being planned already exists in storage");
+            return Err(ProviderError::Exists);
+        }
+        Ok(self.missing_parcels(&inv).await)
+    }
+
+    #[instrument(level = "trace", skip(self, invoice))]
+    async fn create_invoice_with_plan<I>(&self, invoice: I) -> Result<UploadPlan>
+    where
+        I: Signed + Verified + Send + Sync,
+    {
+        let (inv, missing) = self.create_invoice(invoice).await?;
+        // Locations are relative to the storage root so they don't leak where it is on disk
+        let parcels = missing
+            .into_iter()
+            .map(|label| ParcelUpload {
+                location: format!(
+                    "{}/{}/{}",
+                    self.layout.parcel_directory, label.sha256, self.layout.parcel_file
+                ),
+                label,
+            })
+            .collect();
+        Ok(UploadPlan {
+            invoice_name: self.invoice_name(&inv.bindle.id),
+            parcels,
+        })
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn get_yanked_invoice<I>(&self, id: I) -> Result<crate::Invoice>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        if let Some(cache) = self.invoice_cache.as_ref() {
+            if let Some(inv) = cache.lock().await.get(&parsed_id) {
+                debug!("Found invoice in cache, returning");
+                return Ok(inv.clone());
+            }
+        }
+        debug!("Getting invoice from file system");
+
+        let invoice = self.read_invoice_by_id(&parsed_id).await?;
+
+        // Put it into the cache
+        if let Some(cache) = self.invoice_cache.as_ref() {
+            trace!("Putting invoice into cache");
+            cache.lock().await.put(parsed_id, invoice.clone());
+        }
+
+        // Return object
+        Ok(invoice)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn warm_cache(&self, ids: &[String]) -> Result<usize> {
+        if self.invoice_cache.is_none() {
+            debug!("Invoice caching is disabled, not warming cache");
+            return Ok(0);
+        }
+        // Loading an invoice puts it into the cache
+        warm_cache_via_get(self, ids).await
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn invoice_status<I>(&self, id: I) -> Result<InvoiceStatus>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        if let Some(cache) = self.invoice_cache.as_ref() {
+            if let Some(inv) = cache.lock().await.get(&parsed_id) {
+                debug!("Found invoice in cache, returning status");
+                return Ok(InvoiceStatus::from(&*inv));
+            }
+        }
+        self.read_invoice_status(&parsed_id).await
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn get_invoice_with_etag<I>(&self, id: I) -> Result<(crate::Invoice, String)>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        // The ETag is taken from the stored TOML (after decompressing it) rather than the cache so
+        // it always matches what is on disk
+        let inv_toml = self
+            .read_invoice_toml(&self.invoice_name(&parsed_id))
+            .await
+            .map_err(|e| match e {
+                ProviderError::NotFound { .. } => ProviderError::not_found(&parsed_id),
+                e => e,
+            })?;
+        let inv: crate::Invoice = toml::from_slice(&inv_toml)?;
+        if inv.bindle.id != parsed_id {
+            return Err(ProviderError::not_found(&parsed_id));
+        }
+        Ok((inv, invoice_etag(&inv_toml)))
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn get_invoice_raw<I>(&self, id: I) -> Result<Box<dyn AsyncRead + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        // Compressed invoices are decompressed, so callers always get TOML
+        let inv_toml = self
+            .read_invoice_toml(&self.invoice_name(&parsed_id))
+            .await
+            .map_err(|e| match e {
+                ProviderError::NotFound { .. } => ProviderError::not_found(&parsed_id),
+                e => e,
+            })?;
+        // Only the ID and yanked flag are needed, so skip parsing the rest of the invoice
+        let summary: InvoiceSummary = toml::from_slice(&inv_toml)?;
+        if summary.bindle.id != parsed_id {
+            return Err(ProviderError::not_found(&parsed_id));
+        }
+        if summary.yanked.unwrap_or(false) {
+            debug!("Invoice is yanked");
+            return Err(ProviderError::Yanked);
+        }
+        Ok(Box::new(std::io::Cursor::new(inv_toml)))
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn invoice_exists<I>(&self, id: I) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        let invoice_name = self.invoice_name(&parsed_id);
+        debug!(invoice_name = %invoice_name, "Checking if invoice exists in storage");
+        match self.find_invoice_file(&invoice_name).await {
+            Ok(_) => Ok(true),
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => Ok(false),
+            Err(e) => Err(ProviderError::io_at(
+                self.invoice_toml_path(&invoice_name),
+                e,
+            )),
+        }
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn list_invoices(&self) -> Result<Vec<crate::Invoice>> {
+        let mut invoices = Vec::new();
+        for name in self.invoice_names().await? {
+            // Directory names come from the naming strategy and may be opaque, so we have to read
+            // the invoice to find the real name and version
+            match self.read_invoice(&name).await {
+                Ok(inv) => invoices.push(inv),
+                // This can happen if an invoice was removed while listing, so skip it
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        debug!(total = invoices.len(), "Listed invoices");
+        Ok(invoices)
+    }
+
+    /// Pages over the sorted storage names, so only the invoices on the requested page are read.
+    /// With the default [`Sha256Naming`] strategy this is the same order as
+    /// sorting by canonical name
+    #[instrument(level = "trace", skip(self))]
+    async fn list_invoices_paged(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<crate::Invoice>, usize)> {
+        let mut names = self.invoice_names().await?;
+        let total = names.len();
+        names.sort();
+        let mut invoices = Vec::new();
+        for name in names
+            .into_iter()
+            .skip(offset)
+            .take(limit.min(super::MAX_PAGE_SIZE))
+        {
+            match self.read_invoice(&name).await {
+                Ok(inv) => invoices.push(inv),
+                // This can happen if an invoice was removed while listing, so skip it
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        debug!(total, page = invoices.len(), "Listed page of invoices");
+        Ok((invoices, total))
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn query_invoices(&self, term: &str) -> Result<Vec<crate::Invoice>> {
+        let ids = self.index.query_ids(term).await.map_err(|e| {
+            error!(error = %e, "Error querying index");
+            ProviderError::Other(format!("Unable to query search index: {}", e))
+        })?;
+        trace!(total = ids.len(), "Loading matching invoices");
+        let mut invoices = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.read_invoice_by_id(&id).await {
+                Ok(inv) => invoices.push(inv),
+                // The index can be slightly behind storage, so skip anything that is gone
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(invoices)
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn yank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.check_writable()?;
+        let parsed_id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Yanking invoice");
+        self.set_yanked(parsed_id.clone(), true).await?;
+        self.notify_invoice_yanked(&parsed_id).await;
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn unyank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.check_writable()?;
+        let parsed_id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Unyanking invoice");
+        self.set_yanked(parsed_id, false).await
+    }
+
+    #[instrument(level = "trace", skip(self, id, annotations), fields(id))]
+    async fn update_invoice_annotations<I>(
+        &self,
+        id: I,
+        annotations: BTreeMap<String, String>,
+    ) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.check_writable()?;
+        let parsed_id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Updating invoice annotations");
+        self.update_invoice(&parsed_id, |inv| {
+            merge_annotations(inv, annotations)?;
+            self.check_policy(inv)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    #[instrument(level = "trace", skip(self, id, new_parcels), fields(id))]
+    async fn add_parcels_to_invoice<I>(
+        &self,
+        id: I,
+        new_parcels: Vec<crate::Parcel>,
+    ) -> Result<Vec<crate::Label>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        self.check_writable()?;
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!(total = new_parcels.len(), "Adding parcels to invoice");
+        let inv = self
+            .update_invoice(&parsed_id, |inv| {
+                append_parcels(inv, new_parcels)?;
+                self.check_policy(inv)
+            })
+            .await?;
+        Ok(self.missing_parcels(&inv).await)
+    }
+
+    #[instrument(level = "trace", skip(self, id, f), fields(id))]
+    async fn with_invoice_mut<I, F, Fut>(&self, id: I, f: F) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+        F: FnOnce(&mut crate::Invoice) -> Fut + Send,
+        Fut: std::future::Future<Output = Result<()>> + Send,
+    {
+        self.check_writable()?;
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        let invoice_id = self.invoice_name(&parsed_id);
+        let _lock = self.lock_invoice(&invoice_id).await;
+
+        trace!("Fetching invoice from storage");
+        let mut inv = self.read_invoice_by_id(&parsed_id).await?;
+        f(&mut inv).await?;
+        if inv.bindle.id != parsed_id {
+            return Err(ProviderError::Other(
+                "The ID of an invoice cannot be changed".to_string(),
+            ));
+        }
+        self.check_policy(&inv)?;
+        self.store_updated_invoice(&invoice_id, &inv).await?;
+        self.index_version(&parsed_id, inv.yanked.unwrap_or(false));
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id, data), fields(id))]
+    async fn create_parcel<I, R, B>(&self, bindle_id: I, parcel_id: &str, data: R) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+        R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
+        B: bytes::Buf + Send,
+    {
+        self.check_writable()?;
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id: Id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        let label = self.validate_parcel(&parsed_id, parcel_id).await?;
+        self.check_parcel_size(label.size)?;
+
+        // Test if a dir with that SHA exists. If so, check that the upload matches what is stored
+        let par_path = self.parcel_path(parcel_id);
+        match tokio::fs::metadata(&par_path).await {
+            Ok(m) if m.is_dir() => {
+                debug!(path = %par_path.display(), "Parcel directory already exists");
+                return match self.stored_parcel_size(parcel_id).await {
+                    Ok(size) => check_existing_parcel(&label, size),
+                    // The directory exists without any data, so we can't tell what is there
+                    Err(_) => Err(ProviderError::Exists),
+                };
+            }
+            // Something other than a parcel directory is in the way, so creating it would fail
+            Ok(_) => {
+                debug!(path = %par_path.display(), "Parcel path exists but is not a directory");
+                return Err(ProviderError::Exists);
+            }
+            Err(_) => (),
+        }
+        // The space is given back if anything below fails
+        let reservation = self.reserve_quota(label.size)?;
+        // Create box dir
+        trace!(path = %par_path.display(), "Creating parcel directory");
+        if let Err(e) = self.create_dir(&par_path).await {
+            error!(error = %e, "Unable to create parcel storage directory");
+            // Something was created at the path after we checked it
+            if matches!(e.kind(), std::io::ErrorKind::AlreadyExists) {
+                return Err(ProviderError::Exists);
+            }
+            return Err(ProviderError::io_at(par_path, e));
+        }
+
+        // Until the upload completes, make sure the parcel directory is removed if anything fails
+        // (or this future is dropped) so a later upload isn't rejected as already existing. This
+        // is declared before the part file so that the part file is dropped first
+        let data_path = if self.compress_parcels {
+            self.compressed_parcel_path(parcel_id)
+        } else {
+            self.parcel_data_path(parcel_id)
+        };
+        let mut guard = ParcelDirGuard::new(par_path, data_path.clone());
+
+        // Compressed parcels need a stored label to record their uncompressed size, which is
+        // written before the data so the data never exists without it
+        if self.compress_parcels {
+            let mut stored = label.clone();
+            stored
+                .annotations
+                .get_or_insert_with(Default::default)
+                .insert(COMPRESSION_ANNOTATION.to_owned(), GZIP.to_owned());
+            match self.write_parcel_label(&stored).await {
+                Ok(_) => (),
+                // Another upload of the same parcel owns the directory, so leave it alone
+                Err(ProviderError::WriteInProgress) => {
+                    guard.disarm();
+                    return Err(ProviderError::WriteInProgress);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Write data
+        let mut part = match self.part_file(data_path).await {
+            Ok(p) => p,
+            // Another upload of the same parcel owns the directory, so leave it alone
+            Err(ProviderError::WriteInProgress) => {
+                guard.disarm();
+                return Err(ProviderError::WriteInProgress);
+            }
+            Err(e) => return Err(e),
+        };
+        part.write_parcel(data, &label, self.compress_parcels, self.max_parcel_size)
+            .await?;
+        part.finalize().await?;
+        guard.disarm();
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+        self.record_created(&self.parcel_path(parcel_id)).await;
+        self.notify_parcel_created(&label).await;
+
+        if self.sniff_media_types && label.media_type == OCTET_STREAM {
+            // The parcel has already been stored, so failing to detect its type is not fatal
+            trace!("Detecting parcel media type");
+            match self.sniff_parcel_media_type(parcel_id).await {
+                Ok(Some(media_type)) => {
+                    if let Err(e) = self.set_parcel_media_type(&label, &media_type).await {
+                        warn!(error = %e, "Unable to store detected parcel media type");
+                    }
+                }
+                Ok(None) => trace!("Unable to detect parcel media type"),
+                Err(e) => warn!(error = %e, "Unable to detect parcel media type"),
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self, data), fields(size = data.len()))]
+    async fn create_parcel_chunk(&self, parcel_id: &str, offset: u64, data: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        check_parcel_id(parcel_id)?;
+        if self.parcel_data_exists(parcel_id).await {
+            debug!("Parcel has already been stored");
+            return Err(ProviderError::Exists);
+        }
+
+        let par_path = self.parcel_path(parcel_id);
+        if let Err(e) = self.create_dir(&par_path).await {
+            // Something other than a parcel directory is in the way
+            if matches!(e.kind(), std::io::ErrorKind::AlreadyExists) {
+                return Err(ProviderError::Exists);
+            }
+            return Err(ProviderError::io_at(par_path, e));
+        }
+
+        self.create_staging_dir().await?;
+        let _lock = self.lock_parcel(parcel_id).await;
+        let partial_path = self.partial_parcel_path(parcel_id);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&partial_path)
+            .await
+            .map_err(|e| ProviderError::io_at(&partial_path, e))?;
+        let current = file
+            .metadata()
+            .await
+            .map_err(|e| ProviderError::io_at(&partial_path, e))?
+            .len();
+        // Writing past the end would leave a gap of unwritten data. Writing before the end is a
+        // resent chunk, which overwrites what is already there
+        if offset > current {
+            debug!(offset, current, "Rejecting chunk that would leave a gap");
+            return Err(ProviderError::OutOfRange {
+                start: offset,
+                size: current,
+            });
+        }
+        let new_len = offset + data.len() as u64;
+        self.check_parcel_size(new_len)?;
+        // Uploaded data takes up space as soon as it is written, so it is counted against the
+        // quota chunk by chunk rather than only once the parcel is finalized
+        let reservation = self.reserve_quota(new_len.saturating_sub(current))?;
+        trace!(path = %partial_path.display(), offset, "Writing parcel chunk");
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(|e| ProviderError::io_at(&partial_path, e))?;
+        file.write_all(data)
+            .await
+            .map_err(|e| ProviderError::io_at(&partial_path, e))?;
+        file.flush()
+            .await
+            .map_err(|e| ProviderError::io_at(&partial_path, e))?;
+        // Anything after this chunk is from an earlier attempt, so drop it rather than letting
+        // stale data end up in the parcel
+        file.set_len(new_len)
+            .await
+            .map_err(|e| ProviderError::io_at(&partial_path, e))?;
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+        self.release_quota(current.saturating_sub(new_len));
+        if self.sync_writes {
+            file.sync_all()
+                .await
+                .map_err(|e| ProviderError::io_at(&partial_path, e))?;
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self, label), fields(parcel_id = %label.sha256))]
+    async fn finalize_parcel(&self, label: &crate::Label) -> Result<()> {
+        self.check_writable()?;
+        let parcel_id = label.sha256.as_str();
+        check_parcel_id(parcel_id)?;
+        if let Ok(size) = self.stored_parcel_size(parcel_id).await {
+            debug!("Parcel has already been stored");
+            return check_existing_parcel(label, size);
+        }
+
+        let partial_path = self.partial_parcel_path(parcel_id);
+        let file = match File::open(&partial_path).await {
+            Ok(f) => f,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => {
+                return Err(ProviderError::not_found(parcel_id))
+            }
+            Err(e) => return Err(ProviderError::io_at(partial_path, e)),
+        };
+        // The uploaded data was counted against the quota as it was written, and a successful
+        // finalize stores exactly that much
+        let _lock = self.lock_parcel(parcel_id).await;
+
+        if self.compress_parcels {
+            // The data has to be rewritten compressed, which validates it along the way
+            let mut stored = label.clone();
+            stored
+                .annotations
+                .get_or_insert_with(Default::default)
+                .insert(COMPRESSION_ANNOTATION.to_owned(), GZIP.to_owned());
+            self.write_parcel_label(&stored).await?;
+            let res = async {
+                let mut part = self
+                    .part_file(self.compressed_parcel_path(parcel_id))
+                    .await?;
+                part.write_parcel(
+                    FramedRead::new(file, BytesCodec::new()),
+                    label,
+                    true,
+                    self.max_parcel_size,
+                )
+                .await?;
+                part.finalize().await
+            }
+            .await;
+            if let Err(e) = res {
+                // The data was never stored, so a label marking it as compressed would be wrong
+                self.remove_parcel_label(parcel_id).await;
+                return Err(e);
+            }
+            tokio::fs::remove_file(&partial_path)
+                .await
+                .map_err(|e| ProviderError::io_at(partial_path, e))?;
+            self.record_created(&self.parcel_path(parcel_id)).await;
+            self.notify_parcel_created(label).await;
+            return Ok(());
+        }
+
+        trace!(path = %partial_path.display(), "Validating uploaded parcel data");
+        let mut reader = HashingReader::with_algorithm(file, label.algorithm());
+        tokio::io::copy(&mut reader, &mut tokio::io::sink())
+            .await
+            .map_err(|e| ProviderError::io_at(&partial_path, e))?;
+        let size = reader.bytes_read();
+        if size != label.size {
+            return Err(ProviderError::SizeMismatch {
+                expected: label.size,
+                actual: size,
+            });
+        }
+        let actual = reader.finalize();
+        if actual != label.sha256 {
+            return Err(ProviderError::DigestMismatch {
+                expected: label.sha256.clone(),
+                actual,
+            });
+        }
+
+        let data_path = self.parcel_data_path(parcel_id);
+        debug!(path = %data_path.display(), "Moving uploaded parcel into place");
+        move_file(&partial_path, &data_path, self.sync_writes).await?;
+        if self.sync_writes {
+            sync_parent_dir(&data_path).await?;
+        }
+        self.record_created(&self.parcel_path(parcel_id)).await;
+        self.notify_parcel_created(label).await;
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self, data))]
+    async fn create_parcel_computing_sha<R>(
+        &self,
+        media_type: String,
+        name: String,
+        data: &mut R,
+    ) -> Result<crate::Label>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        self.check_writable()?;
+        // The data is spooled next to the parcels directory (or in the staging directory), as we
+        // don't know where it goes until it has all been read. Keeping it on the same file system
+        // means it can just be renamed
+        let spool_dir = self.staging_dir.as_ref().unwrap_or(&self.root);
+        if let Err(e) = self.create_dir(spool_dir).await {
+            return Err(ProviderError::io_at(spool_dir, e));
+        }
+        let (tmp, file) = {
+            let tmp_dir = spool_dir.clone();
+            tokio::task::spawn_blocking(move || {
+                let tmp = tempfile::Builder::new()
+                    .suffix(&format!(".{}", PART_EXTENSION))
+                    .tempfile_in(tmp_dir)?;
+                let file = tmp.reopen()?;
+                Ok::<_, std::io::Error>((tmp, file))
+            })
+            .await
+            .map_err(|e| ProviderError::Other(e.to_string()))?
+            .map_err(|e| ProviderError::io_at(spool_dir, e))?
+        };
+        let mut file = File::from_std(file);
+        #[cfg(target_family = "unix")]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(mode))
+                .await
+                .map_err(|e| ProviderError::io_at(tmp.path(), e))?;
+        }
+
+        trace!(path = %tmp.path().display(), "Copying parcel data to temporary file");
+        let mut reader = HashingReader::new(data);
+        tokio::io::copy(
+            &mut (&mut reader).take(read_limit(self.max_parcel_size)),
+            &mut file,
+        )
+        .instrument(tracing::trace_span!("parcel_data_write"))
+        .await
+        .map_err(|e| ProviderError::io_at(tmp.path(), e))?;
+        self.check_parcel_size(reader.bytes_read())?;
+        file.shutdown()
+            .await
+            .map_err(|e| ProviderError::io_at(tmp.path(), e))?;
+        if self.sync_writes {
+            file.sync_all()
+                .await
+                .map_err(|e| ProviderError::io_at(tmp.path(), e))?;
+        }
+        let label = crate::Label {
+            size: reader.bytes_read(),
+            sha256: reader.finalize(),
+            media_type,
+            name,
+            ..crate::Label::default()
+        };
+        debug!(parcel_id = %label.sha256, size = label.size, "Computed parcel SHA");
+
+        let par_path = self.parcel_path(&label.sha256);
+        // Check for the data rather than the parcel directory, as the directory also exists while
+        // another upload (such as a resumable upload) of the same content is in progress
+        if self.parcel_data_exists(&label.sha256).await {
+            debug!(path = %par_path.display(), "Parcel already exists");
+            return match self.read_parcel_label(&label.sha256).await? {
+                Some(existing) => Ok(existing),
+                // The parcel was uploaded for an invoice, so there is no stored label to return
+                None => Ok(label),
+            };
+        }
+        if tokio::fs::metadata(&par_path)
+            .await
+            .map(|m| m.is_dir())
+            .unwrap_or(false)
+        {
+            debug!(path = %par_path.display(), "Parcel is already being written");
+            return Err(ProviderError::WriteInProgress);
+        }
+        let reservation = self.reserve_quota(label.size)?;
+        trace!(path = %par_path.display(), "Creating parcel directory");
+        if let Err(e) = self.create_dir(&par_path).await {
+            error!(error = %e, "Unable to create parcel storage directory");
+            return Err(ProviderError::io_at(par_path, e));
+        }
+        let data_path = self.parcel_data_path(&label.sha256);
+        let mut guard = ParcelDirGuard::new(par_path, data_path.clone());
+
+        // Write the label first, so the parcel never exists without it
+        match self.write_parcel_label(&label).await {
+            Ok(_) => (),
+            // Another upload of the same content owns the directory, so leave it alone
+            Err(ProviderError::WriteInProgress) => {
+                guard.disarm();
+                return Err(ProviderError::WriteInProgress);
+            }
+            Err(e) => return Err(e),
+        }
+
+        trace!(path = %data_path.display(), "Moving parcel data into place");
+        // Close our handles first, as some platforms can't rename open files
+        drop(file);
+        let tmp = tmp.into_temp_path();
+        move_file(&tmp, &data_path, self.sync_writes).await?;
+        if self.sync_writes {
+            sync_parent_dir(&data_path).await?;
+        }
+        guard.disarm();
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+        self.record_created(&self.parcel_path(&label.sha256)).await;
+        self.notify_parcel_created(&label).await;
+        Ok(label)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn delete_parcel(&self, parcel_id: &str) -> Result<()> {
+        self.check_writable()?;
+        check_parcel_id(parcel_id)?;
+        let par_path = self.parcel_path(parcel_id);
+        trace!(path = %par_path.display(), "Checking if parcel exists on disk");
+        if !tokio::fs::metadata(&par_path)
+            .await
+            .map(|m| m.is_dir())
+            .unwrap_or(false)
+        {
+            return Err(ProviderError::not_found(parcel_id));
+        }
+
+        // Concurrent deletes are serialized so only the one that removes the directory gives back
+        // its space in the quota. References are checked while holding the lock so a parcel that
+        // gets referenced while we wait for it is not removed
+        let _lock = self.lock_parcel(parcel_id).await;
+        trace!("Checking for invoices referencing parcel");
+        if self.parcel_reference_count(parcel_id).await? > 0 {
+            debug!("Parcel is still referenced by an invoice, refusing to delete");
+            return Err(ProviderError::InUse);
+        }
+        let size = self.parcel_dir_usage(parcel_id).await;
+        debug!(path = %par_path.display(), "Deleting parcel from storage");
+        tokio::fs::remove_dir_all(&par_path)
+            .await
+            .map_err(|e| map_io_error(e, parcel_id, &par_path))?;
+        self.uncache_parcel_label(parcel_id).await;
+        self.release_quota(size);
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn set_alias(&self, name: &str, alias: &str, version: &str) -> Result<()> {
+        self.check_writable()?;
+        let id: Id = format!("{}/{}", name, version).parse()?;
+        let status = self.invoice_status(&id).await?;
+        if !status.exists {
+            return Err(ProviderError::not_found(&id));
+        }
+        if status.yanked {
+            debug!("Refusing to point alias at a yanked version");
+            return Err(ProviderError::Yanked);
+        }
+
+        // All aliases of a bindle share a file, so updates to it are serialized like invoice writes
+        let path = self.alias_path(name);
+        let _lock = self.lock_invoice(&path.to_string_lossy()).await;
+        let mut aliases = self.read_aliases(name).await?;
+        aliases.insert(alias.to_owned(), version.to_owned());
+
+        if let Some(dir) = path.parent() {
+            self.create_dir(dir)
+                .await
+                .map_err(|e| ProviderError::io_at(dir, e))?;
+        }
+        let encoded = toml::to_vec(&aliases)?;
+        self.write_part_file(path, &encoded).await
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn resolve_alias(&self, name: &str, alias: &str) -> Result<crate::Invoice> {
+        let version = self
+            .read_aliases(name)
+            .await?
+            .remove(alias)
+            .ok_or_else(|| ProviderError::not_found(format!("{}@{}", name, alias)))?;
+        trace!(%version, "Resolved alias");
+        self.get_invoice(format!("{}/{}", name, version)).await
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn get_latest_invoice(&self, name: &str) -> Result<crate::Invoice> {
+        let latest = {
+            let versions = self.versions.read().unwrap();
+            versions.get(name).and_then(|v| {
+                latest_version(
+                    v.iter()
+                        .filter(|(_, yanked)| !**yanked)
+                        .map(|(version, _)| version),
+                )
+                .cloned()
+            })
+        };
+        match latest {
+            Some(version) => self.get_invoice(format!("{}/{}", name, version)).await,
+            None => Err(ProviderError::not_found(name)),
+        }
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn list_versions(&self, name: &str, include_yanked: bool) -> Result<Vec<String>> {
+        Ok(self
+            .versions
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|v| {
+                v.iter()
+                    .filter(|(_, yanked)| include_yanked || !**yanked)
+                    .map(|(version, _)| version.to_string())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn catalog(&self, include_yanked: bool) -> Result<BTreeMap<String, Vec<String>>> {
+        Ok(self
+            .versions
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, v)| {
+                let versions: Vec<String> = v
+                    .iter()
+                    .filter(|(_, yanked)| include_yanked || !**yanked)
+                    .map(|(version, _)| version.to_string())
+                    .collect();
+                (!versions.is_empty()).then(|| (name.clone(), versions))
+            })
+            .collect())
+    }
+
+    #[instrument(level = "trace", skip(self, id))]
+    async fn invoice_created<I>(&self, id: I) -> Result<SystemTime>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        let invoice_id = self.invoice_name(&parsed_id);
+        self.find_invoice_file(&invoice_id).await.map_err(|e| {
+            map_io_error(e, &parsed_id.to_string(), &self.invoice_path(&invoice_id))
+        })?;
+        self.read_created(&self.invoice_path(&invoice_id)).await
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn parcel_created(&self, parcel_id: &str) -> Result<SystemTime> {
+        check_parcel_id(parcel_id)?;
+        if !self.parcel_data_exists(parcel_id).await {
+            return Err(ProviderError::not_found(parcel_id));
+        }
+        self.read_created(&self.parcel_path(parcel_id)).await
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn list_invoices_since(&self, since: SystemTime) -> Result<Vec<crate::Invoice>> {
+        // Creation times are stored separately, so only invoices new enough are read from disk
+        let mut invoices = Vec::new();
+        for name in self.invoice_names().await? {
+            let created = match self.read_created(&self.invoice_path(&name)).await {
+                Ok(created) if created >= since => created,
+                Ok(_) => continue,
+                // This can happen if an invoice was removed while listing, so skip it
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            match self.read_invoice(&name).await {
+                Ok(inv) => invoices.push((created, inv)),
+                // This can happen if an invoice was removed while listing, so skip it
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        invoices.sort_by_key(|(created, _)| *created);
+        debug!(total = invoices.len(), "Listed invoices created since");
+        Ok(invoices.into_iter().map(|(_, inv)| inv).collect())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn health_check(&self) -> Result<()> {
+        trace!(path = %self.root.display(), "Checking that the storage root is a directory");
+        let metadata = tokio::fs::metadata(&self.root)
+            .await
+            .map_err(|e| ProviderError::io_at(&self.root, e))?;
+        if !metadata.is_dir() {
+            return Err(ProviderError::io_at(
+                &self.root,
+                std::io::Error::new(std::io::ErrorKind::Other, "storage root is not a directory"),
+            ));
+        }
+
+        // A read-only provider never writes, so there is nothing more to check
+        if self.read_only {
+            return Ok(());
+        }
+
+        for dir in [self.invoice_path(""), self.parcel_path("")] {
+            trace!(path = %dir.display(), "Making sure storage directory exists");
+            self.create_dir(&dir)
+                .await
+                .map_err(|e| ProviderError::io_at(&dir, e))?;
+        }
+
+        // Make sure we can actually write by creating and removing a file. Use a random name so
+        // concurrent checks don't conflict
+        let probe = self
+            .root
+            .join(format!(".health-check-{:x}", rand::random::<u64>()));
+        trace!(path = %probe.display(), "Writing health check file");
+        OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&probe)
+            .await
+            .map_err(|e| ProviderError::io_at(&probe, e))?;
+        tokio::fs::remove_file(&probe)
+            .await
+            .map_err(|e| ProviderError::io_at(&probe, e))
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let invoices = self.list_invoices().await?;
+        let referenced = referenced_parcels(&invoices, false);
+        let parcels = self.list_parcel_sizes().await?;
+        let stats = StorageStats {
+            invoice_count: invoices.len(),
+            parcel_count: parcels.len(),
+            total_parcel_bytes: parcels.iter().map(|(_, size)| size).sum(),
+            dangling_parcel_count: parcels
+                .iter()
+                .filter(|(sha, _)| !referenced.contains(sha))
+                .count(),
+        };
+        debug!(?stats, "Computed storage stats");
+        Ok(stats)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn verify_canonical_name(&self, canonical_name: &str) -> Result<()> {
+        // The name is used as a path, so make sure it can't point outside the invoice directory
+        if canonical_name
+            .split('/')
+            .any(|s| s.is_empty() || s == "." || s == ".." || s.contains('\\'))
+        {
+            return Err(ProviderError::not_found(canonical_name));
+        }
+        let inv = self.read_invoice(canonical_name).await?;
+        let expected = self.invoice_name(&inv.bindle.id);
+        if expected != canonical_name {
+            warn!(invoice_name = %canonical_name, %expected, "Invoice is stored under the wrong name");
+            return Err(ProviderError::CanonicalMismatch {
+                canonical_name: canonical_name.to_owned(),
+                expected,
+            });
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn verify_store(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+
+        // Invoices are checked one at a time so only the set of referenced SHAs is kept in memory
+        let mut referenced = std::collections::HashSet::new();
+        for name in self.invoice_names().await? {
+            let inv = match self.read_invoice(&name).await {
+                Ok(inv) => inv,
+                // The invoice was removed after it was listed
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => {
+                    warn!(invoice_name = %name, error = %e, "Unable to read invoice");
+                    report.unreadable_invoices.push(name);
+                    continue;
+                }
+            };
+            if self.verify_canonical_names {
+                match self.verify_canonical_name(&name).await {
+                    Ok(_) => (),
+                    Err(ProviderError::CanonicalMismatch { .. }) => {
+                        report.misplaced_invoices.push(name.clone())
+                    }
+                    // The invoice was removed after it was read
+                    Err(ProviderError::NotFound { .. }) => continue,
+                    Err(e) => {
+                        warn!(invoice_name = %name, error = %e, "Unable to read invoice");
+                        report.unreadable_invoices.push(name);
+                        continue;
+                    }
+                }
+            }
+            referenced.extend(referenced_parcels(std::iter::once(&inv), true));
+            let missing = self.missing_parcels(&inv).await;
+            if !missing.is_empty() {
+                debug!(invoice_id = %inv.bindle.id, total_missing = missing.len(), "Invoice is missing parcels");
+                report.invoices_missing_parcels.insert(
+                    inv.bindle.id.to_string(),
+                    missing.into_iter().map(|l| l.sha256).collect(),
+                );
+            }
+        }
+
+        // This walks the parcel directories rather than using `parcels`, as a parcel with an
+        // unreadable label still needs to be reported by its SHA
+        let mut dirs = Box::pin(self.parcel_dirs());
+        while let Some(sha) = dirs.next().await {
+            let sha = sha?;
+            match self.parcel_is_intact(&sha).await {
+                // This can happen if a parcel is currently being written, so skip it
+                Ok(None) => continue,
+                Ok(Some(false)) => {
+                    warn!(parcel_id = %sha, "Parcel data does not match its SHA");
+                    report.corrupt_parcels.push(sha.clone());
+                }
+                Ok(Some(true)) => (),
+                Err(e) => {
+                    warn!(parcel_id = %sha, error = %e, "Unable to check parcel");
+                    report.corrupt_parcels.push(sha.clone());
+                }
+            }
+            if !referenced.contains(&sha) {
+                report.orphaned_parcels.push(sha);
+            }
+        }
+
+        debug!(ok = report.is_ok(), "Verified storage");
+        Ok(report)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn gc_parcels(&self, keep_yanked: bool) -> Result<Vec<String>> {
+        self.check_writable()?;
+        let invoices = self.list_invoices().await?;
+        let referenced = referenced_parcels(&invoices, keep_yanked);
+
+        let mut removed = Vec::new();
+        for (sha, size) in self.list_parcel_sizes().await? {
+            if referenced.contains(&sha) {
+                continue;
+            }
+            let par_path = self.parcel_path(&sha);
+            // Hold the parcel's lock so a concurrent delete can't give back the same space twice
+            let _lock = self.lock_parcel(&sha).await;
+            // An invoice may have been created since the list above was taken, so check again now
+            // that nothing else can remove the parcel
+            let invoices = self.list_invoices().await?;
+            if referenced_parcels(&invoices, keep_yanked).contains(&sha) {
+                debug!(%sha, "Parcel was referenced during garbage collection, keeping it");
+                continue;
+            }
+            debug!(path = %par_path.display(), "Removing unreferenced parcel");
+            match tokio::fs::remove_dir_all(&par_path).await {
+                Ok(_) => {
+                    self.uncache_parcel_label(&sha).await;
+                    self.release_quota(size);
+                    removed.push(sha)
+                }
+                // Something else may have removed it in the meantime, which is fine
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => continue,
+                Err(e) => return Err(ProviderError::io_at(par_path, e)),
+            }
+        }
+        info!(total = removed.len(), "Garbage collected parcels");
+        Ok(removed)
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
+    async fn get_parcel<I>(
+        &self,
+        bindle_id: I,
+        parcel_id: &str,
+    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        self.validate_parcel(parsed_id, parcel_id).await?;
+
+        let name = self.parcel_data_path(parcel_id);
+        debug!(path = %name.display(), "Getting parcel from storage");
+        let reader = self
+            .open_parcel(parcel_id)
+            .await
+            .map_err(|e| map_io_error(e, parcel_id, &name))?;
+        let parcel_id = parcel_id.to_owned();
+        Ok::<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>, _>(Box::new(
+            FramedRead::new(reader, BytesCodec::new()).map(move |res| {
+                res.map_err(|e| map_io_error(e, &parcel_id, &name))
+                    .map(|b| b.freeze())
+            }),
+        ))
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
+    async fn get_parcel_range<I>(
+        &self,
+        bindle_id: I,
+        parcel_id: &str,
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        self.validate_parcel(parsed_id, parcel_id).await?;
+
+        let name = self.parcel_data_path(parcel_id);
+        debug!(path = %name.display(), start, ?end, "Getting parcel range from storage");
+        let mut reader = match self.retry.retry(|| File::open(&name)).await {
+            Ok(r) => r,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => {
+                // The parcel may be compressed, which can't be seeked, so read and discard the
+                // data before the range instead
+                let size = self.stored_parcel_size(parcel_id).await?;
+                let end = range_end(size, start, end)?;
+                let mut reader = self
+                    .open_parcel(parcel_id)
+                    .await
+                    .map_err(|e| map_io_error(e, parcel_id, &name))?;
+                tokio::io::copy(&mut (&mut reader).take(start), &mut tokio::io::sink())
+                    .await
+                    .map_err(|e| ProviderError::io_at(&name, e))?;
+                let parcel_id = parcel_id.to_owned();
+                return Ok(Box::new(
+                    FramedRead::new(reader.take(end - start), BytesCodec::new()).map(move |res| {
+                        res.map_err(|e| map_io_error(e, &parcel_id, &name))
+                            .map(|b| b.freeze())
+                    }),
+                ));
+            }
+            Err(e) => return Err(map_io_error(e, parcel_id, &name)),
+        };
+        let size = reader
+            .metadata()
+            .await
+            .map_err(|e| ProviderError::io_at(&name, e))?
+            .len();
+        let end = range_end(size, start, end)?;
+        reader
+            .seek(std::io::SeekFrom::Start(start))
+            .await
+            .map_err(|e| ProviderError::io_at(&name, e))?;
+        let parcel_id = parcel_id.to_owned();
+        Ok::<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>, _>(Box::new(
+            FramedRead::new(reader.take(end - start), BytesCodec::new()).map(move |res| {
+                res.map_err(|e| map_io_error(e, &parcel_id, &name))
+                    .map(|b| b.freeze())
+            }),
+        ))
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn get_parcel_size(&self, parcel_id: &str) -> Result<u64> {
+        check_parcel_id(parcel_id)?;
+        trace!(parcel_id, "Reading parcel size from disk");
+        self.stored_parcel_size(parcel_id).await
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
+    async fn parcel_exists<I>(&self, bindle_id: I, parcel_id: &str) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        self.validate_parcel(parsed_id, parcel_id).await?;
+
+        for data_path in [
+            self.parcel_data_path(parcel_id),
+            self.compressed_parcel_path(parcel_id),
+        ] {
+            debug!(path = %data_path.display(), "Checking if parcel exists in storage");
+            match tokio::fs::metadata(&data_path).await {
+                Ok(m) if m.is_file() => return Ok(true),
+                Ok(_) => (),
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound) => (),
+                Err(e) => return Err(ProviderError::io_at(data_path, e)),
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The state of the walk over the parcel directory in [`FileProvider::parcel_dirs`]
+enum ParcelWalk {
+    Start,
+    Reading(tokio::fs::ReadDir),
+    Done,
+}
+
+/// The contents of the catalog file written by [`FileProvider::write_catalog_file`]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Catalog {
+    bindle: Vec<CatalogEntry>,
+}
+
+/// A single bindle listed in the catalog file
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CatalogEntry {
+    name: String,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+/// The binary copy of an invoice written when the binary cache is enabled, along with the digest of
+/// the TOML it was made from
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BinaryInvoice<I> {
+    toml_sha256: String,
+    invoice: I,
+}
+
+/// The parts of an invoice needed for [`InvoiceStatus`]. Parcels are deserialized as
+/// `IgnoredAny` so they are counted without building their labels
+#[derive(serde::Deserialize)]
+struct InvoiceSummary {
+    yanked: Option<bool>,
+    bindle: BindleIdSummary,
+    #[serde(default)]
+    parcel: Vec<serde::de::IgnoredAny>,
+}
+
+/// Just the name and version from an invoice's `[bindle]` table
+#[derive(serde::Deserialize)]
+struct BindleIdSummary {
+    #[serde(flatten)]
+    id: Id,
+}
+
+/// Removes a newly created parcel directory when dropped, unless it has been disarmed. This is used
+/// to clean up after an upload that fails or is cancelled part way through. The directory is left
+/// alone if the parcel data exists, as a concurrent upload of the same parcel may have completed
+struct ParcelDirGuard {
+    path: PathBuf,
+    data_path: PathBuf,
+    armed: bool,
+}
+
+impl ParcelDirGuard {
+    fn new(path: PathBuf, data_path: PathBuf) -> Self {
+        ParcelDirGuard {
+            path,
+            data_path,
+            armed: true,
+        }
+    }
+
+    /// Keeps the directory in place when the guard is dropped
+    fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ParcelDirGuard {
+    fn drop(&mut self) {
+        if !self.armed || self.data_path.is_file() {
+            return;
+        }
+        debug!(path = %self.path.display(), "Removing parcel directory after failed upload");
+        // This has to be synchronous as there is no async drop, same as with `PartFile`
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            if !matches!(e.kind(), std::io::ErrorKind::NotFound) {
+                error!(error = %e, "Unable to clean up parcel directory");
+            }
+        }
+    }
+}
+
+/// Detects a media type from the start of a parcel's data. Binary formats are detected from their
+/// magic bytes. Otherwise, UTF-8 data is detected as TOML if it parses as TOML or as plain text if
+/// it does not
+pub(crate) fn sniff_media_type(data: &[u8]) -> Option<String> {
+    if let Some(kind) = infer::get(data) {
+        return Some(kind.mime_type().to_owned());
+    }
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        // The data may have been cut off in the middle of a character
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&data[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    if text.trim().is_empty() {
+        return None;
+    }
+    if toml::from_str::<toml::Value>(text).is_ok() {
+        Some("application/toml".to_owned())
+    } else {
+        Some("text/plain".to_owned())
+    }
+}
+
+/// Returns the permissions for a directory created alongside files with the given mode, which adds
+/// the execute bit for anyone who can read the files so the directory can be traversed
+#[cfg(target_family = "unix")]
+fn dir_mode(mode: u32) -> u32 {
+    mode | ((mode & 0o444) >> 2)
+}
+
+/// Converts an IO error from accessing the given path into a `ProviderError`, returning `NotFound`
+/// with the given ID if the path does not exist
+fn map_io_error(e: std::io::Error, id: &str, path: &Path) -> ProviderError {
+    if matches!(e.kind(), std::io::ErrorKind::NotFound) {
+        return ProviderError::not_found(id);
+    }
+    ProviderError::io_at(path, e)
+}
+
+/// Returns how many bytes to read from an upload with the given size limit. One byte more than the
+/// limit is read, so an upload that goes over it can be told apart from one that is exactly at it
+fn read_limit(max_size: Option<u64>) -> u64 {
+    max_size
+        .map(|limit| limit.saturating_add(1))
+        .unwrap_or(u64::MAX)
+}
+
+/// Moves a file into place by renaming it. Files can't be renamed to a different file system, such
+/// as when the staging directory is on another device, so if the rename fails the file is copied
+/// into place with [`copy_into_place`] instead
+async fn move_file(from: &Path, to: &Path, sync: bool) -> Result<()> {
+    let e = match tokio::fs::rename(from, to).await {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+    // The error for a rename across file systems differs between platforms, so any failure falls
+    // back to copying. If the rename failed for some other reason, the copy will fail as well
+    warn!(
+        error = %e,
+        from = %from.display(),
+        to = %to.display(),
+        "Unable to rename file into place, copying it instead. This is much slower than renaming it"
+    );
+    copy_into_place(from, to, sync).await
+}
+
+/// Copies a file next to its destination and renames it from there, then removes the original.
+/// That keeps the move atomic, but means all of the data is written a second time
+async fn copy_into_place(from: &Path, to: &Path, sync: bool) -> Result<()> {
+    let dir = to.parent().unwrap_or_else(|| Path::new(".")).to_owned();
+    let tmp = {
+        let tmp_dir = dir.clone();
+        tokio::task::spawn_blocking(move || {
+            tempfile::Builder::new()
+                .suffix(&format!(".{}", PART_EXTENSION))
+                .tempfile_in(tmp_dir)
+                .map(|f| f.into_temp_path())
+        })
+        .await
+        .map_err(|e| ProviderError::Other(e.to_string()))?
+        .map_err(|e| ProviderError::io_at(dir, e))?
+    };
+    // This copies the permissions of the original file along with the data
+    tokio::fs::copy(from, &tmp)
+        .await
+        .map_err(|e| ProviderError::io_at(tmp.to_path_buf(), e))?;
+    if sync {
+        let file = File::open(&tmp)
+            .await
+            .map_err(|e| ProviderError::io_at(tmp.to_path_buf(), e))?;
+        file.sync_all()
+            .await
+            .map_err(|e| ProviderError::io_at(tmp.to_path_buf(), e))?;
+    }
+    let dest = to.to_owned();
+    tokio::task::spawn_blocking(move || tmp.persist(dest).map_err(|e| e.error))
+        .await
+        .map_err(|e| ProviderError::Other(e.to_string()))?
+        .map_err(|e| ProviderError::io_at(to, e))?;
+    tokio::fs::remove_file(from)
+        .await
+        .map_err(|e| ProviderError::io_at(from, e))
+}
+
+/// Syncs the directory containing the given path to disk, so that a newly created or renamed entry
+/// is persisted. This is only supported on Unix and does nothing elsewhere
+async fn sync_parent_dir(path: &Path) -> Result<()> {
+    #[cfg(target_family = "unix")]
+    if let Some(dir) = path.parent() {
+        trace!(path = %dir.display(), "Syncing directory to disk");
+        let dir_file = File::open(dir)
+            .await
+            .map_err(|e| ProviderError::io_at(dir, e))?;
+        dir_file
+            .sync_all()
+            .await
+            .map_err(|e| ProviderError::io_at(dir, e))?;
+    }
+    #[cfg(not(target_family = "unix"))]
+    let _ = path;
+    Ok(())
+}
+
+/// A helper struct for a part file that will clean up the file on drop if it still exists. Also
+/// contains functionality for writing to the file and finalizing it (i.e moving it to the correct
+/// location)
+struct PartFile {
+    path: PathBuf,
+    final_location: PathBuf,
+    file: File,
+    /// Whether the file and its directory are synced to disk when finalized
+    sync: bool,
+}
+
+impl PartFile {
+    /// Creates a new PartFile that will eventually be located at the given `final_location`. This
+    /// will attempt to create a new part file and return an error if one already exists. The part
+    /// file is created in the given staging directory if there is one, and next to the final
+    /// location otherwise. If a mode is given, the file is created with those permissions (only
+    /// supported on Unix)
+    async fn new_in(
+        final_location: PathBuf,
+        staging_dir: Option<&Path>,
+        mode: Option<u32>,
+    ) -> Result<Self> {
+        let part = match staging_dir {
+            // Staged files are named after their destination, so two writes to the same file
+            // still conflict
+            Some(dir) => dir.join(format!(
+                "{}.{}",
+                crate::DigestAlgorithm::Sha256.digest(final_location.to_string_lossy().as_bytes()),
+                PART_EXTENSION
+            )),
+            None => {
+                let extension = match final_location.extension() {
+                    Some(s) => {
+                        let mut ext = s.to_owned();
+                        ext.push(".");
+                        ext.push(PART_EXTENSION);
+                        ext
+                    }
+                    None => OsString::from(PART_EXTENSION),
+                };
+                final_location.with_extension(extension)
+            }
+        };
+        trace!(path = %part.display(), "Checking that a write is not currently in progress");
+        // Make sure we aren't already writing
+        if tokio::fs::metadata(&part)
+            .await
+            .map(|m| m.is_file())
+            .unwrap_or(false)
+        {
+            return Err(ProviderError::WriteInProgress);
+        }
+        #[cfg(target_family = "windows")]
+        let file = match OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .read(true)
+            // 4 is the value for FILE_SHARE_DELETE so we don't have to import the winapi constants.
+            // We need this so we can delete the file on drop. See
+            // https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-createfilea
+            .share_mode(4)
+            .open(&part)
+            .await
+        {
+            Ok(f) => f,
+            // This is the error code for a sharing violation, which will happen if there is a write
+            // in progress
+            Err(e) if e.raw_os_error().unwrap_or_default() == 32 => {
+                return Err(ProviderError::WriteInProgress)
+            }
+            Err(e) => {
+                return Err(ProviderError::io_at(part, e));
+            }
+        };
+        #[cfg(target_family = "windows")]
+        let _ = mode;
+        #[cfg(target_family = "unix")]
+        let file = {
+            use std::os::unix::fs::PermissionsExt;
+            let mut options = OpenOptions::new();
+            options.create_new(true).write(true).read(true);
+            if let Some(mode) = mode {
+                options.mode(mode);
+            }
+            let file = options
+                .open(&part)
+                .await
+                .map_err(|e| ProviderError::io_at(&part, e))?;
+            // Set the permissions explicitly, as the mode given at creation is masked by the umask
+            if let Some(mode) = mode {
+                file.set_permissions(std::fs::Permissions::from_mode(mode))
+                    .await
+                    .map_err(|e| ProviderError::io_at(&part, e))?;
+            }
+            file
+        };
+        Ok(PartFile {
+            path: part,
+            final_location,
+            file,
+            sync: false,
+        })
+    }
+
+    /// Writes the invoice to the part file as TOML. If `compress` is true, the TOML is gzip
+    /// compressed as it is written
+    async fn write_invoice(&mut self, inv: &crate::Invoice, compress: bool) -> Result<()> {
+        debug!(
+            path = %self.path.display(),
+            compress,
+            "Storing invoice in part file"
+        );
+
+        trace!("Encoding invoice to TOML");
+        // Encode the invoice into a TOML object
+        let data = toml::to_vec(inv)?;
+        let res = if compress {
+            tokio::io::copy(&mut GzipEncoder::new(data.as_slice()), &mut self.file)
+                .await
+                .map(|_| ())
+        } else {
+            self.file.write_all(data.as_slice()).await
+        };
+        res.map_err(|e| ProviderError::io_at(&self.path, e))
+    }
+
+    /// Writes the parcel data to the part file, verifying it against the label. If `compress` is
+    /// true, the data is gzip compressed as it is written, but is still verified against the
+    /// uncompressed digest and size. If `max_size` is set, reading stops as soon as the data goes
+    /// over it and a `TooLarge` error is returned
+    async fn write_parcel<R, B>(
+        &mut self,
+        data: R,
+        label: &crate::Label,
+        compress: bool,
+        max_size: Option<u64>,
+    ) -> Result<()>
+    where
+        R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
+        B: bytes::Buf + Send,
+    {
+        let expected_length = label.size;
+        // Create the part file to indicate that we are currently writing
+        debug!(
+            path = %self.path.display(),
+            parcel_id = %label.sha256,
+            "Storing parcel data in part file"
+        );
+        trace!("Copying data to open file");
+        // Hash the data as it is written so verifying it doesn't need another pass over the file
+        let mut reader = HashingReader::with_algorithm(
+            StreamReader::new(
+                data.map(|res| res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
+            ),
+            label.algorithm(),
+        );
+        let mut limited = (&mut reader).take(read_limit(max_size));
+        let res = if compress {
+            let mut encoder = GzipEncoder::new(BufReader::new(&mut limited));
+            tokio::io::copy(&mut encoder, &mut self.file)
+                .instrument(tracing::trace_span!("parcel_data_write", compress))
+                .await
+        } else {
+            tokio::io::copy(&mut limited, &mut self.file)
+                .instrument(tracing::trace_span!("parcel_data_write", compress))
+                .await
+        };
+        res.map_err(|e| ProviderError::io_at(&self.path, e))?;
+        let written = reader.bytes_read();
+        if let Some(limit) = max_size.filter(|limit| written > *limit) {
+            debug!(limit, "Parcel data is over the size limit");
+            return Err(ProviderError::TooLarge { limit });
+        }
+
+        // Make sure the right amount of data was sent
+        trace!(bytes_written = written, "Wrote data to part file");
+        if written != expected_length {
+            return Err(ProviderError::SizeMismatch {
+                expected: expected_length,
+                actual: written,
+            });
+        }
+        self.file
+            .flush()
+            .await
+            .map_err(|e| ProviderError::io_at(&self.path, e))?;
+        trace!("Validating data for parcel");
+        let actual = reader.finalize();
+        if actual != label.sha256 {
+            return Err(ProviderError::DigestMismatch {
+                expected: label.sha256.clone(),
+                actual,
+            });
+        }
+        trace!("Digest validated");
+        Ok(())
+    }
+
+    /// Moves the file to the configured final location, consuming the part file
+    async fn finalize(mut self) -> Result<()> {
+        debug!(
+            renamed_path = %self.final_location.display(),
+            "Renaming part file for parcel"
+        );
+
+        // Close the file handle to avoid any problems with unfinished IO operations
+        self.file
+            .shutdown()
+            .await
+            .map_err(|e| ProviderError::io_at(&self.path, e))?;
+        if self.sync {
+            trace!(path = %self.path.display(), "Syncing part file to disk");
+            self.file
+                .sync_all()
+                .await
+                .map_err(|e| ProviderError::io_at(&self.path, e))?;
+        }
+
+        move_file(&self.path, &self.final_location, self.sync).await?;
+        if self.sync {
+            sync_parent_dir(&self.final_location).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        // Attempt to delete the file, logging an error if the delete failed
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            // If it is any other error besides NotFound, log the error
+            if !matches!(e.kind(), std::io::ErrorKind::NotFound) {
+                error!(error = %e, "Unable to clean up part file, this could lead to conflicts");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::verification::NoopVerified;
+    use crate::{testing, DigestAlgorithm, NoopSigned};
+    use tempfile::tempdir;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_should_generate_paths() {
+        let f = FileProvider::new("test", crate::search::StrictEngine::default()).await;
+        assert_eq!(PathBuf::from("test/invoices/123"), f.invoice_path("123"));
+        assert_eq!(
+            PathBuf::from("test/invoices/123/invoice.toml"),
+            f.invoice_toml_path("123")
+        );
+        assert_eq!(PathBuf::from("test/parcels/123"), f.parcel_path("123"));
+        assert_eq!(
+            PathBuf::from("test/parcels/123/parcel.dat"),
+            f.parcel_data_path("123")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_use_custom_layout() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .invoice_directory("inv")
+        .parcel_directory("par")
+        .invoice_file("bindle.toml")
+        .parcel_file("data.bin")
+        .build()
+        .await;
+        assert_eq!(
+            root.path().join("inv/123/bindle.toml"),
+            store.invoice_toml_path("123")
+        );
+        assert_eq!(
+            root.path().join("par/123/data.bin"),
+            store.parcel_data_path("123")
+        );
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created");
+
+        assert!(store
+            .invoice_toml_path(&scaffold.invoice.canonical_name())
+            .is_file());
+        assert!(store.parcel_data_path(&parcel.sha).is_file());
+        assert!(!root.path().join(INVOICE_DIRECTORY).exists());
+        assert!(!root.path().join(PARCEL_DIRECTORY).exists());
+
+        // A new provider with the same layout should find the stored data
+        let reopened = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .invoice_directory("inv")
+        .parcel_directory("par")
+        .invoice_file("bindle.toml")
+        .parcel_file("data.bin")
+        .build()
+        .await;
+        reopened
+            .get_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Invoice should be found with the custom layout");
+        assert!(reopened
+            .parcel_exists(&scaffold.invoice.bindle.id, &parcel.sha)
+            .await
+            .expect("Checking parcel existence should succeed"));
+    }
+
+    #[tokio::test]
+    async fn test_should_use_naming_strategies() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = &scaffold.invoice.bindle.id;
+        let strategies = [
+            (false, scaffold.invoice.canonical_name()),
+            (true, "enterprise.com/warpcore/1.0.0".to_owned()),
+        ];
+        for (hierarchical, expected_name) in strategies {
+            let configure = |builder: FileProviderBuilder<crate::search::StrictEngine>| {
+                if hierarchical {
+                    builder.naming_strategy(HierarchicalNaming)
+                } else {
+                    builder.naming_strategy(Sha256Naming)
+                }
+            };
+            let root = tempdir().unwrap();
+            let store = configure(FileProviderBuilder::new(
+                root.path(),
+                crate::search::StrictEngine::default(),
+            ))
+            .build()
+            .await;
+            store
+                .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+                .await
+                .expect("Invoice should be created");
+            assert!(
+                root.path()
+                    .join(INVOICE_DIRECTORY)
+                    .join(&expected_name)
+                    .join(INVOICE_TOML)
+                    .is_file(),
+                "Invoice should be stored under {}",
+                expected_name
+            );
+
+            store
+                .get_invoice(id)
+                .await
+                .expect("Invoice should be readable");
+            assert!(store.invoice_exists(id).await.unwrap());
+            assert_eq!(1, store.list_invoices().await.unwrap().len());
+            assert_eq!(1, store.query_invoices("warpcore").await.unwrap().len());
+            store
+                .yank_invoice(id)
+                .await
+                .expect("Invoice should be yanked");
+
+            // A fresh provider should find the invoice again when warming its index
+            let reopened = configure(FileProviderBuilder::new(
+                root.path(),
+                crate::search::StrictEngine::default(),
+            ))
+            .build()
+            .await;
+            assert_eq!(1, reopened.rebuild_index().await.unwrap());
+            assert!(matches!(
+                reopened.get_invoice(id).await,
+                Err(ProviderError::Yanked)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_not_serve_colliding_invoice() {
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .naming_strategy(HierarchicalNaming)
+            .build()
+            .await;
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.bindle.id = "enterprise.com/warp_core/1.0.0".parse().unwrap();
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        // A different bindle that sanitizes to the same path must not be served the stored invoice
+        let colliding: Id = "enterprise.com/warp:core/1.0.0".parse().unwrap();
+        assert_eq!(
+            store.invoice_name(&inv.bindle.id),
+            store.invoice_name(&colliding)
+        );
+        assert!(matches!(
+            store.get_yanked_invoice(&colliding).await,
+            Err(ProviderError::NotFound { .. })
+        ));
+        assert!(matches!(
+            store.get_invoice_with_etag(&colliding).await,
+            Err(ProviderError::NotFound { .. })
+        ));
+        assert!(matches!(
+            store.get_invoice_raw(&colliding).await,
+            Err(ProviderError::NotFound { .. })
+        ));
+        assert!(!store.invoice_status(&colliding).await.unwrap().exists);
+        assert!(matches!(
+            store.yank_invoice(&colliding).await,
+            Err(ProviderError::NotFound { .. })
+        ));
+        store
+            .get_invoice(&inv.bindle.id)
+            .await
+            .expect("The stored invoice should still be served for its own ID");
+    }
+
+    #[tokio::test]
+    async fn test_should_create_yank_invoice() {
+        // Create a temporary directory
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let inv_name = scaffold.invoice.canonical_name();
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        // Create an invoice
+        let (_, missing) = store.create_invoice(signed).await.unwrap();
+        assert_eq!(1, missing.len());
+
+        // Out-of-band read the invoice
+        assert!(store.invoice_toml_path(&inv_name).exists());
+
+        // Yank the invoice
+        store
+            .yank_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .unwrap();
+
+        // Make sure the invoice is yanked
+        let inv2 = store
+            .get_yanked_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .unwrap();
+        assert!(inv2.yanked.unwrap_or(false));
+
+        // Sanity check that this produces an error
+        assert!(store.get_invoice(scaffold.invoice.bindle.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_should_get_invoice_with_complex_id() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v1").await;
+        scaffold.invoice.bindle.id = "example.com/foo/bar/1.2.3-beta.1+build.5".parse().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+
+        let inv = store
+            .get_invoice("example.com/foo/bar/1.2.3-beta.1+build.5")
+            .await
+            .expect("Should be able to fetch invoice with a multi-segment name");
+        assert_eq!(inv.bindle.id.name(), "example.com/foo/bar");
+        assert_eq!(inv.bindle.id.version_string(), "1.2.3-beta.1+build.5");
+
+        let err = store
+            .get_invoice("no-version")
+            .await
+            .expect_err("An ID without a version should fail");
+        assert!(
+            matches!(err, ProviderError::InvalidId(_)),
+            "Error should be of type InvalidId"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_create_invoices_in_bulk() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let v1 = testing::Scaffold::load("valid_v1").await.invoice;
+        let v2 = testing::Scaffold::load("valid_v2").await.invoice;
+        let mut yanked = v1.clone();
+        yanked.bindle.id = "enterprise.com/yanked/1.0.0".parse().unwrap();
+        yanked.yanked = Some(true);
+        store
+            .create_invoice(NoopSigned(NoopVerified(v1.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let invoices = vec![v1.clone(), v2.clone(), yanked.clone()];
+        let results = store
+            .create_invoices(&invoices, |inv| Ok(NoopSigned(NoopVerified(inv))))
+            .await;
+        assert_eq!(3, results.len());
+
+        assert_eq!(v1.bindle.id.to_string(), results[0].0);
+        assert!(
+            matches!(results[0].1, Err(ProviderError::Exists)),
+            "Existing invoice should report Exists, got {:?}",
+            results[0].1
+        );
+        assert_eq!(v2.bindle.id.to_string(), results[1].0);
+        assert_eq!(
+            2,
+            results[1]
+                .1
+                .as_ref()
+                .expect("New invoice should be created")
+                .len(),
+            "Both parcels should be missing"
+        );
+        assert_eq!("enterprise.com/yanked/1.0.0", results[2].0);
+        assert!(matches!(results[2].1, Err(ProviderError::CreateYanked)));
+
+        store
+            .get_invoice(&v2.bindle.id)
+            .await
+            .expect("Invoice created in bulk should be stored");
+    }
+
+    #[tokio::test]
+    async fn test_should_create_invoice_from_reader() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let mut reader = std::io::Cursor::new(toml::to_vec(&scaffold.invoice).unwrap());
+        let (inv, missing) = store
+            .create_invoice_from_reader(&mut reader, |inv| Ok(NoopSigned(NoopVerified(inv))))
+            .await
+            .expect("Invoice should be created from reader");
+        assert_eq!(inv.bindle.id, scaffold.invoice.bindle.id);
+        assert_eq!(1, missing.len());
+        assert!(store.invoice_toml_path(&inv.canonical_name()).exists());
+
+        // Yanked invoices should still be rejected
+        let mut yanked = scaffold.invoice.clone();
+        yanked.bindle.id = "yanked/1.0.0".parse().unwrap();
+        yanked.yanked = Some(true);
+        let mut reader = std::io::Cursor::new(toml::to_vec(&yanked).unwrap());
+        let err = store
+            .create_invoice_from_reader(&mut reader, |inv| Ok(NoopSigned(NoopVerified(inv))))
+            .await
+            .expect_err("Yanked invoice should be rejected");
+        assert!(
+            matches!(err, ProviderError::CreateYanked),
+            "Error should be of type CreateYanked"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_not_yank_missing_invoice() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = &scaffold.invoice.bindle.id;
+
+        assert!(
+            matches!(store.yank_invoice(id).await, Err(ProviderError::NotFound { id: not_found }) if not_found == id.to_string()),
+            "Yanking a nonexistent invoice should fail"
+        );
+        assert!(
+            !store
+                .invoice_path(&scaffold.invoice.canonical_name())
+                .exists(),
+            "Yanking a nonexistent invoice should not create a stub"
+        );
+        assert!(!store.invoice_status(id).await.unwrap().exists);
+    }
+
+    #[tokio::test]
+    async fn test_should_unyank_invoice() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = &scaffold.invoice.bindle.id;
+
+        assert!(
+            matches!(store.unyank_invoice(id).await, Err(ProviderError::NotFound { id: not_found }) if not_found == id.to_string()),
+            "Unyanking a nonexistent invoice should not create it"
+        );
+        assert!(!store
+            .invoice_toml_path(&scaffold.invoice.canonical_name())
+            .exists());
+
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        store
+            .yank_invoice(id)
+            .await
+            .expect("Should be able to yank");
+        assert!(matches!(
+            store.get_invoice(id).await,
+            Err(ProviderError::Yanked)
+        ));
+
+        store
+            .unyank_invoice(id)
+            .await
+            .expect("Should be able to unyank");
+        let inv = store
+            .get_invoice(id)
+            .await
+            .expect("Unyanked invoice should be returned");
+        assert_eq!(Some(false), inv.yanked);
+    }
+
+    #[tokio::test]
+    async fn test_should_cache_invoices() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        for (cache_size, cached) in [(CACHE_SIZE, true), (0, false)] {
+            let root = tempdir().unwrap();
+            let store =
+                FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+                    .cache_size(cache_size)
+                    .build()
+                    .await;
+            store
+                .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+                .await
+                .expect("Invoice should be created");
+            store
+                .get_invoice(&scaffold.invoice.bindle.id)
+                .await
+                .expect("Invoice should be readable");
+
+            // Remove the file behind the provider's back so only a cached copy can be returned
+            tokio::fs::remove_file(store.invoice_toml_path(&scaffold.invoice.canonical_name()))
+                .await
+                .expect("Invoice file should be removed");
+            let res = store.get_invoice(&scaffold.invoice.bindle.id).await;
+            if cached {
+                res.expect("Second read should hit the cache");
+            } else {
+                assert!(
+                    matches!(res, Err(ProviderError::NotFound { .. })),
+                    "Reads should go to disk when caching is disabled"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_write_catalog_file() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let v1 = testing::Scaffold::load("valid_v1").await.invoice;
+        let v2 = testing::Scaffold::load("valid_v2").await.invoice;
+        let mut yanked = v1.clone();
+        yanked.bindle.id = "enterprise.com/warpcore/0.1.0".parse().unwrap();
+        for inv in [&v1, &v2, &yanked] {
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+                .await
+                .expect("Invoice should be created");
+        }
+        store
+            .yank_invoice(&yanked.bindle.id)
+            .await
+            .expect("Invoice should be yanked");
+
+        let path = store
+            .write_catalog_file()
+            .await
+            .expect("Catalog should be written");
+        assert_eq!(root.path().join(CATALOG_FILE), path);
+        let catalog: Catalog = toml::from_slice(&tokio::fs::read(&path).await.unwrap())
+            .expect("Catalog should be valid TOML");
+        let mut expected = vec![&v1, &v2];
+        expected.sort_by(|a, b| {
+            a.bindle
+                .id
+                .name()
+                .cmp(b.bindle.id.name())
+                .then_with(|| a.bindle.id.version().cmp(b.bindle.id.version()))
+        });
+        assert_eq!(
+            expected
+                .iter()
+                .map(|inv| (
+                    inv.bindle.id.name().to_owned(),
+                    inv.bindle.id.version_string(),
+                    inv.bindle.description.clone()
+                ))
+                .collect::<Vec<_>>(),
+            catalog
+                .bindle
+                .into_iter()
+                .map(|e| (e.name, e.version, e.description))
+                .collect::<Vec<_>>(),
+            "Catalog should list only the bindles that aren't yanked"
+        );
+
+        // The catalog is only updated when it is written again
+        store
+            .yank_invoice(&v1.bindle.id)
+            .await
+            .expect("Invoice should be yanked");
+        let catalog: Catalog = toml::from_slice(&tokio::fs::read(&path).await.unwrap()).unwrap();
+        assert_eq!(2, catalog.bindle.len());
+        store.write_catalog_file().await.unwrap();
+        let catalog: Catalog = toml::from_slice(&tokio::fs::read(&path).await.unwrap()).unwrap();
+        assert_eq!(1, catalog.bindle.len());
+    }
+
+    #[tokio::test]
+    async fn test_should_warm_invoice_cache() {
+        let invoices = vec![
+            testing::Scaffold::load("valid_v1").await.invoice,
+            testing::Scaffold::load("valid_v2").await.invoice,
+        ];
+        let ids: Vec<String> = invoices
+            .iter()
+            .map(|inv| inv.bindle.id.to_string())
+            .chain(std::iter::once("enterprise.com/missing/1.0.0".to_owned()))
+            .collect();
+        for (cache_size, expected) in [(CACHE_SIZE, 2), (0, 0)] {
+            let root = tempdir().unwrap();
+            let store =
+                FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+                    .cache_size(cache_size)
+                    .build()
+                    .await;
+            for inv in invoices.iter() {
+                store
+                    .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+                    .await
+                    .expect("Invoice should be created");
+            }
+
+            assert_eq!(
+                expected,
+                store
+                    .warm_cache(&ids)
+                    .await
+                    .expect("Cache should be warmed"),
+                "Only existing invoices should be warmed when caching is enabled"
+            );
+            if cache_size == 0 {
+                continue;
+            }
+
+            // Remove the files behind the provider's back so only a cached copy can be returned
+            for inv in invoices.iter() {
+                tokio::fs::remove_file(store.invoice_toml_path(&inv.canonical_name()))
+                    .await
+                    .expect("Invoice file should be removed");
+                let cached = store
+                    .get_invoice(&inv.bindle.id)
+                    .await
+                    .expect("Warmed invoice should be returned from the cache");
+                assert_eq!(inv.bindle.id, cached.bindle.id);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_yanked_invoice() {
+        // Create a temporary directory
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v1").await;
+        scaffold.invoice.yanked = Some(true);
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        assert!(store.create_invoice(signed).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_should_resolve_parcels_for_active_groups() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v2").await;
+        scaffold.invoice.group = Some(vec![crate::Group {
+            name: "v2".to_owned(),
+            required: None,
+            satisfied_by: None,
+        }]);
+        scaffold.invoice.parcel.as_mut().unwrap()[1].conditions = Some(crate::Condition {
+            member_of: Some(vec!["v2".to_owned()]),
+            requires: None,
+        });
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+
+        let labels = store
+            .resolve_parcels(&scaffold.invoice.bindle.id, &[])
+            .await
+            .expect("Should be able to resolve parcels");
+        assert_eq!(1, labels.len(), "Only the global parcel should be resolved");
+        assert_eq!("isolinear_chip.txt", labels[0].name);
+
+        let labels = store
+            .resolve_parcels(&scaffold.invoice.bindle.id, &["v2".to_owned()])
+            .await
+            .expect("Should be able to resolve parcels");
+        assert_eq!(2, labels.len(), "Group member should be resolved");
+    }
+
+    #[tokio::test]
+    async fn test_should_check_invoice_invariants() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+        store
+            .get_invoice_checked(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Valid invoice should pass checks");
+
+        // Invalid invoices are rejected on create, so write one straight to disk
+        scaffold.invoice.bindle.id = "invalid/1.0.0".parse().unwrap();
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+        scaffold.invoice.parcel.as_mut().unwrap()[0]
+            .label
+            .media_type = String::new();
+        let name = store.invoice_name(&scaffold.invoice.bindle.id);
+        std::fs::write(
+            store.invoice_toml_path(&name),
+            toml::to_vec(&scaffold.invoice).unwrap(),
+        )
+        .unwrap();
+        let err = store
+            .get_invoice_checked("invalid/1.0.0")
+            .await
+            .expect_err("Invalid invoice should fail checks");
+        match err {
+            ProviderError::Invalid(errors) => {
+                assert_eq!(errors, vec![crate::ValidationError::EmptyMediaType(0)])
+            }
+            e => panic!("Expected Invalid error, got {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_parcel_path_traversal() {
+        let root = tempdir().unwrap();
+        // The provider is rooted in a subdirectory so a successful escape would land in `root`
+        let store_root = root.path().join("store");
+        let store = FileProvider::new(&store_root, crate::search::StrictEngine::default()).await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = &scaffold.invoice.bindle.id;
+        // A parcel that would be found if traversal worked
+        let target = root.path().join("parcels").join("escaped");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join(PARCEL_DAT), b"escaped").unwrap();
+
+        let assert_invalid = |res: Result<()>, payload: &str| {
+            assert!(
+                matches!(res, Err(ProviderError::InvalidId(_))),
+                "Expected InvalidId for {}, got {:?}",
+                payload,
+                res
+            )
+        };
+        for payload in [
+            "../../parcels/escaped",
+            "..",
+            "..\\escaped",
+            "sha/../..",
+            "/etc/passwd",
+            "",
+        ] {
+            assert_invalid(store.get_parcel_size(payload).await.map(|_| ()), payload);
+            assert_invalid(store.get_parcel(id, payload).await.map(|_| ()), payload);
+            assert_invalid(store.parcel_exists(id, payload).await.map(|_| ()), payload);
+            assert_invalid(store.delete_parcel(payload).await, payload);
+            assert_invalid(
+                store.create_parcel_chunk(payload, 0, b"data").await,
+                payload,
+            );
+            assert_invalid(
+                store
+                    .create_parcel(
+                        id,
+                        payload,
+                        FramedRead::new(std::io::Cursor::new(b"data".to_vec()), BytesCodec::new()),
+                    )
+                    .await,
+                payload,
+            );
+        }
+        assert!(
+            target.join(PARCEL_DAT).exists(),
+            "Escaped parcel should be untouched"
+        );
+        assert!(
+            !store_root.exists(),
+            "Nothing should be written for rejected IDs"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_malformed_parcel_digests() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let parcels = scaffold.invoice.parcel.as_mut().unwrap();
+        parcels[1].label.sha256 = "../not-a-sha".to_owned();
+        parcels.push(parcels[0].clone());
+        parcels[2].label.sha256 = parcels[0].label.sha256.to_uppercase();
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        match store.create_invoice(signed).await {
+            Err(ProviderError::Invalid(errors)) => assert_eq!(
+                errors,
+                vec![
+                    crate::ValidationError::InvalidSha(1),
+                    crate::ValidationError::InvalidSha(2)
+                ]
+            ),
+            res => panic!("Expected Invalid error, got {:?}", res),
+        }
+        assert!(
+            tokio::fs::metadata(store.invoice_path("")).await.is_err(),
+            "Nothing should be written for an invalid invoice"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_duplicate_parcel_names() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .reject_duplicate_parcel_names(true)
+        .build()
+        .await;
+
+        let parcels = scaffold.invoice.parcel.as_mut().unwrap();
+        assert_ne!(parcels[0].label.sha256, parcels[1].label.sha256);
+        parcels[1].label.name = parcels[0].label.name.clone();
+        let name = parcels[0].label.name.clone();
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        match store.create_invoice(signed).await {
+            Err(ProviderError::Invalid(errors)) => assert_eq!(
+                errors,
+                vec![crate::ValidationError::DuplicateParcelName(name)]
+            ),
+            res => panic!("Expected Invalid error, got {:?}", res),
+        }
+
+        // Duplicate names are only rejected when configured to
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Duplicate names should be allowed by default");
+    }
+
+    #[tokio::test]
+    async fn test_should_enforce_invoice_policy() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .policy(crate::InvoicePolicy {
+            allowed_media_types: Some(vec!["application/octet-stream".to_owned()]),
+            ..Default::default()
+        })
+        .build()
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        match store.create_invoice(signed).await {
+            Err(ProviderError::PolicyViolation(violations)) => assert_eq!(
+                violations,
+                vec![crate::PolicyViolation::DisallowedMediaType(0)]
+            ),
+            res => panic!("Expected PolicyViolation error, got {:?}", res),
+        }
+        assert!(
+            store
+                .get_yanked_invoice(&scaffold.invoice.bindle.id)
+                .await
+                .is_err(),
+            "Invoice breaking the policy should not be stored"
+        );
+
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .policy(crate::InvoicePolicy {
+            required_fields: vec![
+                crate::RequiredField::Description,
+                crate::RequiredField::Authors,
+            ],
+            allowed_media_types: Some(vec!["text/plain".to_owned()]),
+            max_parcels: Some(1),
+        })
+        .build()
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice meeting the policy should be created");
+    }
+
+    #[tokio::test]
+    async fn test_should_enforce_invoice_policy_on_update() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .policy(crate::InvoicePolicy {
+            required_fields: vec![crate::RequiredField::Description],
+            allowed_media_types: Some(vec!["text/plain".to_owned()]),
+            max_parcels: Some(1),
+        })
+        .build()
+        .await;
+
+        let mut parcels = scaffold.invoice.parcel.clone().unwrap();
+        for p in parcels.iter_mut() {
+            p.label.media_type = "text/plain".to_owned();
+        }
+        let mut inv = scaffold.invoice.clone();
+        inv.signature = None;
+        inv.bindle.description = Some("A bindle with a description".to_owned());
+        inv.parcel = Some(vec![parcels.remove(0)]);
+        let id = inv.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv)))
+            .await
+            .expect("Invoice meeting the policy should be created");
+
+        // Going past max_parcels
+        match store.add_parcels_to_invoice(&id, parcels.clone()).await {
+            Err(ProviderError::PolicyViolation(violations)) => assert_eq!(
+                violations,
+                vec![crate::PolicyViolation::TooManyParcels { count: 2, max: 1 }]
+            ),
+            res => panic!("Expected PolicyViolation error, got {:?}", res),
+        }
+
+        // Adding a disallowed media type, with the parcel limit lifted
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .policy(crate::InvoicePolicy {
+            required_fields: vec![crate::RequiredField::Description],
+            allowed_media_types: Some(vec!["text/plain".to_owned()]),
+            max_parcels: None,
+        })
+        .build()
+        .await;
+        parcels[0].label.media_type = "image/png".to_owned();
+        match store.add_parcels_to_invoice(&id, parcels).await {
+            Err(ProviderError::PolicyViolation(violations)) => assert_eq!(
+                violations,
+                vec![crate::PolicyViolation::DisallowedMediaType(1)]
+            ),
+            res => panic!("Expected PolicyViolation error, got {:?}", res),
+        }
+
+        // Clearing a required field
+        let res = store
+            .with_invoice_mut(&id, |inv| {
+                inv.bindle.description = None;
+                async { Ok(()) }
+            })
+            .await;
+        match res {
+            Err(ProviderError::PolicyViolation(violations)) => {
+                assert_eq!(violations, vec![crate::PolicyViolation::MissingDescription])
+            }
+            res => panic!("Expected PolicyViolation error, got {:?}", res),
+        }
+
+        let stored = store.get_invoice(&id).await.unwrap();
+        assert_eq!(1, stored.parcel.unwrap().len());
+        assert!(
+            stored.bindle.description.is_some(),
+            "Rejected updates should not be written"
+        );
+
+        // Yanking doesn't change anything the policy checks, so is always allowed
+        store
+            .yank_invoice(&id)
+            .await
+            .expect("Should be able to yank");
+    }
+
+    #[tokio::test]
+    async fn test_plan_should_validate_like_create() {
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .reject_duplicate_parcel_names(true)
+        .build()
+        .await;
+
+        let mut scaffold = testing::Scaffold::load("valid_v2").await;
+        scaffold.invoice.bindle_version = "99.0.0".to_owned();
+        assert!(matches!(
+            store.plan_invoice(&scaffold.invoice).await,
+            Err(ProviderError::UnsupportedVersion(v)) if v == "99.0.0"
+        ));
+
+        scaffold.invoice.bindle_version = crate::BINDLE_VERSION_1.to_owned();
+        let parcels = scaffold.invoice.parcel.as_mut().unwrap();
+        parcels[1].label.name = parcels[0].label.name.clone();
+        assert!(matches!(
+            store.plan_invoice(&scaffold.invoice).await,
+            Err(ProviderError::Invalid(errors))
+                if matches!(errors[..], [crate::ValidationError::DuplicateParcelName(_)])
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_enforce_invoice_policy_when_planning() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .policy(crate::InvoicePolicy {
+            allowed_media_types: Some(vec!["application/octet-stream".to_owned()]),
+            ..Default::default()
+        })
+        .build()
+        .await;
+
+        match store.plan_invoice(&scaffold.invoice).await {
+            Err(ProviderError::PolicyViolation(violations)) => assert_eq!(
+                violations,
+                vec![crate::PolicyViolation::DisallowedMediaType(0)]
+            ),
+            res => panic!("Expected PolicyViolation error, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_normalize_media_types() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        scaffold.invoice.parcel.as_mut().unwrap()[0]
+            .label
+            .media_type = "not a type".to_owned();
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        match store.create_invoice(signed).await {
+            Err(ProviderError::Invalid(errors)) => {
+                assert_eq!(errors, vec![crate::ValidationError::InvalidMediaType(0)])
+            }
+            res => panic!("Expected Invalid error, got {:?}", res),
+        }
+
+        scaffold.invoice.parcel.as_mut().unwrap()[0]
+            .label
+            .media_type = "Text/Plain".to_owned();
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        let (inv, _) = store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+        assert_eq!(
+            "text/plain",
+            inv.parcel.as_ref().unwrap()[0].label.media_type
+        );
+
+        let inv = store
+            .get_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Invoice should be stored");
+        assert_eq!(
+            "text/plain",
+            inv.parcel.as_ref().unwrap()[0].label.media_type,
+            "Normalized media type should be stored"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_unsupported_version() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v1").await;
+        scaffold.invoice.bindle_version = "99.0.0".to_owned();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        let err = store
+            .create_invoice(signed)
+            .await
+            .expect_err("Invoice with an unsupported version should be rejected");
+        assert!(
+            matches!(err, ProviderError::UnsupportedVersion(v) if v == "99.0.0"),
+            "Error should be of type UnsupportedVersion"
+        );
+        assert!(
+            !store
+                .invoice_path(&scaffold.invoice.canonical_name())
+                .exists(),
+            "No invoice directory should be created"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_write_read_parcel() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+        let root = tempdir().expect("create tempdir");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        // Create the invoice so we can create a parcel
+        store
+            .create_invoice(signed)
+            .await
+            .expect("should be able to create invoice");
+
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("create parcel");
+
+        // Now make sure the parcels reads as existing
+        assert!(
+            store
+                .parcel_exists(&scaffold.invoice.bindle.id, &parcel.sha)
+                .await
+                .expect("Shouldn't get an error while checking for parcel existence"),
+            "Parcel should be reported as existing"
+        );
+
+        // Attempt to read the parcel from the store
+        let mut data = Vec::new();
+        let stream = store
+            .get_parcel(&scaffold.invoice.bindle.id, &parcel.sha)
+            .await
+            .expect("load parcel data");
+        let mut reader = StreamReader::new(
+            stream.map(|res| res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
+        );
+        reader
+            .read_to_end(&mut data)
+            .await
+            .expect("read file into string");
+        assert_eq!(data, parcel.data);
+    }
+
+    #[tokio::test]
+    async fn test_should_stream_bindle() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let root = tempdir().expect("create tempdir");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = scaffold.invoice.bindle.id.clone();
+
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("should be able to create invoice");
+        for parcel in scaffold.parcel_files.values() {
+            store
+                .create_parcel(
+                    &id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("create parcel");
+        }
+
+        let items: Vec<_> = futures::StreamExt::collect(store.stream_bindle(id.clone())).await;
+        assert_eq!(
+            scaffold.invoice.parcel.as_ref().unwrap().len(),
+            items.len(),
+            "Should get one item per parcel"
+        );
+
+        let (label, mut reader) = items
+            .into_iter()
+            .next()
+            .unwrap()
+            .expect("Shouldn't get an error opening a parcel");
+        let expected = scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == label.sha256)
+            .expect("Label should match a scaffold parcel");
+        let mut buf = vec![0; 16];
+        let n = reader.read(&mut buf).await.expect("Should be able to read");
+        assert!(n > 0, "Should read some data");
+        assert_eq!(&expected.data[..n], &buf[..n]);
+
+        // A yanked bindle should only yield an error
+        store
+            .yank_invoice(&id)
+            .await
+            .expect("should be able to yank");
+        let items: Vec<_> = futures::StreamExt::collect(store.stream_bindle(id)).await;
+        assert_eq!(1, items.len());
+        assert!(matches!(items[0], Err(ProviderError::Yanked)));
+    }
+
+    #[tokio::test]
+    async fn test_should_write_read_parcel_with_each_algorithm() {
+        for algorithm in [DigestAlgorithm::Sha256, DigestAlgorithm::Sha512] {
+            let root = tempdir().expect("create tempdir");
+            let mut scaffold = testing::Scaffold::load("valid_v1").await;
+            let store = FileProvider::new(
+                root.path().to_owned(),
+                crate::search::StrictEngine::default(),
+            )
+            .await;
+
+            let data = b"hello from a parcel".to_vec();
+            let digest = algorithm.digest(&data);
+            scaffold.invoice.parcel = Some(vec![crate::Parcel {
+                label: crate::Label {
+                    sha256: digest.clone(),
+                    digest_algorithm: Some(algorithm),
+                    size: data.len() as u64,
+                    ..crate::Label::new("hello.txt".to_owned(), String::new())
+                },
+                conditions: None,
+            }]);
+
+            let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+            store
+                .create_invoice(signed)
+                .await
+                .expect("should be able to create invoice");
+            store
+                .create_parcel(
+                    &scaffold.invoice.bindle.id,
+                    &digest,
+                    FramedRead::new(std::io::Cursor::new(data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("create parcel");
+
+            let stream = store
+                .get_parcel(&scaffold.invoice.bindle.id, &digest)
+                .await
+                .expect("load parcel data");
+            let mut read = Vec::new();
+            StreamReader::new(
+                stream
+                    .map(|res| res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
+            )
+            .read_to_end(&mut read)
+            .await
+            .expect("read parcel data");
+            assert_eq!(read, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_read_parcel_range() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created");
+
+        let read_range = |start, end| {
+            let store = store.clone();
+            let id = scaffold.invoice.bindle.id.clone();
+            let sha = parcel.sha.clone();
+            async move {
+                let mut stream = store.get_parcel_range(id, &sha, start, end).await?;
+                let mut data = Vec::new();
+                while let Some(chunk) = stream.next().await {
+                    data.extend_from_slice(&chunk?);
+                }
+                Ok::<_, ProviderError>(data)
+            }
+        };
+
+        assert_eq!(
+            parcel.data[2..4],
+            read_range(2, Some(4)).await.expect("Should read range"),
+        );
+        assert_eq!(
+            parcel.data[2..],
+            read_range(2, None).await.expect("Should read to end"),
+        );
+        assert_eq!(
+            parcel.data[..],
+            read_range(0, Some(1000))
+                .await
+                .expect("End should be clamped"),
+        );
+        assert!(matches!(
+            read_range(1000, None).await,
+            Err(ProviderError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            store
+                .get_parcel_range(&scaffold.invoice.bindle.id, "nonexistent", 0, None)
+                .await,
+            Err(ProviderError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_export_invoice_tar() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let mut out = Vec::new();
+        assert!(
+            matches!(
+                store
+                    .export_invoice_tar(&scaffold.invoice.bindle.id, &mut out)
+                    .await,
+                Err(ProviderError::MissingParcels(_))
+            ),
+            "Export should fail when parcels are missing"
+        );
+        assert!(out.is_empty(), "Nothing should be written on failure");
+
+        for parcel in scaffold.parcel_files.values() {
+            store
+                .create_parcel(
+                    &scaffold.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+        store
+            .export_invoice_tar(&scaffold.invoice.bindle.id, &mut out)
+            .await
+            .expect("Export should succeed");
+
+        let mut archive = tokio_tar::Archive::new(out.as_slice());
+        let mut entries = archive.entries().expect("Should read entries");
+        let mut files = std::collections::HashMap::new();
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry.expect("Entry should be valid");
+            let path = entry.path().expect("Entry should have a path").into_owned();
+            let mut data = Vec::new();
+            entry
+                .read_to_end(&mut data)
+                .await
+                .expect("Should read entry data");
+            files.insert(path, data);
+        }
+
+        let base = std::path::PathBuf::from(scaffold.invoice.canonical_name());
+        assert_eq!(scaffold.parcel_files.len() + 1, files.len());
+        let inv: crate::Invoice =
+            toml::from_slice(&files[&base.join("invoice.toml")]).expect("Invoice should parse");
+        assert_eq!(scaffold.invoice.bindle.id, inv.bindle.id);
+        for parcel in scaffold.parcel_files.values() {
+            assert_eq!(
+                parcel.data,
+                files[&base.join("parcels").join(format!("{}.dat", parcel.sha))],
+                "Parcel data should match"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_import_exported_tar() {
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let source_root = tempdir().unwrap();
+        let source = FileProvider::new(
+            source_root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        source
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        for parcel in scaffold.parcel_files.values() {
+            source
+                .create_parcel(
+                    &scaffold.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+        let mut archive = Vec::new();
+        source
+            .export_invoice_tar(&scaffold.invoice.bindle.id, &mut archive)
+            .await
+            .expect("Export should succeed");
+
+        let dest_root = tempdir().unwrap();
+        let dest = FileProvider::new(
+            dest_root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        // Corrupt the data of one of the parcels, which should abort the import
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let mut corrupted = archive.clone();
+        let offset = corrupted
+            .windows(parcel.data.len())
+            .position(|w| w == parcel.data.as_slice())
+            .expect("Parcel data should be in the archive");
+        corrupted[offset] ^= 0xff;
+        assert!(matches!(
+            dest.import_invoice_tar(&mut corrupted.as_slice(), |inv| Ok(NoopSigned(
+                NoopVerified(inv)
+            )))
+            .await,
+            Err(ProviderError::DigestMismatch { .. })
+        ));
+        assert!(
+            !dest
+                .invoice_exists(&scaffold.invoice.bindle.id)
+                .await
+                .unwrap(),
+            "Nothing should be stored when the import is aborted"
+        );
+
+        for _ in 0..2 {
+            let inv = dest
+                .import_invoice_tar(&mut archive.as_slice(), |inv| {
+                    Ok(NoopSigned(NoopVerified(inv)))
+                })
+                .await
+                .expect("Import should be idempotent");
+            assert_eq!(scaffold.invoice.bindle.id, inv.bindle.id);
+        }
+
+        let expected = source
+            .get_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .unwrap();
+        let imported = dest
+            .get_invoice_verified(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Imported invoice should have all parcels");
+        assert_eq!(
+            toml::to_string(&expected).unwrap(),
+            toml::to_string(&imported).unwrap(),
+            "Imported invoice should match the exported invoice"
+        );
+        for parcel in scaffold.parcel_files.values() {
+            let mut stream = dest
+                .get_parcel(&scaffold.invoice.bindle.id, &parcel.sha)
+                .await
+                .expect("Parcel should be imported");
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                data.extend_from_slice(&chunk.unwrap());
+            }
+            assert_eq!(parcel.data, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_return_exists_for_duplicate_invoice() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let mut duplicate = scaffold.invoice.clone();
+        duplicate.annotations = Some(
+            [("changed".to_owned(), "true".to_owned())]
+                .into_iter()
+                .collect(),
+        );
+        let err = store
+            .create_invoice(NoopSigned(NoopVerified(duplicate)))
+            .await
+            .expect_err("Creating the same invoice twice should fail");
+        assert!(
+            matches!(err, ProviderError::Exists),
+            "Expected Exists, got {:?}",
+            err
+        );
+        let stored = store
+            .get_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Original invoice should still be readable");
+        assert_eq!(
+            scaffold.invoice.annotations, stored.annotations,
+            "Original invoice should not be overwritten"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_invoice_mut() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        store
+            .with_invoice_mut(&id, |inv| {
+                inv.annotations
+                    .get_or_insert_with(Default::default)
+                    .insert("flag".to_owned(), "on".to_owned());
+                async { Ok(()) }
+            })
+            .await
+            .expect("Invoice should be updated");
+        let stored = store.get_invoice(&id).await.unwrap();
+        assert_eq!(
+            Some("on"),
+            stored
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get("flag"))
+                .map(String::as_str)
+        );
+
+        // Nothing should be written if the closure fails
+        store
+            .with_invoice_mut(&id, |inv| {
+                inv.annotations = None;
+                async { Err(ProviderError::Other("nope".to_owned())) }
+            })
+            .await
+            .expect_err("Error from the closure should be returned");
+        assert_eq!(
+            stored.annotations,
+            store.get_invoice(&id).await.unwrap().annotations
+        );
+
+        store
+            .with_invoice_mut("nonexistent/1.0.0", |_| async { Ok(()) })
+            .await
+            .expect_err("Missing invoice should not be created");
+    }
+
+    #[tokio::test]
+    async fn test_should_plan_invoice() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let index = crate::search::StrictEngine::default();
+        let store = FileProvider::new(root.path().to_owned(), index.clone()).await;
+
+        let planned = store
+            .plan_invoice(&scaffold.invoice)
+            .await
+            .expect("Invoice should be planned");
+        assert!(
+            !store
+                .invoice_path(&scaffold.invoice.canonical_name())
+                .exists(),
+            "Planning should not write the invoice"
+        );
+        assert!(
+            index
+                .query_ids(scaffold.invoice.bindle.id.name())
+                .await
+                .unwrap()
+                .is_empty(),
+            "Planning should not index the invoice"
+        );
+
+        let (_, missing) = store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        assert_eq!(missing, planned);
+
+        let mut yanked = scaffold.invoice.clone();
+        yanked.bindle.id = format!("{}/2.0.0", yanked.bindle.id.name())
+            .parse()
+            .unwrap();
+        yanked.yanked = Some(true);
+        assert!(matches!(
+            store.plan_invoice(&yanked).await,
+            Err(ProviderError::CreateYanked)
+        ));
+        assert!(matches!(
+            store.plan_invoice(&scaffold.invoice).await,
+            Err(ProviderError::Exists)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_get_parcels() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let mut labels: Vec<crate::Label> = scaffold
+            .invoice
+            .parcel
+            .clone()
+            .unwrap()
+            .into_iter()
+            .map(|p| p.label)
+            .collect();
+        // Upload only the second parcel
+        let present = scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == labels[1].sha256)
+            .unwrap();
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &present.sha,
+                FramedRead::new(
+                    std::io::Cursor::new(present.data.clone()),
+                    BytesCodec::new(),
+                ),
+            )
+            .await
+            .expect("Parcel should be created");
+        labels.push(crate::Label::new(
+            "nonexistent.dat".to_owned(),
+            "abcdef1234567890".to_owned(),
+        ));
+
+        let err = store
+            .get_parcels(&labels)
+            .await
+            .err()
+            .expect("Missing parcels should fail");
+        match err {
+            ProviderError::MissingParcels(missing) => assert_eq!(
+                vec![labels[0].sha256.clone(), labels[2].sha256.clone()],
+                missing,
+                "Error should list exactly the missing parcels"
+            ),
+            e => panic!("Expected MissingParcels error, got {:?}", e),
+        }
+
+        let mut readers = store
+            .get_parcels(&labels[1..2])
+            .await
+            .expect("Present parcels should be opened");
+        assert_eq!(1, readers.len());
+        let mut data = Vec::new();
+        readers
+            .get_mut(&present.sha)
+            .expect("Reader should be keyed by SHA")
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        assert_eq!(present.data, data);
+    }
+
+    #[tokio::test]
+    async fn test_should_handle_existing_parcel_upload() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        for _ in 0..2 {
+            store
+                .create_parcel(
+                    &scaffold.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Uploading identical parcel should succeed");
+        }
+
+        // Create a different invoice that claims the same SHA with a different size
+        let mut conflicting = scaffold.invoice.clone();
+        conflicting.bindle.id = "conflicting/1.0.0".parse().unwrap();
+        conflicting.parcel.as_mut().unwrap()[0].label.size += 1;
+        store
+            .create_invoice(NoopSigned(NoopVerified(conflicting.clone())))
+            .await
+            .expect("Invoice should be created");
+        let mut data = parcel.data.clone();
+        data.push(b'!');
+        let err = store
+            .create_parcel(
+                &conflicting.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(data), BytesCodec::new()),
+            )
+            .await
+            .expect_err("Conflicting upload should fail");
+        assert!(
+            matches!(err, ProviderError::DigestConflict { .. }),
+            "Expected DigestConflict, got {:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_return_exists_for_blocked_parcel_path() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        // A parcel directory left behind without any data
+        std::fs::create_dir_all(store.parcel_path(&parcel.sha)).unwrap();
+        let err = store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect_err("Upload into an empty parcel directory should fail");
+        assert!(
+            matches!(err, ProviderError::Exists),
+            "Expected Exists, got {:?}",
+            err
+        );
+
+        // A regular file where the parcel directory should be
+        std::fs::remove_dir(store.parcel_path(&parcel.sha)).unwrap();
+        std::fs::write(store.parcel_path(&parcel.sha), b"not a directory").unwrap();
+        let err = store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect_err("Upload over a regular file should fail");
+        assert!(
+            matches!(err, ProviderError::Exists),
+            "Expected Exists, got {:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_get_parcel_size() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        assert!(matches!(
+            store.get_parcel_size(&parcel.sha).await,
+            Err(ProviderError::NotFound { id }) if id == parcel.sha
+        ));
+
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created");
+        assert_eq!(
+            9,
+            store
+                .get_parcel_size(&parcel.sha)
+                .await
+                .expect("Should get parcel size")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_store_and_retrieve_bindle() {
+        let root = tempdir().expect("create tempdir");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        // Store an invoice first and then create the parcel for it
+        store
+            .create_invoice(signed)
+            .await
+            .expect("should be able to create an invoice");
+
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("unable to store the parcel");
+
+        // Get the bindle
+        let inv = store
+            .get_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("get the invoice we just stored");
+
+        let first_parcel = inv
+            .parcel
+            .expect("parsel vector")
+            .pop()
+            .expect("got a parcel");
+        assert_eq!(first_parcel.label.sha256, parcel.sha)
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_invalid_length() {
+        // Create a temporary directory
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v1").await;
+        let mut parcels = scaffold.invoice.parcel.take().unwrap();
+        // Completely invalid size
+        parcels[0].label.size = 100000;
+        scaffold.invoice.parcel = Some(parcels);
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+        let err = store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect_err("Creating a parcel with invalid length should fail");
+        match err {
+            ProviderError::SizeMismatch { expected, actual } => {
+                assert_eq!(expected, 100000);
+                assert_eq!(actual, parcel.data.len() as u64);
+            }
+            e => panic!("Expected SizeMismatch error, got {:?}", e),
+        }
+        assert!(
+            !store.parcel_path(&parcel.sha).exists(),
+            "Parcel directory should be cleaned up after a failed upload"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_mismatched_digest() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+        // Keep the same length so we fail on the digest rather than the size
+        let mut bad_data = parcel.data.clone();
+        bad_data[0] = bad_data[0].wrapping_add(1);
+        let err = store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(bad_data), BytesCodec::new()),
+            )
+            .await
+            .expect_err("Creating a parcel with mismatched data should fail");
+        match err {
+            ProviderError::DigestMismatch { expected, actual } => {
+                assert_eq!(expected, parcel.sha);
+                assert_ne!(actual, parcel.sha);
+            }
+            e => panic!("Expected DigestMismatch error, got {:?}", e),
+        }
+
+        assert!(
+            !store.parcel_path(&parcel.sha).exists(),
+            "Parcel directory should be cleaned up after a failed upload"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_list_invoices() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        for version in ["1.0.0", "1.1.0", "2.0.0"] {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = format!("{}/{}", inv.bindle.id.name(), version)
+                .parse()
+                .unwrap();
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+        store
+            .yank_invoice(format!("{}/2.0.0", scaffold.invoice.bindle.id.name()))
+            .await
+            .expect("Invoice should be yanked");
+
+        let mut invoices = store
+            .list_invoices()
+            .await
+            .expect("Should be able to list invoices");
+        invoices.sort_by(|a, b| a.bindle.id.version().cmp(b.bindle.id.version()));
+        let versions: Vec<String> = invoices
+            .iter()
+            .map(|i| i.bindle.id.version_string())
+            .collect();
+        assert_eq!(versions, vec!["1.0.0", "1.1.0", "2.0.0"]);
+        assert!(
+            invoices[2].yanked.unwrap_or(false),
+            "Yanked invoice should keep its yanked flag"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_page_through_invoices() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let mut expected = Vec::new();
+        for minor in 0..7 {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = format!("{}/1.{}.0", inv.bindle.id.name(), minor)
+                .parse()
+                .unwrap();
+            expected.push(inv.bindle.id.to_string());
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+        expected.sort();
+
+        for (name, term) in [
+            ("list", None),
+            ("query", Some(scaffold.invoice.bindle.id.name())),
+        ] {
+            let mut seen = Vec::new();
+            let mut offset = 0;
+            loop {
+                let (page, total) = match term {
+                    None => store.list_invoices_paged(offset, 3).await,
+                    Some(term) => store.query_invoices_paged(term, offset, 3).await,
+                }
+                .expect("Should be able to get a page");
+                assert_eq!(7, total, "{} should return the total count", name);
+                assert!(page.len() <= 3, "{} page should respect the limit", name);
+                if page.is_empty() {
+                    break;
+                }
+                offset += page.len();
+                seen.extend(page.into_iter().map(|inv| inv.bindle.id.to_string()));
+            }
+            let mut sorted = seen.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(
+                seen.len(),
+                sorted.len(),
+                "{} pages should not overlap",
+                name
+            );
+            assert_eq!(expected, sorted, "{} should return every invoice", name);
+        }
+
+        // Paging over storage names gives the same order as sorting by canonical name
+        let (page, _) = store.list_invoices_paged(2, 3).await.unwrap();
+        let (expected_page, _) =
+            crate::provider::paginate(store.list_invoices().await.unwrap(), 2, 3);
+        assert_eq!(
+            expected_page
+                .iter()
+                .map(|inv| inv.bindle.id.to_string())
+                .collect::<Vec<_>>(),
+            page.iter()
+                .map(|inv| inv.bindle.id.to_string())
+                .collect::<Vec<_>>()
+        );
+
+        let (page, total) = store
+            .list_invoices_paged(100, 3)
+            .await
+            .expect("Offset past the end should not fail");
+        assert!(page.is_empty());
+        assert_eq!(7, total);
+    }
+
+    #[tokio::test]
+    async fn test_should_create_parcel_computing_sha() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let data = b"some parcel data without a label".to_vec();
+        let label = store
+            .create_parcel_computing_sha(
+                "text/plain".to_owned(),
+                "data.txt".to_owned(),
+                &mut std::io::Cursor::new(data.clone()),
+            )
+            .await
+            .expect("Parcel should be created");
+        assert_eq!(DigestAlgorithm::Sha256.digest(&data), label.sha256);
+        assert_eq!(data.len() as u64, label.size);
+        assert_eq!("text/plain", label.media_type);
+        assert_eq!("data.txt", label.name);
+        assert_eq!(
+            data,
+            std::fs::read(store.parcel_data_path(&label.sha256)).unwrap()
+        );
+        assert_eq!(
+            Some(label.clone()),
+            store.read_parcel_label(&label.sha256).await.unwrap(),
+            "Generated label should be stored with the parcel"
+        );
+        assert!(
+            !std::fs::read_dir(root.path()).unwrap().any(|e| e
+                .unwrap()
+                .path()
+                .to_string_lossy()
+                .ends_with(PART_EXTENSION)),
+            "Temporary file should not be left behind"
+        );
+
+        // Uploading the same content again should return the original label
+        let again = store
+            .create_parcel_computing_sha(
+                "application/octet-stream".to_owned(),
+                "other.bin".to_owned(),
+                &mut std::io::Cursor::new(data.clone()),
+            )
+            .await
+            .expect("Re-uploading identical content should succeed");
+        assert_eq!(label, again);
+    }
+
+    #[tokio::test]
+    async fn test_should_cache_parcel_labels() {
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .label_cache_size(10)
+        .build()
+        .await;
+        let label = store
+            .create_parcel_computing_sha(
+                "text/plain".to_owned(),
+                "cached.txt".to_owned(),
+                &mut std::io::Cursor::new(b"cache me".to_vec()),
+            )
+            .await
+            .expect("Parcel should be created");
+        assert_eq!(
+            Some(&label),
+            store
+                .read_parcel_label(&label.sha256)
+                .await
+                .unwrap()
+                .as_ref()
+        );
+
+        // Clones share the cache, so the label is served from memory even though it is gone
+        std::fs::remove_file(store.parcel_label_path(&label.sha256)).unwrap();
+        assert_eq!(
+            Some(label.clone()),
+            store
+                .clone()
+                .read_parcel_label(&label.sha256)
+                .await
+                .unwrap(),
+            "Label should be served from the cache"
+        );
+
+        // Deleting the parcel drops it from the cache
+        store
+            .delete_parcel(&label.sha256)
+            .await
+            .expect("Parcel should be deleted");
+        assert_eq!(None, store.read_parcel_label(&label.sha256).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_should_delete_parcel() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created");
+
+        // The invoice still references the parcel, so it shouldn't be deleted
+        let err = store
+            .delete_parcel(&parcel.sha)
+            .await
+            .expect_err("Deleting a parcel in use should fail");
+        assert!(
+            matches!(err, ProviderError::InUse),
+            "Error should be of type InUse"
+        );
+
+        // Once the invoice is yanked, the parcel can be deleted
+        store
+            .yank_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Invoice should be yanked");
+        store
+            .delete_parcel(&parcel.sha)
+            .await
+            .expect("Parcel should be deleted");
+        assert!(!store.parcel_path(&parcel.sha).exists());
+
+        let err = store
+            .delete_parcel(&parcel.sha)
+            .await
+            .expect_err("Deleting a nonexistent parcel should fail");
+        assert!(
+            matches!(err, ProviderError::NotFound { .. }),
+            "Error should be of type NotFound"
+        );
+        assert!(
+            err.to_string().contains(&parcel.sha),
+            "Error message should include the parcel SHA"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_recheck_references_after_locking_parcel() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let v1 = testing::Scaffold::load("valid_v1").await;
+        let v2 = testing::Scaffold::load("valid_v2").await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(v2.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        for parcel in v2.parcel_files.values() {
+            store
+                .create_parcel(
+                    &v2.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+        store
+            .yank_invoice(&v2.invoice.bindle.id)
+            .await
+            .expect("Should be able to yank");
+
+        // Start deleting the now unreferenced parcel while something else holds its lock, then
+        // reference it again before the delete can continue
+        let shared = v1.invoice.parcel.as_ref().unwrap()[0].label.sha256.clone();
+        let lock = store.lock_parcel(&shared).await;
+        let delete = {
+            let store = store.clone();
+            let shared = shared.clone();
+            tokio::spawn(async move { store.delete_parcel(&shared).await })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(v1.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        drop(lock);
+
+        let err = delete
+            .await
+            .expect("Task should not panic")
+            .expect_err("Deleting a parcel referenced while waiting should fail");
+        assert!(
+            matches!(err, ProviderError::InUse),
+            "Error should be of type InUse"
+        );
+        assert!(
+            store.parcel_data_path(&shared).exists(),
+            "Referenced parcel should be retained"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_verify_invoice_parcels() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+
+        let err = store
+            .get_invoice_verified(&scaffold.invoice.bindle.id)
+            .await
+            .expect_err("Invoice with missing parcels should fail verification");
+        match err {
+            ProviderError::MissingParcels(missing) => assert_eq!(missing, vec![parcel.sha.clone()]),
+            e => panic!("Expected MissingParcels error, got {:?}", e),
+        }
+
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created");
+        store
+            .get_invoice_verified(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Invoice with all parcels should pass verification");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_invoice_reads() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let ids: Vec<Id> = (0..10)
+            .map(|i| {
+                format!("{}/1.0.{}", scaffold.invoice.bindle.id.name(), i)
+                    .parse()
+                    .unwrap()
+            })
+            .collect();
+        for id in ids.iter() {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = id.clone();
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+
+        let reads = (0..100).map(|i| {
+            let store = store.clone();
+            let id = ids[i % ids.len()].clone();
+            tokio::spawn(async move { store.get_invoice(id).await })
+        });
+        let results = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            futures::future::join_all(reads),
+        )
+        .await
+        .expect("Concurrent reads should not deadlock");
+        for res in results {
+            res.expect("Task should not panic")
+                .expect("Invoice should be readable");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_query_invoices() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        for id in [
+            "example.com/foo/1.0.0",
+            "example.com/bar/1.0.0",
+            "other.com/baz/1.0.0",
+        ] {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = id.parse().unwrap();
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+
+        let mut names: Vec<String> = store
+            .query_invoices("example.com")
+            .await
+            .expect("Query should succeed")
+            .into_iter()
+            .map(|i| i.bindle.id.name().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["example.com/bar", "example.com/foo"]);
+
+        assert!(store
+            .query_invoices("nothing-matches")
+            .await
+            .expect("Query should succeed")
+            .is_empty());
+    }
+
+    /// An observer that records the events it receives
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl StorageObserver for RecordingObserver {
+        async fn on_invoice_created(&self, invoice: &crate::Invoice) -> anyhow::Result<()> {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("invoice_created:{}", invoice.bindle.id));
+            Ok(())
+        }
+
+        async fn on_invoice_yanked(&self, id: &Id) -> anyhow::Result<()> {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("invoice_yanked:{}", id));
+            // Errors must not fail the operation
+            anyhow::bail!("observer failure")
+        }
+
+        async fn on_parcel_created(&self, label: &crate::Label) -> anyhow::Result<()> {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("parcel_created:{}", label.sha256));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_notify_observer() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let observer = Arc::new(RecordingObserver::default());
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .observer(observer.clone())
+            .build()
+            .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        // Failed mutations must not notify the observer
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect_err("Duplicate invoice should not be created");
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        for _ in 0..2 {
+            // The second upload is a no-op, so it should not be reported
+            store
+                .create_parcel(
+                    &id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+        store
+            .yank_invoice(&id)
+            .await
+            .expect("Observer errors should not fail the yank");
+
+        assert_eq!(
+            vec![
+                format!("invoice_created:{}", id),
+                format!("parcel_created:{}", parcel.sha),
+                format!("invoice_yanked:{}", id),
+            ],
+            *observer.events.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_resolve_aliases() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let name = scaffold.invoice.bindle.id.name().to_owned();
+        let first = scaffold.invoice.bindle.id.version_string();
+        let mut inv = scaffold.invoice.clone();
+        inv.bindle.id = format!("{}/9.0.0", name).parse().unwrap();
+        for i in [scaffold.invoice.clone(), inv] {
+            store
+                .create_invoice(NoopSigned(NoopVerified(i)))
+                .await
+                .expect("Invoice should be created");
+        }
+
+        assert!(matches!(
+            store.resolve_alias(&name, "stable").await,
+            Err(ProviderError::NotFound { .. })
+        ));
+        store
+            .set_alias(&name, "stable", &first)
+            .await
+            .expect("Alias should be set");
+        let resolved = store.resolve_alias(&name, "stable").await.unwrap();
+        assert_eq!(first, resolved.bindle.id.version_string());
+
+        // Aliases can be re-pointed, without affecting other aliases
+        store.set_alias(&name, "latest", &first).await.unwrap();
+        store.set_alias(&name, "latest", "9.0.0").await.unwrap();
+        let resolved = store.resolve_alias(&name, "latest").await.unwrap();
+        assert_eq!("9.0.0", resolved.bindle.id.version_string());
+        let resolved = store.resolve_alias(&name, "stable").await.unwrap();
+        assert_eq!(first, resolved.bindle.id.version_string());
+
+        assert!(matches!(
+            store.set_alias(&name, "stable", "10.0.0").await,
+            Err(ProviderError::NotFound { .. })
+        ));
+
+        // Yanking the version an alias points at makes resolving it fail, and yanked versions
+        // can't be aliased
+        store.yank_invoice(format!("{}/9.0.0", name)).await.unwrap();
+        assert!(matches!(
+            store.resolve_alias(&name, "latest").await,
+            Err(ProviderError::Yanked)
+        ));
+        assert!(matches!(
+            store.set_alias(&name, "stable", "9.0.0").await,
+            Err(ProviderError::Yanked)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_get_invoice_with_etag() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = &scaffold.invoice.bindle.id;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let (inv, etag) = store
+            .get_invoice_with_etag(id)
+            .await
+            .expect("Invoice should be loaded");
+        assert_eq!(id, &inv.bindle.id);
+        let (_, again) = store.get_invoice_with_etag(id).await.unwrap();
+        assert_eq!(etag, again, "ETag should be stable across reads");
+        assert!(
+            store
+                .get_invoice_if_none_match(id, &etag)
+                .await
+                .unwrap()
+                .is_none(),
+            "A matching ETag should not return the invoice"
+        );
+
+        store.yank_invoice(id).await.unwrap();
+        let (inv, yanked_etag) = store.get_invoice_with_etag(id).await.unwrap();
+        assert!(inv.yanked.unwrap_or(false));
+        assert_ne!(etag, yanked_etag, "ETag should change after a yank");
+        let (_, returned) = store
+            .get_invoice_if_none_match(id, &etag)
+            .await
+            .unwrap()
+            .expect("A stale ETag should return the invoice");
+        assert_eq!(yanked_etag, returned);
+
+        assert!(matches!(
+            store.get_invoice_with_etag("missing/1.0.0").await,
+            Err(ProviderError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_get_invoice_status() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = scaffold.invoice.bindle.id.clone();
+
+        let status = store.invoice_status(&id).await.unwrap();
+        assert!(!status.exists);
+
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let status = store.invoice_status(&id).await.unwrap();
+        assert_eq!(
+            InvoiceStatus {
+                exists: true,
+                yanked: false,
+                version: id.version_string(),
+                parcel_count: 2,
+            },
+            status
+        );
+
+        store
+            .yank_invoice(&id)
+            .await
+            .expect("Invoice should be yanked");
+        let status = store
+            .invoice_status(&id)
+            .await
+            .expect("Status of a yanked invoice should be returned");
+        assert!(status.exists);
+        assert!(status.yanked);
+        assert_eq!(2, status.parcel_count);
+    }
+
+    #[tokio::test]
+    async fn test_should_upload_parcel_in_chunks() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.invoice.parcel.as_ref().unwrap()[0].label.clone();
+        let data = scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == parcel.sha256)
+            .unwrap()
+            .data
+            .clone();
+        assert!(data.len() >= 3, "Parcel should be large enough to split");
+        let (first, second) = (data.len() / 3, 2 * data.len() / 3);
+
+        store
+            .create_parcel_chunk(&parcel.sha256, 0, &data[..first])
+            .await
+            .expect("First chunk should be written");
+        // A chunk that would leave a gap is rejected
+        assert!(matches!(
+            store
+                .create_parcel_chunk(&parcel.sha256, second as u64, &data[second..])
+                .await,
+            Err(ProviderError::OutOfRange { .. })
+        ));
+        store
+            .create_parcel_chunk(&parcel.sha256, first as u64, &data[first..second])
+            .await
+            .expect("Second chunk should be written");
+        // Resend the second chunk, as if the connection dropped before it was acknowledged
+        store
+            .create_parcel_chunk(&parcel.sha256, first as u64, &data[first..second])
+            .await
+            .expect("Resent chunk should be written");
+        assert!(
+            !store.parcel_exists(&id, &parcel.sha256).await.unwrap(),
+            "Parcel should not exist before it is finalized"
+        );
+        store
+            .create_parcel_chunk(&parcel.sha256, second as u64, &data[second..])
+            .await
+            .expect("Third chunk should be written");
+
+        store
+            .finalize_parcel(&parcel)
+            .await
+            .expect("Parcel should be finalized");
+        assert!(store.parcel_exists(&id, &parcel.sha256).await.unwrap());
+        let mut stored = Vec::new();
+        let mut stream = store.get_parcel(&id, &parcel.sha256).await.unwrap();
+        while let Some(chunk) = stream.next().await {
+            stored.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(data, stored);
+        assert!(
+            tokio::fs::metadata(store.partial_parcel_path(&parcel.sha256))
+                .await
+                .is_err(),
+            "Staging file should be removed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_not_create_parcel_computing_sha_during_chunked_upload() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.invoice.parcel.as_ref().unwrap()[0].label.clone();
+        let data = scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == parcel.sha256)
+            .unwrap()
+            .data
+            .clone();
+        let half = data.len() / 2;
+        store
+            .create_parcel_chunk(&parcel.sha256, 0, &data[..half])
+            .await
+            .expect("First chunk should be written");
+
+        assert!(
+            matches!(
+                store
+                    .create_parcel_computing_sha(
+                        parcel.media_type.clone(),
+                        parcel.name.clone(),
+                        &mut std::io::Cursor::new(data.clone()),
+                    )
+                    .await,
+                Err(ProviderError::WriteInProgress)
+            ),
+            "Parcel should not be reported as stored while a chunked upload is in progress"
+        );
+        assert!(!store.parcel_exists(&id, &parcel.sha256).await.unwrap());
+
+        // The chunked upload should still be able to complete
+        store
+            .create_parcel_chunk(&parcel.sha256, half as u64, &data[half..])
+            .await
+            .expect("Second chunk should be written");
+        store
+            .finalize_parcel(&parcel)
+            .await
+            .expect("Parcel should be finalized");
+        assert!(store.parcel_exists(&id, &parcel.sha256).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_should_discard_stale_chunk_data() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.invoice.parcel.as_ref().unwrap()[0].label.clone();
+        let data = &scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == parcel.sha256)
+            .unwrap()
+            .data;
+        let half = data.len() / 2;
+
+        // A first attempt sends too much data, then the client resumes from the middle
+        let mut too_long = data.clone();
+        too_long.extend_from_slice(b"left over from a failed attempt");
+        store
+            .create_parcel_chunk(&parcel.sha256, 0, &too_long)
+            .await
+            .unwrap();
+        store
+            .create_parcel_chunk(&parcel.sha256, half as u64, &data[half..])
+            .await
+            .unwrap();
+        store
+            .finalize_parcel(&parcel)
+            .await
+            .expect("Stale data after the last chunk should be discarded");
+    }
+
+    #[tokio::test]
+    async fn test_should_remove_label_when_finalize_fails() {
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .compress_parcels(true)
+        .build()
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.invoice.parcel.as_ref().unwrap()[0].label.clone();
+        let data = &scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == parcel.sha256)
+            .unwrap()
+            .data;
+
+        let mut corrupt = data.clone();
+        corrupt[0] ^= 0xff;
+        store
+            .create_parcel_chunk(&parcel.sha256, 0, &corrupt)
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.finalize_parcel(&parcel).await,
+            Err(ProviderError::DigestMismatch { .. })
+        ));
+        assert_eq!(
+            None,
+            store.read_parcel_label(&parcel.sha256).await.unwrap(),
+            "A failed finalize should not leave a label behind"
+        );
+        assert!(!store.parcel_data_exists(&parcel.sha256).await);
+
+        // Fixing the data lets the upload complete
+        store
+            .create_parcel_chunk(&parcel.sha256, 0, data)
+            .await
+            .unwrap();
+        store
+            .finalize_parcel(&parcel)
+            .await
+            .expect("Parcel should be finalized");
+    }
+
+    #[tokio::test]
+    async fn test_should_enforce_max_parcel_size() {
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .max_parcel_size(8)
+        .build()
+        .await;
+
+        // The label is within the limit, but the data sent never ends, so the upload can only
+        // finish if the limit is enforced while streaming
+        let sha = "a".repeat(64);
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.parcel = Some(vec![crate::Parcel {
+            label: crate::Label {
+                sha256: sha.clone(),
+                name: "endless.dat".to_owned(),
+                size: 4,
+                ..crate::Label::default()
+            },
+            conditions: None,
+        }]);
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect("Invoice should be created");
+        let endless = futures::StreamExt::map(
+            futures::stream::repeat(bytes::Bytes::from_static(&[0; 1024])),
+            Ok::<_, std::io::Error>,
+        );
+        assert!(matches!(
+            store.create_parcel(&inv.bindle.id, &sha, endless).await,
+            Err(ProviderError::TooLarge { limit: 8 })
+        ));
+        assert!(
+            !store.parcel_path(&sha).exists(),
+            "Oversized parcel should be cleaned up"
+        );
+        assert!(!store.parcel_exists(&inv.bindle.id, &sha).await.unwrap());
+
+        // A label declaring a size over the limit is rejected before reading anything
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let other_root = tempdir().unwrap();
+        let small = FileProviderBuilder::new(
+            other_root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .max_parcel_size(4)
+        .build()
+        .await;
+        small
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        assert!(matches!(
+            small
+                .create_parcel(
+                    &scaffold.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await,
+            Err(ProviderError::TooLarge { limit: 4 })
+        ));
+        assert!(!small.parcel_path(&parcel.sha).exists());
+    }
+
+    async fn upload_scaffold_parcel(
+        store: &FileProvider<crate::search::StrictEngine>,
+        id: &Id,
+        parcel: &testing::ParcelInfo,
+    ) -> Result<()> {
+        store
+            .create_parcel(
+                id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_should_enforce_storage_quota() {
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = &scaffold.invoice.bindle.id;
+        let mut parcels: Vec<&testing::ParcelInfo> = scaffold.parcel_files.values().collect();
+        parcels.sort_by_key(|p| p.data.len());
+        let total: u64 = parcels.iter().map(|p| p.data.len() as u64).sum();
+
+        // Filling the quota exactly is allowed
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(total)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        for parcel in parcels.iter() {
+            upload_scaffold_parcel(&store, id, parcel)
+                .await
+                .expect("Parcel within the quota should be created");
+        }
+
+        // One byte less means the last parcel doesn't fit
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(total - 1)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        upload_scaffold_parcel(&store, id, parcels[0])
+            .await
+            .expect("Parcel within the quota should be created");
+        assert!(matches!(
+            upload_scaffold_parcel(&store, id, parcels[1]).await,
+            Err(ProviderError::QuotaExceeded { limit }) if limit == total - 1
+        ));
+        assert!(!store.parcel_path(&parcels[1].sha).exists());
+
+        // The parcels already stored are counted when a provider is built
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(total - 1)
+            .build()
+            .await;
+        assert!(matches!(
+            upload_scaffold_parcel(&store, id, parcels[1]).await,
+            Err(ProviderError::QuotaExceeded { .. })
+        ));
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(total)
+            .build()
+            .await;
+        upload_scaffold_parcel(&store, id, parcels[1])
+            .await
+            .expect("Parcel within the quota should be created");
+    }
+
+    #[tokio::test]
+    async fn test_should_enforce_storage_quota_for_concurrent_uploads() {
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = &scaffold.invoice.bindle.id;
+        let parcels: Vec<&testing::ParcelInfo> = scaffold.parcel_files.values().collect();
+        let largest = parcels.iter().map(|p| p.data.len() as u64).max().unwrap();
+
+        // There is only room for one of the parcels
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(largest)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let (first, second) = tokio::join!(
+            upload_scaffold_parcel(&store, id, parcels[0]),
+            upload_scaffold_parcel(&store, id, parcels[1]),
+        );
+        let results = [first, second];
+        assert_eq!(1, results.iter().filter(|r| r.is_ok()).count());
+        assert_eq!(
+            1,
+            results
+                .iter()
+                .filter(|r| matches!(r, Err(ProviderError::QuotaExceeded { .. })))
+                .count()
+        );
+
+        // Garbage collecting the stored parcel frees up its space for the other one
+        store.yank_invoice(id).await.unwrap();
+        assert_eq!(1, store.gc_parcels(false).await.unwrap().len());
+        // The scaffold is already version 2.0.0, so use a version that isn't stored yet
+        let mut inv = scaffold.invoice.clone();
+        inv.bindle.id = format!("{}/3.0.0", id.name()).parse().unwrap();
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect("Invoice should be created");
+        let unstored = if results[0].is_ok() {
+            parcels[1]
+        } else {
+            parcels[0]
+        };
+        upload_scaffold_parcel(&store, &inv.bindle.id, unstored)
+            .await
+            .expect("Parcel should fit once space has been freed");
+    }
+
+    #[tokio::test]
+    async fn test_should_count_chunks_against_storage_quota() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.invoice.parcel.as_ref().unwrap()[0].label.clone();
+        let data = &scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == parcel.sha256)
+            .unwrap()
+            .data;
+        let half = data.len() / 2;
+
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(data.len() as u64 - 1)
+            .build()
+            .await;
+        store
+            .create_parcel_chunk(&parcel.sha256, 0, &data[..half])
+            .await
+            .expect("Chunk within the quota should be written");
+        assert_eq!(half as u64, store.quota.as_ref().unwrap().used());
+        // Resending a chunk doesn't count it twice
+        store
+            .create_parcel_chunk(&parcel.sha256, 0, &data[..half])
+            .await
+            .expect("Resent chunk should be written");
+        assert_eq!(half as u64, store.quota.as_ref().unwrap().used());
+        assert!(matches!(
+            store
+                .create_parcel_chunk(&parcel.sha256, half as u64, &data[half..])
+                .await,
+            Err(ProviderError::QuotaExceeded { .. })
+        ));
+
+        // Unfinished uploads are counted when a provider is built
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(data.len() as u64)
+            .build()
+            .await;
+        assert_eq!(half as u64, store.quota.as_ref().unwrap().used());
+        store
+            .create_parcel_chunk(&parcel.sha256, half as u64, &data[half..])
+            .await
+            .expect("Chunk within the quota should be written");
+        store
+            .finalize_parcel(&parcel)
+            .await
+            .expect("Parcel should be finalized");
+        assert_eq!(data.len() as u64, store.quota.as_ref().unwrap().used());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_should_release_quota_once_for_concurrent_deletes() {
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = &scaffold.invoice.bindle.id;
+        let parcels: Vec<&testing::ParcelInfo> = scaffold.parcel_files.values().collect();
+        let total: u64 = parcels.iter().map(|p| p.data.len() as u64).sum();
+
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .max_total_bytes(total)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        for parcel in parcels.iter() {
+            upload_scaffold_parcel(&store, id, parcel).await.unwrap();
+        }
+        store.yank_invoice(id).await.unwrap();
+
+        let sha = &parcels[0].sha;
+        let deletes = (0..8).map(|_| {
+            let store = store.clone();
+            let sha = sha.clone();
+            tokio::spawn(async move { store.delete_parcel(&sha).await })
+        });
+        let results: Vec<_> = futures::future::join_all(deletes)
+            .await
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            1,
+            results.iter().filter(|r| r.is_ok()).count(),
+            "Only one delete should remove the parcel"
+        );
+        let remaining: u64 = parcels
+            .iter()
+            .filter(|p| store.parcel_path(&p.sha).exists())
+            .map(|p| p.data.len() as u64)
+            .sum();
+        assert_eq!(
+            remaining,
+            store.quota.as_ref().unwrap().used(),
+            "Space should only be given back once per removed parcel"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_bad_chunked_upload() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let label = crate::Label {
+            sha256: crate::DigestAlgorithm::Sha256.digest(b"hello world"),
+            size: 11,
+            ..crate::Label::default()
+        };
+
+        assert!(matches!(
+            store.finalize_parcel(&label).await,
+            Err(ProviderError::NotFound { .. })
+        ));
+        assert!(store
+            .create_parcel_chunk("../escape", 0, b"hello")
+            .await
+            .is_err());
+
+        store
+            .create_parcel_chunk(&label.sha256, 0, b"hello there")
+            .await
+            .expect("Chunk should be written");
+        assert!(matches!(
+            store.finalize_parcel(&label).await,
+            Err(ProviderError::DigestMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_stream_parcel_labels() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        assert_eq!(0, store.parcels().collect::<Vec<_>>().await.len());
+
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        for parcel in scaffold.parcel_files.values() {
+            store
+                .create_parcel(
+                    &id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+        let generated = store
+            .create_parcel_computing_sha(
+                "text/plain".to_owned(),
+                "generated.txt".to_owned(),
+                &mut std::io::Cursor::new(b"generated parcel".to_vec()),
+            )
+            .await
+            .expect("Parcel should be created");
+
+        let labels: Vec<crate::Label> = store
+            .parcels()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_>>()
+            .expect("All labels should be read");
+        assert_eq!(3, labels.len());
+        for parcel in scaffold.parcel_files.values() {
+            let label = labels.iter().find(|l| l.sha256 == parcel.sha).unwrap();
+            assert_eq!(parcel.data.len() as u64, label.size);
+        }
+        assert!(
+            labels.contains(&generated),
+            "Stored label should be returned"
+        );
+
+        // A corrupt label should be reported without stopping the stream
+        tokio::fs::write(store.parcel_label_path(&generated.sha256), b"not a label")
+            .await
+            .unwrap();
+        let results: Vec<_> = store.parcels().collect().await;
+        assert_eq!(3, results.len());
+        assert_eq!(1, results.iter().filter(|r| r.is_err()).count());
+    }
+
+    #[tokio::test]
+    async fn test_should_detect_misplaced_invoices() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .verify_canonical_names(true)
+        .build()
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let name = store.invoice_name(&scaffold.invoice.bindle.id);
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        store
+            .verify_canonical_name(&name)
+            .await
+            .expect("Invoice should be stored under its canonical name");
+
+        // Plant a copy of the invoice where a different bindle would be stored
+        let wrong_name = store.invoice_name(&"enterprise.com/other/1.0.0".parse().unwrap());
+        tokio::fs::create_dir_all(store.invoice_path(&wrong_name))
+            .await
+            .unwrap();
+        tokio::fs::copy(
+            store.invoice_toml_path(&name),
+            store.invoice_toml_path(&wrong_name),
+        )
+        .await
+        .unwrap();
+
+        match store.verify_canonical_name(&wrong_name).await {
+            Err(ProviderError::CanonicalMismatch {
+                canonical_name,
+                expected,
+            }) => {
+                assert_eq!(wrong_name, canonical_name);
+                assert_eq!(name, expected);
+            }
+            res => panic!("Expected CanonicalMismatch error, got {:?}", res),
+        }
+        assert!(matches!(
+            store.verify_canonical_name("../invoices").await,
+            Err(ProviderError::NotFound { .. })
+        ));
+
+        let report = store
+            .verify_store()
+            .await
+            .expect("Store should be verified");
+        assert_eq!(vec![wrong_name], report.misplaced_invoices);
+        assert!(!report.is_ok());
+
+        // Names are only checked by verify_store when configured to
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let report = store
+            .verify_store()
+            .await
+            .expect("Store should be verified");
+        assert!(report.misplaced_invoices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_should_verify_store() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let labels: Vec<crate::Label> = scaffold
+            .invoice
+            .parcel
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|p| p.label.clone())
+            .collect();
+        // Only upload the first parcel so the second is missing
+        let parcel = scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == labels[0].sha256)
+            .unwrap();
+        store
+            .create_parcel(
+                &id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Parcel should be created");
+
+        let report = store
+            .verify_store()
+            .await
+            .expect("Store should be verified");
+        assert!(report.corrupt_parcels.is_empty());
+        assert!(report.orphaned_parcels.is_empty());
+        assert_eq!(
+            Some(&vec![labels[1].sha256.clone()]),
+            report.invoices_missing_parcels.get(&id.to_string())
+        );
+
+        // Corrupt the stored parcel's bytes without changing its size
+        let data_path = store.parcel_data_path(&parcel.sha);
+        let mut data = tokio::fs::read(&data_path).await.unwrap();
+        data[0] ^= 0xff;
+        tokio::fs::write(&data_path, data).await.unwrap();
+
+        let report = store
+            .verify_store()
+            .await
+            .expect("Store should be verified");
+        assert_eq!(vec![parcel.sha.clone()], report.corrupt_parcels);
+        assert!(!report.is_ok());
+
+        // Stray files in the parcel directory aren't parcels, so they are skipped
+        tokio::fs::write(store.parcel_path("").join(".DS_Store"), b"junk")
+            .await
+            .unwrap();
+        let report = store
+            .verify_store()
+            .await
+            .expect("Stray file should not stop verification");
+        assert_eq!(vec![parcel.sha.clone()], report.corrupt_parcels);
+        assert!(report.orphaned_parcels.is_empty());
+        assert_eq!(
+            1,
+            store
+                .storage_stats()
+                .await
+                .expect("Stray file should not stop computing stats")
+                .parcel_count
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_store_compressed_invoices() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .compress_invoices(true)
+            .build()
+            .await;
+        let mut scaffold = testing::Scaffold::load("valid_v1").await;
+        let template = scaffold.invoice.parcel.as_ref().unwrap()[0].clone();
+        scaffold.invoice.parcel = Some(
+            (0..2000)
+                .map(|i| {
+                    let mut parcel = template.clone();
+                    parcel.label.sha256 =
+                        crate::DigestAlgorithm::Sha256.digest(i.to_string().as_bytes());
+                    parcel.label.name = format!("parcel-{}.txt", i);
+                    parcel
+                })
+                .collect(),
+        );
+        let id = scaffold.invoice.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let name = store.invoice_name(&id);
+        let compressed = store.compressed_invoice_path(&name);
+        assert!(compressed.is_file(), "Invoice should be stored compressed");
+        assert!(!store.invoice_toml_path(&name).exists());
+        let raw_size = toml::to_vec(&scaffold.invoice).unwrap().len() as u64;
+        assert!(std::fs::metadata(&compressed).unwrap().len() < raw_size);
+
+        // Read from a fresh provider so the invoice isn't served from the cache
+        let reopened =
+            FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+                .compress_invoices(true)
+                .build()
+                .await;
+        assert!(reopened.invoice_exists(&id).await.unwrap());
+        let inv = reopened
+            .get_invoice(&id)
+            .await
+            .expect("Compressed invoice should be readable");
+        assert_eq!(2000, inv.parcel.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_should_read_uncompressed_invoices_with_compression_enabled() {
+        let root = tempdir().expect("Should be able to create temp directory");
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        FileProvider::new(root.path(), crate::search::StrictEngine::default())
+            .await
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .compress_invoices(true)
+            .build()
+            .await;
+        let inv = store
+            .get_invoice(&id)
+            .await
+            .expect("Existing uncompressed invoice should be readable");
+        assert_eq!(id, inv.bindle.id);
+        assert_eq!(1, store.query_invoices(id.name()).await.unwrap().len());
+
+        // Rewriting the invoice should switch it to the compressed format
+        store
+            .yank_invoice(&id)
+            .await
+            .expect("Invoice should be yanked");
+        let name = store.invoice_name(&id);
+        assert!(store.compressed_invoice_path(&name).is_file());
+        assert!(!store.invoice_toml_path(&name).exists());
+        assert!(store.get_yanked_invoice(&id).await.unwrap().yanked.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_should_round_trip_compressed_parcels() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = &scaffold.invoice.bindle.id;
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .compress_parcels(true)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        // Data that doesn't match the label should still be rejected
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        let mut tampered = parcel.data.clone();
+        tampered[0] ^= 0xff;
+        store
+            .create_parcel(
+                id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(tampered), BytesCodec::new()),
+            )
+            .await
+            .expect_err("Tampered data should fail verification");
+        assert!(
+            !store.parcel_path(&parcel.sha).exists(),
+            "Failed upload should be cleaned up"
+        );
+
+        for parcel in scaffold.parcel_files.values() {
+            for _ in 0..2 {
+                store
+                    .create_parcel(
+                        id,
+                        &parcel.sha,
+                        FramedRead::new(
+                            std::io::Cursor::new(parcel.data.clone()),
+                            BytesCodec::new(),
+                        ),
+                    )
+                    .await
+                    .expect("Uploading a compressed parcel (twice) should succeed");
+            }
+            assert!(store.compressed_parcel_path(&parcel.sha).is_file());
+            assert!(
+                !store.parcel_data_path(&parcel.sha).exists(),
+                "Only the compressed data should be stored"
+            );
+            let label = store
+                .read_parcel_label(&parcel.sha)
+                .await
+                .unwrap()
+                .expect("Compressed parcel should have a stored label");
+            assert_eq!(
+                Some("gzip"),
+                label
+                    .annotations
+                    .as_ref()
+                    .and_then(|a| a.get(COMPRESSION_ANNOTATION))
+                    .map(String::as_str)
+            );
+
+            let mut stream = store.get_parcel(id, &parcel.sha).await.unwrap();
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                data.extend_from_slice(&chunk.unwrap());
+            }
+            assert_eq!(
+                parcel.data, data,
+                "get_parcel should return the original bytes"
+            );
+
+            assert_eq!(
+                parcel.data.len() as u64,
+                store.get_parcel_size(&parcel.sha).await.unwrap()
+            );
+            assert!(store.parcel_exists(id, &parcel.sha).await.unwrap());
+
+            let mut stream = store
+                .get_parcel_range(id, &parcel.sha, 2, Some(5))
+                .await
+                .unwrap();
+            let mut range = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                range.extend_from_slice(&chunk.unwrap());
+            }
+            assert_eq!(&parcel.data[2..5], range.as_slice());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_write_with_sync_enabled() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .sync_writes(true)
+            .build()
+            .await;
+
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created with sync enabled");
+        for parcel in scaffold.parcel_files.values() {
+            store
+                .create_parcel(
+                    &scaffold.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created with sync enabled");
+        }
+        let label = store
+            .create_parcel_computing_sha(
+                "text/plain".to_owned(),
+                "synced.txt".to_owned(),
+                &mut std::io::Cursor::new(b"synced data".to_vec()),
+            )
+            .await
+            .expect("Unlabeled parcel should be created with sync enabled");
+
+        store
+            .get_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Synced invoice should be readable");
+        assert!(store.parcel_data_path(&label.sha256).is_file());
+        assert!(store.parcel_label_path(&label.sha256).is_file());
+    }
+
+    #[tokio::test]
+    async fn test_part_file_is_all_or_nothing() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let dest = root.path().join(INVOICE_TOML);
+
+        // Simulate an interrupted write by dropping the part file before it is finalized
+        let mut part = PartFile::new_in(dest.clone(), None, None).await.unwrap();
+        part.write_invoice(&scaffold.invoice, false).await.unwrap();
+        let part_path = part.path.clone();
+        assert!(part_path.exists(), "Part file should exist while writing");
+        drop(part);
+        assert!(
+            !part_path.exists(),
+            "Part file should be cleaned up if it is never finalized"
+        );
+        assert!(
+            !dest.exists(),
+            "Final file should not exist if the write never finished"
+        );
+
+        // A new write should not be blocked by the interrupted one
+        let mut part = PartFile::new_in(dest.clone(), None, None).await.unwrap();
+        part.write_invoice(&scaffold.invoice, false).await.unwrap();
+        part.finalize().await.unwrap();
+        assert!(!part_path.exists(), "Part file should be renamed away");
+        let inv: crate::Invoice = toml::from_slice(&std::fs::read(&dest).unwrap())
+            .expect("Finalized invoice should be complete");
+        assert_eq!(inv.bindle.id, scaffold.invoice.bindle.id);
+    }
+
+    #[tokio::test]
+    async fn test_should_check_invoice_exists() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        assert!(!store
+            .invoice_exists(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Checking a missing invoice should not error"));
+
+        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
+        store
+            .create_invoice(signed)
+            .await
+            .expect("Invoice should be created");
+        assert!(store
+            .invoice_exists(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Checking an existing invoice should not error"));
+
+        store
+            .yank_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Invoice should be yanked");
+        assert!(
+            store
+                .invoice_exists(&scaffold.invoice.bindle.id)
+                .await
+                .expect("Checking a yanked invoice should not error"),
+            "Yanked invoices should still exist"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_count_parcel_references() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+
+        assert_eq!(
+            0,
+            store
+                .parcel_reference_count(&parcel.sha)
+                .await
+                .expect("Counting in empty storage should succeed")
+        );
+
+        let mut other = scaffold.invoice.clone();
+        other.bindle.id = "another/bindle/1.0.0".parse().unwrap();
+        for inv in [scaffold.invoice.clone(), other] {
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+        assert_eq!(
+            2,
+            store
+                .parcel_reference_count(&parcel.sha)
+                .await
+                .expect("Counting references should succeed")
+        );
+
+        // Yanked invoices shouldn't count as a reference
+        store
+            .yank_invoice("another/bindle/1.0.0")
+            .await
+            .expect("Invoice should be yanked");
+        assert_eq!(
+            1,
+            store
+                .parcel_reference_count(&parcel.sha)
+                .await
+                .expect("Counting references should succeed")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_rebuild_index() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        for version in ["1.0.0", "1.1.0", "2.0.0"] {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = format!("{}/{}", inv.bindle.id.name(), version)
+                .parse()
+                .unwrap();
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+
+        // Building a provider warms its index, so empty the index out before rebuilding it
+        let index = crate::search::StrictEngine::default();
+        let fresh = FileProviderBuilder::new(root.path(), index.clone())
+            .build()
+            .await;
+        for inv in fresh.list_invoices().await.unwrap() {
+            index.remove(&inv).await.unwrap();
+        }
+        let name = scaffold.invoice.bindle.id.name();
+        assert!(index.query_ids(name).await.unwrap().is_empty());
+
+        let total = fresh
+            .rebuild_index()
+            .await
+            .expect("Index should be rebuilt");
+        assert_eq!(3, total);
+        assert_eq!(3, index.query_ids(name).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_should_skip_corrupt_invoices_when_rebuilding_index() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = scaffold.invoice.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        // Write an unparseable invoice next to the valid one
+        let corrupt = store.invoice_path("corrupt");
+        std::fs::create_dir_all(&corrupt).unwrap();
+        std::fs::write(corrupt.join(INVOICE_TOML), "not an invoice").unwrap();
+
+        let reopened = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        assert_eq!(
+            1,
+            reopened
+                .rebuild_index()
+                .await
+                .expect("Corrupt invoice should be skipped")
+        );
+        assert_eq!(
+            vec![id.version_string()],
+            reopened.list_versions(id.name(), false).await.unwrap()
+        );
+        assert_eq!(
+            id,
+            reopened
+                .get_latest_invoice(id.name())
+                .await
+                .expect("Valid invoice should still be found")
+                .bindle
+                .id
+        );
+    }
+
+    /// A search index that always fails to index
+    #[derive(Clone, Default)]
+    struct FailingIndex;
+
+    #[async_trait::async_trait]
+    impl Search for FailingIndex {
+        async fn query(
+            &self,
+            term: &str,
+            _filter: &str,
+            options: crate::search::SearchOptions,
+        ) -> anyhow::Result<crate::search::Matches> {
+            crate::search::NoopEngine::default()
+                .query(term, "", options)
+                .await
+        }
+
+        async fn query_ids(&self, _term: &str) -> anyhow::Result<Vec<Id>> {
+            Ok(Vec::new())
+        }
+
+        async fn index(&self, _: &crate::Invoice) -> anyhow::Result<()> {
+            anyhow::bail!("index is broken")
+        }
+
+        async fn remove(&self, _: &crate::Invoice) -> anyhow::Result<()> {
+            anyhow::bail!("index is broken")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_index_errors() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+
+        // By default, index failures should be ignored
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(root.path().to_owned(), FailingIndex).await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Index failures should not fail invoice creation by default");
+        store
+            .yank_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Index failures should not fail yanking by default");
+
+        // In strict mode, they should be returned as errors
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path().to_owned(), FailingIndex)
+            .fail_on_index_error(true)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect_err("Index failures should fail invoice creation in strict mode");
+        assert!(
+            !store
+                .invoice_path(&scaffold.invoice.canonical_name())
+                .exists(),
+            "Unindexed invoice should be cleaned up"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_only_remove_unindexed_invoice_file() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let with_id = |id: &str| {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = id.parse().unwrap();
+            inv
+        };
+        // With hierarchical naming, this is stored inside the directory of `foo/1.0.0`
+        let nested = with_id("foo/1.0.0/2.0.0");
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .naming_strategy(HierarchicalNaming)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(nested.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let failing = FileProviderBuilder::new(root.path(), FailingIndex)
+            .naming_strategy(HierarchicalNaming)
+            .fail_on_index_error(true)
+            .build()
+            .await;
+        let inv = with_id("foo/1.0.0");
+        failing
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect_err("Index failures should fail invoice creation in strict mode");
+        assert!(
+            !failing
+                .invoice_toml_path(&failing.invoice_name(&inv.bindle.id))
+                .exists(),
+            "Unindexed invoice should be cleaned up"
+        );
+        assert_eq!(
+            nested.bindle.id,
+            store
+                .get_invoice(&nested.bindle.id)
+                .await
+                .expect("Invoice stored beneath the unindexed one should be left alone")
+                .bindle
+                .id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_stage_writes_in_staging_dir() {
+        let root = tempdir().unwrap();
+        let staging = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .staging_dir(staging.path().join("staging"))
+        .build()
+        .await;
+
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Should be able to create invoice");
+        let mut parcels = scaffold.parcel_files.values();
+        let parcel = parcels.next().unwrap();
+        upload_scaffold_parcel(&store, &scaffold.invoice.bindle.id, parcel)
+            .await
+            .expect("Should be able to upload parcel");
+
+        // Resumable uploads are staged too
+        let resumed = parcels.next().unwrap();
+        let (first, rest) = resumed.data.split_at(resumed.data.len() / 2);
+        store
+            .create_parcel_chunk(&resumed.sha, 0, first)
+            .await
+            .expect("Should write first chunk");
+        assert!(
+            staging
+                .path()
+                .join("staging")
+                .join(format!("{}.{}", resumed.sha, PARTIAL_EXTENSION))
+                .exists(),
+            "Partial upload should be kept in the staging directory"
+        );
+        store
+            .create_parcel_chunk(&resumed.sha, first.len() as u64, rest)
+            .await
+            .expect("Should write second chunk");
+        let label = scaffold
+            .invoice
+            .parcel
+            .iter()
+            .flatten()
+            .find(|p| p.label.sha256 == resumed.sha)
+            .map(|p| p.label.clone())
+            .unwrap();
+        store
+            .finalize_parcel(&label)
+            .await
+            .expect("Should finalize staged upload");
+
+        let inv = store
+            .get_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Invoice should land in the root");
+        assert_eq!(inv.bindle.id, scaffold.invoice.bindle.id);
+        for p in [parcel, resumed] {
+            assert_eq!(
+                std::fs::read(store.parcel_data_path(&p.sha)).expect("Parcel should land in root"),
+                p.data
+            );
+        }
+        assert_eq!(
+            std::fs::read_dir(staging.path().join("staging"))
+                .unwrap()
+                .count(),
+            0,
+            "Nothing should be left in the staging directory"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_copy_file_into_place() {
+        // This is the path taken when the staging directory is on a different file system, which
+        // can't be set up in a test, so it is called directly
+        let staging = tempdir().unwrap();
+        let root = tempdir().unwrap();
+        let from = staging.path().join("staged");
+        let to = root.path().join("parcel.dat");
+        std::fs::write(&from, b"some parcel data").unwrap();
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&from, std::fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        copy_into_place(&from, &to, true)
+            .await
+            .expect("Should be able to copy file into place");
+
+        assert_eq!(std::fs::read(&to).unwrap(), b"some parcel data");
+        assert!(!from.exists(), "Original file should be removed");
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&to).unwrap().permissions().mode() & 0o777,
+                0o640,
+                "Permissions of the original file should be kept"
+            );
+        }
+        assert_eq!(
+            std::fs::read_dir(root.path()).unwrap().count(),
+            1,
+            "No temporary files should be left next to the destination"
+        );
+
+        // A failed copy leaves the destination alone
+        let missing = staging.path().join("missing");
+        assert!(copy_into_place(&missing, &to, false).await.is_err());
+        assert_eq!(std::fs::read(&to).unwrap(), b"some parcel data");
+        assert_eq!(std::fs::read_dir(root.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_should_drop_yanked_from_index() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let id = scaffold.invoice.bindle.id.clone();
+        let root = tempdir().unwrap();
+        let index = crate::search::StrictEngine::default();
+        let store = FileProviderBuilder::new(root.path(), index.clone())
+            .drop_yanked_from_index(true)
+            .build()
+            .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("should be able to create invoice");
+        assert_eq!(vec![id.clone()], index.query_ids(id.name()).await.unwrap());
+
+        store.yank_invoice(&id).await.expect("should yank invoice");
+        assert!(
+            index.query_ids(id.name()).await.unwrap().is_empty(),
+            "Yanked invoice should be removed from the index"
+        );
+        store
+            .get_yanked_invoice(&id)
+            .await
+            .expect("Yanked invoice should still be fetchable");
+
+        store
+            .unyank_invoice(&id)
+            .await
+            .expect("should unyank invoice");
+        assert_eq!(
+            vec![id.clone()],
+            index.query_ids(id.name()).await.unwrap(),
+            "Unyanked invoice should be indexed again"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_compute_storage_stats() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        assert_eq!(
+            StorageStats::default(),
+            store.storage_stats().await.expect("Empty stats"),
+            "An empty store should have no stats"
+        );
+
+        let v1 = testing::Scaffold::load("valid_v1").await;
+        let v2 = testing::Scaffold::load("valid_v2").await;
+        for scaffold in [&v1, &v2] {
+            store
+                .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+                .await
+                .expect("Invoice should be created");
+        }
+        // The first parcel is shared between both versions
+        for parcel in v2.parcel_files.values() {
+            store
+                .create_parcel(
+                    &v2.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+
+        let stats = store.storage_stats().await.expect("Should compute stats");
+        assert_eq!(2, stats.invoice_count);
+        assert_eq!(2, stats.parcel_count);
+        assert_eq!(20, stats.total_parcel_bytes);
+        assert_eq!(0, stats.dangling_parcel_count);
+
+        // Once v2 is yanked, its unique parcel is no longer referenced
+        store
+            .yank_invoice(&v2.invoice.bindle.id)
+            .await
+            .expect("Should be able to yank");
+        let stats = store.storage_stats().await.expect("Should compute stats");
+        assert_eq!(2, stats.invoice_count, "Yanked invoices should be counted");
+        assert_eq!(1, stats.dangling_parcel_count);
+    }
+
+    #[tokio::test]
+    async fn test_should_gc_parcels() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let v1 = testing::Scaffold::load("valid_v1").await;
+        let v2 = testing::Scaffold::load("valid_v2").await;
+        for scaffold in [&v1, &v2] {
+            store
+                .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+                .await
+                .expect("Invoice should be created");
+        }
+        for parcel in v2.parcel_files.values() {
+            store
+                .create_parcel(
+                    &v2.invoice.bindle.id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+
+        assert!(
+            store.gc_parcels(false).await.expect("Should gc").is_empty(),
+            "No parcels should be collected while all are referenced"
+        );
+
+        store
+            .yank_invoice(&v2.invoice.bindle.id)
+            .await
+            .expect("Should be able to yank");
+        assert!(
+            store.gc_parcels(true).await.expect("Should gc").is_empty(),
+            "Yanked invoices should keep their parcels when requested"
+        );
+
+        let shared = &v1.invoice.parcel.as_ref().unwrap()[0].label.sha256;
+        let dangling = v2
+            .parcel_files
+            .values()
+            .find(|p| &p.sha != shared)
+            .expect("v2 should have a unique parcel");
+        let removed = store.gc_parcels(false).await.expect("Should gc");
+        assert_eq!(vec![dangling.sha.clone()], removed);
+        assert!(
+            !store.parcel_data_path(&dangling.sha).exists(),
+            "Dangling parcel should be removed from disk"
+        );
+        assert!(
+            store.parcel_data_path(shared).exists(),
+            "Shared parcel should be retained"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_should_serialize_concurrent_yanks() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        let yanks = (0..2).map(|_| {
+            let store = store.clone();
+            let id = scaffold.invoice.bindle.id.clone();
+            tokio::spawn(async move { store.yank_invoice(id).await })
+        });
+        for res in futures::future::join_all(yanks).await {
+            res.expect("Task should not panic")
+                .expect("Concurrent yanks should succeed");
+        }
+
+        let raw = tokio::fs::read(store.invoice_toml_path(&scaffold.invoice.canonical_name()))
+            .await
+            .expect("Invoice should exist on disk");
+        let inv: crate::Invoice = toml::from_slice(&raw).expect("Invoice should be valid TOML");
+        assert!(inv.yanked.unwrap_or(false), "Invoice should be yanked");
+    }
+
+    #[tokio::test]
+    async fn test_should_get_latest_invoice() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let name = scaffold.invoice.bindle.id.name().to_owned();
+
+        assert!(
+            matches!(
+                store.get_latest_invoice(&name).await,
+                Err(ProviderError::NotFound { .. })
+            ),
+            "A bindle without any versions should not be found"
+        );
+
+        for version in ["1.0.0", "1.10.0", "1.2.0", "2.0.0-rc.1", "3.0.0"] {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = format!("{}/{}", name, version).parse().unwrap();
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+        // Another bindle with a higher version should not be picked up
+        let mut other = scaffold.invoice.clone();
+        other.bindle.id = format!("{}-other/9.0.0", name).parse().unwrap();
+        store
+            .create_invoice(NoopSigned(NoopVerified(other)))
+            .await
+            .expect("Invoice should be created");
+
+        let latest = store
+            .get_latest_invoice(&name)
+            .await
+            .expect("Should find latest invoice");
+        assert_eq!("3.0.0", latest.bindle.id.version_string());
+
+        // Once the latest is yanked, release versions should still win over the pre-release
+        store
+            .yank_invoice(format!("{}/3.0.0", name))
+            .await
+            .expect("Should be able to yank");
+        let latest = store
+            .get_latest_invoice(&name)
+            .await
+            .expect("Should find latest invoice");
+        assert_eq!(
+            "1.10.0",
+            latest.bindle.id.version_string(),
+            "Versions should be compared as semver and skip yanked and pre-release versions"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_add_parcels_to_invoice() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let id = &scaffold.invoice.bindle.id;
+        let mut parcels = scaffold.invoice.parcel.clone().unwrap();
+        let mut inv = scaffold.invoice.clone();
+        inv.parcel = None;
+        inv.signature = None;
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv)))
+            .await
+            .expect("Invoice should be created");
+
+        let missing = store
+            .add_parcels_to_invoice(id, parcels.clone())
+            .await
+            .expect("Parcels should be added");
+        let mut expected: Vec<crate::Label> = parcels.iter().map(|p| p.label.clone()).collect();
+        expected.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+        assert_eq!(expected, missing);
+        let inv = store.get_invoice(id).await.unwrap();
+        assert_eq!(Some(parcels.clone()), inv.parcel);
+
+        // Adding a parcel that is already in the invoice is a no-op
+        let uploaded = scaffold
+            .parcel_files
+            .values()
+            .find(|p| p.sha == parcels[0].label.sha256)
+            .unwrap();
+        store
+            .create_parcel(
+                id,
+                &uploaded.sha,
+                FramedRead::new(
+                    std::io::Cursor::new(uploaded.data.clone()),
+                    BytesCodec::new(),
+                ),
+            )
+            .await
+            .expect("Parcel should be created");
+        let missing = store
+            .add_parcels_to_invoice(id, vec![parcels[0].clone()])
+            .await
+            .unwrap();
+        assert_eq!(vec![parcels[1].label.clone()], missing);
+        assert_eq!(
+            2,
+            store.get_invoice(id).await.unwrap().parcel.unwrap().len()
+        );
+
+        store.yank_invoice(id).await.unwrap();
+        parcels[0].label.sha256 = "b".repeat(64);
+        assert!(matches!(
+            store.add_parcels_to_invoice(id, parcels).await,
+            Err(ProviderError::Yanked)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_not_add_parcels_to_signed_invoice() {
+        use crate::signature::KeyRing;
+        use crate::{SecretKeyEntry, SignatureRole, VerificationStrategy};
+        use std::convert::TryInto;
+
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let key = SecretKeyEntry::new("Test <test@example.com>", vec![SignatureRole::Creator]);
+        let keyring = KeyRing::new(vec![(&key).try_into().expect("convert to public key")]);
+        let strategy = VerificationStrategy::CreativeIntegrity;
+
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let mut parcels = scaffold.invoice.parcel.clone().unwrap();
+        let mut inv = scaffold.invoice.clone();
+        inv.signature = None;
+        inv.parcel = Some(vec![parcels.remove(0)]);
+        inv.sign(SignatureRole::Creator, &key)
+            .expect("Should be able to sign invoice");
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect("Invoice should be created");
+
+        assert!(matches!(
+            store.add_parcels_to_invoice(&inv.bindle.id, parcels).await,
+            Err(ProviderError::SignedInvoice)
+        ));
+        let stored = store.get_invoice(&inv.bindle.id).await.unwrap();
+        assert_eq!(1, stored.parcel.unwrap().len());
+        assert!(
+            store
+                .verify_invoice(&inv.bindle.id, &strategy, &keyring)
+                .await
+                .expect("Should be able to verify invoice"),
+            "Signatures should still verify after a rejected append"
+        );
+
+        // Adding parcels that are already in the invoice doesn't change it, so is allowed
+        store
+            .add_parcels_to_invoice(&inv.bindle.id, inv.parcel.clone().unwrap())
+            .await
+            .expect("Adding existing parcels should be a no-op");
+        assert!(store
+            .verify_invoice(&inv.bindle.id, &strategy, &keyring)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_should_keep_unknown_fields_when_yanking() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        // An invoice written by a newer version of Bindle, with fields this version doesn't know
+        let mut raw = toml::to_string(&scaffold.invoice).unwrap();
+        raw.insert_str(0, "futureField = \"from the future\"\n");
+        raw.push_str("\n[futureTable]\nanswer = 42\n");
+        let inv: crate::Invoice = toml::from_str(&raw).expect("Invoice should parse");
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect("Should be able to create invoice");
+
+        store
+            .yank_invoice(&inv.bindle.id)
+            .await
+            .expect("Should be able to yank invoice");
+        let stored: toml::Value = toml::from_slice(
+            &std::fs::read(store.invoice_toml_path(&store.invoice_name(&inv.bindle.id))).unwrap(),
+        )
+        .expect("Stored invoice should be valid TOML");
+        assert_eq!(stored["yanked"].as_bool(), Some(true));
+        assert_eq!(stored["futureField"].as_str(), Some("from the future"));
+        assert_eq!(stored["futureTable"]["answer"].as_integer(), Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_misspelled_fields() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        // The parcels are written under the wrong key, so they would be kept as an extra field
+        // and the invoice would look like it has no parcels
+        let raw = toml::to_string(&scaffold.invoice)
+            .unwrap()
+            .replace("[[parcel]]", "[[parcels]]")
+            .replace("[parcel.", "[parcels.");
+        let inv: crate::Invoice = toml::from_str(&raw).expect("Invoice should parse");
+        assert!(inv.parcel.is_none());
+        match store.create_invoice(NoopSigned(NoopVerified(inv))).await {
+            Err(ProviderError::Invalid(errors)) => assert_eq!(
+                errors,
+                vec![crate::ValidationError::MisspelledField(
+                    "parcels".to_owned()
+                )]
+            ),
+            res => panic!("Expected Invalid error, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_get_raw_invoice() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = &scaffold.invoice.bindle.id;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Should be able to create invoice");
+
+        let mut raw = Vec::new();
+        store
+            .get_invoice_raw(id)
+            .await
+            .expect("Should be able to get raw invoice")
+            .read_to_end(&mut raw)
+            .await
+            .unwrap();
+        assert_eq!(
+            raw,
+            std::fs::read(store.invoice_toml_path(&store.invoice_name(id))).unwrap(),
+            "Raw invoice should match the stored file byte for byte"
+        );
+
+        store.yank_invoice(id).await.expect("Should yank invoice");
+        assert!(matches!(
+            store.get_invoice_raw(id).await,
+            Err(ProviderError::Yanked)
+        ));
+        assert!(matches!(
+            store.get_invoice_raw("enterprise.com/missing/1.0.0").await,
+            Err(ProviderError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_should_get_parcel_with_timeout() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = &scaffold.invoice.bindle.id;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Should be able to create invoice");
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        upload_scaffold_parcel(&store, id, parcel)
+            .await
+            .expect("Should be able to upload parcel");
+
+        let mut data = Vec::new();
+        StreamReader::new(
+            store
+                .get_parcel_with_timeout(id, &parcel.sha, Duration::from_secs(10))
+                .await
+                .expect("Should be able to get parcel")
+                .map(|res| res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
+        )
+        .read_to_end(&mut data)
+        .await
+        .expect("Parcel should be read before the timeout");
+        assert_eq!(parcel.data, data);
+    }
+
+    #[tokio::test]
+    async fn test_should_time_out_stalled_parcel_reader() {
+        use sha2::{Digest, Sha256};
+
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        // The parcel needs to be big enough to be read in several chunks, so the reader can't
+        // just buffer all of it
+        let data = vec![7u8; 1024 * 1024];
+        let sha = format!("{:x}", Sha256::digest(&data));
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.signature = None;
+        let label = &mut inv.parcel.as_mut().unwrap()[0].label;
+        label.sha256 = sha.clone();
+        label.size = data.len() as u64;
+        let id = inv.bindle.id.clone();
+        store
+            .create_invoice(NoopSigned(NoopVerified(inv)))
+            .await
+            .expect("Should be able to create invoice");
+        store
+            .create_parcel(
+                &id,
+                &sha,
+                FramedRead::new(std::io::Cursor::new(data), BytesCodec::new()),
+            )
+            .await
+            .expect("Should be able to upload parcel");
+
+        let mut stream = store
+            .get_parcel_with_timeout(&id, &sha, Duration::from_millis(200))
+            .await
+            .expect("Should be able to get parcel");
+        assert!(matches!(stream.next().await, Some(Ok(_))));
+        // Stop reading like a stalled client would
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let mut timed_out = false;
+        while let Some(res) = stream.next().await {
+            if let Err(e) = res {
+                assert!(matches!(e, ProviderError::Timeout(_)), "Got {:?}", e);
+                timed_out = true;
+            }
+        }
+        assert!(timed_out, "A stalled reader should time out");
+    }
+
+    #[tokio::test]
+    async fn test_should_get_parcels_by_media_type() {
+        let root = tempdir().unwrap();
+        let mut scaffold = testing::Scaffold::load("valid_v2").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let id = scaffold.invoice.bindle.id.clone();
+        let wasm_sha = {
+            let label = &mut scaffold.invoice.parcel.as_mut().unwrap()[1].label;
+            label.media_type = "application/wasm".to_owned();
+            label.sha256.clone()
+        };
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Should be able to create invoice");
+        for parcel in scaffold.parcel_files.values() {
+            upload_scaffold_parcel(&store, &id, parcel)
+                .await
+                .expect("Should be able to upload parcel");
+        }
+
+        let parcels = store
+            .get_parcels_by_media_type(&id, &["application/wasm".to_owned()])
+            .await
+            .expect("Should be able to get parcels");
+        assert_eq!(1, parcels.len());
+        for (label, mut reader) in parcels {
+            assert_eq!(wasm_sha, label.sha256);
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).await.unwrap();
+            let expected = scaffold
+                .parcel_files
+                .values()
+                .find(|p| p.sha == wasm_sha)
+                .unwrap();
+            assert_eq!(expected.data, data);
+        }
+
+        let parcels = store
+            .get_parcels_by_media_type(&id, &["text/*".to_owned()])
+            .await
+            .expect("Should be able to get parcels");
+        assert_eq!(1, parcels.len());
+        assert_eq!("text/plain", parcels[0].0.media_type);
+
+        let parcels = store
+            .get_parcels_by_media_type(&id, &["text/plain".to_owned(), "application/*".to_owned()])
+            .await
+            .expect("Should be able to get parcels");
+        assert_eq!(2, parcels.len());
+
+        let parcels = store
+            .get_parcels_by_media_type(&id, &["image/png".to_owned()])
+            .await
+            .expect("Should be able to get parcels");
+        assert!(parcels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_should_record_creation_times() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let before = SystemTime::now();
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Should be able to create invoice");
+        let parcel = scaffold.parcel_files.values().next().unwrap();
+        upload_scaffold_parcel(&store, &scaffold.invoice.bindle.id, parcel)
+            .await
+            .expect("Should be able to upload parcel");
+        let after = SystemTime::now();
+
+        let created = store
+            .invoice_created(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Invoice creation time should be recorded");
+        assert!(before <= created && created <= after);
+        let created = store
+            .parcel_created(&parcel.sha)
+            .await
+            .expect("Parcel creation time should be recorded");
+        assert!(before <= created && created <= after);
+
+        // Backdate the first invoice rather than waiting, so the two invoices can be told apart
+        // even with a coarse system clock
+        let created_path = store
+            .invoice_toml_path(&store.invoice_name(&scaffold.invoice.bindle.id))
+            .with_file_name(CREATED_FILE);
+        let backdated = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        std::fs::write(
+            &created_path,
+            backdated
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            backdated,
+            store
+                .invoice_created(&scaffold.invoice.bindle.id)
+                .await
+                .unwrap()
+        );
+
+        // Only invoices created at or after the given time are listed
+        let mut newer = scaffold.invoice.clone();
+        newer.bindle.id = format!("{}/2.0.0", newer.bindle.id.name()).parse().unwrap();
+        store
+            .create_invoice(NoopSigned(NoopVerified(newer.clone())))
+            .await
+            .expect("Should be able to create invoice");
+        let ids = |invoices: Vec<crate::Invoice>| {
+            invoices
+                .into_iter()
+                .map(|inv| inv.bindle.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(store
+                .list_invoices_since(backdated + Duration::from_secs(1))
+                .await
+                .unwrap()),
+            vec![newer.bindle.id.clone()]
+        );
+        assert_eq!(
+            ids(store.list_invoices_since(backdated).await.unwrap()),
+            vec![scaffold.invoice.bindle.id.clone(), newer.bindle.id],
+            "Invoices should be listed from oldest to newest"
+        );
+        assert!(store
+            .list_invoices_since(SystemTime::now() + Duration::from_secs(60))
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(matches!(
+            store.invoice_created("enterprise.com/missing/1.0.0").await,
+            Err(ProviderError::NotFound { .. })
+        ));
+        assert!(matches!(
+            store.parcel_created(&"0".repeat(64)).await,
+            Err(ProviderError::NotFound { .. })
+        ));
+
+        // Data stored before creation times were recorded is treated as created at the epoch, and
+        // rewriting it doesn't change that
+        std::fs::remove_file(&created_path).unwrap();
+        store
+            .yank_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Should be able to yank invoice");
+        assert_eq!(
+            UNIX_EPOCH,
+            store
+                .invoice_created(&scaffold.invoice.bindle.id)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_list_catalog() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        assert!(store.catalog(true).await.unwrap().is_empty());
+
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        for id in [
+            "example.com/foo/1.10.0",
+            "example.com/foo/1.2.0",
+            "example.com/foo/2.0.0-rc.1",
+            "example.com/bar/0.1.0",
+            "example.com/bar/0.2.0",
+            "other.com/baz/1.0.0",
+        ] {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = id.parse().unwrap();
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+        store.yank_invoice("example.com/bar/0.2.0").await.unwrap();
+        store.yank_invoice("other.com/baz/1.0.0").await.unwrap();
+
+        let expected: BTreeMap<String, Vec<String>> = [
+            ("example.com/bar", vec!["0.1.0", "0.2.0"]),
+            ("example.com/foo", vec!["1.2.0", "1.10.0", "2.0.0-rc.1"]),
+            ("other.com/baz", vec!["1.0.0"]),
+        ]
+        .iter()
+        .map(|(name, versions)| {
+            (
+                name.to_string(),
+                versions.iter().map(|v| v.to_string()).collect(),
+            )
+        })
+        .collect();
+        assert_eq!(expected, store.catalog(true).await.unwrap());
+
+        let catalog = store.catalog(false).await.unwrap();
+        assert_eq!(
+            vec!["example.com/bar", "example.com/foo"],
+            catalog.keys().collect::<Vec<_>>(),
+            "Names with only yanked versions should be left out"
+        );
+        assert_eq!(vec!["0.1.0"], catalog["example.com/bar"]);
+    }
+
+    #[tokio::test]
+    async fn test_should_find_invoices_by_annotation() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        let name = inv.bindle.id.name().to_owned();
+        for (version, channel) in [
+            ("1.0.0", Some("stable")),
+            ("1.1.0", None),
+            ("2.0.0", Some("stable")),
+            ("3.0.0", Some("beta")),
+        ] {
+            inv.bindle.id = format!("{}/{}", name, version).parse().unwrap();
+            inv.annotations = channel.map(|c| {
+                let mut annotations = crate::AnnotationMap::new();
+                annotations.insert("channel".to_owned(), c.to_owned());
+                annotations
+            });
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+                .await
+                .expect("Invoice should be created");
+        }
+
+        let versions = |invoices: Vec<crate::Invoice>| {
+            let mut versions: Vec<String> = invoices
+                .into_iter()
+                .map(|inv| inv.bindle.id.version_string())
+                .collect();
+            versions.sort();
+            versions
+        };
+        let found = store
+            .find_invoices_by_annotation("channel", "stable", false)
+            .await
+            .expect("Should be able to search annotations");
+        assert_eq!(vec!["1.0.0", "2.0.0"], versions(found));
+
+        store
+            .yank_invoice(format!("{}/1.0.0", name))
+            .await
+            .expect("Should be able to yank");
+        let found = store
+            .find_invoices_by_annotation("channel", "stable", false)
+            .await
+            .expect("Should be able to search annotations");
+        assert_eq!(vec!["2.0.0"], versions(found));
+        let found = store
+            .find_invoices_by_annotation("channel", "stable", true)
+            .await
+            .expect("Should be able to search annotations");
+        assert_eq!(vec!["1.0.0", "2.0.0"], versions(found));
+    }
+
+    #[tokio::test]
+    async fn test_should_update_invoice_annotations() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let mut inv = testing::Scaffold::load("valid_v2").await.invoice;
+        let mut annotations = crate::AnnotationMap::new();
+        annotations.insert("channel".to_owned(), "beta".to_owned());
+        annotations.insert("team".to_owned(), "warp".to_owned());
+        inv.annotations = Some(annotations);
+        let (original, _) = store
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect("Invoice should be created");
+        // Make sure the invoice is cached so we can check the cache is invalidated
+        store.get_invoice(&inv.bindle.id).await.unwrap();
+
+        let mut update = BTreeMap::new();
+        update.insert("channel".to_owned(), "stable".to_owned());
+        update.insert("promoted".to_owned(), "true".to_owned());
+        store
+            .update_invoice_annotations(&inv.bindle.id, update)
+            .await
+            .expect("Should be able to update annotations");
+
+        // Read from a fresh provider to make sure the change was persisted to disk
+        let reopened = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        for updated in [
+            store.get_invoice(&inv.bindle.id).await.unwrap(),
+            reopened.get_invoice(&inv.bindle.id).await.unwrap(),
+        ] {
+            let annotations = updated.annotations.clone().unwrap();
+            assert_eq!(3, annotations.len());
+            assert_eq!("stable", annotations["channel"]);
+            assert_eq!("warp", annotations["team"]);
+            assert_eq!("true", annotations["promoted"]);
+
+            // Everything else should be untouched
+            let mut without_annotations = updated;
+            without_annotations.annotations = original.annotations.clone();
+            assert_eq!(
+                toml::to_string(&original).unwrap(),
+                toml::to_string(&without_annotations).unwrap()
+            );
+        }
+
+        store
+            .yank_invoice(&inv.bindle.id)
+            .await
+            .expect("Should be able to yank");
+        assert!(
+            matches!(
+                store
+                    .update_invoice_annotations(&inv.bindle.id, BTreeMap::new())
+                    .await,
+                Err(ProviderError::Yanked)
+            ),
+            "Yanked invoices should not be updated"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_find_parcels_by_annotation() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let parcel = |name: &str, os: Option<&str>| crate::Parcel {
+            label: crate::Label {
+                annotations: os.map(|os| {
+                    let mut annotations = crate::AnnotationMap::new();
+                    annotations.insert("os".to_owned(), os.to_owned());
+                    annotations
+                }),
+                ..crate::Label::new(
+                    name.to_owned(),
+                    DigestAlgorithm::Sha256.digest(name.as_bytes()),
+                )
+            },
+            conditions: None,
+        };
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.group = None;
+        let name = inv.bindle.id.name().to_owned();
+        let versions = [
+            (
+                "1.0.0",
+                vec![
+                    parcel("linux-bin", Some("linux")),
+                    parcel("windows-bin", Some("windows")),
+                    parcel("readme", None),
+                ],
+            ),
+            // This shares the linux parcel with the first version
+            (
+                "2.0.0",
+                vec![
+                    parcel("linux-bin", Some("linux")),
+                    parcel("linux-lib", Some("linux")),
+                ],
+            ),
+            ("3.0.0", vec![parcel("linux-yanked", Some("linux"))]),
+        ];
+        for (version, parcels) in versions {
+            inv.bindle.id = format!("{}/{}", name, version).parse().unwrap();
+            inv.parcel = Some(parcels);
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+                .await
+                .expect("Invoice should be created");
+        }
+        store
+            .yank_invoice(format!("{}/3.0.0", name))
+            .await
+            .expect("Should be able to yank");
+
+        let mut found: Vec<String> = store
+            .find_parcels_by_annotation("os", "linux")
+            .await
+            .expect("Should be able to search annotations")
+            .into_iter()
+            .map(|l| l.name)
+            .collect();
+        found.sort();
+        assert_eq!(vec!["linux-bin", "linux-lib"], found);
+
+        assert_eq!(
+            1,
+            store
+                .find_parcels_by_annotation("os", "windows")
+                .await
+                .unwrap()
+                .len()
+        );
+        assert!(store
+            .find_parcels_by_annotation("arch", "linux")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_should_clean_up_failed_parcel_upload() {
+        let root = tempdir().unwrap();
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("Invoice should be created");
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+
+        // Simulate the upload being cut off part way through
+        let failing = tokio_stream::iter(vec![
+            Ok(bytes::Bytes::from(parcel.data[..2].to_vec())),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "connection reset",
+            )),
+        ]);
+        store
+            .create_parcel(&scaffold.invoice.bindle.id, &parcel.sha, failing)
+            .await
+            .expect_err("Failed upload should return an error");
+        assert!(
+            !store.parcel_path(&parcel.sha).exists(),
+            "Parcel directory should be removed after a failed upload"
+        );
+
+        store
+            .create_parcel(
+                &scaffold.invoice.bindle.id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("Retrying the upload should succeed");
+        assert!(store.parcel_data_path(&parcel.sha).is_file());
+    }
+
+    #[tokio::test]
+    async fn test_should_sniff_media_types() {
+        use sha2::Digest;
+
+        let png: Vec<u8> =
+            b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0".to_vec();
+        let toml_data = b"[package]\nname = \"foo\"\nversion = \"1.0.0\"\n".to_vec();
+        let known = b"\x89PNG\r\n\x1a\n but declared as something else".to_vec();
+        let parcels = [
+            ("image.png", png.clone(), OCTET_STREAM),
+            ("Cargo.toml", toml_data.clone(), OCTET_STREAM),
+            ("declared.bin", known.clone(), "application/x-custom"),
+        ];
+
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.parcel = Some(
+            parcels
+                .iter()
+                .map(|(name, data, media_type)| crate::Parcel {
+                    label: crate::Label {
+                        sha256: format!("{:x}", sha2::Sha256::digest(data)),
+                        name: name.to_string(),
+                        media_type: media_type.to_string(),
+                        size: data.len() as u64,
+                        ..crate::Label::default()
+                    },
+                    conditions: None,
+                })
+                .collect(),
+        );
+        inv.group = None;
+
+        for sniff in [true, false] {
+            let root = tempdir().unwrap();
+            let store = FileProviderBuilder::new(
+                root.path().to_owned(),
+                crate::search::StrictEngine::default(),
+            )
+            .sniff_media_types(sniff)
+            .build()
+            .await;
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+                .await
+                .expect("Invoice should be created");
+            for (parcel, (_, data, _)) in inv.parcel.as_ref().unwrap().iter().zip(parcels.iter()) {
+                store
+                    .create_parcel(
+                        &inv.bindle.id,
+                        &parcel.label.sha256,
+                        FramedRead::new(std::io::Cursor::new(data.clone()), BytesCodec::new()),
+                    )
+                    .await
+                    .expect("Parcel should be created");
+            }
+
+            let mut media_types = Vec::new();
+            for parcel in inv.parcel.as_ref().unwrap() {
+                let stored = store
+                    .read_parcel_label(&parcel.label.sha256)
+                    .await
+                    .expect("Should be able to read label");
+                media_types.push(
+                    stored
+                        .map(|l| l.media_type)
+                        .unwrap_or_else(|| parcel.label.media_type.clone()),
+                );
+            }
+            assert_eq!(
+                inv.parcel.as_ref().unwrap(),
+                &store
+                    .get_invoice(&inv.bindle.id)
+                    .await
+                    .unwrap()
+                    .parcel
+                    .unwrap(),
+                "Sniffing should never modify the invoice"
+            );
+            if sniff {
+                assert_eq!(
+                    vec!["image/png", "application/toml", "application/x-custom"],
+                    media_types
+                );
+            } else {
+                assert_eq!(
+                    vec![OCTET_STREAM, OCTET_STREAM, "application/x-custom"],
+                    media_types,
+                    "Media types should not change when sniffing is disabled"
+                );
+            }
+
+            // The sniffed bytes must still be stored
+            let mut stored = Vec::new();
+            let mut stream = store
+                .get_parcel(
+                    &inv.bindle.id,
+                    &inv.parcel.as_ref().unwrap()[0].label.sha256,
+                )
+                .await
+                .expect("Should be able to get parcel");
+            while let Some(chunk) = stream.next().await {
+                stored.extend_from_slice(&chunk.expect("Should be able to read chunk"));
+            }
+            assert_eq!(png, stored);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_version_index() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let name = scaffold.invoice.bindle.id.name().to_owned();
+        assert!(store.list_versions(&name, true).await.unwrap().is_empty());
+
+        for version in ["1.10.0", "1.2.0", "1.0.0-alpha", "1.0.0"] {
+            let mut inv = scaffold.invoice.clone();
+            inv.bindle.id = format!("{}/{}", name, version).parse().unwrap();
+            store
+                .create_invoice(NoopSigned(NoopVerified(inv)))
+                .await
+                .expect("Invoice should be created");
+        }
+        assert_eq!(
+            vec!["1.0.0-alpha", "1.0.0", "1.2.0", "1.10.0"],
+            store.list_versions(&name, false).await.unwrap()
+        );
+
+        store
+            .yank_invoice(format!("{}/1.2.0", name))
+            .await
+            .expect("Should be able to yank");
+        assert_eq!(
+            vec!["1.0.0-alpha", "1.0.0", "1.10.0"],
+            store.list_versions(&name, false).await.unwrap(),
+            "Yanked versions should be excluded"
+        );
+        assert_eq!(
+            vec!["1.0.0-alpha", "1.0.0", "1.2.0", "1.10.0"],
+            store.list_versions(&name, true).await.unwrap(),
+            "Yanked versions should be included when requested"
+        );
+
+        // A new provider over the same directory should rebuild the same index from disk
+        let rebuilt = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        for include_yanked in [true, false] {
+            assert_eq!(
+                store.list_versions(&name, include_yanked).await.unwrap(),
+                rebuilt.list_versions(&name, include_yanked).await.unwrap()
+            );
+        }
+
+        store
+            .unyank_invoice(format!("{}/1.2.0", name))
+            .await
+            .expect("Should be able to unyank");
+        assert_eq!(
+            vec!["1.0.0-alpha", "1.0.0", "1.2.0", "1.10.0"],
+            store.list_versions(&name, false).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+        store
+            .health_check()
+            .await
+            .expect("Writable root should be healthy");
+        assert!(store.invoice_path("").is_dir());
+        assert!(store.parcel_path("").is_dir());
+        assert_eq!(
+            2,
+            std::fs::read_dir(root.path()).unwrap().count(),
+            "Health check should clean up after itself"
+        );
+
+        // A root that is a file can never be healthy
+        let file_root = root.path().join("not-a-dir");
+        std::fs::write(&file_root, b"hello").unwrap();
+        let store = FileProvider::new(file_root, crate::search::StrictEngine::default()).await;
+        assert!(matches!(
+            store.health_check().await,
+            Err(ProviderError::Io { path: Some(_), .. })
+        ));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn test_health_check_read_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempdir().unwrap();
+        let read_only = root.path().join("read-only");
+        std::fs::create_dir(&read_only).unwrap();
+        std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o555)).unwrap();
+        // Privileged users (such as root in a container) can write regardless of permissions, in
+        // which case there is nothing to test
+        if std::fs::write(read_only.join("probe"), b"").is_ok() {
+            return;
+        }
+
+        let store = FileProvider::new(&read_only, crate::search::StrictEngine::default()).await;
+        let err = store
+            .health_check()
+            .await
+            .expect_err("Read only root should not be healthy");
+        assert!(
+            matches!(err, ProviderError::Io { path: Some(_), .. }),
+            "Expected an IO error with a path, got {:?}",
+            err
+        );
+        std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_should_create_invoice_with_plan() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let uploaded = scaffold.parcel_files.get("parcel").unwrap();
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(root.path(), crate::search::StrictEngine::default()).await;
+
+        // Upload one parcel through an earlier version so it is already in storage
+        let mut first = scaffold.invoice.clone();
+        first.bindle.id = format!("{}/0.1.0", scaffold.invoice.bindle.id.name())
+            .parse()
+            .unwrap();
+        store
+            .create_invoice(NoopSigned(NoopVerified(first.clone())))
+            .await
+            .expect("should be able to create invoice");
+        store
+            .create_parcel(
+                &first.bindle.id,
+                &uploaded.sha,
+                FramedRead::new(
+                    std::io::Cursor::new(uploaded.data.clone()),
+                    BytesCodec::new(),
+                ),
+            )
+            .await
+            .expect("create parcel");
+
+        let plan = store
+            .create_invoice_with_plan(NoopSigned(NoopVerified(scaffold.invoice.clone())))
+            .await
+            .expect("should be able to create invoice");
+        assert_eq!(
+            store.invoice_name(&scaffold.invoice.bindle.id),
+            plan.invoice_name
+        );
+
+        let mut expected: Vec<String> = scaffold
+            .parcel_files
+            .values()
+            .filter(|p| p.sha != uploaded.sha)
+            .map(|p| p.sha.clone())
+            .collect();
+        expected.sort();
+        let mut planned: Vec<String> = plan
+            .parcels
+            .iter()
+            .map(|p| p.label.sha256.clone())
+            .collect();
+        planned.sort();
+        assert_eq!(
+            expected, planned,
+            "Plan should list exactly the missing parcels"
         );
 
-        // Close the file handle to avoid any problems with unfinished IO operations
-        self.file.shutdown().await?;
+        for upload in plan.parcels {
+            assert_eq!(
+                format!(
+                    "{}/{}/{}",
+                    PARCEL_DIRECTORY, upload.label.sha256, PARCEL_DAT
+                ),
+                upload.location
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_reject_writes_when_read_only() {
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+        let root = tempdir().unwrap();
+        let id = scaffold.invoice.bindle.id.clone();
 
-        tokio::fs::rename(&self.path, &self.final_location)
+        // Populate the storage with a writable provider first
+        let writer = FileProvider::new(root.path(), crate::search::StrictEngine::default()).await;
+        writer
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
             .await
-            .map_err(|e| e.into())
-    }
-}
+            .expect("should be able to create invoice");
+        writer
+            .create_parcel(
+                &id,
+                &parcel.sha,
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+            )
+            .await
+            .expect("create parcel");
 
-impl Drop for PartFile {
-    fn drop(&mut self) {
-        // Attempt to delete the file, logging an error if the delete failed
-        if let Err(e) = std::fs::remove_file(&self.path) {
-            // If it is any other error besides NotFound, log the error
-            if !matches!(e.kind(), std::io::ErrorKind::NotFound) {
-                error!(error = %e, "Unable to clean up part file, this could lead to conflicts");
-            }
-        }
-    }
-}
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .read_only(true)
+            .build()
+            .await;
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::verification::NoopVerified;
-    use crate::{testing, NoopSigned};
-    use tempfile::tempdir;
-    use tokio::io::AsyncReadExt;
+        let mut other = scaffold.invoice.clone();
+        other.bindle.id = format!("{}/2.0.0", id.name()).parse().unwrap();
+        assert!(matches!(
+            store.create_invoice(NoopSigned(NoopVerified(other))).await,
+            Err(ProviderError::ReadOnly)
+        ));
+        assert!(matches!(
+            store
+                .create_parcel(
+                    &id,
+                    &parcel.sha,
+                    FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
+                )
+                .await,
+            Err(ProviderError::ReadOnly)
+        ));
+        assert!(matches!(
+            store.yank_invoice(&id).await,
+            Err(ProviderError::ReadOnly)
+        ));
+        assert!(matches!(
+            store.unyank_invoice(&id).await,
+            Err(ProviderError::ReadOnly)
+        ));
+        assert!(matches!(
+            store.update_invoice_annotations(&id, BTreeMap::new()).await,
+            Err(ProviderError::ReadOnly)
+        ));
+        assert!(matches!(
+            store.delete_parcel(&parcel.sha).await,
+            Err(ProviderError::ReadOnly)
+        ));
+        assert!(matches!(
+            store.gc_parcels(false).await,
+            Err(ProviderError::ReadOnly)
+        ));
 
-    #[tokio::test]
-    async fn test_should_generate_paths() {
-        let f = FileProvider::new("test", crate::search::StrictEngine::default()).await;
-        assert_eq!(PathBuf::from("test/invoices/123"), f.invoice_path("123"));
+        // Reads should all still work
+        store.get_invoice(&id).await.expect("should read invoice");
+        assert!(store.invoice_exists(&id).await.unwrap());
+        assert_eq!(1, store.list_invoices().await.unwrap().len());
+        assert_eq!(1, store.query_invoices(id.name()).await.unwrap().len());
+        assert!(store.parcel_exists(&id, &parcel.sha).await.unwrap());
         assert_eq!(
-            PathBuf::from("test/invoices/123/invoice.toml"),
-            f.invoice_toml_path("123")
+            parcel.data.len() as u64,
+            store.get_parcel_size(&parcel.sha).await.unwrap()
         );
-        assert_eq!(PathBuf::from("test/parcels/123"), f.parcel_path("123"));
-        assert_eq!(
-            PathBuf::from("test/parcels/123/parcel.dat"),
-            f.parcel_data_path("123")
+        let mut stream = store
+            .get_parcel(&id, &parcel.sha)
+            .await
+            .expect("should get parcel");
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(parcel.data, data);
+        store.health_check().await.expect("should be healthy");
+        assert!(
+            !store
+                .get_yanked_invoice(&id)
+                .await
+                .unwrap()
+                .yanked
+                .unwrap_or(false),
+            "Invoice should not have been yanked"
         );
     }
 
     #[tokio::test]
-    async fn test_should_create_yank_invoice() {
-        // Create a temporary directory
-        let root = tempdir().unwrap();
+    async fn test_should_use_binary_invoice_cache() {
         let scaffold = testing::Scaffold::load("valid_v1").await;
-        let store = FileProvider::new(
-            root.path().to_owned(),
-            crate::search::StrictEngine::default(),
-        )
-        .await;
-        let inv_name = scaffold.invoice.canonical_name();
-
-        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
-        // Create an invoice
-        let (_, missing) = store.create_invoice(signed).await.unwrap();
-        assert_eq!(1, missing.len());
-
-        // Out-of-band read the invoice
-        assert!(store.invoice_toml_path(&inv_name).exists());
+        let root = tempdir().unwrap();
+        // Disable the in-memory cache so every read goes to disk
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .cache_size(0)
+            .binary_cache(true)
+            .build()
+            .await;
+        let id = scaffold.invoice.bindle.id.clone();
+        let name = store.invoice_name(&id);
+        let bin_path = store.invoice_bin_path(&name);
 
-        // Yank the invoice
         store
-            .yank_invoice(&scaffold.invoice.bindle.id)
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
             .await
-            .unwrap();
+            .expect("should be able to create invoice");
+        store.get_invoice(&id).await.expect("should read invoice");
+        assert!(bin_path.is_file(), "Binary cache should be written on read");
 
-        // Make sure the invoice is yanked
-        let inv2 = store
-            .get_yanked_invoice(&scaffold.invoice.bindle.id)
-            .await
-            .unwrap();
-        assert!(inv2.yanked.unwrap_or(false));
+        let toml_path = store.invoice_toml_path(&name);
+        let toml_sha = |path: &Path| {
+            use sha2::Digest;
+            format!("{:x}", sha2::Sha256::digest(std::fs::read(path).unwrap()))
+        };
 
-        // Sanity check that this produces an error
-        assert!(store.get_invoice(scaffold.invoice.bindle.id).await.is_err());
-    }
+        // Replace the binary copy with different data to prove that it is what gets read
+        let mut cached = scaffold.invoice.clone();
+        cached.annotations = Some(
+            vec![("source".to_owned(), "binary".to_owned())]
+                .into_iter()
+                .collect(),
+        );
+        let write_bin = |inv: &crate::Invoice, toml_sha256: String| {
+            let data = to_cbor(&BinaryInvoice {
+                toml_sha256,
+                invoice: inv,
+            })
+            .unwrap();
+            std::fs::write(&bin_path, data).unwrap();
+        };
+        write_bin(&cached, toml_sha(&toml_path));
+        let inv = store.get_invoice(&id).await.expect("should read invoice");
+        assert_eq!(
+            Some("binary"),
+            inv.annotations.unwrap().get("source").map(String::as_str),
+            "Binary cache should be used when it was made from the current TOML"
+        );
 
-    #[tokio::test]
-    async fn test_should_reject_yanked_invoice() {
-        // Create a temporary directory
-        let root = tempdir().unwrap();
-        let mut scaffold = testing::Scaffold::load("valid_v1").await;
-        scaffold.invoice.yanked = Some(true);
-        let store = FileProvider::new(
-            root.path().to_owned(),
-            crate::search::StrictEngine::default(),
-        )
-        .await;
+        // Changing the TOML should invalidate the binary copy
+        let mut updated = scaffold.invoice.clone();
+        updated.annotations = Some(
+            vec![("source".to_owned(), "toml".to_owned())]
+                .into_iter()
+                .collect(),
+        );
+        std::fs::write(&toml_path, toml::to_vec(&updated).unwrap()).unwrap();
+        let inv = store.get_invoice(&id).await.expect("should read invoice");
+        assert_eq!(
+            Some("toml"),
+            inv.annotations.unwrap().get("source").map(String::as_str),
+            "Stale binary cache should not be used"
+        );
+        let rebuilt: BinaryInvoice<crate::Invoice> =
+            from_cbor(&std::fs::read(&bin_path).unwrap()).unwrap();
+        assert_eq!(toml_sha(&toml_path), rebuilt.toml_sha256);
+        assert_eq!(
+            Some("toml"),
+            rebuilt
+                .invoice
+                .annotations
+                .unwrap()
+                .get("source")
+                .map(String::as_str),
+            "Binary cache should be rebuilt from the TOML"
+        );
 
-        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
-        assert!(store.create_invoice(signed).await.is_err());
+        // Yanking should remove the binary copy, and a copy of the un-yanked invoice written
+        // afterwards (as a reader racing the yank could) should not be used
+        let pre_yank_sha = toml_sha(&toml_path);
+        store.yank_invoice(&id).await.expect("should yank invoice");
+        assert!(
+            !bin_path.exists(),
+            "Binary cache should be removed on write"
+        );
+        write_bin(&updated, pre_yank_sha);
+        assert!(
+            matches!(store.get_invoice(&id).await, Err(ProviderError::Yanked)),
+            "Stale binary cache should not hide the yank"
+        );
     }
 
+    #[cfg(target_family = "unix")]
     #[tokio::test]
-    async fn test_should_write_read_parcel() {
+    async fn test_should_set_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
         let scaffold = testing::Scaffold::load("valid_v1").await;
         let parcel = scaffold.parcel_files.get("parcel").unwrap();
-        let root = tempdir().expect("create tempdir");
-        let store = FileProvider::new(
-            root.path().to_owned(),
-            crate::search::StrictEngine::default(),
-        )
-        .await;
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(root.path(), crate::search::StrictEngine::default())
+            .mode(0o600)
+            .build()
+            .await;
 
-        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
-        // Create the invoice so we can create a parcel
         store
-            .create_invoice(signed)
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
             .await
             .expect("should be able to create invoice");
-
         store
             .create_parcel(
                 &scaffold.invoice.bindle.id,
@@ -757,108 +8731,238 @@ mod test {
             .await
             .expect("create parcel");
 
-        // Now make sure the parcels reads as existing
-        assert!(
-            store
-                .parcel_exists(&scaffold.invoice.bindle.id, &parcel.sha)
-                .await
-                .expect("Shouldn't get an error while checking for parcel existence"),
-            "Parcel should be reported as existing"
+        let mode = |path: PathBuf| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(0o600, mode(store.parcel_data_path(&parcel.sha)));
+        assert_eq!(0o700, mode(store.parcel_path(&parcel.sha)));
+        assert_eq!(
+            0o600,
+            mode(store.invoice_toml_path(&store.invoice_name(&scaffold.invoice.bindle.id)))
         );
+    }
 
-        // Attempt to read the parcel from the store
-        let mut data = Vec::new();
-        let stream = store
-            .get_parcel(&scaffold.invoice.bindle.id, &parcel.sha)
+    #[tokio::test]
+    async fn test_should_list_parcel_labels() {
+        let root = tempdir().unwrap();
+        let store = FileProvider::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .await;
+
+        let scaffold = testing::Scaffold::load("valid_v2").await;
+        store
+            .create_invoice(NoopSigned(NoopVerified(scaffold.invoice.clone())))
             .await
-            .expect("load parcel data");
-        let mut reader = StreamReader::new(
-            stream.map(|res| res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
+            .expect("Invoice should be created");
+        let labels = store
+            .list_parcel_labels(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Should be able to list parcel labels");
+        let expected: Vec<String> = scaffold
+            .invoice
+            .parcel
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|p| p.label.name.clone())
+            .collect();
+        assert_eq!(2, labels.len());
+        assert_eq!(
+            expected,
+            labels.into_iter().map(|l| l.name).collect::<Vec<_>>()
         );
-        reader
-            .read_to_end(&mut data)
+
+        // An invoice with no parcels should return an empty list
+        let mut empty = scaffold.invoice.clone();
+        // The scaffold is already version 2.0.0, so use a version that isn't stored yet
+        empty.bindle.id = format!("{}/3.0.0", empty.bindle.id.name()).parse().unwrap();
+        empty.parcel = None;
+        empty.group = None;
+        store
+            .create_invoice(NoopSigned(NoopVerified(empty.clone())))
             .await
-            .expect("read file into string");
-        assert_eq!(data, parcel.data);
+            .expect("Invoice should be created");
+        assert!(store
+            .list_parcel_labels(&empty.bindle.id)
+            .await
+            .expect("Should be able to list parcel labels")
+            .is_empty());
+
+        store
+            .yank_invoice(&scaffold.invoice.bindle.id)
+            .await
+            .expect("Should be able to yank");
+        assert!(
+            matches!(
+                store.list_parcel_labels(&scaffold.invoice.bindle.id).await,
+                Err(ProviderError::Yanked)
+            ),
+            "Listing labels of a yanked invoice should fail"
+        );
     }
 
     #[tokio::test]
-    async fn test_should_store_and_retrieve_bindle() {
-        let root = tempdir().expect("create tempdir");
+    async fn test_should_verify_invoice_signatures() {
+        use crate::signature::KeyRing;
+        use crate::{SecretKeyEntry, SignatureRole, VerificationStrategy};
+        use std::convert::TryInto;
+
+        let root = tempdir().unwrap();
         let store = FileProvider::new(
             root.path().to_owned(),
             crate::search::StrictEngine::default(),
         )
         .await;
 
-        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let key = SecretKeyEntry::new("Test <test@example.com>", vec![SignatureRole::Creator]);
+        let keyring = KeyRing::new(vec![(&key).try_into().expect("convert to public key")]);
+        let strategy = VerificationStrategy::CreativeIntegrity;
 
-        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
-        // Store an invoice first and then create the parcel for it
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.signature = None;
+        inv.sign(SignatureRole::Creator, &key)
+            .expect("Should be able to sign invoice");
         store
-            .create_invoice(signed)
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
             .await
-            .expect("should be able to create an invoice");
+            .expect("Invoice should be created");
+        assert!(store
+            .verify_invoice(&inv.bindle.id, &strategy, &keyring)
+            .await
+            .expect("Should be able to verify invoice"));
 
-        let parcel = scaffold.parcel_files.get("parcel").unwrap();
+        // A key that isn't on the keyring should not verify
+        let other = SecretKeyEntry::new("Other <other@example.com>", vec![SignatureRole::Creator]);
+        let other_keyring = KeyRing::new(vec![(&other).try_into().expect("convert to public key")]);
+        assert!(!store
+            .verify_invoice(&inv.bindle.id, &strategy, &other_keyring)
+            .await
+            .expect("Should be able to verify invoice"));
 
+        // Changing the version after signing invalidates the signature
+        let mut tampered = inv.clone();
+        tampered.bindle.id = format!("{}/2.0.0", inv.bindle.id.name()).parse().unwrap();
         store
-            .create_parcel(
-                &scaffold.invoice.bindle.id,
-                &parcel.sha,
-                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
-            )
+            .create_invoice(NoopSigned(NoopVerified(tampered.clone())))
             .await
-            .expect("unable to store the parcel");
+            .expect("Invoice should be created");
+        assert!(!store
+            .verify_invoice(&tampered.bindle.id, &strategy, &keyring)
+            .await
+            .expect("Should be able to verify invoice"));
+    }
 
-        // Get the bindle
-        let inv = store
-            .get_invoice(&scaffold.invoice.bindle.id)
+    #[tokio::test]
+    async fn test_should_find_missing_parcels_with_bounded_concurrency() {
+        use sha2::Digest;
+
+        let root = tempdir().unwrap();
+        let store = FileProviderBuilder::new(
+            root.path().to_owned(),
+            crate::search::StrictEngine::default(),
+        )
+        .parcel_check_concurrency(4)
+        .build()
+        .await;
+
+        let data: Vec<Vec<u8>> = (0..200)
+            .map(|i| format!("parcel number {}", i).into_bytes())
+            .collect();
+        let parcels: Vec<crate::Parcel> = data
+            .iter()
+            .enumerate()
+            .map(|(i, d)| crate::Parcel {
+                label: crate::Label {
+                    sha256: format!("{:x}", sha2::Sha256::digest(d)),
+                    name: format!("parcel-{}.txt", i),
+                    size: d.len() as u64,
+                    ..crate::Label::default()
+                },
+                conditions: None,
+            })
+            .collect();
+
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.parcel = Some(parcels.clone());
+        let (_, missing) = store
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
             .await
-            .expect("get the invoice we just stored");
+            .expect("Invoice should be created");
+        assert_eq!(200, missing.len(), "All parcels should be missing");
 
-        let first_parcel = inv
-            .parcel
-            .expect("parsel vector")
-            .pop()
-            .expect("got a parcel");
-        assert_eq!(first_parcel.label.sha256, parcel.sha)
+        // Upload every third parcel
+        for (parcel, d) in parcels.iter().zip(data.iter()).step_by(3) {
+            store
+                .create_parcel(
+                    &inv.bindle.id,
+                    &parcel.label.sha256,
+                    FramedRead::new(std::io::Cursor::new(d.clone()), BytesCodec::new()),
+                )
+                .await
+                .expect("Parcel should be created");
+        }
+
+        // A new version referencing the same parcels should only be missing the rest, sorted by SHA
+        inv.bindle.id = format!("{}/2.0.0", inv.bindle.id.name()).parse().unwrap();
+        let (_, missing) = store
+            .create_invoice(NoopSigned(NoopVerified(inv)))
+            .await
+            .expect("Invoice should be created");
+        let mut expected: Vec<crate::Label> = parcels
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 3 != 0)
+            .map(|(_, p)| p.label.clone())
+            .collect();
+        expected.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+        assert_eq!(expected, missing);
     }
 
     #[tokio::test]
-    async fn test_should_reject_invalid_length() {
-        // Create a temporary directory
+    async fn test_should_sort_missing_parcels_by_sha() {
+        use sha2::Digest;
+
         let root = tempdir().unwrap();
-        let mut scaffold = testing::Scaffold::load("valid_v1").await;
-        let mut parcels = scaffold.invoice.parcel.take().unwrap();
-        // Completely invalid size
-        parcels[0].label.size = 100000;
-        scaffold.invoice.parcel = Some(parcels);
         let store = FileProvider::new(
             root.path().to_owned(),
             crate::search::StrictEngine::default(),
         )
         .await;
 
-        let signed = NoopSigned(NoopVerified(scaffold.invoice.clone()));
-        store
-            .create_invoice(signed)
+        let mut labels: Vec<crate::Label> = ["alpha", "bravo", "charlie", "delta", "echo"]
+            .iter()
+            .map(|name| crate::Label {
+                sha256: format!("{:x}", sha2::Sha256::digest(name.as_bytes())),
+                name: format!("{}.txt", name),
+                size: name.len() as u64,
+                ..crate::Label::default()
+            })
+            .collect();
+        // Make sure the invoice lists the parcels out of SHA order
+        labels.sort_by(|a, b| b.sha256.cmp(&a.sha256));
+
+        let mut inv = testing::Scaffold::load("valid_v1").await.invoice;
+        inv.parcel = Some(
+            labels
+                .iter()
+                .map(|label| crate::Parcel {
+                    label: label.clone(),
+                    conditions: None,
+                })
+                .collect(),
+        );
+        let planned = store
+            .plan_invoice(&inv)
+            .await
+            .expect("Invoice should be planned");
+        let (_, missing) = store
+            .create_invoice(NoopSigned(NoopVerified(inv)))
             .await
             .expect("Invoice should be created");
 
-        let parcel = scaffold.parcel_files.get("parcel").unwrap();
-        let err = store
-            .create_parcel(
-                &scaffold.invoice.bindle.id,
-                &parcel.sha,
-                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new()),
-            )
-            .await
-            .expect_err("Creating a parcel with invalid length should fail");
-        assert!(
-            matches!(err, ProviderError::SizeMismatch),
-            "Error should be of type SizeMismatch"
-        )
+        labels.reverse();
+        assert_eq!(labels, missing, "Missing parcels should be sorted by SHA");
+        assert_eq!(labels, planned, "Planned parcels should be sorted by SHA");
     }
 
     // Running this as multi thread to make sure both processes run simultaneously
@@ -905,11 +9009,18 @@ mod test {
             ),
         );
 
-        // At least one should fail
-        assert!(
-            !(firstp.is_ok() && secondp.is_ok()),
-            "One of the create parcel tasks should fail"
-        );
+        // Identical uploads are idempotent, so both may succeed if one finishes before the other
+        // starts. Otherwise, the other should see the write in progress
+        for res in [&firstp, &secondp] {
+            assert!(
+                matches!(
+                    res,
+                    Ok(_) | Err(ProviderError::Exists) | Err(ProviderError::WriteInProgress)
+                ),
+                "Unexpected result from concurrent parcel write: {:?}",
+                res
+            );
+        }
         // At least one should succeed
         assert!(
             firstp.is_ok() || secondp.is_ok(),
@@ -0,0 +1,91 @@
+//! Strategies for naming the directories invoices are stored in
+
+use sha2::{Digest, Sha256};
+
+/// A strategy for turning a bindle name and version into the name used to store its invoice on
+/// disk. The returned name may contain `/` separators, in which case the invoice will be stored in
+/// nested directories
+pub trait NamingStrategy: Send + Sync {
+    /// Returns the canonical name for the given bindle name and version. This must always return
+    /// the same value for the same inputs, and should not return the same value for different
+    /// inputs
+    fn canonical_name(&self, name: &str, version: &str) -> String;
+}
+
+/// The default naming strategy, which names invoices using the hex encoded SHA-256 of
+/// `name/version`. This is the same as [`Invoice::canonical_name`](crate::Invoice::canonical_name)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Naming;
+
+impl NamingStrategy for Sha256Naming {
+    fn canonical_name(&self, name: &str, version: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(name);
+        hasher.update("/");
+        hasher.update(version);
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// A human readable naming strategy that stores invoices under `name/version`, so
+/// `example.com/foo/1.0.0` is stored in `example.com/foo/1.0.0/`.
+///
+/// Each path segment is sanitized for the file system by replacing any character other than ASCII
+/// letters, digits, `.`, `_`, `+` and `-` with `_`, and segments made up of only dots are replaced
+/// as well. Note that this means names differing only in sanitized characters (or only in case, on
+/// case insensitive file systems) will collide. Only the first of the colliding bindles can be
+/// stored, and the others are reported as not found rather than being served its invoice
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HierarchicalNaming;
+
+impl NamingStrategy for HierarchicalNaming {
+    fn canonical_name(&self, name: &str, version: &str) -> String {
+        name.split('/')
+            .chain(std::iter::once(version))
+            .map(sanitize_segment)
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+fn sanitize_segment(segment: &str) -> String {
+    if segment.is_empty() || segment.chars().all(|c| c == '.') {
+        return "_".repeat(segment.len().max(1));
+    }
+    segment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sha256_naming_matches_id() {
+        let id: crate::Id = "example.com/foo/1.0.0".parse().unwrap();
+        assert_eq!(
+            id.sha(),
+            Sha256Naming.canonical_name(id.name(), &id.version_string())
+        );
+    }
+
+    #[test]
+    fn test_hierarchical_naming() {
+        assert_eq!(
+            "example.com/foo/1.0.0-rc.1+build.2",
+            HierarchicalNaming.canonical_name("example.com/foo", "1.0.0-rc.1+build.2")
+        );
+        assert_eq!(
+            "_/__/my_bindle/1.0.0",
+            HierarchicalNaming.canonical_name("./../my bindle", "1.0.0")
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! Hooks for reacting to changes made to a [`FileProvider`](super::FileProvider)
+
+use crate::Id;
+
+/// An observer that is notified after data in a [`FileProvider`](super::FileProvider) has been
+/// successfully changed. This allows side effects such as cache invalidation or webhooks to be
+/// kept out of the provider itself.
+///
+/// Each method is only called once the change has been stored, and any error returned is logged
+/// rather than failing the operation. All methods do nothing by default, so implementors only need
+/// to handle the events they care about
+#[async_trait::async_trait]
+pub trait StorageObserver: Send + Sync {
+    /// Called after a new invoice has been stored
+    async fn on_invoice_created(&self, invoice: &crate::Invoice) -> anyhow::Result<()> {
+        let _ = invoice;
+        Ok(())
+    }
+
+    /// Called after the invoice with the given ID has been yanked
+    async fn on_invoice_yanked(&self, id: &Id) -> anyhow::Result<()> {
+        let _ = id;
+        Ok(())
+    }
+
+    /// Called after a new parcel has been stored. This is not called when uploading a parcel that
+    /// already exists
+    async fn on_parcel_created(&self, label: &crate::Label) -> anyhow::Result<()> {
+        let _ = label;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+//! Tracking of the total size of stored parcels, so that a storage quota can be enforced
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tracing::{debug, trace};
+
+use crate::provider::{ProviderError, Result};
+
+/// A limit on the total size of all stored parcels, along with a running count of how much of it
+/// is used. The count is shared between clones of a provider so concurrent uploads can't go over
+/// the limit together
+#[derive(Debug)]
+pub(crate) struct ParcelQuota {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl ParcelQuota {
+    /// Returns a new quota with the given limit, where `used` bytes are already stored
+    pub(crate) fn new(limit: u64, used: u64) -> Self {
+        ParcelQuota {
+            limit,
+            used: AtomicU64::new(used),
+        }
+    }
+
+    /// Reserves space for a parcel of the given size, returning a `QuotaExceeded` error if it
+    /// would not fit. The space is given back when the reservation is dropped unless it is
+    /// committed
+    pub(crate) fn reserve(self: &Arc<Self>, size: u64) -> Result<QuotaReservation> {
+        let limit = self.limit;
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(size).filter(|total| *total <= limit)
+            })
+            .map_err(|used| {
+                debug!(used, size, limit, "Parcel would exceed the storage quota");
+                ProviderError::QuotaExceeded { limit }
+            })?;
+        trace!(size, "Reserved space for parcel");
+        Ok(QuotaReservation {
+            quota: Arc::clone(self),
+            size,
+            committed: false,
+        })
+    }
+
+    /// Returns how much of the quota is currently used
+    #[cfg(test)]
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Gives back the space used by a parcel that has been removed
+    pub(crate) fn release(&self, size: u64) {
+        // Never wraps around, in case the parcel was stored while the count was being initialized
+        let _ = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+}
+
+/// Space reserved in a [`ParcelQuota`] for an upload in progress. Dropping the reservation without
+/// committing it (such as when the upload fails) gives the space back
+pub(crate) struct QuotaReservation {
+    quota: Arc<ParcelQuota>,
+    size: u64,
+    committed: bool,
+}
+
+impl QuotaReservation {
+    /// Keeps the reserved space counted as used, once the parcel has been stored
+    pub(crate) fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        if !self.committed {
+            trace!(
+                size = self.size,
+                "Releasing reserved space for failed upload"
+            );
+            self.quota.release(self.size);
+        }
+    }
+}
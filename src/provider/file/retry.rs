@@ -0,0 +1,212 @@
+//! Retrying of IO operations that fail with transient errors, such as those seen on network file
+//! systems.
+//!
+//! Reads, opens and metadata lookups are retried, as are writes of invoices, labels and other
+//! files whose data is already in memory, since those can simply be written again. Parcel uploads
+//! are never retried, as the stream they are read from can't be replayed
+
+use std::future::Future;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::provider::ProviderError;
+
+/// The default number of times an IO operation is attempted before giving up
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+/// The default delay before the first retry. Each following retry waits twice as long
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(10);
+
+/// How IO operations that fail with a transient error are retried
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    /// The maximum number of attempts, including the first one. 1 disables retries
+    pub(crate) max_attempts: u32,
+    /// The delay before the first retry, doubled for each retry after that
+    pub(crate) base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs the given operation, retrying it with exponential backoff for as long as it fails
+    /// with a transient error and attempts remain. Any other error (such as `NotFound`) is
+    /// returned immediately, and once all attempts are used the last error is returned
+    pub(crate) async fn retry<F, Fut, T, E>(&self, mut op: F) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Transient + std::fmt::Display,
+    {
+        let mut attempt: u32 = 1;
+        loop {
+            match op().await {
+                Err(e) if attempt < self.max_attempts && e.is_transient() => {
+                    let delay = self.base_delay.saturating_mul(1 << (attempt - 1).min(16));
+                    warn!(error = %e, attempt, ?delay, "Transient IO error, retrying");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// An error that may go away if the failed operation is tried again
+pub(crate) trait Transient {
+    /// Returns whether the error is likely to go away if the operation is tried again
+    fn is_transient(&self) -> bool;
+}
+
+impl Transient for std::io::Error {
+    fn is_transient(&self) -> bool {
+        matches!(
+            self.kind(),
+            std::io::ErrorKind::Interrupted
+                | std::io::ErrorKind::WouldBlock
+                | std::io::ErrorKind::TimedOut
+        )
+    }
+}
+
+impl Transient for ProviderError {
+    fn is_transient(&self) -> bool {
+        matches!(self, ProviderError::Io { source, .. } if source.is_transient())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+
+    use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+    /// A reader that fails with the given error kind while the shared failure count is above zero,
+    /// decrementing it each time, and then returns its data
+    struct FlakyReader<'a> {
+        failures: &'a AtomicUsize,
+        kind: std::io::ErrorKind,
+        data: &'static [u8],
+    }
+
+    impl AsyncRead for FlakyReader<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Poll::Ready(Err(std::io::Error::new(self.kind, "flaky")));
+            }
+            let n = self.data.len().min(buf.remaining());
+            buf.put_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    /// Reads everything from a new flaky reader, returning the data and the number of attempts
+    async fn read_flaky(
+        failures: usize,
+        kind: std::io::ErrorKind,
+    ) -> (std::io::Result<Vec<u8>>, usize) {
+        let failures = &AtomicUsize::new(failures);
+        let mut attempts = 0;
+        let res = policy()
+            .retry(|| {
+                attempts += 1;
+                let mut reader = FlakyReader {
+                    failures,
+                    kind,
+                    data: b"hello",
+                };
+                async move {
+                    let mut out = Vec::new();
+                    reader.read_to_end(&mut out).await?;
+                    Ok(out)
+                }
+            })
+            .await;
+        (res, attempts)
+    }
+
+    #[tokio::test]
+    async fn test_should_retry_transient_errors() {
+        let (res, attempts) = read_flaky(2, std::io::ErrorKind::WouldBlock).await;
+        assert_eq!(
+            b"hello".to_vec(),
+            res.expect("Operation should eventually succeed")
+        );
+        assert_eq!(3, attempts);
+    }
+
+    #[tokio::test]
+    async fn test_should_give_up_after_max_attempts() {
+        let (res, attempts) = read_flaky(5, std::io::ErrorKind::TimedOut).await;
+        let err = res.expect_err("Operation should fail once attempts are used up");
+        assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+        assert_eq!(3, attempts);
+    }
+
+    #[tokio::test]
+    async fn test_should_not_retry_permanent_errors() {
+        let (res, attempts) = read_flaky(2, std::io::ErrorKind::NotFound).await;
+        assert_eq!(
+            std::io::ErrorKind::NotFound,
+            res.expect_err("Operation should fail").kind()
+        );
+        assert_eq!(1, attempts, "NotFound should not be retried");
+    }
+
+    #[tokio::test]
+    async fn test_should_retry_transient_provider_errors() {
+        let mut attempts = 0;
+        let res = policy()
+            .retry(|| {
+                attempts += 1;
+                let failed = attempts < 3;
+                async move {
+                    if failed {
+                        return Err(ProviderError::io_at(
+                            "invoice.toml",
+                            std::io::Error::new(std::io::ErrorKind::Interrupted, "flaky"),
+                        ));
+                    }
+                    Ok(())
+                }
+            })
+            .await;
+        res.expect("Operation should eventually succeed");
+        assert_eq!(3, attempts);
+
+        let mut attempts = 0;
+        let res: Result<(), ProviderError> = policy()
+            .retry(|| {
+                attempts += 1;
+                async { Err(ProviderError::Exists) }
+            })
+            .await;
+        assert!(matches!(res, Err(ProviderError::Exists)));
+        assert_eq!(1, attempts, "Errors other than IO should not be retried");
+    }
+}
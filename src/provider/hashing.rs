@@ -0,0 +1,140 @@
+//! Helpers for hashing data as it is streamed, so that verifying a digest doesn't require a second
+//! pass over the data.
+//!
+//! This will only be available if the `providers` feature is enabled
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::invoice::Hasher;
+use crate::DigestAlgorithm;
+
+/// An `AsyncRead` wrapper that forwards all data from the inner reader unchanged while computing
+/// its digest. Once the data has been read, call [`finalize`](HashingReader::finalize) to get the
+/// hex encoded digest
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+    bytes_read: u64,
+}
+
+impl<R> HashingReader<R> {
+    /// Wraps the given reader, computing a SHA-256 digest of everything read from it
+    pub fn new(inner: R) -> Self {
+        HashingReader::with_algorithm(inner, DigestAlgorithm::Sha256)
+    }
+
+    /// Wraps the given reader, computing a digest using the given algorithm
+    pub fn with_algorithm(inner: R, algorithm: DigestAlgorithm) -> Self {
+        HashingReader {
+            inner,
+            hasher: algorithm.hasher(),
+            bytes_read: 0,
+        }
+    }
+
+    /// Returns the number of bytes read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Consumes the reader, returning the lowercase hex encoded digest of all data read. Any data
+    /// that has not been read yet is not included
+    pub fn finalize(self) -> String {
+        self.hasher.finalize_hex()
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let start = buf.filled().len();
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            let read = &buf.filled()[start..];
+            this.hasher.update(read);
+            this.bytes_read += read.len() as u64;
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use sha2::{Digest, Sha256};
+    use tokio::io::AsyncReadExt;
+
+    /// A reader that returns at most `chunk_size` bytes from each read
+    struct ChunkedReader {
+        data: Vec<u8>,
+        pos: usize,
+        chunk_size: usize,
+    }
+
+    impl AsyncRead for ChunkedReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let end = (self.pos + self.chunk_size)
+                .min(self.data.len())
+                .min(self.pos + buf.remaining());
+            buf.put_slice(&self.data[self.pos..end]);
+            self.pos = end;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..10_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn test_hashing_reader_single_shot() {
+        let data = test_data();
+        let mut reader = HashingReader::new(std::io::Cursor::new(data.clone()));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+
+        assert_eq!(data, out, "Data should be passed through unchanged");
+        assert_eq!(data.len() as u64, reader.bytes_read());
+        assert_eq!(format!("{:x}", Sha256::digest(&data)), reader.finalize());
+    }
+
+    #[tokio::test]
+    async fn test_hashing_reader_chunked() {
+        let data = test_data();
+        let mut reader = HashingReader::new(ChunkedReader {
+            data: data.clone(),
+            pos: 0,
+            chunk_size: 7,
+        });
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+
+        assert_eq!(data, out, "Data should be passed through unchanged");
+        assert_eq!(format!("{:x}", Sha256::digest(&data)), reader.finalize());
+    }
+
+    #[tokio::test]
+    async fn test_hashing_reader_with_algorithm() {
+        let data = test_data();
+        let mut reader = HashingReader::with_algorithm(
+            std::io::Cursor::new(data.clone()),
+            DigestAlgorithm::Sha512,
+        );
+        tokio::io::copy(&mut reader, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        assert_eq!(DigestAlgorithm::Sha512.digest(&data), reader.finalize());
+    }
+}
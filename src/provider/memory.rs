@@ -0,0 +1,456 @@
+//! An in-memory `Provider` implementation.
+//!
+//! All data is kept in memory and is lost when the provider is dropped. This makes it well suited
+//! for unit tests and ephemeral servers, but it should not be used anywhere data needs to be
+//! persisted. Invoices are keyed by the same canonical name used by the other providers, so it
+//! behaves identically to the `FileProvider` and `EmbeddedProvider` from the point of view of a
+//! caller.
+//!
+//! This will only be available if the `provider` feature is enabled
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use tokio::io::AsyncReadExt;
+use tokio::sync::RwLock;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio_util::io::StreamReader;
+use tracing::{debug, error, info, instrument, trace};
+
+use crate::provider::{
+    check_existing_parcel, check_invoice_policy, merge_annotations, referenced_parcels,
+    validate_new_invoice, Provider, ProviderError, Result, StorageStats,
+};
+use crate::search::Search;
+use crate::verification::Verified;
+use crate::{Id, Signed};
+
+/// The most memory preallocated for parcel data based on the size declared in its label. Larger
+/// parcels grow their buffer as the data arrives, so a bogus size can't exhaust memory up front
+const MAX_PREALLOCATION: u64 = 8 * 1024 * 1024;
+
+/// An in-memory backend for storing and retrieving bindles and parcels.
+///
+/// A MemoryProvider needs a search engine implementation. When invoices are created or yanked,
+/// the index will be updated.
+pub struct MemoryProvider<T> {
+    invoices: Arc<RwLock<HashMap<String, crate::Invoice>>>,
+    parcels: Arc<RwLock<HashMap<String, Vec<u8>>>>,
+    index: T,
+    policy: Option<Arc<crate::InvoicePolicy>>,
+    reject_duplicate_parcel_names: bool,
+}
+
+impl<T: Clone> Clone for MemoryProvider<T> {
+    fn clone(&self) -> Self {
+        MemoryProvider {
+            invoices: Arc::clone(&self.invoices),
+            parcels: Arc::clone(&self.parcels),
+            index: self.index.clone(),
+            policy: self.policy.clone(),
+            reject_duplicate_parcel_names: self.reject_duplicate_parcel_names,
+        }
+    }
+}
+
+impl<T: Search + Send + Sync> MemoryProvider<T> {
+    pub fn new(index: T) -> Self {
+        debug!("Creating new memory provider");
+        MemoryProvider {
+            invoices: Arc::new(RwLock::new(HashMap::new())),
+            parcels: Arc::new(RwLock::new(HashMap::new())),
+            index,
+            policy: None,
+            reject_duplicate_parcel_names: false,
+        }
+    }
+
+    /// Sets a policy that invoices must meet to be created or to have their annotations updated.
+    /// Invoices that break any of its rules are rejected with a
+    /// [`PolicyViolation`](crate::provider::ProviderError::PolicyViolation) error listing every
+    /// rule broken. Invoices already in storage are only checked when their contents are changed,
+    /// so they can still be yanked and unyanked. No policy is set by default
+    pub fn policy(mut self, policy: crate::InvoicePolicy) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Sets whether invoices containing different parcels with the same name are rejected when
+    /// they are created, with an [`Invalid`](crate::provider::ProviderError::Invalid) error listing
+    /// each duplicated name. Otherwise such invoices are accepted with a warning. See
+    /// [`Invoice::check_parcel_names`](crate::Invoice::check_parcel_names). Disabled by default
+    pub fn reject_duplicate_parcel_names(mut self, reject: bool) -> Self {
+        self.reject_duplicate_parcel_names = reject;
+        self
+    }
+
+    /// Sets the yanked status of an existing invoice and re-indexes it. Returns `NotFound` if the
+    /// invoice does not exist
+    async fn set_yanked(&self, parsed_id: &Id, yanked: bool) -> Result<()> {
+        self.update_invoice(parsed_id, |inv| {
+            inv.yanked = Some(yanked);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Applies the given update to an existing invoice and re-indexes it. Returns `NotFound` if
+    /// the invoice does not exist. If the update returns an error, the invoice is left unchanged
+    async fn update_invoice<F>(&self, parsed_id: &Id, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut crate::Invoice) -> Result<()> + Send,
+    {
+        let inv = {
+            let mut invoices = self.invoices.write().await;
+            let inv = invoices
+                .get_mut(&parsed_id.sha())
+                .ok_or_else(|| ProviderError::not_found(parsed_id))?;
+            // Update a copy so a failed update doesn't leave a partial change behind
+            let mut updated = inv.clone();
+            update(&mut updated)?;
+            *inv = updated.clone();
+            updated
+        };
+
+        // Attempt to update the index. Right now, we log an error if the index update
+        // fails.
+        trace!("Indexing updated invoice");
+        if let Err(e) = self.index.index(&inv).await {
+            error!(error = %e, "Error indexing updated invoice");
+        }
+        Ok(())
+    }
+}
+
+impl<T> MemoryProvider<T> {
+    /// Returns a copy of the invoice with the given ID
+    async fn read_invoice(&self, id: &Id) -> Result<crate::Invoice> {
+        self.invoices
+            .read()
+            .await
+            .get(&id.sha())
+            .cloned()
+            .ok_or_else(|| ProviderError::not_found(id))
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: crate::search::Search + Send + Sync> Provider for MemoryProvider<T> {
+    #[instrument(level = "trace", skip(self, invoice), fields(invoice_id = tracing::field::Empty))]
+    async fn create_invoice<I>(&self, invoice: I) -> Result<(crate::Invoice, Vec<crate::Label>)>
+    where
+        I: Signed + Verified + Send + Sync,
+    {
+        let mut inv = invoice.signed();
+        tracing::span::Span::current()
+            .record("invoice_id", &tracing::field::display(&inv.bindle.id));
+        validate_new_invoice(
+            &mut inv,
+            self.policy.as_deref(),
+            self.reject_duplicate_parcel_names,
+        )?;
+
+        let invoice_id = inv.canonical_name();
+
+        {
+            let mut invoices = self.invoices.write().await;
+            if invoices.contains_key(&invoice_id) {
+                debug!("Invoice being created already exists in storage");
+                return Err(ProviderError::Exists);
+            }
+            debug!("Inserting invoice into memory");
+            invoices.insert(invoice_id, inv.clone());
+        }
+
+        // Attempt to update the index. Right now, we log an error if the index update
+        // fails.
+        if let Err(e) = self.index.index(&inv).await {
+            error!(error = %e, "Error indexing new invoice");
+        }
+
+        trace!("Checking for missing parcels listed in newly created invoice");
+        let parcels = self.parcels.read().await;
+        let mut missing: Vec<crate::Label> = inv
+            .parcel
+            .as_ref()
+            .map(|p| {
+                p.iter()
+                    .filter(|k| !parcels.contains_key(&k.label.sha256))
+                    .map(|k| k.label.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        drop(parcels);
+        missing.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+        Ok((inv, missing))
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn get_yanked_invoice<I>(&self, id: I) -> Result<crate::Invoice>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        debug!("Getting invoice from memory");
+        self.read_invoice(&parsed_id).await
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn invoice_exists<I>(&self, id: I) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        Ok(self.invoices.read().await.contains_key(&parsed_id.sha()))
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn list_invoices(&self) -> Result<Vec<crate::Invoice>> {
+        Ok(self.invoices.read().await.values().cloned().collect())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn query_invoices(&self, term: &str) -> Result<Vec<crate::Invoice>> {
+        let ids = self.index.query_ids(term).await.map_err(|e| {
+            error!(error = %e, "Error querying index");
+            ProviderError::Other(format!("Unable to query search index: {}", e))
+        })?;
+        trace!(total = ids.len(), "Loading matching invoices");
+        let mut invoices = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.read_invoice(&id).await {
+                Ok(inv) => invoices.push(inv),
+                // The index can be slightly behind storage, so skip anything that is gone
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(invoices)
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn yank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Yanking invoice");
+        self.set_yanked(&parsed_id, true).await
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn unyank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Unyanking invoice");
+        self.set_yanked(&parsed_id, false).await
+    }
+
+    #[instrument(level = "trace", skip(self, id, annotations), fields(id))]
+    async fn update_invoice_annotations<I>(
+        &self,
+        id: I,
+        annotations: BTreeMap<String, String>,
+    ) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Updating invoice annotations");
+        self.update_invoice(&parsed_id, |inv| {
+            merge_annotations(inv, annotations)?;
+            check_invoice_policy(inv, self.policy.as_deref())
+        })
+        .await
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id, data), fields(id))]
+    async fn create_parcel<I, R, B>(&self, bindle_id: I, parcel_id: &str, data: R) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+        R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
+        B: bytes::Buf + Send,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        let label = self.validate_parcel(parsed_id, parcel_id).await?;
+
+        if let Some(existing) = self.parcels.read().await.get(parcel_id) {
+            debug!("Parcel already exists");
+            return check_existing_parcel(&label, existing.len() as u64);
+        }
+
+        debug!("Reading data from stream");
+        let mut parcel_data: Vec<u8> =
+            Vec::with_capacity(label.size.min(MAX_PREALLOCATION) as usize);
+        // Read at most one byte more than the label declares, which is enough to tell that the
+        // data is too large without buffering all of it
+        StreamReader::new(
+            data.map(|res| res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))),
+        )
+        .take(label.size.saturating_add(1))
+        .read_to_end(&mut parcel_data)
+        .await?;
+
+        debug!("Validating size");
+        if parcel_data.len() as u64 != label.size {
+            info!(
+                expected = label.size,
+                read_bytes = parcel_data.len(),
+                "Attempted to insert parcel with incorrect size"
+            );
+            return Err(ProviderError::SizeMismatch {
+                expected: label.size,
+                actual: parcel_data.len() as u64,
+            });
+        }
+
+        debug!("Validating digest");
+        let calculated = label.algorithm().digest(&parcel_data);
+        if label.sha256 != calculated {
+            info!(expected_sha = %label.sha256, %calculated, "Mismatched SHA when creating parcel");
+            return Err(ProviderError::DigestMismatch {
+                expected: label.sha256,
+                actual: calculated,
+            });
+        }
+
+        debug!("Inserting parcel into memory");
+        let mut parcels = self.parcels.write().await;
+        // Check again in case another upload finished while we were reading the data. As the
+        // digest has been validated, the content is the same
+        if parcels.contains_key(parcel_id) {
+            return Ok(());
+        }
+        parcels.insert(parcel_id.to_owned(), parcel_data);
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn delete_parcel(&self, parcel_id: &str) -> Result<()> {
+        if !self.parcels.read().await.contains_key(parcel_id) {
+            return Err(ProviderError::not_found(parcel_id));
+        }
+
+        trace!("Checking for invoices referencing parcel");
+        if self.parcel_reference_count(parcel_id).await? > 0 {
+            debug!("Parcel is still referenced by an invoice, refusing to delete");
+            return Err(ProviderError::InUse);
+        }
+
+        debug!("Deleting parcel from memory");
+        self.parcels
+            .write()
+            .await
+            .remove(parcel_id)
+            .map(|_| ())
+            .ok_or_else(|| ProviderError::not_found(parcel_id))
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        // Everything is held in memory, so there is no storage that can become unreachable
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let invoices = self.invoices.read().await;
+        let referenced = referenced_parcels(invoices.values(), false);
+        let parcels = self.parcels.read().await;
+        Ok(StorageStats {
+            invoice_count: invoices.len(),
+            parcel_count: parcels.len(),
+            total_parcel_bytes: parcels.values().map(|d| d.len() as u64).sum(),
+            dangling_parcel_count: parcels
+                .keys()
+                .filter(|sha| !referenced.contains(*sha))
+                .count(),
+        })
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn gc_parcels(&self, keep_yanked: bool) -> Result<Vec<String>> {
+        let referenced = referenced_parcels(self.invoices.read().await.values(), keep_yanked);
+        let mut parcels = self.parcels.write().await;
+        let removed: Vec<String> = parcels
+            .keys()
+            .filter(|sha| !referenced.contains(*sha))
+            .cloned()
+            .collect();
+        for sha in removed.iter() {
+            debug!(%sha, "Removing unreferenced parcel");
+            parcels.remove(sha);
+        }
+        Ok(removed)
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
+    async fn get_parcel<I>(
+        &self,
+        bindle_id: I,
+        parcel_id: &str,
+    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        self.validate_parcel(parsed_id, parcel_id).await?;
+
+        debug!("Getting parcel from memory");
+        let data = match self.parcels.read().await.get(parcel_id) {
+            // Wrap the data in a cursor so it implements AsyncRead and can be streamed
+            Some(d) => std::io::Cursor::new(d.clone()),
+            None => return Err(ProviderError::not_found(parcel_id)),
+        };
+
+        Ok::<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>, _>(Box::new(
+            FramedRead::new(data, BytesCodec::new())
+                .map(|res| res.map_err(ProviderError::from).map(|b| b.freeze())),
+        ))
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn get_parcel_size(&self, parcel_id: &str) -> Result<u64> {
+        self.parcels
+            .read()
+            .await
+            .get(parcel_id)
+            .map(|d| d.len() as u64)
+            .ok_or_else(|| ProviderError::not_found(parcel_id))
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
+    async fn parcel_exists<I>(&self, bindle_id: I, parcel_id: &str) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        self.validate_parcel(parsed_id, parcel_id).await?;
+
+        debug!("Checking if parcel exists in memory");
+        Ok(self.parcels.read().await.contains_key(parcel_id))
+    }
+}
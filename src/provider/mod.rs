@@ -21,19 +21,35 @@
 //! will generally contain another Provider implementation or an HTTP client to talk to another
 //! server upstream
 
+mod copy;
 #[cfg(feature = "providers")]
 pub mod embedded;
 #[cfg(feature = "providers")]
 pub mod file;
+#[cfg(feature = "providers")]
+pub mod hashing;
+#[cfg(feature = "providers")]
+pub mod memory;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "providers")]
+pub mod timeout;
+pub mod token;
 
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::convert::TryInto;
+use std::path::PathBuf;
 
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
 use tokio_stream::Stream;
 
+use crate::signature::KeyRing;
 use crate::verification::Verified;
-use crate::SignatureError;
 use crate::{Id, Signed};
+use crate::{SignatureError, VerificationStrategy};
+
+pub use copy::copy_bindle;
 
 /// A custom shorthand result type that always has an error type of [`ProviderError`](ProviderError)
 pub type Result<T> = core::result::Result<T, ProviderError>;
@@ -55,11 +71,218 @@ pub trait Provider {
     /// list of missing parcels
     ///
     /// It must verify that each referenced parcel is present in storage. Any parcel that is not
-    /// present must be returned in the list of labels.
+    /// present must be returned in the list of labels. The missing labels are sorted by SHA so the
+    /// result is the same no matter the order the parcels are listed in the invoice or checked in
     async fn create_invoice<I>(&self, inv: I) -> Result<(crate::Invoice, Vec<super::Label>)>
     where
         I: Signed + Verified + Send + Sync;
 
+    /// Creates an invoice just like `create_invoice`, but returns an [`UploadPlan`] describing
+    /// where each missing parcel should be uploaded rather than only the list of missing labels
+    ///
+    /// The default implementation uses the location of each parcel in the Bindle HTTP API,
+    /// relative to the server's base URL. Storage backends should override this to return a
+    /// location in their own storage, such as a path or presigned URL
+    async fn create_invoice_with_plan<I>(&self, inv: I) -> Result<UploadPlan>
+    where
+        I: Signed + Verified + Send + Sync,
+    {
+        let (inv, missing) = self.create_invoice(inv).await?;
+        Ok(UploadPlan {
+            invoice_name: inv.canonical_name(),
+            parcels: missing
+                .into_iter()
+                .map(|label| ParcelUpload {
+                    location: format!("_i/{}@{}", inv.bindle.id, label.sha256),
+                    label,
+                })
+                .collect(),
+        })
+    }
+
+    /// Performs a dry run of `create_invoice`, returning the labels of the parcels that would be
+    /// missing (sorted by SHA) without storing anything or updating the index.
+    ///
+    /// Implementations must apply the same checks as `create_invoice`, so this returns the same
+    /// errors (such as [`ProviderError::CreateYanked`] or [`ProviderError::Exists`]) that creating
+    /// the invoice would. The default implementation returns an error, as not all providers can
+    /// check for missing parcels without creating the invoice
+    async fn plan_invoice(&self, inv: &super::Invoice) -> Result<Vec<super::Label>> {
+        let _ = inv;
+        Err(ProviderError::Unsupported("planning invoices".to_string()))
+    }
+
+    /// Creates an invoice by streaming its TOML representation from the given reader. Returns the
+    /// newly created invoice and a list of missing parcels, just like `create_invoice`
+    ///
+    /// The data is buffered to a temporary file rather than in memory before it is parsed. As
+    /// providers only accept signed and verified invoices, the parsed invoice is passed to
+    /// `verify_and_sign` before continuing through the normal `create_invoice` path
+    async fn create_invoice_from_reader<R, F, I>(
+        &self,
+        data: &mut R,
+        verify_and_sign: F,
+    ) -> Result<(super::Invoice, Vec<super::Label>)>
+    where
+        R: AsyncRead + Unpin + Send,
+        F: FnOnce(super::Invoice) -> Result<I> + Send,
+        I: Signed + Verified + Send + Sync,
+    {
+        let mut file = temp_file().await?;
+        tokio::io::copy(data, &mut file).await?;
+        file.seek(std::io::SeekFrom::Start(0)).await?;
+        let mut raw = Vec::new();
+        file.read_to_end(&mut raw).await?;
+
+        let inv: super::Invoice = toml::from_slice(&raw)?;
+        self.create_invoice(verify_and_sign(inv)?).await
+    }
+
+    /// Creates each of the given invoices, returning the result of each create keyed by the
+    /// invoice's `name/version`, in the same order as the invoices were given. A successful result
+    /// contains the invoice's missing parcels, just like `create_invoice`.
+    ///
+    /// A failure only affects its own invoice, so the rest of the batch is still attempted. For
+    /// example, an invoice that already exists has a [`ProviderError::Exists`] error in its slot.
+    /// As with `create_invoice_from_reader`, each invoice is passed to `verify_and_sign` before it
+    /// is created
+    async fn create_invoices<F, I>(
+        &self,
+        invs: &[super::Invoice],
+        verify_and_sign: F,
+    ) -> Vec<(String, Result<Vec<super::Label>>)>
+    where
+        F: Fn(super::Invoice) -> Result<I> + Send + Sync,
+        I: Signed + Verified + Send + Sync,
+    {
+        let mut results = Vec::with_capacity(invs.len());
+        for inv in invs {
+            let key = inv.bindle.id.to_string();
+            let res = match verify_and_sign(inv.clone()) {
+                Ok(signed) => self
+                    .create_invoice(signed)
+                    .await
+                    .map(|(_, missing)| missing),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res.as_ref() {
+                tracing::debug!(invoice_id = %key, error = %e, "Unable to create invoice in batch");
+            }
+            results.push((key, res));
+        }
+        results
+    }
+
+    /// Imports a bindle from a tar archive in the format written by
+    /// [`export_invoice_tar`](Provider::export_invoice_tar), returning the stored invoice.
+    ///
+    /// Every parcel in the archive is checked against the digest in its label before anything is
+    /// stored, so a mismatch aborts the whole import. Parcels are buffered to temporary files rather
+    /// than in memory, and each file is only open while it is being written or stored. As with `create_invoice_from_reader`, the invoice is passed to
+    /// `verify_and_sign` before it is created. Importing is idempotent: an invoice or parcel that
+    /// already exists is left as is. This is only available if the `providers` feature is enabled
+    #[cfg(feature = "providers")]
+    async fn import_invoice_tar<R, F, I>(
+        &self,
+        data: &mut R,
+        verify_and_sign: F,
+    ) -> Result<super::Invoice>
+    where
+        R: AsyncRead + Unpin + Send + Sync,
+        F: FnOnce(super::Invoice) -> Result<I> + Send,
+        I: Signed + Verified + Send + Sync,
+    {
+        use futures::StreamExt;
+        use tokio::io::AsyncWriteExt;
+
+        let mut archive = tokio_tar::Archive::new(data);
+        let mut entries = archive.entries()?;
+        let mut inv: Option<super::Invoice> = None;
+        let mut parcels: Vec<(String, tempfile::TempPath)> = Vec::new();
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            let file_name = match entry.path()?.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            if file_name == "invoice.toml" {
+                let mut raw = Vec::new();
+                entry.read_to_end(&mut raw).await?;
+                inv = Some(toml::from_slice(&raw)?);
+                continue;
+            }
+            let sha = match file_name.strip_suffix(".dat") {
+                Some(sha) => sha.to_owned(),
+                None => continue,
+            };
+
+            // Parcels are checked against their labels, so the invoice must come first
+            let label = inv
+                .as_ref()
+                .ok_or_else(|| {
+                    ProviderError::Other(
+                        "Archive must contain the invoice before any parcels".to_string(),
+                    )
+                })?
+                .parcel
+                .iter()
+                .flatten()
+                .find(|p| p.label.sha256 == sha)
+                .map(|p| p.label.clone())
+                .ok_or_else(|| {
+                    ProviderError::Other(format!(
+                        "Archive contains parcel {} that is not in the invoice",
+                        sha
+                    ))
+                })?;
+
+            let path = temp_path().await?;
+            let mut file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .await?;
+            let mut hasher = label.algorithm().hasher();
+            let mut buf = vec![0; 8192];
+            loop {
+                let n = entry.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n]).await?;
+            }
+            let actual = hasher.finalize_hex();
+            if actual != sha {
+                return Err(ProviderError::DigestMismatch {
+                    expected: sha,
+                    actual,
+                });
+            }
+            // Close the file so archives with many parcels don't run out of file descriptors
+            file.flush().await?;
+            drop(file);
+            parcels.push((sha, path));
+        }
+
+        let inv = inv.ok_or_else(|| {
+            ProviderError::Other("Archive does not contain an invoice".to_string())
+        })?;
+        let id = inv.bindle.id.clone();
+        match self.create_invoice(verify_and_sign(inv)?).await {
+            Ok(_) | Err(ProviderError::Exists) => (),
+            Err(e) => return Err(e),
+        }
+        for (sha, path) in parcels {
+            let file = tokio::fs::File::open(&path).await?;
+            let stream = tokio_util::io::ReaderStream::new(file);
+            match self.create_parcel(&id, &sha, stream).await {
+                Ok(_) | Err(ProviderError::Exists) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.get_yanked_invoice(id).await
+    }
+
     /// Load an invoice and return it
     ///
     /// This will return an invoice if the bindle exists and is not yanked. The default
@@ -77,6 +300,321 @@ pub trait Provider {
         }
     }
 
+    /// Load an invoice and verify that all of its parcels exist in storage
+    ///
+    /// This behaves like `get_invoice`, but will also check for the existence of each parcel
+    /// referenced by the invoice, returning a [`ProviderError::MissingParcels`] error containing
+    /// the SHAs of any parcels that are not present. This is useful when you want to make sure a
+    /// bindle is complete before handing it off to a consumer. As it requires an existence check
+    /// for every parcel, it is more expensive than `get_invoice`
+    async fn get_invoice_verified<I>(&self, id: I) -> Result<super::Invoice>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        let inv = self.get_invoice(&parsed_id).await?;
+
+        let zero_vec = Vec::with_capacity(0);
+        let checks = inv.parcel.as_ref().unwrap_or(&zero_vec).iter().map(|p| {
+            let parsed_id = &parsed_id;
+            async move {
+                match self.parcel_exists(parsed_id, &p.label.sha256).await {
+                    Ok(true) => Ok(None),
+                    Ok(false) => Ok(Some(p.label.sha256.clone())),
+                    Err(e) => Err(e),
+                }
+            }
+        });
+        let missing = futures::future::join_all(checks)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            return Err(ProviderError::MissingParcels(missing));
+        }
+        Ok(inv)
+    }
+
+    /// Load an invoice and return the labels of only the parcels that should be used when the
+    /// given groups are active. This allows a client to fetch just the parcels relevant to a
+    /// specific deployment profile. See [`Invoice::active_parcels`](crate::Invoice::active_parcels)
+    /// for the details of how conditions are matched
+    async fn resolve_parcels<I>(&self, id: I, active_groups: &[String]) -> Result<Vec<super::Label>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let inv = self.get_invoice(id).await?;
+        Ok(inv
+            .active_parcels(active_groups)
+            .into_iter()
+            .map(|p| p.label)
+            .collect())
+    }
+
+    /// Load the latest non-yanked version of the bindle with the given name
+    ///
+    /// Versions are compared using semver precedence, with pre-release versions only considered if
+    /// no release versions exist. Returns a [`ProviderError::NotFound`] error if there are no
+    /// non-yanked versions of the bindle. The default implementation scans every invoice returned
+    /// by `list_invoices`, so providers that can look up versions by name should override it
+    async fn get_latest_invoice(&self, name: &str) -> Result<super::Invoice> {
+        let invoices: Vec<_> = self
+            .list_invoices()
+            .await?
+            .into_iter()
+            .filter(|inv| inv.bindle.id.name() == name && !inv.yanked.unwrap_or(false))
+            .collect();
+        let latest = latest_version(invoices.iter().map(|inv| inv.bindle.id.version()))
+            .cloned()
+            .ok_or_else(|| ProviderError::not_found(name))?;
+        Ok(invoices
+            .into_iter()
+            .find(|inv| inv.bindle.id.version() == &latest)
+            .expect("latest version should come from the list of invoices"))
+    }
+
+    /// Returns all stored versions of the bindle with the given name, sorted from lowest to
+    /// highest by semver precedence. Yanked versions are only included if `include_yanked` is
+    /// true. Returns an empty list if there are no versions of the bindle.
+    ///
+    /// The default implementation scans every invoice returned by `list_invoices`, so providers
+    /// that can look up versions by name should override it
+    async fn list_versions(&self, name: &str, include_yanked: bool) -> Result<Vec<String>> {
+        let versions: BTreeSet<semver::Version> = self
+            .list_invoices()
+            .await?
+            .into_iter()
+            .filter(|inv| {
+                inv.bindle.id.name() == name && (include_yanked || !inv.yanked.unwrap_or(false))
+            })
+            .map(|inv| inv.bindle.id.version().clone())
+            .collect();
+        Ok(versions.iter().map(|v| v.to_string()).collect())
+    }
+
+    /// Returns every stored bindle name mapped to its versions, with the versions sorted from
+    /// lowest to highest by semver precedence like `list_versions`. Yanked versions are only
+    /// included if `include_yanked` is true, and names without any included versions are left out.
+    ///
+    /// The default implementation scans every invoice returned by `list_invoices`, so providers
+    /// that can look up versions by name should override it
+    async fn catalog(&self, include_yanked: bool) -> Result<BTreeMap<String, Vec<String>>> {
+        let mut versions: BTreeMap<String, BTreeSet<semver::Version>> = BTreeMap::new();
+        for inv in self.list_invoices().await? {
+            if include_yanked || !inv.yanked.unwrap_or(false) {
+                versions
+                    .entry(inv.bindle.id.name().to_owned())
+                    .or_default()
+                    .insert(inv.bindle.id.version().clone());
+            }
+        }
+        Ok(versions
+            .into_iter()
+            .map(|(name, v)| (name, v.iter().map(|v| v.to_string()).collect()))
+            .collect())
+    }
+
+    /// Returns when the invoice with the given ID was stored, including if it is yanked.
+    ///
+    /// The default implementation returns an error, as not all providers record creation times
+    async fn invoice_created<I>(&self, id: I) -> Result<std::time::SystemTime>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let _ = id;
+        Err(ProviderError::Unsupported("creation times".to_string()))
+    }
+
+    /// Returns when the parcel with the given SHA was stored.
+    ///
+    /// The default implementation returns an error, as not all providers record creation times
+    async fn parcel_created(&self, parcel_id: &str) -> Result<std::time::SystemTime> {
+        let _ = parcel_id;
+        Err(ProviderError::Unsupported("creation times".to_string()))
+    }
+
+    /// Returns all invoices stored at or after the given time, including yanked ones, ordered from
+    /// oldest to newest.
+    ///
+    /// The default implementation looks up the creation time of every invoice returned by
+    /// `list_invoices` using `invoice_created`
+    async fn list_invoices_since(
+        &self,
+        since: std::time::SystemTime,
+    ) -> Result<Vec<super::Invoice>> {
+        let mut invoices = Vec::new();
+        for inv in self.list_invoices().await? {
+            let created = match self.invoice_created(&inv.bindle.id).await {
+                Ok(created) => created,
+                // This can happen if an invoice was removed while listing, so skip it
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            if created >= since {
+                invoices.push((created, inv));
+            }
+        }
+        invoices.sort_by_key(|(created, _)| *created);
+        Ok(invoices.into_iter().map(|(_, inv)| inv).collect())
+    }
+
+    /// Points the given alias (such as `latest` or `stable`) of the bindle with the given name at
+    /// one of its versions, replacing where the alias pointed before. Unlike versions, aliases are
+    /// mutable. Returns a [`ProviderError::NotFound`] error if the version does not exist, or a
+    /// [`ProviderError::Yanked`] error if it is yanked.
+    ///
+    /// The default implementation returns an error, as not all providers support aliases
+    async fn set_alias(&self, name: &str, alias: &str, version: &str) -> Result<()> {
+        let _ = (name, alias, version);
+        Err(ProviderError::Unsupported("aliases".to_string()))
+    }
+
+    /// Loads the invoice the given alias of the bindle with the given name points at. Returns a
+    /// [`ProviderError::NotFound`] error if the alias has not been set, or a
+    /// [`ProviderError::Yanked`] error if the version it points at has since been yanked.
+    ///
+    /// The default implementation returns an error, as not all providers support aliases
+    async fn resolve_alias(&self, name: &str, alias: &str) -> Result<super::Invoice> {
+        let _ = (name, alias);
+        Err(ProviderError::Unsupported("aliases".to_string()))
+    }
+
+    /// Returns all invoices that have an annotation with the given key and value, such as all
+    /// invoices annotated with `channel = "stable"`. Yanked invoices are only included if
+    /// `include_yanked` is true.
+    ///
+    /// The default implementation scans every invoice returned by `list_invoices`
+    async fn find_invoices_by_annotation(
+        &self,
+        key: &str,
+        value: &str,
+        include_yanked: bool,
+    ) -> Result<Vec<super::Invoice>> {
+        Ok(self
+            .list_invoices()
+            .await?
+            .into_iter()
+            .filter(|inv| include_yanked || !inv.yanked.unwrap_or(false))
+            .filter(|inv| {
+                inv.annotations
+                    .as_ref()
+                    .and_then(|a| a.get(key))
+                    .map(|v| v == value)
+                    .unwrap_or(false)
+            })
+            .collect())
+    }
+
+    /// Returns the labels of all parcels in non-yanked invoices that have an annotation with the
+    /// given key and value, such as all parcels annotated with `os = "linux"`. Labels that appear
+    /// identically in multiple invoices are only returned once.
+    ///
+    /// The default implementation scans every invoice returned by `list_invoices`
+    async fn find_parcels_by_annotation(
+        &self,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<super::Label>> {
+        let mut seen = HashSet::new();
+        Ok(self
+            .list_invoices()
+            .await?
+            .into_iter()
+            .filter(|inv| !inv.yanked.unwrap_or(false))
+            .flat_map(|inv| inv.parcel.unwrap_or_default())
+            .map(|p| p.label)
+            .filter(|label| {
+                label
+                    .annotations
+                    .as_ref()
+                    .and_then(|a| a.get(key))
+                    .map(|v| v == value)
+                    .unwrap_or(false)
+            })
+            .filter(|label| seen.insert(label.clone()))
+            .collect())
+    }
+
+    /// Load an invoice and return the labels of all of its parcels, in the order they appear in
+    /// the invoice. Returns an empty list if the invoice has no parcels. Like `get_invoice`, this
+    /// returns a [`ProviderError::Yanked`] error if the invoice is yanked
+    async fn list_parcel_labels<I>(&self, id: I) -> Result<Vec<super::Label>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let inv = self.get_invoice(id).await?;
+        Ok(inv
+            .parcel
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| p.label)
+            .collect())
+    }
+
+    /// Load an invoice and check that it does not violate any invariants of the spec
+    ///
+    /// This behaves like `get_invoice`, but also runs [`Invoice::validate`](crate::Invoice::validate)
+    /// on the loaded invoice, returning a [`ProviderError::Invalid`] error containing all violations
+    /// if it is not valid
+    async fn get_invoice_checked<I>(&self, id: I) -> Result<super::Invoice>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let inv = self.get_invoice(id).await?;
+        let errors = inv.validate();
+        if !errors.is_empty() {
+            return Err(ProviderError::Invalid(errors));
+        }
+        Ok(inv)
+    }
+
+    /// Load an invoice and check its signatures against the given keyring using the given
+    /// verification strategy
+    ///
+    /// Returns `Ok(false)` if the signatures on the stored invoice do not satisfy the strategy (for
+    /// example, if the invoice was modified after it was signed or none of the signers are in the
+    /// keyring). Errors loading the invoice, including it being yanked, are returned as normal.
+    /// Invoices are signed with [`Invoice::sign`](crate::Invoice::sign) before they are created
+    async fn verify_invoice<I>(
+        &self,
+        id: I,
+        strategy: &VerificationStrategy,
+        keyring: &KeyRing,
+    ) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let inv = self.get_invoice(id).await?;
+        match strategy.verify(inv, keyring) {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::debug!(error = %e, "Invoice failed signature verification");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Loads each of the given invoices (including yanked ones) into the provider's invoice cache,
+    /// so the first request for a known popular bindle doesn't have to wait for it to be loaded.
+    /// Returns the number of invoices now in the cache. Invoices that don't exist are skipped,
+    /// but any other error is returned.
+    ///
+    /// The default implementation does nothing and returns 0, as not all providers have a cache
+    async fn warm_cache(&self, ids: &[String]) -> Result<usize> {
+        let _ = ids;
+        Ok(0)
+    }
+
     /// Load an invoice, even if it is yanked. This is called by the default implementation of
     /// `get_invoice`
     async fn get_yanked_invoice<I>(&self, id: I) -> Result<super::Invoice>
@@ -84,12 +622,237 @@ pub trait Provider {
         I: TryInto<Id> + Send,
         I::Error: Into<ProviderError>;
 
+    /// Returns the stored TOML of an invoice exactly as it was written, so it can be passed on
+    /// without parsing and re-serializing it. Like `get_invoice`, this returns a
+    /// [`ProviderError::Yanked`] error if the invoice is yanked.
+    ///
+    /// The default implementation serializes the invoice loaded with `get_invoice`, so it does not
+    /// preserve the original bytes. Providers with direct access to the stored bytes should
+    /// override this
+    async fn get_invoice_raw<I>(&self, id: I) -> Result<Box<dyn AsyncRead + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let inv = self.get_invoice(id).await?;
+        Ok(Box::new(std::io::Cursor::new(toml::to_vec(&inv)?)))
+    }
+
+    /// Loads an invoice, even if it is yanked, along with an opaque ETag for its current content.
+    /// The ETag stays the same across reads until the invoice changes (for example, when it is
+    /// yanked or its annotations are updated), so it can be used for HTTP caching. The ETag is
+    /// returned without the surrounding quotes used in HTTP headers.
+    ///
+    /// The default implementation hashes the serialized invoice loaded with `get_yanked_invoice`.
+    /// Providers with direct access to the stored bytes should override this to hash them instead
+    async fn get_invoice_with_etag<I>(&self, id: I) -> Result<(super::Invoice, String)>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let inv = self.get_yanked_invoice(id).await?;
+        let etag = invoice_etag(&toml::to_vec(&inv)?);
+        Ok((inv, etag))
+    }
+
+    /// Loads an invoice and its ETag like `get_invoice_with_etag`, but returns `None` if the ETag
+    /// still matches the given one. This allows a server to answer a conditional request with
+    /// `304 Not Modified`
+    async fn get_invoice_if_none_match<I>(
+        &self,
+        id: I,
+        etag: &str,
+    ) -> Result<Option<(super::Invoice, String)>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let (inv, current) = self.get_invoice_with_etag(id).await?;
+        if current == etag {
+            return Ok(None);
+        }
+        Ok(Some((inv, current)))
+    }
+
+    /// Merges the given annotations into the annotations of an existing invoice, replacing the
+    /// values of any keys that are already set. Nothing else about the invoice is changed, which
+    /// allows a bindle to be retagged (for example, promoting it from `channel = "beta"` to
+    /// `channel = "stable"`) without recreating it. Returns a [`ProviderError::Yanked`] error if
+    /// the invoice is yanked.
+    ///
+    /// The default implementation returns an error, as not all providers support modifying
+    /// invoices
+    async fn update_invoice_annotations<I>(
+        &self,
+        id: I,
+        annotations: BTreeMap<String, String>,
+    ) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let _ = (id, annotations);
+        Err(ProviderError::Unsupported(
+            "updating invoice annotations".to_string(),
+        ))
+    }
+
+    /// Loads the invoice with the given ID, passes it to the given closure to modify, and then
+    /// writes it back and re-indexes it. The invoice is locked for the whole operation, so
+    /// concurrent updates cannot overwrite each other. If the closure returns an error, nothing is
+    /// written.
+    ///
+    /// This is a general purpose primitive for read-modify-write operations that are not covered by
+    /// more specific methods. Callers are responsible for keeping the invoice valid. The default
+    /// implementation returns an error, as not all providers support modifying invoices
+    async fn with_invoice_mut<I, F, Fut>(&self, id: I, f: F) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+        F: FnOnce(&mut super::Invoice) -> Fut + Send,
+        Fut: std::future::Future<Output = Result<()>> + Send,
+    {
+        let _ = (id, f);
+        Err(ProviderError::Unsupported("modifying invoices".to_string()))
+    }
+
+    /// Appends the given parcels to an existing invoice, so a bindle can be built up incrementally
+    /// without recreating its invoice. Parcels with the same SHA as one already in the invoice are
+    /// skipped. The invoice is rewritten and re-indexed, and the labels of all of its parcels that
+    /// are not yet in storage are returned, sorted by SHA like `create_invoice`. Returns a
+    /// [`ProviderError::Yanked`] error if the invoice is yanked, and a
+    /// [`ProviderError::SignedInvoice`] error if the invoice is signed, as adding parcels would
+    /// invalidate its signatures.
+    ///
+    /// The default implementation uses `with_invoice_mut` and then checks each parcel with
+    /// `parcel_exists`
+    async fn add_parcels_to_invoice<I>(
+        &self,
+        id: I,
+        new_parcels: Vec<super::Parcel>,
+    ) -> Result<Vec<super::Label>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        self.with_invoice_mut(&parsed_id, |inv| {
+            let res = append_parcels(inv, new_parcels);
+            async move { res }
+        })
+        .await?;
+        let inv = self.get_yanked_invoice(&parsed_id).await?;
+        let mut missing = Vec::new();
+        for parcel in inv.parcel.into_iter().flatten() {
+            if !self.parcel_exists(&parsed_id, &parcel.label.sha256).await? {
+                missing.push(parcel.label);
+            }
+        }
+        missing.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+        Ok(missing)
+    }
+
+    /// Checks if the given invoice exists in storage. Yanked invoices are still considered to exist
+    ///
+    /// The default implementation loads the invoice with `get_yanked_invoice`, so most providers
+    /// should override this with a cheaper check that does not load the full invoice
+    async fn invoice_exists<I>(&self, id: I) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        match self.get_yanked_invoice(id).await {
+            Ok(_) => Ok(true),
+            Err(ProviderError::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns a summary of the given invoice's status, such as whether it is yanked and how many
+    /// parcels it has. Unlike `get_invoice`, this does not return an error for yanked invoices, and
+    /// a missing invoice is reported with `exists` set to false rather than a `NotFound` error.
+    ///
+    /// The default implementation loads the full invoice with `get_yanked_invoice`, so providers
+    /// should override this if they can read the status without loading the parcel list
+    async fn invoice_status<I>(&self, id: I) -> Result<InvoiceStatus>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        match self.get_yanked_invoice(&parsed_id).await {
+            Ok(inv) => Ok(InvoiceStatus::from(&inv)),
+            Err(ProviderError::NotFound { .. }) => Ok(InvoiceStatus::missing(&parsed_id)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns all invoices currently in storage, including yanked ones.
+    ///
+    /// Yanked invoices keep their `yanked` flag so callers can filter them as needed. The default
+    /// implementation returns an error, as not all providers (such as caches or proxies) are able
+    /// to enumerate their contents
+    async fn list_invoices(&self) -> Result<Vec<super::Invoice>> {
+        Err(ProviderError::Unsupported("listing invoices".to_string()))
+    }
+
+    /// Returns all invoices whose bindle name contains the given term, including yanked ones.
+    ///
+    /// Implementations should use their search index to find matching invoices and then load them
+    /// from storage. The default implementation returns an error, as not all providers have an
+    /// index
+    async fn query_invoices(&self, term: &str) -> Result<Vec<super::Invoice>> {
+        let _ = term;
+        Err(ProviderError::Unsupported("querying invoices".to_string()))
+    }
+
+    /// Returns a single page of [`list_invoices`](Provider::list_invoices), along with the total
+    /// number of invoices.
+    ///
+    /// Implementations must use a stable order, so consecutive pages never overlap or skip an
+    /// invoice as long as storage doesn't change in between. The default implementation loads
+    /// every invoice with `list_invoices` and orders them by canonical name. `limit` is clamped to
+    /// [`MAX_PAGE_SIZE`], and an `offset` past the end returns an empty page
+    async fn list_invoices_paged(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<super::Invoice>, usize)> {
+        let invoices = self.list_invoices().await?;
+        Ok(paginate(invoices, offset, limit))
+    }
+
+    /// Returns a single page of [`query_invoices`](Provider::query_invoices), along with the total
+    /// number of matches. Ordering and clamping work the same as in
+    /// [`list_invoices_paged`](Provider::list_invoices_paged)
+    async fn query_invoices_paged(
+        &self,
+        term: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<super::Invoice>, usize)> {
+        let invoices = self.query_invoices(term).await?;
+        Ok(paginate(invoices, offset, limit))
+    }
+
     /// Remove an invoice by ID
     async fn yank_invoice<I>(&self, id: I) -> Result<()>
     where
         I: TryInto<Id> + Send,
         I::Error: Into<ProviderError>;
 
+    /// Reverses a yank, marking the invoice as usable again. This must not create the invoice if
+    /// it does not exist, returning [`ProviderError::NotFound`] instead. The default
+    /// implementation returns an error, as not all providers support unyanking
+    async fn unyank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let _ = id;
+        Err(ProviderError::Unsupported("unyanking invoices".to_string()))
+    }
+
     /// Checks if the given parcel ID exists within an invoice. The default implementation will fetch
     /// the parcel and check if the given parcel ID exists. Returns the parcel label if valid. Most
     /// providers should implement some sort of caching for `get_yanked_invoice` to avoid fetching
@@ -100,6 +863,7 @@ pub trait Provider {
         I: TryInto<Id> + Send,
         I::Error: Into<ProviderError>,
     {
+        check_parcel_id(parcel_id)?;
         let inv = self.get_yanked_invoice(bindle_id).await?;
         match inv
             .parcel
@@ -108,7 +872,7 @@ pub trait Provider {
             .find(|p| p.label.sha256 == parcel_id)
         {
             Some(p) => Ok(p.label),
-            None => Err(ProviderError::NotFound),
+            None => Err(ProviderError::not_found(parcel_id)),
         }
     }
 
@@ -117,7 +881,12 @@ pub trait Provider {
     ///
     /// For some terminal providers, the bindle ID may not be necessary, but it is always required
     /// for an implementation. Implementors MUST validate that the length of the sent parcel is the
-    /// same as specified in the invoice
+    /// same as specified in the invoice.
+    ///
+    /// As parcels are content addressed, uploading a parcel that already exists is a no-op that
+    /// returns `Ok`. However, if the label's declared size does not match the stored parcel, two
+    /// labels are claiming the same SHA for different content, and implementors must return
+    /// [`ProviderError::DigestConflict`]
     async fn create_parcel<I, R, B>(&self, bindle_id: I, parcel_id: &str, data: R) -> Result<()>
     where
         I: TryInto<Id> + Send,
@@ -125,6 +894,147 @@ pub trait Provider {
         R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
         B: bytes::Buf + Send;
 
+    /// Stores parcel data without a label, computing its SHA-256 digest as it is written. Returns
+    /// a label for the stored parcel with the computed SHA and size filled in.
+    ///
+    /// This is for clients that cannot compute the SHA themselves. Uploading content that is
+    /// already stored is a no-op that returns the label it was originally stored with. The default
+    /// implementation returns an error, as not all providers can store parcels without a label
+    async fn create_parcel_computing_sha<R>(
+        &self,
+        media_type: String,
+        name: String,
+        data: &mut R,
+    ) -> Result<super::Label>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let _ = (media_type, name, data);
+        Err(ProviderError::Unsupported(
+            "creating parcels without a SHA".to_string(),
+        ))
+    }
+
+    /// Writes a chunk of a parcel's data at the given byte offset, as part of a resumable upload.
+    /// Once all of the data has been written, call [`finalize_parcel`](Provider::finalize_parcel)
+    /// to verify it and make the parcel available.
+    ///
+    /// Chunks may be resent (for example, after a dropped connection), in which case they
+    /// overwrite any data already written at that offset and discard anything written after it,
+    /// so an upload always resumes from the last chunk sent. A chunk starting past the end of the
+    /// data written so far would leave a gap, and so is rejected with
+    /// [`ProviderError::OutOfRange`]. Chunks for the same parcel should not be written
+    /// concurrently. The default implementation returns an error, as not all providers support
+    /// resumable uploads
+    async fn create_parcel_chunk(&self, parcel_id: &str, offset: u64, data: &[u8]) -> Result<()> {
+        let _ = (parcel_id, offset, data);
+        Err(ProviderError::Unsupported("resumable uploads".to_string()))
+    }
+
+    /// Completes a resumable upload started with
+    /// [`create_parcel_chunk`](Provider::create_parcel_chunk). The uploaded data is checked
+    /// against the size and SHA in the label before it is made available, returning
+    /// [`ProviderError::SizeMismatch`] or [`ProviderError::DigestMismatch`] if it does not match.
+    /// Returns `NotFound` if no chunks have been uploaded for the parcel.
+    ///
+    /// The default implementation returns an error, as not all providers support resumable
+    /// uploads
+    async fn finalize_parcel(&self, label: &super::Label) -> Result<()> {
+        let _ = label;
+        Err(ProviderError::Unsupported("resumable uploads".to_string()))
+    }
+
+    /// Returns the number of non-yanked invoices that reference the parcel with the given SHA
+    ///
+    /// As parcels are content addressed, many invoices can share the same parcel. This count can
+    /// be used to check whether a parcel is safe to delete. The default implementation scans all
+    /// invoices returned by `list_invoices`
+    async fn parcel_reference_count(&self, parcel_id: &str) -> Result<usize> {
+        Ok(self
+            .list_invoices()
+            .await?
+            .into_iter()
+            .filter(|inv| {
+                !inv.yanked.unwrap_or(false)
+                    && inv
+                        .parcel
+                        .as_ref()
+                        .map(|p| p.iter().any(|p| p.label.sha256 == parcel_id))
+                        .unwrap_or(false)
+            })
+            .count())
+    }
+
+    /// Deletes the parcel with the given SHA from storage.
+    ///
+    /// Implementors must return [`ProviderError::NotFound`] if the parcel does not exist and
+    /// [`ProviderError::InUse`] if any non-yanked invoice still references the parcel. The default
+    /// implementation returns an error, as not all providers support deletion
+    async fn delete_parcel(&self, parcel_id: &str) -> Result<()> {
+        let _ = parcel_id;
+        Err(ProviderError::Unsupported("deleting parcels".to_string()))
+    }
+
+    /// Checks that the underlying storage is reachable and usable, returning an error describing
+    /// the problem if it is not. This is meant for backing readiness probes, so implementations
+    /// should exercise the storage (for example, by writing and removing a file) rather than only
+    /// checking in-memory state.
+    ///
+    /// The default implementation returns an error, as a provider that doesn't check its storage
+    /// shouldn't be reported as healthy
+    async fn health_check(&self) -> Result<()> {
+        Err(ProviderError::Unsupported("health checks".to_string()))
+    }
+
+    /// Returns a summary of what is currently held in storage, such as the number of invoices and
+    /// parcels and how many parcels are no longer referenced by any non-yanked invoice. This is
+    /// useful for reporting storage efficiency or previewing a garbage collection.
+    ///
+    /// The default implementation returns an error, as not all providers can enumerate their data
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        Err(ProviderError::Unsupported("storage stats".to_string()))
+    }
+
+    /// Deletes every parcel that is not referenced by a non-yanked invoice, returning the SHAs of
+    /// the removed parcels. If `keep_yanked` is true, parcels referenced only by yanked invoices
+    /// are kept as well.
+    ///
+    /// Implementors should build the set of referenced parcels by scanning the invoices once rather
+    /// than checking each parcel individually. Note that an invoice created while a collection is
+    /// running may reference a parcel that is removed. The default implementation returns an
+    /// error, as not all providers support deletion
+    async fn gc_parcels(&self, keep_yanked: bool) -> Result<Vec<String>> {
+        let _ = keep_yanked;
+        Err(ProviderError::Unsupported("garbage collection".to_string()))
+    }
+
+    /// Checks that the invoice stored under the given canonical name actually belongs there, by
+    /// re-deriving the canonical name from the stored invoice's name and version. Returns a
+    /// [`ProviderError::CanonicalMismatch`] error if they don't match, which means the invoice
+    /// was written to the wrong place (for example, by a naming collision or a bug) and could
+    /// overwrite or be overwritten by another bindle.
+    ///
+    /// The default implementation returns an error, as not all providers store invoices by
+    /// canonical name
+    async fn verify_canonical_name(&self, canonical_name: &str) -> Result<()> {
+        let _ = canonical_name;
+        Err(ProviderError::Unsupported(
+            "verifying canonical names".to_string(),
+        ))
+    }
+
+    /// Checks the integrity of everything in storage. Every parcel's data is re-hashed and compared
+    /// against its SHA, and every invoice is checked to make sure it can be read and that all of
+    /// its parcels exist. Problems are collected in the returned [`VerifyReport`] rather than
+    /// returned as errors, so a single bad entry does not stop the check.
+    ///
+    /// As this reads all data in storage, implementors should process entries one at a time
+    /// rather than loading everything into memory. The default implementation returns an error,
+    /// as not all providers can enumerate their data
+    async fn verify_store(&self) -> Result<VerifyReport> {
+        Err(ProviderError::Unsupported("verifying storage".to_string()))
+    }
+
     /// Get a specific parcel using its SHA.
     ///
     /// For some terminal providers, the bindle ID may not be necessary, but it is always required
@@ -138,6 +1048,160 @@ pub trait Provider {
         I: TryInto<Id> + Send,
         I::Error: Into<ProviderError>;
 
+    /// Load an invoice and open a reader for each of its parcels whose media type matches any of
+    /// the given media types, returned along with the parcel's label in the order the parcels
+    /// appear in the invoice. This allows a client to fetch only the parcels it can use, such as
+    /// just the `application/wasm` parcels. Media types can use a wildcard subtype, such as
+    /// `image/*`. See [`Label::media_type_matches`](crate::Label::media_type_matches) for how
+    /// they are compared.
+    ///
+    /// Like `get_invoice`, this returns a [`ProviderError::Yanked`] error if the invoice is yanked.
+    /// All of the readers are opened before returning, so a missing parcel fails the whole call.
+    /// This is only available if the `providers` feature is enabled
+    #[cfg(feature = "providers")]
+    async fn get_parcels_by_media_type<I>(
+        &self,
+        id: I,
+        media_types: &[String],
+    ) -> Result<Vec<(super::Label, Box<dyn AsyncRead + Unpin + Send + Sync>)>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        use futures::StreamExt;
+
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        let inv = self.get_invoice(&parsed_id).await?;
+        let mut parcels = Vec::new();
+        for label in inv.parcel.unwrap_or_default().into_iter().map(|p| p.label) {
+            if !media_types.iter().any(|m| label.media_type_matches(m)) {
+                continue;
+            }
+            let stream = self.get_parcel(&parsed_id, &label.sha256).await?;
+            let reader: Box<dyn AsyncRead + Unpin + Send + Sync> =
+                Box::new(tokio_util::io::StreamReader::new(stream.map(|res| {
+                    res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+                })));
+            parcels.push((label, reader));
+        }
+        Ok(parcels)
+    }
+
+    /// Get a specific parcel using its SHA, just like `get_parcel`, but fail with a
+    /// [`ProviderError::Timeout`] error if the data makes no progress for the given duration,
+    /// either because storage stops producing data or because the caller stops reading it. This
+    /// stops a stalled read or a slow client from holding on to the parcel indefinitely. See
+    /// [`IdleTimeout`](timeout::IdleTimeout) for how the timeout is measured. This is only
+    /// available if the `providers` feature is enabled
+    #[cfg(feature = "providers")]
+    async fn get_parcel_with_timeout<I>(
+        &self,
+        bindle_id: I,
+        parcel_id: &str,
+        timeout: std::time::Duration,
+    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let stream = self.get_parcel(bindle_id, parcel_id).await?;
+        Ok(Box::new(timeout::IdleTimeout::new(stream, timeout)))
+    }
+
+    /// Get the bytes of a parcel from `start` up to (but not including) `end`. If `end` is `None`
+    /// or past the end of the parcel, the rest of the parcel is returned. This is intended for
+    /// serving HTTP range requests without reading the whole parcel.
+    ///
+    /// Implementors must return [`ProviderError::NotFound`] if the parcel does not exist and
+    /// [`ProviderError::OutOfRange`] if `start` is past the end of the parcel. The default
+    /// implementation reads and discards the data before `start`, so providers that can seek
+    /// should override it
+    async fn get_parcel_range<I>(
+        &self,
+        bindle_id: I,
+        parcel_id: &str,
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = bindle_id.try_into().map_err(|e| e.into())?;
+        let label = self.validate_parcel(parsed_id.clone(), parcel_id).await?;
+        let end = range_end(label.size, start, end)?;
+        let stream = self.get_parcel(parsed_id, parcel_id).await?;
+        Ok(Box::new(slice_stream(stream, start, end)))
+    }
+
+    /// Writes an uncompressed tar archive containing the invoice and all of its parcels to the
+    /// given writer. This can be used to export a bindle for backup or to move it to another
+    /// server.
+    ///
+    /// The archive uses the same layout as a standalone bindle, with the invoice stored at
+    /// `<canonical name>/invoice.toml` and each parcel stored at
+    /// `<canonical name>/parcels/<sha>.dat`. If any parcels are missing, a
+    /// [`ProviderError::MissingParcels`] error is returned before anything is written. This is
+    /// only available if the `providers` feature is enabled
+    #[cfg(feature = "providers")]
+    async fn export_invoice_tar<I, W>(&self, id: I, out: &mut W) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+        W: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        use futures::StreamExt;
+        use tokio::io::AsyncWriteExt;
+
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        // This checks that all parcels exist so we can fail before writing anything
+        let inv = self.get_invoice_verified(&parsed_id).await?;
+        let base = inv.canonical_name();
+
+        let data = toml::to_vec(&inv)?;
+        write_tar_header(out, &format!("{}/invoice.toml", base), data.len() as u64).await?;
+        out.write_all(&data).await?;
+        write_tar_padding(out, data.len() as u64).await?;
+
+        for label in inv.parcel.iter().flatten().map(|p| &p.label) {
+            let path = format!("{}/parcels/{}.dat", base, label.sha256);
+            write_tar_header(out, &path, label.size).await?;
+            let mut stream = self.get_parcel(&parsed_id, &label.sha256).await?;
+            let mut written: u64 = 0;
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                written += chunk.len() as u64;
+                out.write_all(&chunk).await?;
+            }
+            // The header has already been written, so the archive is corrupt if the size is off
+            if written != label.size {
+                return Err(ProviderError::SizeMismatch {
+                    expected: label.size,
+                    actual: written,
+                });
+            }
+            write_tar_padding(out, written).await?;
+        }
+
+        // A tar archive ends with two empty blocks
+        out.write_all(&[0; TAR_BLOCK_SIZE * 2]).await?;
+        out.flush().await?;
+        Ok(())
+    }
+
+    /// Returns the size in bytes of the parcel with the given SHA without loading its data, or
+    /// [`ProviderError::NotFound`] if the parcel does not exist. This is useful for things like
+    /// displaying download progress.
+    ///
+    /// The default implementation returns an error, as not all providers can look up a parcel
+    /// without a bindle ID
+    async fn get_parcel_size(&self, parcel_id: &str) -> Result<u64> {
+        let _ = parcel_id;
+        Err(ProviderError::Unsupported(
+            "fetching parcel sizes".to_string(),
+        ))
+    }
+
     /// Checks if the given parcel exists in storage.
     ///
     /// This should not load the full parcel but only indicate if the parcel exists. For some
@@ -148,6 +1212,466 @@ pub trait Provider {
         I::Error: Into<ProviderError>;
 }
 
+/// The parcels that still need to be uploaded after creating an invoice, and where to upload them.
+/// See [`Provider::create_invoice_with_plan`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadPlan {
+    /// The canonical name the invoice was stored under
+    pub invoice_name: String,
+    /// Every parcel in the invoice that is not yet in storage
+    pub parcels: Vec<ParcelUpload>,
+}
+
+/// A single missing parcel in an [`UploadPlan`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParcelUpload {
+    /// The label of the missing parcel
+    pub label: super::Label,
+    /// Where the parcel should be uploaded to. The format depends on the provider
+    pub location: String,
+}
+
+/// A lightweight summary of an invoice's status. See [`Provider::invoice_status`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InvoiceStatus {
+    /// Whether the invoice exists in storage. If this is false, `version` is the requested version
+    /// and all other fields are empty
+    pub exists: bool,
+    /// Whether the invoice has been yanked
+    pub yanked: bool,
+    /// The version of the invoice
+    pub version: String,
+    /// The number of parcels in the invoice
+    pub parcel_count: usize,
+}
+
+impl InvoiceStatus {
+    /// Returns the status of an invoice that does not exist
+    pub(crate) fn missing(id: &Id) -> Self {
+        InvoiceStatus {
+            exists: false,
+            yanked: false,
+            version: id.version_string(),
+            parcel_count: 0,
+        }
+    }
+}
+
+impl From<&super::Invoice> for InvoiceStatus {
+    fn from(inv: &super::Invoice) -> Self {
+        InvoiceStatus {
+            exists: true,
+            yanked: inv.yanked.unwrap_or(false),
+            version: inv.bindle.id.version_string(),
+            parcel_count: inv.parcel.as_ref().map(|p| p.len()).unwrap_or_default(),
+        }
+    }
+}
+
+/// A summary of the data held by a provider. See [`Provider::storage_stats`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    /// The total number of invoices, including yanked invoices
+    pub invoice_count: usize,
+    /// The total number of parcels
+    pub parcel_count: usize,
+    /// The sum of the sizes of all parcels, in bytes
+    pub total_parcel_bytes: u64,
+    /// The number of parcels that are not referenced by any non-yanked invoice
+    pub dangling_parcel_count: usize,
+}
+
+/// The problems found while checking the integrity of storage. See [`Provider::verify_store`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// The SHAs of parcels whose data does not match their SHA or stored label
+    pub corrupt_parcels: Vec<String>,
+    /// The SHAs of parcels that are not referenced by any invoice, including yanked invoices
+    pub orphaned_parcels: Vec<String>,
+    /// The names of invoices that exist in storage but could not be read or parsed
+    pub unreadable_invoices: Vec<String>,
+    /// A map of invoice IDs to the SHAs of the parcels they reference that are not in storage
+    pub invoices_missing_parcels: BTreeMap<String, Vec<String>>,
+    /// The canonical names of invoices that are stored under the wrong name. Only checked by
+    /// providers configured to do so. See [`Provider::verify_canonical_name`]
+    pub misplaced_invoices: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_parcels.is_empty()
+            && self.orphaned_parcels.is_empty()
+            && self.unreadable_invoices.is_empty()
+            && self.invoices_missing_parcels.is_empty()
+            && self.misplaced_invoices.is_empty()
+    }
+}
+
+/// The maximum number of invoices returned in a single page by
+/// [`Provider::list_invoices_paged`] and [`Provider::query_invoices_paged`]
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Sorts the invoices by canonical name and returns the requested page along with the total
+/// number of invoices. The limit is clamped to [`MAX_PAGE_SIZE`]
+pub(crate) fn paginate(
+    mut invoices: Vec<super::Invoice>,
+    offset: usize,
+    limit: usize,
+) -> (Vec<super::Invoice>, usize) {
+    let total = invoices.len();
+    invoices.sort_by_cached_key(|inv| inv.canonical_name());
+    let page = invoices
+        .into_iter()
+        .skip(offset)
+        .take(limit.min(MAX_PAGE_SIZE))
+        .collect();
+    (page, total)
+}
+
+/// Normalizes the media type of every parcel in the invoice (see
+/// [`normalize_media_type`](crate::normalize_media_type)) so that equivalent types are always
+/// stored the same way. Returns an [`ProviderError::Invalid`] error listing every parcel with an
+/// invalid media type
+pub(crate) fn normalize_media_types(inv: &mut super::Invoice) -> Result<()> {
+    let mut errors = Vec::new();
+    for (i, p) in inv.parcel.iter_mut().flatten().enumerate() {
+        match p.label.normalized_media_type() {
+            Some(media_type) => p.label.media_type = media_type,
+            None => errors.push(crate::ValidationError::InvalidMediaType(i)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ProviderError::Invalid(errors));
+    }
+    Ok(())
+}
+
+/// Runs every check an invoice must pass before any provider creates it, normalizing its media
+/// types in place. Invoices containing different parcels with the same name are only rejected if
+/// `reject_duplicate_parcel_names` is set, otherwise they are accepted with a warning. If a policy
+/// is given, the invoice must also meet it
+pub(crate) fn validate_new_invoice(
+    inv: &mut super::Invoice,
+    policy: Option<&super::InvoicePolicy>,
+    reject_duplicate_parcel_names: bool,
+) -> Result<()> {
+    // It is illegal to create a yanked invoice.
+    if inv.yanked.unwrap_or(false) {
+        tracing::debug!(id = %inv.bindle.id, "Invoice is set to yanked");
+        return Err(ProviderError::CreateYanked);
+    }
+
+    // Make sure we understand the version of the spec this invoice was written against
+    if inv.bindle_version != crate::BINDLE_VERSION_1 {
+        tracing::debug!(bindle_version = %inv.bindle_version, "Invoice has an unsupported bindle version");
+        return Err(ProviderError::UnsupportedVersion(
+            inv.bindle_version.clone(),
+        ));
+    }
+
+    // Store media types in a consistent form so equivalent types always compare equal
+    normalize_media_types(inv)?;
+    // Digests are used to build storage paths and keys, so reject malformed ones before storing
+    // anything
+    check_parcel_digests(inv)?;
+
+    // Unknown fields are kept for forward compatibility, but ones that look like typos of known
+    // fields would silently drop data (such as `[[parcels]]` instead of `[[parcel]]`)
+    let misspelled = inv.check_extra_fields();
+    if !misspelled.is_empty() {
+        tracing::debug!(?misspelled, "Invoice contains misspelled fields");
+        return Err(ProviderError::Invalid(
+            misspelled
+                .into_iter()
+                .map(crate::ValidationError::MisspelledField)
+                .collect(),
+        ));
+    }
+
+    let duplicates = inv.check_parcel_names();
+    if !duplicates.is_empty() {
+        if reject_duplicate_parcel_names {
+            tracing::debug!(?duplicates, "Invoice contains duplicate parcel names");
+            return Err(ProviderError::Invalid(
+                duplicates
+                    .into_iter()
+                    .map(crate::ValidationError::DuplicateParcelName)
+                    .collect(),
+            ));
+        }
+        tracing::warn!(
+            ?duplicates,
+            "Invoice contains different parcels with the same name"
+        );
+    }
+
+    check_invoice_policy(inv, policy)
+}
+
+/// Checks the invoice against the given policy, if any, returning a
+/// [`ProviderError::PolicyViolation`] error listing every rule it breaks
+pub(crate) fn check_invoice_policy(
+    inv: &super::Invoice,
+    policy: Option<&super::InvoicePolicy>,
+) -> Result<()> {
+    if let Some(policy) = policy {
+        if let Err(violations) = crate::check_policy(inv, policy) {
+            tracing::debug!(?violations, "Invoice does not meet policy");
+            return Err(ProviderError::PolicyViolation(violations));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a parcel ID is safe to use as a path segment or storage key. Parcel IDs are hex
+/// encoded digests, so anything other than ASCII letters and digits (such as `/`, `\` or `..`) is
+/// rejected with an [`ProviderError::InvalidId`] error
+pub(crate) fn check_parcel_id(parcel_id: &str) -> Result<()> {
+    if parcel_id.is_empty() || !parcel_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ProviderError::InvalidId(
+            crate::id::ParseError::InvalidParcelId(parcel_id.to_owned()),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that every parcel in the invoice has a well formed digest (see
+/// [`Label::has_valid_digest`](crate::Label::has_valid_digest)), as digests are used to build
+/// storage paths. Returns an [`ProviderError::Invalid`] error listing every parcel with an invalid
+/// digest
+pub(crate) fn check_parcel_digests(inv: &super::Invoice) -> Result<()> {
+    let errors: Vec<_> = inv
+        .parcel
+        .iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, p)| !p.label.has_valid_digest())
+        .map(|(i, _)| crate::ValidationError::InvalidSha(i))
+        .collect();
+    if !errors.is_empty() {
+        return Err(ProviderError::Invalid(errors));
+    }
+    Ok(())
+}
+
+/// Returns the SHAs of all parcels referenced by the given invoices. Yanked invoices are skipped
+/// unless `include_yanked` is true
+pub(crate) fn referenced_parcels<'a>(
+    invoices: impl IntoIterator<Item = &'a super::Invoice>,
+    include_yanked: bool,
+) -> HashSet<String> {
+    invoices
+        .into_iter()
+        .filter(|inv| include_yanked || !inv.yanked.unwrap_or(false))
+        .filter_map(|inv| inv.parcel.as_ref())
+        .flatten()
+        .map(|p| p.label.sha256.clone())
+        .collect()
+}
+
+#[cfg(feature = "providers")]
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Writes a tar header for a regular file with the given path and size
+#[cfg(feature = "providers")]
+async fn write_tar_header<W: tokio::io::AsyncWrite + Unpin>(
+    out: &mut W,
+    path: &str,
+    size: u64,
+) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    // The ustar format is used as it allows paths longer than 100 bytes by splitting them into a
+    // prefix and name, which is needed for the long SHAs used in the paths
+    let mut header = tokio_tar::Header::new_ustar();
+    header.set_path(path)?;
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_entry_type(tokio_tar::EntryType::Regular);
+    header.set_cksum();
+    out.write_all(header.as_bytes()).await?;
+    Ok(())
+}
+
+/// Pads the data of a tar entry with the given size out to a full block
+#[cfg(feature = "providers")]
+async fn write_tar_padding<W: tokio::io::AsyncWrite + Unpin>(out: &mut W, size: u64) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let remainder = (size % TAR_BLOCK_SIZE as u64) as usize;
+    if remainder != 0 {
+        out.write_all(&[0; TAR_BLOCK_SIZE][remainder..]).await?;
+    }
+    Ok(())
+}
+
+/// Merges the given annotations into the invoice's annotations for providers implementing
+/// `update_invoice_annotations`, returning a `Yanked` error if the invoice is yanked
+pub(crate) fn merge_annotations(
+    inv: &mut super::Invoice,
+    annotations: BTreeMap<String, String>,
+) -> Result<()> {
+    if inv.yanked.unwrap_or(false) {
+        return Err(ProviderError::Yanked);
+    }
+    inv.annotations
+        .get_or_insert_with(BTreeMap::new)
+        .extend(annotations);
+    Ok(())
+}
+
+/// Appends the given parcels to the invoice for providers implementing `add_parcels_to_invoice`,
+/// skipping any whose SHA is already in the invoice. Returns a `Yanked` error if the invoice is
+/// yanked, a `SignedInvoice` error if parcels would be added to a signed invoice (as signatures
+/// cover every parcel), or an `Invalid` error if any of the new labels are invalid
+pub(crate) fn append_parcels(inv: &mut super::Invoice, parcels: Vec<super::Parcel>) -> Result<()> {
+    if inv.yanked.unwrap_or(false) {
+        return Err(ProviderError::Yanked);
+    }
+    let mut seen: HashSet<String> = inv
+        .parcel
+        .iter()
+        .flatten()
+        .map(|p| p.label.sha256.clone())
+        .collect();
+    let parcels: Vec<_> = parcels
+        .into_iter()
+        .filter(|p| seen.insert(p.label.sha256.clone()))
+        .collect();
+    if parcels.is_empty() {
+        return Ok(());
+    }
+    if inv
+        .signature
+        .as_ref()
+        .map(|s| !s.is_empty())
+        .unwrap_or(false)
+    {
+        return Err(ProviderError::SignedInvoice);
+    }
+    inv.parcel.get_or_insert_with(Vec::new).extend(parcels);
+    normalize_media_types(inv)?;
+    check_parcel_digests(inv)
+}
+
+/// Returns the latest of the given versions by semver precedence, preferring release versions over
+/// pre-release versions
+pub(crate) fn latest_version<'a>(
+    versions: impl IntoIterator<Item = &'a semver::Version>,
+) -> Option<&'a semver::Version> {
+    versions.into_iter().max_by_key(|v| (v.pre.is_empty(), *v))
+}
+
+/// Warms a provider's cache by loading each of the given invoices with `get_yanked_invoice`, for
+/// providers that cache whatever they load. Returns the number of invoices loaded, skipping any
+/// that don't exist
+pub(crate) async fn warm_cache_via_get<P: Provider + Sync>(
+    provider: &P,
+    ids: &[String],
+) -> Result<usize> {
+    let mut warmed = 0;
+    for id in ids {
+        match provider.get_yanked_invoice(id.as_str()).await {
+            Ok(_) => warmed += 1,
+            Err(ProviderError::NotFound { .. }) => {
+                tracing::debug!(invoice_id = %id, "Invoice to warm does not exist, skipping")
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    tracing::debug!(warmed, "Warmed invoice cache");
+    Ok(warmed)
+}
+
+/// Returns the ETag for the given serialized invoice, which is the hex encoded SHA-256 of the data
+pub(crate) fn invoice_etag(data: &[u8]) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(data))
+}
+
+/// Checks the label of a parcel being uploaded against the size of an already stored parcel with
+/// the same SHA, returning a `DigestConflict` error if they disagree
+pub(crate) fn check_existing_parcel(label: &super::Label, existing_size: u64) -> Result<()> {
+    if existing_size != label.size {
+        return Err(ProviderError::DigestConflict {
+            sha: label.sha256.clone(),
+            existing: existing_size,
+            declared: label.size,
+        });
+    }
+    Ok(())
+}
+
+/// Validates a requested byte range against the size of a parcel, returning the exclusive end of
+/// the range clamped to the size of the parcel
+pub(crate) fn range_end(size: u64, start: u64, end: Option<u64>) -> Result<u64> {
+    let end = end.unwrap_or(size).min(size);
+    if start > size || start > end {
+        return Err(ProviderError::OutOfRange { start, size });
+    }
+    Ok(end)
+}
+
+/// Encodes the given value as CBOR
+#[cfg(feature = "providers")]
+pub(crate) fn to_cbor<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ciborium::ser::into_writer(value, &mut data)?;
+    Ok(data)
+}
+
+/// Decodes a value from the given CBOR data
+#[cfg(feature = "providers")]
+pub(crate) fn from_cbor<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T> {
+    Ok(ciborium::de::from_reader(data)?)
+}
+
+/// Creates an anonymous temporary file for buffering data. Creating it touches the disk, so it is
+/// done on a blocking thread
+pub(crate) async fn temp_file() -> Result<tokio::fs::File> {
+    let file = tokio::task::spawn_blocking(tempfile::tempfile)
+        .await
+        .map_err(|e| ProviderError::Other(e.to_string()))??;
+    Ok(tokio::fs::File::from_std(file))
+}
+
+/// Creates a named temporary file for buffering data and returns its path, which removes the file
+/// when dropped. Unlike [`temp_file`], the file is not kept open
+#[cfg(feature = "providers")]
+pub(crate) async fn temp_path() -> Result<tempfile::TempPath> {
+    let file = tokio::task::spawn_blocking(tempfile::NamedTempFile::new)
+        .await
+        .map_err(|e| ProviderError::Other(e.to_string()))??;
+    Ok(file.into_temp_path())
+}
+
+/// Trims a stream of parcel data down to the bytes in the range `start..end`
+fn slice_stream<S>(stream: S, start: u64, end: u64) -> impl Stream<Item = Result<bytes::Bytes>>
+where
+    S: Stream<Item = Result<bytes::Bytes>>,
+{
+    use futures::StreamExt;
+
+    stream
+        .scan(0u64, move |pos, res| {
+            // Stop reading once we've passed the end of the range
+            if *pos >= end {
+                return futures::future::ready(None);
+            }
+            let chunk = res.map(|mut chunk| {
+                let chunk_start = *pos;
+                *pos += chunk.len() as u64;
+                let from = start.saturating_sub(chunk_start).min(chunk.len() as u64);
+                let to = end.saturating_sub(chunk_start).min(chunk.len() as u64);
+                chunk.truncate(to as usize);
+                chunk.split_off(from as usize)
+            });
+            futures::future::ready(Some(chunk))
+        })
+        .filter(|res| futures::future::ready(!matches!(res, Ok(chunk) if chunk.is_empty())))
+}
+
 /// ProviderError describes the possible error states when storing and retrieving bindles.
 #[derive(Error, Debug)]
 pub enum ProviderError {
@@ -157,12 +1681,27 @@ pub enum ProviderError {
     /// The error returned when the invoice is valid, but is already set to yanked
     #[error("bindle cannot be created as yanked")]
     CreateYanked,
-    /// When the resource is not found in the store
-    #[error("resource not found: if an item does not appear in our records, it does not exist!")]
-    NotFound,
-    /// Any errors that occur due to IO issues. Contains the underlying IO `Error`
-    #[error("resource could not be loaded")]
-    Io(#[from] std::io::Error),
+    /// The invoice being created targets a version of the bindle spec that is not supported
+    #[error("unsupported bindle version {0}")]
+    UnsupportedVersion(String),
+    /// When the resource is not found in the store. Contains the ID of the invoice or parcel that
+    /// could not be found
+    #[error(
+        "resource {id} not found: if an item does not appear in our records, it does not exist!"
+    )]
+    NotFound { id: String },
+    /// The invoice exists, but some of the parcels it references are not in the store. Contains
+    /// the SHAs of the missing parcels
+    #[error("invoice is missing parcels: {}", .0.join(", "))]
+    MissingParcels(Vec<String>),
+    /// Any errors that occur due to IO issues. Contains the underlying IO `Error` and, if known,
+    /// the path that was being accessed
+    #[error("resource could not be loaded{}", .path.as_ref().map(|p| format!(" from {}", p.display())).unwrap_or_default())]
+    Io {
+        path: Option<PathBuf>,
+        #[source]
+        source: std::io::Error,
+    },
     /// The resource being created already exists in the system
     #[error("resource already exists")]
     Exists,
@@ -170,14 +1709,55 @@ pub enum ProviderError {
     #[error("invalid ID given")]
     InvalidId(#[from] crate::id::ParseError),
     /// An uploaded parcel does not match the SHA-256 sum provided with its label
-    #[error("digest does not match")]
-    DigestMismatch,
-    #[error("parcel size does not match invoice")]
-    SizeMismatch,
+    #[error("digest does not match: expected {expected}, got {actual}")]
+    DigestMismatch { expected: String, actual: String },
+    /// An uploaded parcel does not match the size provided with its label
+    #[error("parcel size does not match invoice: expected {expected} bytes, got {actual}")]
+    SizeMismatch { expected: u64, actual: u64 },
     #[error(
         "a write operation is currently in progress for this resource and it cannot be accessed"
     )]
     WriteInProgress,
+    /// The requested range starts past the end of the resource
+    #[error("range starting at {start} is out of bounds for a resource of {size} bytes")]
+    OutOfRange { start: u64, size: u64 },
+    /// A parcel with the same SHA already exists, but its size does not match the size declared by
+    /// the label of the new upload
+    #[error("parcel {sha} already exists with a size of {existing} bytes, but the label declares {declared} bytes")]
+    DigestConflict {
+        sha: String,
+        existing: u64,
+        declared: u64,
+    },
+    /// The resource cannot be removed because it is still referenced by a non-yanked invoice
+    #[error("resource is still in use by an invoice")]
+    InUse,
+    /// The provider is read-only and does not accept any changes
+    #[error("storage is read-only")]
+    ReadOnly,
+    /// An uploaded parcel is larger than the maximum size the provider accepts
+    #[error("parcel is larger than the limit of {limit} bytes")]
+    TooLarge { limit: u64 },
+    /// Storing a parcel would take the total size of all stored parcels over the provider's quota
+    #[error("storing the parcel would exceed the storage quota of {limit} bytes")]
+    QuotaExceeded { limit: u64 },
+    /// The invoice stored under a canonical name has a name and version that belong under a
+    /// different canonical name. See [`Provider::verify_canonical_name`]
+    #[error("invoice stored as {canonical_name} should be stored as {expected}")]
+    CanonicalMismatch {
+        canonical_name: String,
+        expected: String,
+    },
+    /// Reading a resource made no progress within the allowed time. Contains the timeout
+    #[error("timed out after {0:?} without making progress")]
+    Timeout(std::time::Duration),
+    /// The invoice is signed, and the change would invalidate its signatures. Create a new version
+    /// of the bindle and sign it instead
+    #[error("invoice is signed and cannot be changed without invalidating its signatures")]
+    SignedInvoice,
+    /// A token granting access to a resource is invalid or has expired
+    #[error("token is invalid or has expired")]
+    Unauthorized,
     /// An error that occurs when the provider implementation uses a proxy and that proxy request
     /// encounters an error. Only available with the `client` feature enabled
     #[cfg(feature = "client")]
@@ -187,6 +1767,13 @@ pub enum ProviderError {
     /// The data cannot be properly deserialized from TOML
     #[error("resource is malformed")]
     Malformed(#[from] toml::de::Error),
+    /// The data can be deserialized, but violates one or more invariants of the spec
+    #[error("resource is invalid: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))]
+    Invalid(Vec<crate::ValidationError>),
+    /// The invoice is valid, but breaks one or more rules of the configured
+    /// [`InvoicePolicy`](crate::InvoicePolicy)
+    #[error("invoice does not meet policy: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))]
+    PolicyViolation(Vec<crate::PolicyViolation>),
     /// The data cannot be properly serialized from TOML
     #[error("resource cannot be stored")]
     Unserializable(#[from] toml::ser::Error),
@@ -194,12 +1781,38 @@ pub enum ProviderError {
     #[error("failed signature check invoice")]
     FailedSigning(#[from] SignatureError),
 
+    /// The provider does not implement the requested operation. Contains a description of the
+    /// operation
+    #[error("provider does not support {0}")]
+    Unsupported(String),
+
     /// A catch-all for uncategorized errors. Contains an error message describing the underlying
     /// issue
     #[error("{0}")]
     Other(String),
 }
 
+impl ProviderError {
+    /// Returns a `NotFound` error for the given invoice or parcel ID
+    pub fn not_found(id: impl ToString) -> Self {
+        ProviderError::NotFound { id: id.to_string() }
+    }
+
+    /// Returns an `Io` error for an operation on the given path
+    pub fn io_at(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        ProviderError::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+}
+
+impl From<std::io::Error> for ProviderError {
+    fn from(source: std::io::Error) -> Self {
+        ProviderError::Io { path: None, source }
+    }
+}
+
 impl From<std::convert::Infallible> for ProviderError {
     fn from(_: std::convert::Infallible) -> ProviderError {
         // This can never happen (by definition of infallible), so it doesn't matter what we return
@@ -211,12 +1824,23 @@ impl From<std::convert::Infallible> for ProviderError {
 // deserialization/serialization errors that aren't tied to TOML as backends can serialize how they
 // want. For now there is this workaround
 #[cfg(feature = "providers")]
-impl From<serde_cbor::Error> for ProviderError {
-    fn from(e: serde_cbor::Error) -> Self {
-        if e.is_io() {
-            ProviderError::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
-        } else {
-            ProviderError::Other(format!("Unable to parse CBOR payload: {}", e))
+impl From<ciborium::ser::Error<std::io::Error>> for ProviderError {
+    fn from(e: ciborium::ser::Error<std::io::Error>) -> Self {
+        match e {
+            ciborium::ser::Error::Io(e) => ProviderError::from(e),
+            ciborium::ser::Error::Value(msg) => {
+                ProviderError::Other(format!("Unable to serialize CBOR payload: {}", msg))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "providers")]
+impl From<ciborium::de::Error<std::io::Error>> for ProviderError {
+    fn from(e: ciborium::de::Error<std::io::Error>) -> Self {
+        match e {
+            ciborium::de::Error::Io(e) => ProviderError::from(e),
+            e => ProviderError::Other(format!("Unable to parse CBOR payload: {}", e)),
         }
     }
 }
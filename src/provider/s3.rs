@@ -0,0 +1,952 @@
+//! A `Provider` implementation backed by an S3 compatible object store.
+//!
+//! Invoices and parcels are stored as objects in a single bucket, using keys that mirror the
+//! layout of the [`FileProvider`](crate::provider::file::FileProvider):
+//! `invoices/<canonical name>/invoice.toml` and `parcels/<sha>/parcel.dat`. All keys can optionally
+//! be placed under a common prefix so that multiple installations can share a bucket.
+//!
+//! Invoices and parcels are created with a conditional `PUT` (`If-None-Match: *`), so an existing
+//! object is never overwritten by a create. This requires an object store that supports
+//! conditional writes. Updates to existing invoices (such as yanking) are not locked, so concurrent
+//! updates of the same invoice from different servers can race, with the last write winning.
+//!
+//! This will only be available if the `s3` feature is enabled
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE, IF_NONE_MATCH};
+use reqwest::{Body, StatusCode};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, error, info, instrument, trace, warn};
+
+use crate::provider::{
+    check_existing_parcel, check_invoice_policy, merge_annotations, referenced_parcels,
+    validate_new_invoice, Provider, ProviderError, Result, StorageStats,
+};
+use crate::search::Search;
+use crate::verification::Verified;
+use crate::{Id, Signed};
+
+const INVOICE_DIRECTORY: &str = "invoices";
+const PARCEL_DIRECTORY: &str = "parcels";
+const INVOICE_FILE: &str = "invoice.toml";
+const PARCEL_FILE: &str = "parcel.dat";
+/// How long the presigned URL for each request is valid. Requests are sent immediately after
+/// signing, so this only needs to cover clock skew and slow uploads
+const SIGNATURE_DURATION: Duration = Duration::from_secs(15 * 60);
+/// The maximum number of parcels checked for existence at once when creating an invoice
+const PARCEL_CHECK_CONCURRENCY: usize = 32;
+
+/// The settings needed to connect to an S3 compatible bucket
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    /// The URL of the S3 endpoint, such as `https://s3.us-east-1.amazonaws.com`
+    pub endpoint: String,
+    /// The name of the bucket to store data in
+    pub bucket: String,
+    /// The region the bucket is in
+    pub region: String,
+    /// The access key ID used to sign requests
+    pub access_key: String,
+    /// The secret access key used to sign requests
+    pub secret_key: String,
+    /// Whether to address the bucket as part of the path (`https://endpoint/bucket/key`) rather
+    /// than as a subdomain (`https://bucket.endpoint/key`). Most self hosted object stores need
+    /// this enabled
+    pub path_style: bool,
+    /// An optional prefix prepended (with a `/` separator) to every key
+    pub prefix: Option<String>,
+}
+
+impl S3Config {
+    /// Loads the configuration from the environment, returning `None` if any required variable is
+    /// missing. The endpoint and bucket are read from `BINDLE_S3_ENDPOINT` and `BINDLE_S3_BUCKET`
+    /// and the credentials from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`. The optional
+    /// `BINDLE_S3_REGION` (defaults to `us-east-1`), `BINDLE_S3_PATH_STYLE` and `BINDLE_S3_PREFIX`
+    /// variables are also read
+    pub fn from_env() -> Option<Self> {
+        Some(S3Config {
+            endpoint: std::env::var("BINDLE_S3_ENDPOINT").ok()?,
+            bucket: std::env::var("BINDLE_S3_BUCKET").ok()?,
+            region: std::env::var("BINDLE_S3_REGION").unwrap_or_else(|_| "us-east-1".to_owned()),
+            access_key: std::env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_key: std::env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+            path_style: std::env::var("BINDLE_S3_PATH_STYLE")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+            prefix: std::env::var("BINDLE_S3_PREFIX").ok(),
+        })
+    }
+}
+
+/// A backend for storing and retrieving bindles and parcels in an S3 compatible bucket.
+///
+/// An S3Provider needs a search engine implementation. When invoices are created or yanked, the
+/// index will be updated. As the bucket is the source of truth, the index is rebuilt from the
+/// stored invoices when the provider is created.
+pub struct S3Provider<T> {
+    bucket: Arc<Bucket>,
+    credentials: Arc<Credentials>,
+    prefix: Option<String>,
+    client: reqwest::Client,
+    index: T,
+    policy: Option<Arc<crate::InvoicePolicy>>,
+    reject_duplicate_parcel_names: bool,
+}
+
+impl<T: Clone> Clone for S3Provider<T> {
+    fn clone(&self) -> Self {
+        S3Provider {
+            bucket: Arc::clone(&self.bucket),
+            credentials: Arc::clone(&self.credentials),
+            prefix: self.prefix.clone(),
+            client: self.client.clone(),
+            index: self.index.clone(),
+            policy: self.policy.clone(),
+            reject_duplicate_parcel_names: self.reject_duplicate_parcel_names,
+        }
+    }
+}
+
+impl<T: Search + Send + Sync> S3Provider<T> {
+    /// Returns a new provider for the configured bucket, warming the search index with any
+    /// invoices already stored in it. Returns an error if the endpoint or bucket name is invalid
+    pub async fn new(config: S3Config, index: T) -> Result<Self> {
+        debug!(endpoint = %config.endpoint, bucket = %config.bucket, "Creating new S3 provider");
+        let endpoint: url::Url = config
+            .endpoint
+            .parse()
+            .map_err(|e| ProviderError::Other(format!("Invalid S3 endpoint: {}", e)))?;
+        let style = if config.path_style {
+            UrlStyle::Path
+        } else {
+            UrlStyle::VirtualHost
+        };
+        let bucket = Bucket::new(endpoint, style, config.bucket, config.region)
+            .map_err(|e| ProviderError::Other(format!("Invalid S3 bucket: {}", e)))?;
+        let provider = S3Provider {
+            bucket: Arc::new(bucket),
+            credentials: Arc::new(Credentials::new(config.access_key, config.secret_key)),
+            prefix: config
+                .prefix
+                .map(|p| p.trim_matches('/').to_owned())
+                .filter(|p| !p.is_empty()),
+            client: reqwest::Client::new(),
+            index,
+            policy: None,
+            reject_duplicate_parcel_names: false,
+        };
+        debug!("warming index");
+        if let Err(e) = provider.rebuild_index().await {
+            warn!(error = %e, "Error warming index");
+        }
+        Ok(provider)
+    }
+
+    /// Sets a policy that invoices must meet to be created or to have their annotations updated.
+    /// Invoices that break any of its rules are rejected with a
+    /// [`PolicyViolation`](crate::provider::ProviderError::PolicyViolation) error listing every
+    /// rule broken. Invoices already in storage are only checked when their contents are changed,
+    /// so they can still be yanked and unyanked. No policy is set by default
+    pub fn policy(mut self, policy: crate::InvoicePolicy) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Sets whether invoices containing different parcels with the same name are rejected when
+    /// they are created, with an [`Invalid`](crate::provider::ProviderError::Invalid) error listing
+    /// each duplicated name. Otherwise such invoices are accepted with a warning. See
+    /// [`Invoice::check_parcel_names`](crate::Invoice::check_parcel_names). Disabled by default
+    pub fn reject_duplicate_parcel_names(mut self, reject: bool) -> Self {
+        self.reject_duplicate_parcel_names = reject;
+        self
+    }
+
+    /// Re-indexes every invoice in the bucket, returning the number of invoices indexed
+    pub async fn rebuild_index(&self) -> Result<usize> {
+        let invoices = self.list_invoices().await?;
+        for inv in invoices.iter() {
+            trace!(id = %inv.bindle.id, "Indexing invoice");
+            if let Err(e) = self.index.index(inv).await {
+                error!(id = %inv.bindle.id, error = %e, "Error indexing invoice");
+            }
+        }
+        Ok(invoices.len())
+    }
+
+    /// Sets the yanked status of an existing invoice and re-indexes it. Returns `NotFound` if the
+    /// invoice does not exist
+    async fn set_yanked(&self, parsed_id: &Id, yanked: bool) -> Result<()> {
+        self.update_invoice(parsed_id, |inv| {
+            inv.yanked = Some(yanked);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Applies the given update to an existing invoice, stores it and re-indexes it. Returns
+    /// `NotFound` if the invoice does not exist. If the update returns an error, nothing is stored
+    async fn update_invoice<F>(&self, parsed_id: &Id, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut crate::Invoice) -> Result<()> + Send,
+    {
+        let mut inv = self.read_invoice(parsed_id).await?;
+        update(&mut inv)?;
+        self.write_invoice(&inv, false).await?;
+
+        // Attempt to update the index. Right now, we log an error if the index update
+        // fails.
+        trace!("Indexing updated invoice");
+        if let Err(e) = self.index.index(&inv).await {
+            error!(error = %e, "Error indexing updated invoice");
+        }
+        Ok(())
+    }
+}
+
+impl<T> S3Provider<T> {
+    /// Returns the key with the configured prefix prepended
+    fn key(&self, key: String) -> String {
+        match self.prefix.as_ref() {
+            Some(p) => format!("{}/{}", p, key),
+            None => key,
+        }
+    }
+
+    fn invoice_key(&self, id: &Id) -> String {
+        self.key(format!(
+            "{}/{}/{}",
+            INVOICE_DIRECTORY,
+            id.sha(),
+            INVOICE_FILE
+        ))
+    }
+
+    fn parcel_key(&self, parcel_id: &str) -> String {
+        self.key(format!(
+            "{}/{}/{}",
+            PARCEL_DIRECTORY, parcel_id, PARCEL_FILE
+        ))
+    }
+
+    /// Fetches the object with the given key. The ID is used for any `NotFound` error
+    async fn get_object(&self, key: &str, id: &str) -> Result<reqwest::Response> {
+        let url = self
+            .bucket
+            .get_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_DURATION);
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(map_request_error)?;
+        check_response(resp, id).await
+    }
+
+    /// Returns the size of the object with the given key, or `None` if it does not exist
+    async fn head_object(&self, key: &str) -> Result<Option<u64>> {
+        let url = self
+            .bucket
+            .head_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_DURATION);
+        let resp = self
+            .client
+            .head(url)
+            .send()
+            .await
+            .map_err(map_request_error)?;
+        match check_response(resp, key).await {
+            Ok(resp) => Ok(Some(
+                resp.headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+            )),
+            Err(ProviderError::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stores an object under the given key. If `create_new` is set, the write is conditional and
+    /// returns `Exists` if the object is already present
+    async fn put_object(
+        &self,
+        key: &str,
+        body: Body,
+        len: u64,
+        content_type: &str,
+        create_new: bool,
+    ) -> Result<()> {
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_DURATION);
+        let mut req = self
+            .client
+            .put(url)
+            .header(CONTENT_LENGTH, len)
+            .header(CONTENT_TYPE, content_type)
+            .body(body);
+        if create_new {
+            req = req.header(IF_NONE_MATCH, "*");
+        }
+        let resp = req.send().await.map_err(map_request_error)?;
+        check_response(resp, key).await.map(|_| ())
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<()> {
+        let url = self
+            .bucket
+            .delete_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_DURATION);
+        let resp = self
+            .client
+            .delete(url)
+            .send()
+            .await
+            .map_err(map_request_error)?;
+        check_response(resp, key).await.map(|_| ())
+    }
+
+    /// Lists the key and size of every object under the given prefix, following continuation
+    /// tokens until all pages have been read
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<(String, u64)>> {
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut action = self.bucket.list_objects_v2(Some(&self.credentials));
+            action.with_prefix(prefix);
+            if let Some(t) = token.as_deref() {
+                action.with_continuation_token(t);
+            }
+            let url = action.sign(SIGNATURE_DURATION);
+            let resp = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .map_err(map_request_error)?;
+            let body = check_response(resp, prefix)
+                .await?
+                .text()
+                .await
+                .map_err(map_request_error)?;
+            let parsed = rusty_s3::actions::ListObjectsV2::parse_response(&body).map_err(|e| {
+                ProviderError::Other(format!("Unable to parse S3 list response: {}", e))
+            })?;
+            objects.extend(parsed.contents.into_iter().map(|o| (o.key, o.size)));
+            match parsed.next_continuation_token {
+                Some(t) => token = Some(t),
+                None => break,
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Returns the SHA and size of every stored parcel
+    async fn list_parcels(&self) -> Result<Vec<(String, u64)>> {
+        let prefix = self.key(format!("{}/", PARCEL_DIRECTORY));
+        let suffix = format!("/{}", PARCEL_FILE);
+        Ok(self
+            .list_objects(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|(key, size)| {
+                key.strip_prefix(&prefix)
+                    .and_then(|k| k.strip_suffix(&suffix))
+                    .map(|sha| (sha.to_owned(), size))
+            })
+            .collect())
+    }
+
+    async fn read_invoice(&self, id: &Id) -> Result<crate::Invoice> {
+        self.read_invoice_key(&self.invoice_key(id), &id.to_string())
+            .await
+    }
+
+    async fn read_invoice_key(&self, key: &str, id: &str) -> Result<crate::Invoice> {
+        let raw = self
+            .get_object(key, id)
+            .await?
+            .bytes()
+            .await
+            .map_err(map_request_error)?;
+        Ok(toml::from_slice(&raw)?)
+    }
+
+    async fn write_invoice(&self, inv: &crate::Invoice, create_new: bool) -> Result<()> {
+        let data = toml::to_vec(inv)?;
+        let len = data.len() as u64;
+        self.put_object(
+            &self.invoice_key(&inv.bindle.id),
+            Body::from(data),
+            len,
+            "application/toml",
+            create_new,
+        )
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: crate::search::Search + Send + Sync> Provider for S3Provider<T> {
+    #[instrument(level = "trace", skip(self, invoice), fields(invoice_id = tracing::field::Empty))]
+    async fn create_invoice<I>(&self, invoice: I) -> Result<(crate::Invoice, Vec<crate::Label>)>
+    where
+        I: Signed + Verified + Send + Sync,
+    {
+        let mut inv = invoice.signed();
+        tracing::span::Span::current()
+            .record("invoice_id", &tracing::field::display(&inv.bindle.id));
+        validate_new_invoice(
+            &mut inv,
+            self.policy.as_deref(),
+            self.reject_duplicate_parcel_names,
+        )?;
+
+        debug!("Writing invoice to bucket");
+        self.write_invoice(&inv, true).await?;
+
+        // Attempt to update the index. Right now, we log an error if the index update
+        // fails.
+        if let Err(e) = self.index.index(&inv).await {
+            error!(error = %e, "Error indexing new invoice");
+        }
+
+        trace!("Checking for missing parcels listed in newly created invoice");
+        let labels = inv
+            .parcel
+            .iter()
+            .flatten()
+            .map(|p| p.label.clone())
+            .collect::<Vec<_>>();
+        let checks = labels.into_iter().map(|label| async move {
+            let exists = self.head_object(&self.parcel_key(&label.sha256)).await?;
+            Ok::<_, ProviderError>((label, exists.is_some()))
+        });
+        let mut results =
+            futures::StreamExt::buffered(futures::stream::iter(checks), PARCEL_CHECK_CONCURRENCY);
+        let mut missing = Vec::new();
+        while let Some(res) = futures::StreamExt::next(&mut results).await {
+            let (label, exists) = res?;
+            if !exists {
+                missing.push(label);
+            }
+        }
+        missing.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+        Ok((inv, missing))
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn get_yanked_invoice<I>(&self, id: I) -> Result<crate::Invoice>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+
+        debug!("Getting invoice from bucket");
+        self.read_invoice(&parsed_id).await
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn invoice_exists<I>(&self, id: I) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        Ok(self
+            .head_object(&self.invoice_key(&parsed_id))
+            .await?
+            .is_some())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn list_invoices(&self) -> Result<Vec<crate::Invoice>> {
+        let suffix = format!("/{}", INVOICE_FILE);
+        let keys = self
+            .list_objects(&self.key(format!("{}/", INVOICE_DIRECTORY)))
+            .await?;
+        let mut invoices = Vec::new();
+        for (key, _) in keys.into_iter().filter(|(k, _)| k.ends_with(&suffix)) {
+            match self.read_invoice_key(&key, &key).await {
+                Ok(inv) => invoices.push(inv),
+                // The invoice may have been removed since it was listed
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(invoices)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn query_invoices(&self, term: &str) -> Result<Vec<crate::Invoice>> {
+        let ids = self.index.query_ids(term).await.map_err(|e| {
+            error!(error = %e, "Error querying index");
+            ProviderError::Other(format!("Unable to query search index: {}", e))
+        })?;
+        trace!(total = ids.len(), "Loading matching invoices");
+        let mut invoices = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.read_invoice(&id).await {
+                Ok(inv) => invoices.push(inv),
+                // The index can be slightly behind storage, so skip anything that is gone
+                Err(ProviderError::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(invoices)
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn yank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Yanking invoice");
+        self.set_yanked(&parsed_id, true).await
+    }
+
+    #[instrument(level = "trace", skip(self, id), fields(id))]
+    async fn unyank_invoice<I>(&self, id: I) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Unyanking invoice");
+        self.set_yanked(&parsed_id, false).await
+    }
+
+    #[instrument(level = "trace", skip(self, id, annotations), fields(id))]
+    async fn update_invoice_annotations<I>(
+        &self,
+        id: I,
+        annotations: BTreeMap<String, String>,
+    ) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        let parsed_id: Id = id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        debug!("Updating invoice annotations");
+        self.update_invoice(&parsed_id, |inv| {
+            merge_annotations(inv, annotations)?;
+            check_invoice_policy(inv, self.policy.as_deref())
+        })
+        .await
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id, data), fields(id))]
+    async fn create_parcel<I, R, B>(&self, bindle_id: I, parcel_id: &str, data: R) -> Result<()>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+        R: Stream<Item = std::io::Result<B>> + Unpin + Send + Sync + 'static,
+        B: bytes::Buf + Send,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        let label = self.validate_parcel(parsed_id, parcel_id).await?;
+
+        let key = self.parcel_key(parcel_id);
+        if let Some(size) = self.head_object(&key).await? {
+            debug!("Parcel already exists");
+            return check_existing_parcel(&label, size);
+        }
+
+        // The data has to be validated before anything is stored and S3 needs the length up
+        // front, so spool it to a temporary file while hashing it. Reading stops as soon as there
+        // is more data than the label declares, so a client can't fill up the disk
+        debug!("Buffering parcel data");
+        let mut file = super::temp_file().await?;
+        let mut hasher = label.algorithm().hasher();
+        let mut size: u64 = 0;
+        let mut data = data;
+        while let Some(chunk) = data.next().await {
+            let mut chunk = chunk?;
+            let bytes = chunk.copy_to_bytes(chunk.remaining());
+            size += bytes.len() as u64;
+            if size > label.size {
+                info!(
+                    expected = label.size,
+                    read_bytes = size,
+                    "Attempted to insert parcel larger than its label"
+                );
+                return Err(ProviderError::SizeMismatch {
+                    expected: label.size,
+                    actual: size,
+                });
+            }
+            hasher.update(&bytes);
+            file.write_all(&bytes).await?;
+        }
+        file.flush().await?;
+
+        debug!("Validating size");
+        if size != label.size {
+            info!(
+                expected = label.size,
+                read_bytes = size,
+                "Attempted to insert parcel with incorrect size"
+            );
+            return Err(ProviderError::SizeMismatch {
+                expected: label.size,
+                actual: size,
+            });
+        }
+
+        debug!("Validating digest");
+        let calculated = hasher.finalize_hex();
+        if label.sha256 != calculated {
+            info!(expected_sha = %label.sha256, %calculated, "Mismatched SHA when creating parcel");
+            return Err(ProviderError::DigestMismatch {
+                expected: label.sha256,
+                actual: calculated,
+            });
+        }
+
+        debug!("Writing parcel to bucket");
+        file.seek(std::io::SeekFrom::Start(0)).await?;
+        let body = Body::wrap_stream(tokio_util::io::ReaderStream::new(file));
+        match self
+            .put_object(&key, body, size, &label.media_type, true)
+            .await
+        {
+            // Another upload finished while we were reading the data
+            Err(ProviderError::Exists) => match self.head_object(&key).await? {
+                Some(existing) => check_existing_parcel(&label, existing),
+                None => Err(ProviderError::Exists),
+            },
+            res => res,
+        }
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn delete_parcel(&self, parcel_id: &str) -> Result<()> {
+        let key = self.parcel_key(parcel_id);
+        if self.head_object(&key).await?.is_none() {
+            return Err(ProviderError::not_found(parcel_id));
+        }
+
+        trace!("Checking for invoices referencing parcel");
+        if self.parcel_reference_count(parcel_id).await? > 0 {
+            debug!("Parcel is still referenced by an invoice, refusing to delete");
+            return Err(ProviderError::InUse);
+        }
+
+        debug!("Deleting parcel from bucket");
+        self.delete_object(&key).await
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn health_check(&self) -> Result<()> {
+        trace!("Checking that the bucket can be listed");
+        let url = self
+            .bucket
+            .list_objects_v2(Some(&self.credentials))
+            .sign(SIGNATURE_DURATION);
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(map_request_error)?;
+        check_response(resp, self.bucket.name()).await.map(|_| ())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let invoices = self.list_invoices().await?;
+        let referenced = referenced_parcels(invoices.iter(), false);
+        let parcels = self.list_parcels().await?;
+        Ok(StorageStats {
+            invoice_count: invoices.len(),
+            parcel_count: parcels.len(),
+            total_parcel_bytes: parcels.iter().map(|(_, size)| size).sum(),
+            dangling_parcel_count: parcels
+                .iter()
+                .filter(|(sha, _)| !referenced.contains(sha))
+                .count(),
+        })
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn gc_parcels(&self, keep_yanked: bool) -> Result<Vec<String>> {
+        let invoices = self.list_invoices().await?;
+        let referenced = referenced_parcels(invoices.iter(), keep_yanked);
+        let mut removed = Vec::new();
+        for (sha, _) in self.list_parcels().await? {
+            if referenced.contains(&sha) {
+                continue;
+            }
+            debug!(%sha, "Removing unreferenced parcel");
+            match self.delete_object(&self.parcel_key(&sha)).await {
+                Ok(_) | Err(ProviderError::NotFound { .. }) => removed.push(sha),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(removed)
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
+    async fn get_parcel<I>(
+        &self,
+        bindle_id: I,
+        parcel_id: &str,
+    ) -> Result<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        self.validate_parcel(parsed_id, parcel_id).await?;
+
+        debug!("Getting parcel from bucket");
+        let resp = self
+            .get_object(&self.parcel_key(parcel_id), parcel_id)
+            .await?;
+        Ok::<Box<dyn Stream<Item = Result<bytes::Bytes>> + Unpin + Send + Sync>, _>(Box::new(
+            resp.bytes_stream()
+                .map(|res| res.map_err(map_request_error)),
+        ))
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn get_parcel_size(&self, parcel_id: &str) -> Result<u64> {
+        self.head_object(&self.parcel_key(parcel_id))
+            .await?
+            .ok_or_else(|| ProviderError::not_found(parcel_id))
+    }
+
+    #[instrument(level = "trace", skip(self, bindle_id), fields(id))]
+    async fn parcel_exists<I>(&self, bindle_id: I, parcel_id: &str) -> Result<bool>
+    where
+        I: TryInto<Id> + Send,
+        I::Error: Into<ProviderError>,
+    {
+        debug!("Validating bindle -> parcel relationship");
+        let parsed_id = bindle_id.try_into().map_err(|e| e.into())?;
+        tracing::Span::current().record("id", &tracing::field::display(&parsed_id));
+        self.validate_parcel(parsed_id, parcel_id).await?;
+
+        debug!("Checking if parcel exists in bucket");
+        Ok(self
+            .head_object(&self.parcel_key(parcel_id))
+            .await?
+            .is_some())
+    }
+}
+
+/// Maps the status of an S3 response to a `ProviderError`, returning the response if it was
+/// successful. The ID is used for any `NotFound` error
+async fn check_response(resp: reqwest::Response, id: &str) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await.unwrap_or_default();
+    Err(status_error(status, id, &body))
+}
+
+/// Returns the error for an unsuccessful S3 response with the given status and body
+fn status_error(status: StatusCode, id: &str, body: &str) -> ProviderError {
+    match status {
+        StatusCode::NOT_FOUND => ProviderError::not_found(id),
+        StatusCode::PRECONDITION_FAILED => ProviderError::Exists,
+        // Returned when a conditional write races with another write of the same key
+        StatusCode::CONFLICT => ProviderError::WriteInProgress,
+        status => {
+            error!(%status, %body, "S3 request failed");
+            ProviderError::Other(format!("S3 request failed with status {}", status))
+        }
+    }
+}
+
+fn map_request_error(e: reqwest::Error) -> ProviderError {
+    ProviderError::Other(format!("Unable to communicate with S3: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing;
+    use crate::verification::NoopVerified;
+    use crate::NoopSigned;
+    use tokio_util::codec::{BytesCodec, FramedRead};
+
+    /// Builds a provider without connecting to the endpoint, for testing requests are put
+    /// together correctly
+    fn offline_provider(prefix: Option<&str>) -> S3Provider<crate::search::StrictEngine> {
+        let bucket = Bucket::new(
+            "http://localhost:9000".parse().unwrap(),
+            UrlStyle::Path,
+            "bindle".to_owned(),
+            "us-east-1".to_owned(),
+        )
+        .unwrap();
+        S3Provider {
+            bucket: Arc::new(bucket),
+            credentials: Arc::new(Credentials::new("access", "secret")),
+            prefix: prefix.map(|p| p.to_owned()),
+            client: reqwest::Client::new(),
+            index: crate::search::StrictEngine::default(),
+        }
+    }
+
+    #[test]
+    fn test_should_build_keys() {
+        let id: Id = "example.com/foo/1.0.0".parse().unwrap();
+        let sha = "a".repeat(64);
+
+        let provider = offline_provider(None);
+        assert_eq!(
+            format!("invoices/{}/invoice.toml", id.sha()),
+            provider.invoice_key(&id)
+        );
+        assert_eq!(
+            format!("parcels/{}/parcel.dat", sha),
+            provider.parcel_key(&sha)
+        );
+
+        let provider = offline_provider(Some("team/bindle"));
+        assert_eq!(
+            format!("team/bindle/invoices/{}/invoice.toml", id.sha()),
+            provider.invoice_key(&id)
+        );
+        assert_eq!(
+            format!("team/bindle/parcels/{}/parcel.dat", sha),
+            provider.parcel_key(&sha)
+        );
+
+        // Keys end up in the path of the signed request URL
+        let url = provider
+            .bucket
+            .get_object(Some(&provider.credentials), &provider.parcel_key(&sha))
+            .sign(SIGNATURE_DURATION);
+        assert_eq!(
+            format!("/bindle/team/bindle/parcels/{}/parcel.dat", sha),
+            url.path()
+        );
+    }
+
+    #[test]
+    fn test_should_map_status_errors() {
+        assert!(matches!(
+            status_error(StatusCode::NOT_FOUND, "example.com/foo/1.0.0", ""),
+            ProviderError::NotFound { .. }
+        ));
+        assert!(matches!(
+            status_error(StatusCode::PRECONDITION_FAILED, "key", ""),
+            ProviderError::Exists
+        ));
+        assert!(matches!(
+            status_error(StatusCode::CONFLICT, "key", ""),
+            ProviderError::WriteInProgress
+        ));
+        assert!(matches!(
+            status_error(StatusCode::FORBIDDEN, "key", "AccessDenied"),
+            ProviderError::Other(_)
+        ));
+    }
+
+    /// Connects to the bucket configured in the environment (see `S3Config::from_env`). Each run
+    /// stores its data under a unique prefix
+    async fn test_provider() -> S3Provider<crate::search::StrictEngine> {
+        let mut config = S3Config::from_env().expect(
+            "BINDLE_S3_ENDPOINT, BINDLE_S3_BUCKET, AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set to run the S3 tests",
+        );
+        let run = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        config.prefix = Some(format!(
+            "{}/bindle-test-{}",
+            config.prefix.unwrap_or_default(),
+            run
+        ));
+        S3Provider::new(config, crate::search::StrictEngine::default())
+            .await
+            .expect("Unable to create S3 provider")
+    }
+
+    // This needs a real bucket, so it only runs when asked for with `--ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_s3_invoice_and_parcel_roundtrip() {
+        let provider = test_provider().await;
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+        let inv = scaffold.invoice.clone();
+        let id = inv.bindle.id.clone();
+
+        let (_, missing) = provider
+            .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+            .await
+            .expect("Should be able to create invoice");
+        assert_eq!(scaffold.parcel_files.len(), missing.len());
+        assert!(matches!(
+            provider
+                .create_invoice(NoopSigned(NoopVerified(inv.clone())))
+                .await,
+            Err(ProviderError::Exists)
+        ));
+
+        for parcel in scaffold.parcel_files.values() {
+            let stream =
+                FramedRead::new(std::io::Cursor::new(parcel.data.clone()), BytesCodec::new());
+            provider
+                .create_parcel(&id, &parcel.sha, stream)
+                .await
+                .expect("Should be able to create parcel");
+            assert!(provider.parcel_exists(&id, &parcel.sha).await.unwrap());
+
+            let mut stream = provider
+                .get_parcel(&id, &parcel.sha)
+                .await
+                .expect("Should be able to get parcel");
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                data.extend_from_slice(&chunk.unwrap());
+            }
+            assert_eq!(parcel.data, data);
+        }
+
+        let fetched = provider.get_invoice(&id).await.unwrap();
+        assert_eq!(inv.bindle.id, fetched.bindle.id);
+
+        provider.yank_invoice(&id).await.unwrap();
+        assert!(matches!(
+            provider.get_invoice(&id).await,
+            Err(ProviderError::Yanked)
+        ));
+        assert!(provider.get_yanked_invoice(&id).await.is_ok());
+
+        assert!(matches!(
+            provider.get_yanked_invoice("not/real/1.0.0").await,
+            Err(ProviderError::NotFound { .. })
+        ));
+
+        // Everything is referenced only by a yanked invoice, so it can all be collected
+        let removed = provider.gc_parcels(false).await.unwrap();
+        assert_eq!(scaffold.parcel_files.len(), removed.len());
+    }
+}
@@ -0,0 +1,177 @@
+//! Helpers for limiting how long parcel data can be streamed without making progress, so a
+//! stalled read doesn't hold on to a file handle or buffer forever.
+//!
+//! This will only be available if the `providers` feature is enabled
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, StreamExt};
+
+use super::{ProviderError, Result};
+
+/// A `Stream` wrapper that fails with a [`ProviderError::Timeout`] error if either side of the
+/// stream goes longer than the timeout without making progress.
+///
+/// The inner stream is driven by a background task that reads one item ahead of the consumer. The
+/// task gives up if the inner stream takes longer than the timeout to produce an item (a stalled
+/// source), or if the consumer takes longer than the timeout to take the next item (a stalled
+/// client). Either way, the inner stream is dropped as soon as the timeout fires, releasing any
+/// file handle or buffer it holds, and the consumer gets a `Timeout` error after any item that was
+/// already read. Once the error is returned, the stream ends.
+///
+/// As the inner stream is moved into a task, this must be created from within a Tokio runtime
+pub struct IdleTimeout<T> {
+    rx: mpsc::Receiver<Result<T>>,
+    timeout: Duration,
+    timed_out: Arc<AtomicBool>,
+    done: bool,
+}
+
+impl<T: Send + 'static> IdleTimeout<T> {
+    /// Wraps the given stream, failing if it or its consumer makes no progress for the given
+    /// duration
+    pub fn new<S>(inner: S, timeout: Duration) -> Self
+    where
+        S: Stream<Item = Result<T>> + Unpin + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(1);
+        let timed_out = Arc::new(AtomicBool::new(false));
+        tokio::spawn(drive(inner, tx, timeout, timed_out.clone()));
+        IdleTimeout {
+            rx,
+            timeout,
+            timed_out,
+            done: false,
+        }
+    }
+}
+
+/// Reads items from the inner stream and hands them to the consumer, stopping as soon as either
+/// side stalls for longer than the timeout
+async fn drive<S, T>(
+    mut inner: S,
+    tx: mpsc::Sender<Result<T>>,
+    timeout: Duration,
+    timed_out: Arc<AtomicBool>,
+) where
+    S: Stream<Item = Result<T>> + Unpin,
+{
+    loop {
+        let item = match tokio::time::timeout(timeout, inner.next()).await {
+            Ok(Some(item)) => item,
+            Ok(None) => return,
+            Err(_) => {
+                timed_out.store(true, Ordering::SeqCst);
+                return;
+            }
+        };
+        match tokio::time::timeout(timeout, tx.reserve()).await {
+            Ok(Ok(permit)) => permit.send(item),
+            // The consumer has gone away, so nobody is left to read the data
+            Ok(Err(_)) => return,
+            Err(_) => {
+                timed_out.store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+}
+
+impl<T> Stream for IdleTimeout<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+        match this.rx.poll_recv(cx) {
+            Poll::Ready(None) => {
+                this.done = true;
+                // The flag is always set before the sender is dropped, so it is safe to check here
+                if this.timed_out.load(Ordering::SeqCst) {
+                    Poll::Ready(Some(Err(ProviderError::Timeout(this.timeout))))
+                } else {
+                    Poll::Ready(None)
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_should_time_out_stalled_stream() {
+        let stalled = futures::stream::iter(vec![Ok(1)]).chain(futures::stream::pending());
+        let mut stream = IdleTimeout::new(stalled, Duration::from_millis(20));
+
+        assert!(matches!(stream.next().await, Some(Ok(1))));
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(ProviderError::Timeout(t))) if t == Duration::from_millis(20)
+        ));
+        assert!(
+            stream.next().await.is_none(),
+            "Stream should end after timing out"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_should_restart_timer_on_progress() {
+        // Each item takes less than the timeout, even though all of them together take longer
+        let slow = futures::stream::unfold(0, |i| async move {
+            if i == 5 {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Some((Ok(i), i + 1))
+        })
+        .boxed();
+        let items: Vec<_> = IdleTimeout::new(slow, Duration::from_millis(60))
+            .collect()
+            .await;
+        assert_eq!(5, items.len());
+        assert!(items.iter().all(|i| i.is_ok()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_should_time_out_stalled_consumer() {
+        // The inner stream holds on to this, so we can tell when it has been dropped
+        let handle = Arc::new(());
+        let held = handle.clone();
+        let source = futures::stream::iter(0..10).map(move |i| {
+            let _ = &held;
+            Ok(i)
+        });
+        let mut stream = IdleTimeout::new(source, Duration::from_secs(1));
+
+        assert!(matches!(stream.next().await, Some(Ok(0))));
+        // Stop reading for longer than the timeout. The source always has data ready, so only
+        // the consumer is idle
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(
+            1,
+            Arc::strong_count(&handle),
+            "Inner stream should be dropped once the consumer times out"
+        );
+
+        // The item that was already read ahead is still returned before the error
+        assert!(matches!(stream.next().await, Some(Ok(1))));
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(ProviderError::Timeout(_)))
+        ));
+        assert!(stream.next().await.is_none());
+    }
+}
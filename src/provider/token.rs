@@ -0,0 +1,135 @@
+//! Time limited capability tokens that grant access to a single parcel, for handing out parcel
+//! downloads without proxying the data
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::debug;
+
+use super::{ProviderError, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Returns a token granting time limited access to the parcel with the given SHA, so a server can
+/// hand out parcel downloads without checking the caller's credentials on each request. The token
+/// is made up of the expiry (in seconds since the Unix epoch, so with a resolution of one second)
+/// and an HMAC-SHA256 of the SHA and expiry keyed with the secret, separated by a `.`. Check it
+/// with [`verify_parcel_token`]
+pub fn make_parcel_token(sha: &str, expiry: SystemTime, secret: &[u8]) -> String {
+    // An expiry before the epoch has already passed, so treating it as the epoch is equivalent
+    let expiry = expiry
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!("{}.{}", expiry, sign(sha, expiry, secret))
+}
+
+/// Checks that the token was made by [`make_parcel_token`] with the given secret for the parcel
+/// with the given SHA and has not expired, returning a [`ProviderError::Unauthorized`] error if not
+pub fn verify_parcel_token(sha: &str, token: &str, secret: &[u8]) -> Result<()> {
+    let (expiry, mac) = token.split_once('.').ok_or_else(|| {
+        debug!("Parcel token is malformed");
+        ProviderError::Unauthorized
+    })?;
+    let expiry: u64 = expiry.parse().map_err(|_| {
+        debug!("Parcel token has an invalid expiry");
+        ProviderError::Unauthorized
+    })?;
+    let mac = decode_hex(mac).ok_or_else(|| {
+        debug!("Parcel token has an invalid signature");
+        ProviderError::Unauthorized
+    })?;
+    // `verify_slice` compares in constant time, so the signature can't be guessed byte by byte
+    if new_mac(sha, expiry, secret).verify_slice(&mac).is_err() {
+        debug!("Parcel token signature does not match");
+        return Err(ProviderError::Unauthorized);
+    }
+    if UNIX_EPOCH + Duration::from_secs(expiry) <= SystemTime::now() {
+        debug!(expiry, "Parcel token has expired");
+        return Err(ProviderError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Returns the hex encoded HMAC of the parcel SHA and expiry
+fn sign(sha: &str, expiry: u64, secret: &[u8]) -> String {
+    format!("{:x}", new_mac(sha, expiry, secret).finalize().into_bytes())
+}
+
+/// Returns an HMAC that has been fed the parcel SHA and expiry
+fn new_mac(sha: &str, expiry: u64, secret: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}\n{}", sha, expiry).as_bytes());
+    mac
+}
+
+/// Decodes a lowercase hex string, returning `None` if it is not valid hex
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SECRET: &[u8] = b"super secret";
+    const SHA: &str = "e1cbb0c3879af8347246f12c559a86b5b3b7c2a7f3e1d1ba2d0c5d1f3d1b8a2e";
+
+    #[test]
+    fn test_should_verify_valid_token() {
+        let token = make_parcel_token(SHA, SystemTime::now() + Duration::from_secs(60), SECRET);
+        verify_parcel_token(SHA, &token, SECRET).expect("Token should be valid");
+    }
+
+    #[test]
+    fn test_should_reject_expired_token() {
+        let token = make_parcel_token(SHA, SystemTime::now() - Duration::from_secs(60), SECRET);
+        assert!(matches!(
+            verify_parcel_token(SHA, &token, SECRET),
+            Err(ProviderError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn test_should_reject_tampered_token() {
+        let token = make_parcel_token(SHA, SystemTime::now() + Duration::from_secs(60), SECRET);
+        let (expiry, mac) = token.split_once('.').unwrap();
+
+        // Extending the expiry invalidates the signature
+        let extended = format!("{}.{}", expiry.parse::<u64>().unwrap() + 3600, mac);
+        // As does changing the signature itself
+        let flipped = format!(
+            "{}.{}{}",
+            expiry,
+            if mac.starts_with('0') { '1' } else { '0' },
+            &mac[1..]
+        );
+        for bad in [
+            extended.as_str(),
+            flipped.as_str(),
+            "garbage",
+            "123.abc",
+            "",
+        ] {
+            assert!(
+                matches!(
+                    verify_parcel_token(SHA, bad, SECRET),
+                    Err(ProviderError::Unauthorized)
+                ),
+                "Token {:?} should be rejected",
+                bad
+            );
+        }
+
+        // The token is only valid for the parcel and secret it was made with
+        assert!(verify_parcel_token(&"0".repeat(64), &token, SECRET).is_err());
+        assert!(verify_parcel_token(SHA, &token, b"another secret").is_err());
+    }
+}
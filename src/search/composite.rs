@@ -0,0 +1,297 @@
+//! A query engine that combines several other engines, such as a strict index and a full-text
+//! index, and keeps them all up to date
+
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+
+use tracing::{instrument, trace, warn};
+
+use crate::search::{Matches, Search, SearchOptions};
+
+/// A search engine that fans every operation out to a list of other engines. Invoices are indexed
+/// in (and removed from) every engine, and query results from all engines are merged with
+/// duplicates removed.
+///
+/// Results are merged in engine order (results from earlier engines come first) before the offset
+/// and limit are applied, so paging through the merged results is consistent. This means each
+/// engine is asked for up to `offset + limit` results. The `total` of a query is exact if every
+/// engine returned all of its matches, otherwise it is a lower bound. Engines are shared using an
+/// `Arc`, so the same engine can also be queried directly
+#[derive(Default, Clone)]
+pub struct CompositeEngine {
+    engines: Vec<Arc<dyn Search + Send + Sync>>,
+}
+
+impl CompositeEngine {
+    /// Returns a new composite engine without any engines
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an engine to the composite. Results from earlier engines are listed first when merging
+    pub fn with_engine<S: Search + Send + Sync + 'static>(mut self, engine: S) -> Self {
+        self.engines.push(Arc::new(engine));
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Search for CompositeEngine {
+    #[instrument(level = "trace", skip(self))]
+    async fn query(
+        &self,
+        term: &str,
+        filter: &str,
+        options: SearchOptions,
+    ) -> anyhow::Result<Matches> {
+        let mut merged = Matches::new(&options, term.to_owned());
+        // Every engine may hold the whole page, so each is read up to the end of the page
+        let wanted = options.offset.saturating_add(options.limit as u64);
+        let mut invoices = Vec::new();
+        let mut seen = HashSet::new();
+        let mut exhausted = true;
+        for engine in self.engines.iter() {
+            let mut fetched: u64 = 0;
+            loop {
+                let matches = engine
+                    .query(
+                        term,
+                        filter,
+                        SearchOptions {
+                            offset: fetched,
+                            limit: u8::MAX,
+                            strict: options.strict,
+                            yanked: options.yanked,
+                        },
+                    )
+                    .await?;
+                merged.total = merged.total.max(matches.total);
+                let found = matches.invoices.len() as u64;
+                fetched += found;
+                invoices.extend(
+                    matches
+                        .invoices
+                        .into_iter()
+                        .filter(|inv| seen.insert(inv.bindle.id.clone())),
+                );
+                if !matches.more || found == 0 {
+                    break;
+                }
+                if fetched >= wanted {
+                    exhausted = false;
+                    break;
+                }
+            }
+        }
+
+        let union = invoices.len() as u64;
+        // If an engine wasn't read to the end, its unread results may or may not be duplicates
+        merged.total = if exhausted {
+            union
+        } else {
+            merged.total.max(union)
+        };
+        merged.more = !exhausted || union > wanted;
+        merged.invoices = invoices
+            .into_iter()
+            .skip(options.offset as usize)
+            .take(options.limit as usize)
+            .collect();
+        trace!(
+            total_matches = merged.total,
+            returned = merged.invoices.len(),
+            "Merged query results"
+        );
+        Ok(merged)
+    }
+
+    async fn query_ids(&self, term: &str) -> anyhow::Result<Vec<crate::Id>> {
+        let mut ids = Vec::new();
+        let mut seen = HashSet::new();
+        for engine in self.engines.iter() {
+            ids.extend(
+                engine
+                    .query_ids(term)
+                    .await?
+                    .into_iter()
+                    .filter(|id| seen.insert(id.clone())),
+            );
+        }
+        Ok(ids)
+    }
+
+    async fn complete_name(&self, prefix: &str, limit: usize) -> anyhow::Result<Vec<String>> {
+        let mut names = BTreeSet::new();
+        for engine in self.engines.iter() {
+            names.extend(engine.complete_name(prefix, limit).await?);
+        }
+        Ok(names.into_iter().take(limit).collect())
+    }
+
+    async fn index(&self, document: &crate::Invoice) -> anyhow::Result<()> {
+        // Every engine is updated even if one fails, so a single failing engine doesn't leave the
+        // others out of date
+        let mut result = Ok(());
+        for engine in self.engines.iter() {
+            if let Err(e) = engine.index(document).await {
+                warn!(invoice_id = %document.bindle.id, error = %e, "Engine failed to index invoice");
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    async fn remove(&self, document: &crate::Invoice) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for engine in self.engines.iter() {
+            if let Err(e) = engine.remove(document).await {
+                warn!(invoice_id = %document.bindle.id, error = %e, "Engine failed to remove invoice");
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::search::StrictEngine;
+
+    fn invoice(name: &str) -> crate::Invoice {
+        crate::Invoice::builder()
+            .name(name)
+            .version("1.0.0")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn composite_engine_should_index_in_every_engine() {
+        let strict = StrictEngine::default();
+        let other = StrictEngine::default();
+        let composite = CompositeEngine::new()
+            .with_engine(strict.clone())
+            .with_engine(other.clone());
+
+        let inv = invoice("example.com/foo");
+        composite.index(&inv).await.expect("successfully indexed");
+        for engine in [&strict, &other] {
+            assert_eq!(
+                vec![inv.bindle.id.clone()],
+                engine.query_ids("foo").await.unwrap()
+            );
+        }
+
+        composite.remove(&inv).await.expect("successfully removed");
+        for engine in [&strict, &other] {
+            assert!(engine.query_ids("foo").await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn composite_engine_should_union_results() {
+        let strict = StrictEngine::default();
+        let other = StrictEngine::default();
+        let composite = CompositeEngine::new()
+            .with_engine(strict.clone())
+            .with_engine(other.clone());
+
+        // One invoice is in both engines, the others only in one each
+        let shared = invoice("example.com/shared");
+        let first = invoice("example.com/first");
+        let second = invoice("example.com/second");
+        composite.index(&shared).await.unwrap();
+        strict.index(&first).await.unwrap();
+        other.index(&second).await.unwrap();
+
+        let matches = composite
+            .query("example.com", "", SearchOptions::default())
+            .await
+            .expect("query should succeed");
+        let mut names: Vec<&str> = matches
+            .invoices
+            .iter()
+            .map(|i| i.bindle.id.name())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(
+            vec![
+                "example.com/first",
+                "example.com/second",
+                "example.com/shared"
+            ],
+            names
+        );
+        assert_eq!(3, matches.total);
+        assert_eq!(3, composite.query_ids("example.com").await.unwrap().len());
+        assert_eq!(
+            vec!["example.com/second", "example.com/shared"],
+            composite.complete_name("example.com/s", 10).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn composite_engine_should_page_merged_results() {
+        let strict = StrictEngine::default();
+        let other = StrictEngine::default();
+        let composite = CompositeEngine::new()
+            .with_engine(strict.clone())
+            .with_engine(other.clone());
+
+        // Two invoices in each engine, plus one in both
+        for name in ["a", "b", "shared"] {
+            strict
+                .index(&invoice(&format!("example.com/{}", name)))
+                .await
+                .unwrap();
+        }
+        for name in ["c", "d", "shared"] {
+            other
+                .index(&invoice(&format!("example.com/{}", name)))
+                .await
+                .unwrap();
+        }
+
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            let matches = composite
+                .query(
+                    "example.com",
+                    "",
+                    SearchOptions {
+                        offset,
+                        limit: 2,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .expect("query should succeed");
+            assert!(matches.invoices.len() <= 2, "Page should respect the limit");
+            assert_eq!(5, matches.total);
+            offset += matches.invoices.len() as u64;
+            names.extend(
+                matches
+                    .invoices
+                    .into_iter()
+                    .map(|inv| inv.bindle.id.name().to_owned()),
+            );
+            if !matches.more {
+                break;
+            }
+        }
+        names.sort_unstable();
+        assert_eq!(
+            vec![
+                "example.com/a",
+                "example.com/b",
+                "example.com/c",
+                "example.com/d",
+                "example.com/shared"
+            ],
+            names,
+            "Every result should be returned exactly once"
+        );
+    }
+}
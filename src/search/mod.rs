@@ -1,12 +1,16 @@
 //! Common types and traits for use in implementing query functionality for a Bindle server. Note
 //! that this functionality is quite likely to change
+use std::collections::BTreeSet;
+
 use serde::{Deserialize, Serialize};
 
+mod composite;
 mod noop;
 mod strict;
 
+pub use composite::CompositeEngine;
 pub use noop::NoopEngine;
-pub use strict::StrictEngine;
+pub use strict::{MatchMode, StrictEngine};
 
 #[derive(Debug)]
 /// The search options for performing this query and returning results
@@ -99,6 +103,47 @@ pub trait Search {
         options: SearchOptions,
     ) -> anyhow::Result<Matches>;
 
+    /// Returns the IDs of all indexed invoices whose bindle name contains the given term,
+    /// including yanked invoices.
+    ///
+    /// Unlike `query`, this does not return the invoices themselves. It is meant to be used by
+    /// providers that want to load the matching invoices from their own storage.
+    ///
+    /// The default implementation pages through every result of a non-strict `query` that
+    /// includes yanked invoices, so engines that can look up IDs directly should override this
+    async fn query_ids(&self, term: &str) -> anyhow::Result<Vec<crate::Id>> {
+        let mut ids = Vec::new();
+        loop {
+            let options = SearchOptions {
+                offset: ids.len() as u64,
+                limit: u8::MAX,
+                strict: false,
+                yanked: true,
+            };
+            let matches = self.query(term, "", options).await?;
+            let found = matches.invoices.len();
+            ids.extend(matches.invoices.into_iter().map(|inv| inv.bindle.id));
+            if !matches.more || found == 0 {
+                return Ok(ids);
+            }
+        }
+    }
+
+    /// Returns up to `limit` distinct bindle names that start with the given prefix, sorted by
+    /// name. This is meant for typeahead style completion. The prefix is matched ignoring case, but
+    /// names are returned in the casing they were indexed with.
+    ///
+    /// The default implementation scans every ID returned by `query_ids` for an empty term, so
+    /// engines with a dedicated name index should override this
+    async fn complete_name(&self, prefix: &str, limit: usize) -> anyhow::Result<Vec<String>> {
+        let ids = self.query_ids("").await?;
+        Ok(complete_names(
+            ids.iter().map(|id| id.name()),
+            prefix,
+            limit,
+        ))
+    }
+
     /// Given an invoice, extract information from it that will be useful for searching.
     ///
     /// This high-level feature does not provide any guarantees about how it will
@@ -112,4 +157,33 @@ pub trait Search {
     /// as such, following the protocol specification's requirements for yanked
     /// invoices.
     async fn index(&self, document: &crate::Invoice) -> anyhow::Result<()>;
+
+    /// Removes the given invoice from the index, so it no longer appears in any query results.
+    ///
+    /// Removing an invoice that is not in the index is not an error. The default implementation
+    /// returns an error, as not all engines support removing invoices
+    async fn remove(&self, document: &crate::Invoice) -> anyhow::Result<()> {
+        let _ = document;
+        Err(anyhow::anyhow!(
+            "This search engine does not support removing invoices"
+        ))
+    }
+}
+
+/// Returns up to `limit` of the given names that start with the prefix (ignoring case), sorted and
+/// without duplicates
+pub(crate) fn complete_names<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    prefix: &str,
+    limit: usize,
+) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    names
+        .into_iter()
+        .filter(|name| name.to_lowercase().starts_with(&prefix))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .take(limit)
+        .map(|name| name.to_owned())
+        .collect()
 }
@@ -17,7 +17,15 @@ impl Search for NoopEngine {
         Ok(Matches::new(&options, term.to_owned()))
     }
 
+    async fn query_ids(&self, _term: &str) -> anyhow::Result<Vec<crate::Id>> {
+        Ok(Vec::new())
+    }
+
     async fn index(&self, _: &crate::Invoice) -> anyhow::Result<()> {
         Ok(())
     }
+
+    async fn remove(&self, _: &crate::Invoice) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
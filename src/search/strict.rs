@@ -7,7 +7,36 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{debug, instrument, trace};
 
-use crate::search::{Matches, Search, SearchOptions};
+use crate::search::{complete_names, Matches, Search, SearchOptions};
+
+/// How a query term is compared against the name of a bindle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// The term must be contained within the bindle name, matching case exactly. This is the
+    /// default
+    Exact,
+    /// The term must be contained within the bindle name, ignoring case
+    CaseInsensitive,
+    /// The bindle name must start with the term, matching case exactly
+    Prefix,
+}
+
+impl Default for MatchMode {
+    fn default() -> Self {
+        MatchMode::Exact
+    }
+}
+
+impl MatchMode {
+    /// Returns whether the given bindle name matches the term in this mode
+    fn matches(&self, name: &str, term: &str) -> bool {
+        match self {
+            MatchMode::Exact => name.contains(term),
+            MatchMode::CaseInsensitive => name.to_lowercase().contains(&term.to_lowercase()),
+            MatchMode::Prefix => name.starts_with(term),
+        }
+    }
+}
 
 /// Implements strict query processing.
 #[derive(Clone)]
@@ -16,14 +45,28 @@ pub struct StrictEngine {
     // search results predictable. This greatly simplifies the process of doing offsets
     // and limits.
     index: Arc<RwLock<BTreeMap<String, crate::Invoice>>>,
+    match_mode: MatchMode,
 }
 
 impl Default for StrictEngine {
     fn default() -> Self {
+        StrictEngine::with_match_mode(MatchMode::default())
+    }
+}
+
+impl StrictEngine {
+    /// Returns a new, empty engine that compares query terms using the given [`MatchMode`]
+    pub fn with_match_mode(match_mode: MatchMode) -> Self {
         StrictEngine {
             index: Arc::new(RwLock::new(BTreeMap::new())),
+            match_mode,
         }
     }
+
+    /// Returns the [`MatchMode`] used by this engine
+    pub fn match_mode(&self) -> MatchMode {
+        self.match_mode
+    }
 }
 
 #[async_trait::async_trait]
@@ -45,8 +88,9 @@ impl Search for StrictEngine {
                 // Per the spec:
                 // - if `term` is present, then it must be contained within the name field of the bindle.
                 // - if a version filter is present, then the version of the bindle must abide by the filter.
+                // How the term is compared depends on the configured match mode
                 debug!(term, filter, "comparing term and filter");
-                i.bindle.id.name().contains(term)
+                self.match_mode.matches(i.bindle.id.name(), term)
                     && (filter.is_empty() || i.version_in_range(filter))
             })
             .map(|(_, v)| (*v).clone())
@@ -54,7 +98,7 @@ impl Search for StrictEngine {
 
         debug!(total_matches = found.len(), "Found matches");
         let mut matches = Matches::new(&options, term.to_owned());
-        matches.strict = true;
+        matches.strict = self.match_mode == MatchMode::Exact;
         matches.yanked = false;
         matches.total = found.len() as u64;
 
@@ -79,6 +123,28 @@ impl Search for StrictEngine {
         Ok(matches)
     }
 
+    #[instrument(level = "trace", skip(self))]
+    async fn query_ids(&self, term: &str) -> anyhow::Result<Vec<crate::Id>> {
+        Ok(self
+            .index
+            .read()
+            .await
+            .values()
+            .filter(|i| self.match_mode.matches(i.bindle.id.name(), term))
+            .map(|i| i.bindle.id.clone())
+            .collect())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn complete_name(&self, prefix: &str, limit: usize) -> anyhow::Result<Vec<String>> {
+        let index = self.index.read().await;
+        Ok(complete_names(
+            index.values().map(|i| i.bindle.id.name()),
+            prefix,
+            limit,
+        ))
+    }
+
     async fn index(&self, invoice: &crate::Invoice) -> anyhow::Result<()> {
         self.index
             .write()
@@ -86,6 +152,11 @@ impl Search for StrictEngine {
             .insert(invoice.name(), invoice.clone());
         Ok(())
     }
+
+    async fn remove(&self, invoice: &crate::Invoice) -> anyhow::Result<()> {
+        self.index.write().await.remove(&invoice.name());
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -141,6 +212,227 @@ mod test {
         // TODO: Need to test yanked bindles
     }
 
+    #[tokio::test]
+    async fn strict_engine_should_query_ids() {
+        let inv = invoice_fixture("my/bindle".to_owned(), "1.2.3".to_owned());
+        let inv2 = invoice_fixture("my/bindle".to_owned(), "1.3.0".to_owned());
+        let inv3 = invoice_fixture("other/thing".to_owned(), "1.0.0".to_owned());
+        let searcher = StrictEngine::default();
+        for i in [&inv, &inv2, &inv3] {
+            searcher.index(i).await.expect("successfully indexed");
+        }
+
+        let mut ids = searcher
+            .query_ids("bindle")
+            .await
+            .expect("query should succeed");
+        ids.sort_by_key(|id| id.to_string());
+        assert_eq!(ids, vec![inv.bindle.id.clone(), inv2.bindle.id.clone()]);
+
+        assert!(searcher
+            .query_ids("nonexistent")
+            .await
+            .expect("query should succeed")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn strict_engine_should_remove() {
+        let inv = invoice_fixture("my/bindle".to_owned(), "1.2.3".to_owned());
+        let inv2 = invoice_fixture("my/bindle".to_owned(), "1.3.0".to_owned());
+        let searcher = StrictEngine::default();
+        for i in [&inv, &inv2] {
+            searcher.index(i).await.expect("successfully indexed");
+        }
+
+        searcher
+            .remove(&inv)
+            .await
+            .expect("successfully removed my/bindle/1.2.3");
+        let matches = searcher
+            .query("my/bindle", "1.2.3", SearchOptions::default())
+            .await
+            .expect("query should succeed");
+        assert!(
+            matches.invoices.is_empty(),
+            "Removed invoice should not match"
+        );
+        assert_eq!(
+            vec![inv2.bindle.id.clone()],
+            searcher.query_ids("my/bindle").await.unwrap()
+        );
+
+        // Removing something that isn't indexed is a no-op
+        searcher
+            .remove(&inv)
+            .await
+            .expect("removing a missing invoice should succeed");
+    }
+
+    #[tokio::test]
+    async fn strict_engine_should_complete_name() {
+        let searcher = StrictEngine::default();
+        for (name, version) in [
+            ("example/foo", "1.0.0"),
+            ("example/foo", "1.1.0"),
+            ("example/foobar", "1.0.0"),
+            ("other/baz", "1.0.0"),
+        ] {
+            searcher
+                .index(&invoice_fixture(name.to_owned(), version.to_owned()))
+                .await
+                .expect("successfully indexed");
+        }
+
+        assert_eq!(
+            vec!["example/foo", "example/foobar"],
+            searcher.complete_name("example/foo", 10).await.unwrap()
+        );
+        assert_eq!(
+            vec!["example/foo", "example/foobar"],
+            searcher.complete_name("EXAMPLE/F", 10).await.unwrap(),
+            "Matching should ignore case"
+        );
+        assert_eq!(
+            vec!["example/foo"],
+            searcher.complete_name("example/", 1).await.unwrap()
+        );
+        assert!(searcher.complete_name("nope", 10).await.unwrap().is_empty());
+    }
+
+    async fn match_mode_fixture(mode: MatchMode) -> StrictEngine {
+        let searcher = StrictEngine::with_match_mode(mode);
+        for (name, version) in [
+            ("example.com/Foo", "1.0.0"),
+            ("example.com/foo-bar", "1.0.0"),
+            ("other.com/foo", "2.0.0"),
+        ] {
+            searcher
+                .index(&invoice_fixture(name.to_owned(), version.to_owned()))
+                .await
+                .expect("successfully indexed");
+        }
+        searcher
+    }
+
+    async fn matching_names(searcher: &StrictEngine, term: &str) -> Vec<String> {
+        searcher
+            .query(term, "", SearchOptions::default())
+            .await
+            .expect("query should succeed")
+            .invoices
+            .into_iter()
+            .map(|i| i.bindle.id.name().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn strict_engine_exact_mode() {
+        let searcher = match_mode_fixture(MatchMode::Exact).await;
+        assert_eq!(MatchMode::Exact, StrictEngine::default().match_mode());
+
+        assert_eq!(
+            vec!["example.com/Foo"],
+            matching_names(&searcher, "Foo").await
+        );
+        assert_eq!(
+            vec!["example.com/foo-bar", "other.com/foo"],
+            matching_names(&searcher, "foo").await
+        );
+        assert!(matching_names(&searcher, "FOO").await.is_empty());
+        assert!(
+            searcher
+                .query("Foo", "", SearchOptions::default())
+                .await
+                .unwrap()
+                .strict
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_engine_case_insensitive_mode() {
+        let searcher = match_mode_fixture(MatchMode::CaseInsensitive).await;
+
+        let all = vec!["example.com/Foo", "example.com/foo-bar", "other.com/foo"];
+        assert_eq!(all, matching_names(&searcher, "foo").await);
+        assert_eq!(all, matching_names(&searcher, "FOO").await);
+        assert_eq!(
+            vec!["example.com/foo-bar"],
+            matching_names(&searcher, "Foo-BAR").await
+        );
+        assert_eq!(
+            2,
+            searcher.query_ids("EXAMPLE.COM").await.unwrap().len(),
+            "query_ids should honor the match mode"
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_engine_prefix_mode() {
+        let searcher = match_mode_fixture(MatchMode::Prefix).await;
+
+        assert_eq!(
+            vec!["example.com/Foo", "example.com/foo-bar"],
+            matching_names(&searcher, "example.com/").await
+        );
+        assert_eq!(
+            vec!["example.com/foo-bar"],
+            matching_names(&searcher, "example.com/f").await
+        );
+        assert!(
+            matching_names(&searcher, "foo").await.is_empty(),
+            "Prefix mode should not match in the middle of a name"
+        );
+        assert!(matching_names(&searcher, "Example.com").await.is_empty());
+    }
+
+    /// An engine that only implements the required methods, to test the defaults
+    struct QueryOnly(StrictEngine);
+
+    #[async_trait::async_trait]
+    impl Search for QueryOnly {
+        async fn query(
+            &self,
+            term: &str,
+            filter: &str,
+            options: SearchOptions,
+        ) -> anyhow::Result<Matches> {
+            self.0.query(term, filter, options).await
+        }
+
+        async fn index(&self, document: &crate::Invoice) -> anyhow::Result<()> {
+            self.0.index(document).await
+        }
+    }
+
+    #[tokio::test]
+    async fn default_methods_should_use_query() {
+        let engine = QueryOnly(StrictEngine::default());
+        // More than fit in a single page of results
+        for patch in 0..300 {
+            engine
+                .index(&invoice_fixture(
+                    "my/bindle".to_owned(),
+                    format!("1.0.{}", patch),
+                ))
+                .await
+                .unwrap();
+        }
+        let mut yanked = invoice_fixture("my/yanked".to_owned(), "1.0.0".to_owned());
+        yanked.yanked = Some(true);
+        engine.index(&yanked).await.unwrap();
+
+        let mut ids = engine.query_ids("my/").await.expect("Should query IDs");
+        ids.sort_by_key(|id| id.to_string());
+        ids.dedup();
+        assert_eq!(
+            301,
+            ids.len(),
+            "Every page and yanked invoices should be included"
+        );
+        assert!(engine.remove(&yanked).await.is_err());
+    }
+
     fn invoice_fixture(name: String, version: String) -> Invoice {
         let labels = vec![
             crate::Label {
@@ -187,6 +479,7 @@ mod test {
             ),
             group: None,
             signature: None,
+            extra: Default::default(),
         }
     }
 }
@@ -113,7 +113,7 @@ mod test {
     #[rstest]
     #[tokio::test]
     async fn test_successful_workflow<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -292,7 +292,7 @@ mod test {
     #[rstest]
     #[tokio::test]
     async fn test_yank<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -362,7 +362,7 @@ mod test {
     // This isn't meant to test all of the possible validation failures (that should be done in a unit
     // test for storage), just the main validation failures from the API
     async fn test_invoice_validation<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -405,12 +405,53 @@ mod test {
         );
     }
 
+    #[rstest]
+    #[tokio::test]
+    async fn test_misspelled_field<T>(
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
+        #[future]
+        provider_setup: (T, StrictEngine, MockKeyStore),
+    ) where
+        T: Provider + Clone + Send + Sync + 'static,
+    {
+        let (store, index, ks) = provider_setup.await;
+        let scaffold = testing::RawScaffold::load("valid_v1").await;
+
+        let api = super::routes::api(
+            store,
+            index,
+            AlwaysAuthenticate,
+            AlwaysAuthorize,
+            ks,
+            VerificationStrategy::default(),
+            scaffold.keyring.clone(),
+        );
+
+        // Extra fields aren't signed, so this doesn't invalidate the signatures
+        let mut body = b"yankd = false\n".to_vec();
+        body.extend_from_slice(&scaffold.invoice);
+        let res = warp::test::request()
+            .method("POST")
+            .header("Content-Type", "application/toml")
+            .path("/v1/_i")
+            .body(&body)
+            .reply(&api)
+            .await;
+
+        assert_eq!(
+            res.status(),
+            warp::http::StatusCode::BAD_REQUEST,
+            "Invoice with a misspelled field should be rejected. Body: {}",
+            String::from_utf8_lossy(res.body())
+        );
+    }
+
     #[rstest]
     #[tokio::test]
     // This isn't meant to test all of the possible validation failures (that should be done in a unit
     // test for storage), just the main validation failures from the API
     async fn test_parcel_validation<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -444,7 +485,7 @@ mod test {
             .await
             .expect("Unable to create parcel");
 
-        // Already created parcel
+        // Already created parcel. Uploading identical content again is a no-op
         let res = warp::test::request()
             .method("POST")
             .path(&format!(
@@ -456,7 +497,7 @@ mod test {
             .await;
         assert_eq!(
             res.status(),
-            warp::http::StatusCode::CONFLICT,
+            warp::http::StatusCode::OK,
             "Body: {}",
             String::from_utf8_lossy(res.body())
         );
@@ -496,7 +537,7 @@ mod test {
     // Once again, this isn't meant to exercise all of the query functionality, just that the API
     // functions properly
     async fn test_queries<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -605,7 +646,7 @@ mod test {
     #[rstest]
     #[tokio::test]
     async fn test_missing<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -680,7 +721,7 @@ mod test {
     #[rstest]
     #[tokio::test]
     async fn test_host_signed<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -731,7 +772,7 @@ mod test {
     #[rstest]
     #[tokio::test]
     async fn test_anonymous_get<T>(
-        #[values(testing::setup(), testing::setup_embedded())]
+        #[values(testing::setup(), testing::setup_embedded(), testing::setup_memory())]
         #[future]
         provider_setup: (T, StrictEngine, MockKeyStore),
     ) where
@@ -822,4 +863,88 @@ mod test {
             String::from_utf8_lossy(res.body())
         );
     }
+
+    #[tokio::test]
+    async fn test_duplicate_parcel_names_on_memory_provider() {
+        let (store, index, ks) = testing::setup_memory().await;
+        let store = store.reject_duplicate_parcel_names(true);
+        let scaffold = testing::Scaffold::load("valid_v1").await;
+
+        let api = super::routes::api(
+            store,
+            index,
+            AlwaysAuthenticate,
+            AlwaysAuthorize,
+            ks,
+            VerificationStrategy::default(),
+            scaffold.keyring.clone(),
+        );
+
+        // Add a different parcel with the same name as an existing one, then re-sign the invoice
+        let mut inv = scaffold.invoice.clone();
+        let parcels = inv.parcel.as_mut().unwrap();
+        let mut duplicate = parcels[0].clone();
+        duplicate.label.sha256 = "0".repeat(64);
+        parcels.push(duplicate);
+        inv.signature = None;
+        inv.sign(
+            SignatureRole::Creator,
+            scaffold
+                .keys
+                .get_first_matching(&SignatureRole::Creator, None)
+                .unwrap(),
+        )
+        .unwrap();
+        let inv = toml::to_vec(&inv).expect("serialization shouldn't fail");
+
+        let res = warp::test::request()
+            .method("POST")
+            .header("Content-Type", "application/toml")
+            .path("/v1/_i")
+            .body(&inv)
+            .reply(&api)
+            .await;
+
+        assert_eq!(
+            res.status(),
+            warp::http::StatusCode::BAD_REQUEST,
+            "Invoice with duplicate parcel names should be rejected. Body: {}",
+            String::from_utf8_lossy(res.body())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_policy_on_memory_provider() {
+        let (store, index, ks) = testing::setup_memory().await;
+        let store = store.policy(crate::InvoicePolicy {
+            max_parcels: Some(0),
+            ..Default::default()
+        });
+        let scaffold = testing::RawScaffold::load("valid_v1").await;
+
+        let api = super::routes::api(
+            store,
+            index,
+            AlwaysAuthenticate,
+            AlwaysAuthorize,
+            ks,
+            VerificationStrategy::default(),
+            scaffold.keyring.clone(),
+        );
+
+        let res = warp::test::request()
+            .method("POST")
+            .header("Content-Type", "application/toml")
+            .path("/v1/_i")
+            .body(&scaffold.invoice)
+            .reply(&api)
+            .await;
+
+        assert_eq!(
+            res.status(),
+            warp::http::StatusCode::BAD_REQUEST,
+            "Invoice breaking the policy should be rejected. Body: {}",
+            String::from_utf8_lossy(res.body())
+        );
+    }
 }
@@ -118,24 +118,47 @@ impl Reply for SerializedData {
 pub fn into_reply(error: ProviderError) -> warp::reply::WithStatus<SerializedData> {
     let status_code = match &error {
         ProviderError::CreateYanked => StatusCode::UNPROCESSABLE_ENTITY,
-        ProviderError::NotFound => StatusCode::NOT_FOUND,
-        ProviderError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => {
+        ProviderError::NotFound { .. } | ProviderError::MissingParcels(_) => StatusCode::NOT_FOUND,
+        ProviderError::Io { source, .. } if source.kind() == std::io::ErrorKind::NotFound => {
             // Remap the error in the case this is a not found error
-            return reply_from_error(ProviderError::NotFound, StatusCode::NOT_FOUND);
+            return reply_from_error("resource not found", StatusCode::NOT_FOUND);
         }
-        ProviderError::Exists | ProviderError::WriteInProgress => StatusCode::CONFLICT,
+        ProviderError::Io { .. } => {
+            // Don't leak paths on the server to the client
+            return reply_from_error(
+                "resource could not be loaded",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+        ProviderError::Exists
+        | ProviderError::WriteInProgress
+        | ProviderError::InUse
+        | ProviderError::SignedInvoice
+        | ProviderError::DigestConflict { .. } => StatusCode::CONFLICT,
         ProviderError::Malformed(_)
+        | ProviderError::Invalid(_)
+        | ProviderError::PolicyViolation(_)
         | ProviderError::Unserializable(_)
-        | ProviderError::DigestMismatch
+        | ProviderError::DigestMismatch { .. }
         | ProviderError::InvalidId(_)
-        | ProviderError::SizeMismatch => StatusCode::BAD_REQUEST,
+        | ProviderError::UnsupportedVersion(_)
+        | ProviderError::SizeMismatch { .. } => StatusCode::BAD_REQUEST,
         ProviderError::Yanked => StatusCode::FORBIDDEN,
+        ProviderError::ReadOnly => StatusCode::METHOD_NOT_ALLOWED,
+        ProviderError::OutOfRange { .. } => StatusCode::RANGE_NOT_SATISFIABLE,
+        ProviderError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        ProviderError::QuotaExceeded { .. } => StatusCode::INSUFFICIENT_STORAGE,
+        ProviderError::Unauthorized => StatusCode::UNAUTHORIZED,
+        ProviderError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        ProviderError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
         #[cfg(feature = "client")]
         ProviderError::ProxyError(e) => {
             // Unwrap the inner error so as to provide better details to the client
             return reply_from_error(e, StatusCode::INTERNAL_SERVER_ERROR);
         }
-        ProviderError::Other(_) | ProviderError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        ProviderError::CanonicalMismatch { .. } | ProviderError::Other(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
         ProviderError::FailedSigning(e) => {
             // Unwrap the inner error so as to provide better details to the client
             return reply_from_error(e, StatusCode::BAD_REQUEST);
@@ -516,9 +516,11 @@ mod test {
                 media_type: "text/plain".to_owned(),
                 size: parcel_data.len() as u64,
                 sha256: sha_string.clone(),
+                digest_algorithm: None,
                 annotations: None,
                 origin: None,
                 feature: None,
+                extra: Default::default(),
             },
             conditions: None,
         }]);
@@ -620,9 +622,11 @@ mod test {
                 media_type: "text/plain".to_owned(),
                 size: parcel_data.len() as u64,
                 sha256: sha_string.clone(),
+                digest_algorithm: None,
                 annotations: None,
                 origin: None,
                 feature: None,
+                extra: Default::default(),
             },
             conditions: None,
         }]);
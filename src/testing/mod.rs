@@ -16,6 +16,7 @@ use crate::invoice::signature::{
 };
 use crate::provider::embedded::EmbeddedProvider;
 use crate::provider::file::FileProvider;
+use crate::provider::memory::MemoryProvider;
 use crate::search::StrictEngine;
 use crate::signature::{KeyRingLoader, LabelMatch};
 
@@ -207,6 +208,15 @@ pub async fn setup_embedded() -> (EmbeddedProvider<StrictEngine>, StrictEngine,
     (store, index, kstore)
 }
 
+/// Returns an in-memory `Provider` implementation configured with a strict Search implementation
+/// and a mock key store for use in testing API endpoints
+pub async fn setup_memory() -> (MemoryProvider<StrictEngine>, StrictEngine, MockKeyStore) {
+    let index = StrictEngine::default();
+    let store = MemoryProvider::new(index.clone());
+    let kstore = MockKeyStore::new();
+    (store, index, kstore)
+}
+
 /// Loads all scaffolds in the scaffolds directory, returning them as a hashmap with the directory
 /// name as the key and a `RawScaffold` as a value. There is not an equivalent for loading all
 /// scaffolds as a `Scaffold` object, because some of them may be invalid on will not deserialize